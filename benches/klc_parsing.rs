@@ -0,0 +1,101 @@
+//! Benchmarks reading very large KLC files, like generated layouts with full Unicode
+//! dead key trees.
+//!
+//! Reading the generated layout of about 30 000 lines should stay under 20 ms.
+
+use std::{
+    fs,
+    hint::black_box,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use klc_install::{
+    klc::{DeadKey, KlcDocument},
+    utils::ReadUtf16Line,
+};
+
+const DEAD_KEYS: u32 = 300;
+const COMPOSITIONS: u32 = 100;
+
+/// Generates a layout with `DEAD_KEYS` dead keys of `COMPOSITIONS` compositions each.
+fn generate_klc() -> String {
+    let mut content = String::from("\u{feff}KBD\tbench\t\"Benchmark\"\r\n\r\n");
+    content.push_str("LOCALEID\t\"00000415\"\r\n\r\n");
+
+    for dead_key in 0..DEAD_KEYS {
+        content.push_str(&format!("DEADKEY\t{:04x}\r\n\r\n", 0x2000 + dead_key));
+        for base in 0..COMPOSITIONS {
+            let composed = 0x3000 + dead_key * COMPOSITIONS + base;
+            content.push_str(&format!(
+                "{:04x}\t{:04x}\t// {} -> {}\r\n",
+                0x41 + base,
+                composed,
+                base,
+                composed
+            ));
+        }
+        content.push_str("\r\n");
+    }
+
+    content.push_str("ENDKBD\r\n");
+    content
+}
+
+/// Writes the content as UTF-16 with a BOM, like MSKLC does.
+fn write_utf16(path: &Path, content: &str) {
+    let bytes: Vec<u8> = content
+        .encode_utf16()
+        .flat_map(|unit| unit.to_le_bytes())
+        .collect();
+    fs::write(path, bytes).unwrap();
+}
+
+/// Writes the generated layout and a layout including it to a temporary directory.
+fn write_files() -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("klc-install-bench-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let base_path = dir.join("base.klc");
+    write_utf16(&base_path, &generate_klc());
+
+    // Overriding every dead key merges all rows of the base
+    let overlay_path = dir.join("overlay.klc");
+    write_utf16(
+        &overlay_path,
+        &generate_klc().replacen("KBD", ";#include base.klc\r\nKBD", 1),
+    );
+
+    (base_path, overlay_path)
+}
+
+fn bench_parsing(c: &mut Criterion) {
+    let (base_path, overlay_path) = write_files();
+
+    c.bench_function("utf16_lines", |b| {
+        b.iter(|| {
+            let reader = BufReader::new(fs::File::open(&base_path).unwrap());
+            let lines = reader.utf16_lines().collect::<Result<Vec<_>, _>>();
+            black_box(lines.unwrap())
+        })
+    });
+
+    c.bench_function("KlcDocument::read_from_file", |b| {
+        b.iter(|| black_box(KlcDocument::read_from_file(&base_path).unwrap()))
+    });
+
+    c.bench_function("KlcDocument::read_resolved", |b| {
+        b.iter(|| black_box(KlcDocument::read_resolved(&overlay_path).unwrap()))
+    });
+
+    let lines = KlcDocument::read_from_file(&base_path).unwrap().to_lines();
+    c.bench_function("DeadKey::parse", |b| {
+        b.iter(|| black_box(DeadKey::parse(lines.iter().map(|line| line.as_str())).unwrap()))
+    });
+
+    _ = fs::remove_dir_all(base_path.parent().unwrap());
+}
+
+criterion_group!(benches, bench_parsing);
+criterion_main!(benches);
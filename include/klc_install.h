@@ -0,0 +1,25 @@
+/*
+ * C API of klc-install, exported by klc_install.dll.
+ *
+ * Requests and responses are UTF-8 JSON strings, described in src/ffi.rs
+ * and src/json_schema.rs.
+ * Every response must be freed with klc_install_free_string.
+ */
+
+#ifndef KLC_INSTALL_H
+#define KLC_INSTALL_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+char *klc_install_list(void);
+char *klc_install_install(const char *request);
+char *klc_install_uninstall(const char *request);
+void klc_install_free_string(char *response);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,75 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    path::PathBuf,
+};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use windows::Win32::{
+    System::SystemInformation::{
+        GetNativeSystemInfo, PROCESSOR_ARCHITECTURE_AMD64, PROCESSOR_ARCHITECTURE_ARM64,
+        PROCESSOR_ARCHITECTURE_IA64, SYSTEM_INFO,
+    },
+    UI::Shell::{FOLDERID_System, FOLDERID_SystemX86},
+};
+
+use crate::get_known_folder::get_known_folder;
+
+/// Architecture a layout DLL is built for.
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Arch {
+    Amd64,
+    X86,
+    /// x86 build used by 32-bit applications on 64-bit Windows.
+    Wow64,
+    Ia64,
+    Arm64,
+}
+
+impl Arch {
+    /// Returns the architecture of the running Windows, even if this process is emulated.
+    pub fn get_native() -> Arch {
+        let mut info = SYSTEM_INFO::default();
+        unsafe { GetNativeSystemInfo(&mut info) };
+
+        match unsafe { info.Anonymous.Anonymous.wProcessorArchitecture } {
+            PROCESSOR_ARCHITECTURE_AMD64 => Arch::Amd64,
+            PROCESSOR_ARCHITECTURE_ARM64 => Arch::Arm64,
+            PROCESSOR_ARCHITECTURE_IA64 => Arch::Ia64,
+            _ => Arch::X86,
+        }
+    }
+
+    /// Returns KBDUTOOL's flag selecting the architecture, if KBDUTOOL supports it.
+    pub fn get_kbdutool_flag(self) -> Option<char> {
+        match self {
+            Arch::Amd64 => Some('m'),
+            Arch::X86 => Some('x'),
+            Arch::Wow64 => Some('o'),
+            Arch::Ia64 => Some('i'),
+            Arch::Arm64 => None,
+        }
+    }
+
+    /// Returns the directory the DLL has to be placed in on this machine,
+    /// or `None` if it's built for a different machine.
+    pub fn get_system_dir(self, native: Arch) -> Result<Option<PathBuf>, String> {
+        if self == native {
+            return get_known_folder(&FOLDERID_System).map(Some);
+        }
+
+        if self == Arch::Wow64 && native != Arch::X86 {
+            return get_known_folder(&FOLDERID_SystemX86).map(Some);
+        }
+
+        Ok(None)
+    }
+}
+
+impl Display for Arch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = self.to_possible_value().unwrap();
+        write!(f, "{}", name.get_name())
+    }
+}
@@ -0,0 +1,223 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::manifest::Locales;
+
+/// Names of the metadata file at the root of a bundle, in TOML or JSON.
+const METADATA_FILE_NAMES: [&str; 2] = ["layout.toml", "layout.json"];
+
+/// Describes the layout of a bundle. Unset options fall back to the command line ones.
+#[derive(Debug, Default, Deserialize)]
+pub struct BundleMetadata {
+    /// Path to the .KLC or .DLL file in the bundle.
+    ///
+    /// Only needed if the bundle contains multiple layout files.
+    #[serde(default)]
+    pub file: Option<String>,
+
+    #[serde(default)]
+    pub locale: Locales,
+
+    #[serde(default, alias = "description")]
+    pub text: Option<String>,
+
+    #[serde(default)]
+    pub id: Option<String>,
+
+    #[serde(default, alias = "key")]
+    pub registry_key: Option<String>,
+
+    #[serde(default)]
+    pub dll_name: Option<String>,
+}
+
+/// An extracted bundle with the layout file to install.
+#[derive(Debug)]
+pub struct Bundle {
+    pub layout_path: PathBuf,
+    pub metadata: BundleMetadata,
+}
+
+pub fn is_bundle(file_path: &Path) -> bool {
+    file_path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+}
+
+fn is_layout_file(file_path: &Path) -> bool {
+    file_path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("klc") || ext.eq_ignore_ascii_case("dll"))
+}
+
+impl BundleMetadata {
+    fn read_from_file(file_path: &Path) -> Result<BundleMetadata, String> {
+        let content = fs::read_to_string(file_path).map_err(|e| e.to_string())?;
+
+        let is_json = file_path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+        if is_json {
+            serde_json::from_str(&content).map_err(|e| e.to_string())
+        } else {
+            toml::from_str(&content).map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// Extracts every file of the .ZIP archive into the directory, returning their relative paths.
+#[cfg(feature = "bundles")]
+fn extract_archive(bundle_path: &Path, target_dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let file = fs::File::open(bundle_path)
+        .map_err(|e| format!("Couldn't open {}. {}", bundle_path.display(), e))?;
+    let mut archive = zip::ZipArchive::new(file)
+        .map_err(|e| format!("{} isn't a valid bundle. {}", bundle_path.display(), e))?;
+
+    let mut extracted = Vec::new();
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(|e| e.to_string())?;
+        // Skips entries escaping the directory, like ../../Windows/System32/kbdus.dll
+        let Some(name) = entry.enclosed_name() else {
+            continue;
+        };
+        let path = target_dir.join(&name);
+
+        if entry.is_dir() {
+            fs::create_dir_all(&path).map_err(|e| e.to_string())?;
+            continue;
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let mut output = fs::File::create(&path)
+            .map_err(|e| format!("Couldn't extract {}. {}", name.display(), e))?;
+        std::io::copy(&mut entry, &mut output)
+            .map_err(|e| format!("Couldn't extract {}. {}", name.display(), e))?;
+
+        extracted.push(name);
+    }
+
+    Ok(extracted)
+}
+
+/// Bundles need the `bundles` feature, which slim builds leave out.
+#[cfg(not(feature = "bundles"))]
+fn extract_archive(bundle_path: &Path, _target_dir: &Path) -> Result<Vec<PathBuf>, String> {
+    Err(format!(
+        "This build of klc-install can't install bundles. Extract {} and install its layout file instead.",
+        bundle_path.display()
+    ))
+}
+
+impl Bundle {
+    /// Extracts the bundle into the directory and finds the layout file in it.
+    pub fn extract(bundle_path: &Path, target_dir: &Path) -> Result<Bundle, String> {
+        let extracted = extract_archive(bundle_path, target_dir)?;
+
+        let metadata = match METADATA_FILE_NAMES
+            .iter()
+            .find(|file_name| extracted.iter().any(|name| name == Path::new(file_name)))
+        {
+            Some(file_name) => BundleMetadata::read_from_file(&target_dir.join(file_name))
+                .map_err(|e| format!("Couldn't read {} of the bundle. {}", file_name, e))?,
+            None => BundleMetadata::default(),
+        };
+
+        let layout_name = find_layout_file(&extracted, metadata.file.as_deref())
+            .map_err(|e| format!("{} {}", bundle_path.display(), e))?;
+
+        Ok(Bundle {
+            layout_path: target_dir.join(layout_name),
+            metadata,
+        })
+    }
+}
+
+/// Picks the layout file among the extracted ones: the one named by the metadata, or the only
+/// .KLC file, or the only .DLL file.
+fn find_layout_file<'a>(
+    extracted: &'a [PathBuf],
+    requested: Option<&str>,
+) -> Result<&'a Path, String> {
+    if let Some(requested) = requested {
+        return extracted
+            .iter()
+            .find(|name| *name == Path::new(requested))
+            .map(|name| name.as_path())
+            .ok_or_else(|| format!("doesn't contain the layout file {}.", requested));
+    }
+
+    let layout_files = extracted
+        .iter()
+        .filter(|name| is_layout_file(name))
+        .collect::<Vec<_>>();
+    let klc_files = layout_files
+        .iter()
+        .filter(|name| {
+            name.extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("klc"))
+        })
+        .collect::<Vec<_>>();
+
+    match (klc_files.as_slice(), layout_files.as_slice()) {
+        ([klc_file], _) => Ok(klc_file.as_path()),
+        ([], [dll_file]) => Ok(dll_file.as_path()),
+        ([], []) => Err("doesn't contain a .KLC or .DLL file.".to_string()),
+        _ => Err(
+            "contains multiple layout files. Set the file to install in its layout.toml."
+                .to_string(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bundle_metadata() {
+        let metadata: BundleMetadata = toml::from_str(
+            r#"
+            locale = "pl-PL"
+            description = "Multilingual"
+            id = "0F00"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(metadata.file, None);
+        assert_eq!(metadata.locale.to_vec(), ["pl-PL"]);
+        assert_eq!(metadata.text.as_deref(), Some("Multilingual"));
+        assert_eq!(metadata.id.as_deref(), Some("0F00"));
+    }
+
+    #[test]
+    fn test_find_layout_file() {
+        let extracted = [
+            PathBuf::from("layout.toml"),
+            PathBuf::from("multilin.KLC"),
+            PathBuf::from("amd64/multilin.dll"),
+        ];
+        assert_eq!(
+            find_layout_file(&extracted, None).unwrap(),
+            Path::new("multilin.KLC")
+        );
+        assert_eq!(
+            find_layout_file(&extracted, Some("amd64/multilin.dll")).unwrap(),
+            Path::new("amd64/multilin.dll")
+        );
+        assert!(find_layout_file(&extracted, Some("kbdpl.dll")).is_err());
+
+        assert_eq!(
+            find_layout_file(&extracted[2..], None).unwrap(),
+            Path::new("amd64/multilin.dll")
+        );
+        assert!(find_layout_file(&extracted[..1], None).is_err());
+        assert!(find_layout_file(&[extracted[2].clone(), "kbdpl.dll".into()], None).is_err());
+    }
+}
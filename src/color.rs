@@ -0,0 +1,128 @@
+use std::{
+    fmt::Display,
+    io::{self, IsTerminal},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use windows::Win32::System::Console::{
+    GetConsoleMode, GetStdHandle, SetConsoleMode, CONSOLE_MODE, ENABLE_VIRTUAL_TERMINAL_PROCESSING,
+    STD_ERROR_HANDLE, STD_HANDLE, STD_OUTPUT_HANDLE,
+};
+
+use crate::porcelain::{emit, Event};
+
+/// Whether the standard output and error are colored, decided once by `init`.
+static STDOUT_COLOR: AtomicBool = AtomicBool::new(false);
+static STDERR_COLOR: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Bold,
+    Dim,
+    Red,
+    Yellow,
+    Green,
+}
+
+impl Style {
+    fn get_code(self) -> &'static str {
+        match self {
+            Style::Bold => "1",
+            Style::Dim => "2",
+            Style::Red => "31",
+            Style::Yellow => "33",
+            Style::Green => "32",
+        }
+    }
+}
+
+/// Whether colors are wanted, following https://no-color.org: a `NO_COLOR` that isn't empty
+/// turns them off, like `--no-color`.
+fn wants_color(no_color: bool, no_color_env: Option<&str>) -> bool {
+    !no_color && no_color_env.is_none_or(str::is_empty)
+}
+
+/// Lets the console interpret the escape codes, which older consoles don't do by default.
+///
+/// Fails when the stream isn't a console, e.g. when it's redirected.
+fn enable_escape_codes(stream: STD_HANDLE) -> bool {
+    unsafe {
+        let Ok(handle) = GetStdHandle(stream) else {
+            return false;
+        };
+        let mut mode = CONSOLE_MODE::default();
+        if GetConsoleMode(handle, &mut mode).is_err() {
+            return false;
+        }
+        SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING).is_ok()
+    }
+}
+
+/// Decides whether the output is colored. Until it's called, nothing is.
+pub fn init(no_color: bool) {
+    let no_color_env = std::env::var("NO_COLOR").ok();
+    if !wants_color(no_color, no_color_env.as_deref()) {
+        return;
+    }
+
+    STDOUT_COLOR.store(
+        io::stdout().is_terminal() && enable_escape_codes(STD_OUTPUT_HANDLE),
+        Ordering::Relaxed,
+    );
+    STDERR_COLOR.store(
+        io::stderr().is_terminal() && enable_escape_codes(STD_ERROR_HANDLE),
+        Ordering::Relaxed,
+    );
+}
+
+fn apply_style(text: impl Display, style: Style, enabled: bool) -> String {
+    if enabled {
+        format!("\x1b[{}m{}\x1b[0m", style.get_code(), text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Styles text printed to the standard output.
+pub fn paint(text: impl Display, style: Style) -> String {
+    apply_style(text, style, STDOUT_COLOR.load(Ordering::Relaxed))
+}
+
+/// Styles text printed to the standard error.
+pub fn paint_stderr(text: impl Display, style: Style) -> String {
+    apply_style(text, style, STDERR_COLOR.load(Ordering::Relaxed))
+}
+
+/// Formats a warning for the standard output, with the `Warning:` label in yellow.
+fn warning(message: &str) -> String {
+    format!("{} {}", paint("Warning:", Style::Yellow), message)
+}
+
+/// Prints a warning, which is also an event in porcelain mode.
+pub fn print_warning(message: impl Display) {
+    let message = message.to_string();
+    emit(&Event::Warning { message: &message });
+    println!("{}", warning(&message));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_wants_color() {
+        assert!(wants_color(false, None));
+        assert!(wants_color(false, Some("")));
+        assert!(!wants_color(false, Some("1")));
+        assert!(!wants_color(true, None));
+    }
+
+    #[test]
+    fn test_apply_style() {
+        assert_eq!(
+            apply_style("Error", Style::Red, true),
+            "\x1b[31mError\x1b[0m"
+        );
+        assert_eq!(apply_style("Error", Style::Red, false), "Error");
+    }
+}
@@ -0,0 +1,411 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use clap::Args;
+use serde::Deserialize;
+
+use crate::{
+    arch::Arch,
+    error::{CompileError, InstallError},
+    klc::{parse_variable, read_variables_file, KlcDocument, KlcInfo},
+    tool_runner::{ToolCommand, ToolRunner},
+    version_resource::{parse_version, VersionResource},
+};
+
+#[derive(Args, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct TemplateVars {
+    /// Sets a variable used by ${NAME} placeholders in the KLC file.
+    #[arg(long = "var", value_name = "NAME=VALUE")]
+    pub vars: Vec<String>,
+
+    /// Reads variables for placeholders from a file with one NAME=VALUE per line.
+    ///
+    /// Variables given with --var take precedence.
+    #[arg(long, value_name = "FILE")]
+    pub vars_file: Option<String>,
+}
+
+impl TemplateVars {
+    fn get_variables(&self) -> Result<HashMap<String, String>, String> {
+        let mut variables = match &self.vars_file {
+            Some(vars_file) => read_variables_file(Path::new(vars_file))?,
+            None => HashMap::new(),
+        };
+
+        for var in &self.vars {
+            let (name, value) = parse_variable(var)?;
+            variables.insert(name, value);
+        }
+
+        Ok(variables)
+    }
+}
+
+/// Checks if MSKLC is installed in the given directory.
+///
+/// Returns the path to KBDUTOOL if found.
+pub fn get_kbdutool(msklc_dir: &Path) -> Result<PathBuf, CompileError> {
+    let msklc_path = msklc_dir.canonicalize().map_err(|e| e.to_string())?;
+    let mut kbdutool_path = msklc_path.join("kbdutool.exe");
+
+    if !kbdutool_path.exists() {
+        kbdutool_path = msklc_path.join("bin/i386/kbdutool.exe");
+
+        if !kbdutool_path.exists() {
+            return Err(CompileError::NotInMsklcDir);
+        }
+    }
+
+    Ok(kbdutool_path)
+}
+
+/// Tries to find MSKLC's KBDUTOOL in the PATH.
+pub fn find_kbdutool_in_path() -> Result<PathBuf, CompileError> {
+    let path_env = std::env::var("PATH").map_err(|e| e.to_string())?;
+    let path_env = path_env.split(';');
+
+    for path in path_env {
+        let path = Path::new(path);
+
+        // Check for MSKLC
+        let msklc_path = path.join("MSKLC.exe");
+
+        if !msklc_path.exists() {
+            continue;
+        }
+
+        return get_kbdutool(path);
+    }
+
+    Err(CompileError::NotInPath)
+}
+
+/// The directory this process keeps its intermediate files in, e.g. `%TEMP%\klc-install-1234`.
+pub fn get_work_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("klc-install-{}", std::process::id()))
+}
+
+/// Removes the work directory once the command is done with its files.
+pub fn remove_work_dir() {
+    _ = fs::remove_dir_all(get_work_dir());
+}
+
+/// Resolves the `;#include` directives and `${NAME}` placeholders of a KLC file.
+///
+/// If there are any, the resulting layout is written to the work directory and its path is
+/// returned. Otherwise, the original path is returned.
+pub fn prepare_klc_file(file_path: &Path, template_vars: &TemplateVars) -> Result<PathBuf, String> {
+    let includes = KlcDocument::read_from_file(file_path)?.includes;
+
+    let mut resolved = KlcDocument::read_resolved(file_path)?;
+    let substituted = resolved.substitute_variables(&template_vars.get_variables()?)?;
+
+    if includes.is_empty() && substituted == 0 {
+        return Ok(file_path.to_path_buf());
+    }
+
+    // Shared directories like %TEMP% could have other files of the same name
+    let prepared_dir = get_work_dir().join("prepared");
+    fs::create_dir_all(&prepared_dir).map_err(|e| {
+        format!(
+            "Couldn't create the directory {}. {}",
+            prepared_dir.display(),
+            e
+        )
+    })?;
+    let resolved_path = prepared_dir.join(file_path.file_name().unwrap());
+    resolved.write_to_file(&resolved_path)?;
+
+    if !includes.is_empty() {
+        println!("Merged the included files {}.", includes.join(", "));
+    }
+    if substituted > 0 {
+        println!("Substituted {} template placeholders.", substituted);
+    }
+
+    Ok(resolved_path)
+}
+
+/// Checks that the file exists and looks like a PE image.
+pub fn verify_dll_file(dll_path: &Path) -> Result<(), String> {
+    let mut header = [0u8; 2];
+    let mut file = std::fs::File::open(dll_path)
+        .map_err(|e| format!("Couldn't open the DLL file {}. {}", dll_path.display(), e))?;
+    file.read_exact(&mut header).map_err(|e| e.to_string())?;
+
+    if &header != b"MZ" {
+        return Err(format!(
+            "The file {} is not a valid DLL file.",
+            dll_path.display()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Creates an empty directory for KBDUTOOL to compile in, so its intermediate files
+/// don't end up in the working directory.
+pub fn create_scratch_dir(arch: Arch) -> Result<PathBuf, String> {
+    let scratch_dir = get_work_dir().join(arch.to_string());
+
+    if scratch_dir.exists() {
+        fs::remove_dir_all(&scratch_dir).map_err(|e| e.to_string())?;
+    }
+    fs::create_dir_all(&scratch_dir).map_err(|e| {
+        format!(
+            "Couldn't create the directory {}. {}",
+            scratch_dir.display(),
+            e
+        )
+    })?;
+
+    Ok(scratch_dir)
+}
+
+/// Compiles the KLC file with KBDUTOOL in the scratch directory, returning the path of the DLL.
+///
+/// `arch` is KBDUTOOL's architecture flag: `m` for AMD64, `x` for x86, `o` for WOW64 or `i` for IA64.
+/// A dry runner doesn't compile anything, so the returned DLL doesn't exist.
+pub fn compile_klc_file(
+    runner: &dyn ToolRunner,
+    kbdutool_path: &Path,
+    file_path: &Path,
+    layout_name: &str,
+    arch: char,
+    scratch_dir: &Path,
+) -> Result<PathBuf, CompileError> {
+    // KBDUTOOL resolves the file from its working directory
+    let file_path = file_path.canonicalize().map_err(|e| e.to_string())?;
+
+    let command = ToolCommand::new(kbdutool_path, scratch_dir)
+        .arg(format!("-wu{}", arch))
+        .arg(&file_path);
+    let kbdutool_output = runner.run(&command).map_err(CompileError::Run)?;
+
+    // KBDUTOOL names the DLL after the layout, not the file
+    let dll_path = scratch_dir.join(layout_name).with_extension("dll");
+    if runner.is_dry_run() {
+        return Ok(dll_path);
+    }
+
+    println!("KBDUTOOL output: {}", kbdutool_output.stdout);
+
+    if !kbdutool_output.success {
+        return Err(CompileError::Failed(kbdutool_output.stderr));
+    }
+
+    dll_path.canonicalize().map_err(CompileError::DllNotFound)
+}
+
+/// Copies everything KBDUTOOL produced next to the DLL into the output directory.
+fn copy_build_artifacts(dll_path: &Path, out_dir: &Path) -> Result<(), String> {
+    fs::create_dir_all(out_dir)
+        .map_err(|e| format!("Couldn't create the directory {}. {}", out_dir.display(), e))?;
+
+    for entry in fs::read_dir(dll_path.parent().unwrap()).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.is_file() {
+            fs::copy(&path, out_dir.join(path.file_name().unwrap()))
+                .map_err(|e| format!("Couldn't copy {}. {}", path.display(), e))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes the generated sources and object files, leaving only the DLL.
+fn remove_intermediate_files(dll_path: &Path) -> Result<(), String> {
+    for entry in fs::read_dir(dll_path.parent().unwrap()).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.is_file() && path.file_name() != dll_path.file_name() {
+            _ = fs::remove_file(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// The architectures a layout is built for by default.
+pub fn get_default_archs(native_arch: Arch, no_wow64: bool) -> Vec<Arch> {
+    let mut archs = vec![native_arch];
+    // On 64-bit Windows, 32-bit applications need a WOW64 build in SysWOW64
+    if native_arch != Arch::X86 && !no_wow64 {
+        archs.push(Arch::Wow64);
+    }
+    archs
+}
+
+/// Compiles the KLC file for every architecture and stamps the DLLs with the layout's metadata.
+///
+/// The build artifacts are copied to `artifacts_dir` if given, then only the DLLs are kept.
+/// A dry runner only prints the KBDUTOOL commands, returning where the DLLs would be.
+pub fn build_layout_dlls(
+    runner: &dyn ToolRunner,
+    file_path: &Path,
+    klc_info: &KlcInfo,
+    msklc: Option<&str>,
+    archs: &[Arch],
+    artifacts_dir: Option<&Path>,
+) -> Result<Vec<(Arch, PathBuf)>, InstallError> {
+    let layout_name = &klc_info.layout_name;
+
+    // 1. Try to find MSKLC
+    let kbdutool_path = if let Some(msklc) = msklc {
+        get_kbdutool(&Path::new(&msklc))?
+    } else {
+        find_kbdutool_in_path()?
+    };
+
+    // 2. Compile the KLC file for every architecture
+    let mut builds: Vec<(Arch, PathBuf)> = Vec::new();
+    for &arch in archs {
+        if builds.iter().any(|(built, _)| *built == arch) {
+            continue;
+        }
+
+        let flag = arch
+            .get_kbdutool_flag()
+            .ok_or_else(|| format!("KBDUTOOL can't compile layouts for {}.", arch))?;
+
+        // All builds have the same name, so they can't share the directory
+        let scratch_dir = create_scratch_dir(arch)?;
+
+        let arch_dll_path = compile_klc_file(
+            runner,
+            &kbdutool_path,
+            file_path,
+            layout_name,
+            flag,
+            &scratch_dir,
+        )?;
+        if runner.is_dry_run() {
+            builds.push((arch, arch_dll_path));
+            continue;
+        }
+        println!(
+            "The compiled {} DLL file is at: {}",
+            arch,
+            arch_dll_path.display()
+        );
+
+        builds.push((arch, arch_dll_path));
+    }
+
+    if runner.is_dry_run() {
+        return Ok(builds);
+    }
+
+    // 3. Stamp the DLLs with the layout's metadata instead of KBDUTOOL's defaults
+    let version = match &klc_info.version {
+        Some(version) => parse_version(version)?,
+        None => [1, 0, 0, 0],
+    };
+    let version_resource = VersionResource {
+        version,
+        product_name: klc_info.layout_text.clone(),
+        internal_name: layout_name.clone(),
+        original_filename: format!("{}.dll", layout_name),
+        company: klc_info.company.clone(),
+        copyright: klc_info.copyright.clone(),
+    };
+    for (_, arch_dll_path) in &builds {
+        version_resource.write_to_file(arch_dll_path)?;
+    }
+
+    if let Some(artifacts_dir) = artifacts_dir {
+        for (arch, arch_dll_path) in &builds {
+            let arch_output_dir = artifacts_dir.join(arch.to_string());
+            copy_build_artifacts(arch_dll_path, &arch_output_dir)?;
+            println!(
+                "The {} build artifacts were copied to: {}",
+                arch,
+                arch_output_dir.display()
+            );
+        }
+    }
+    for (_, arch_dll_path) in &builds {
+        remove_intermediate_files(arch_dll_path)?;
+    }
+
+    Ok(builds)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, io};
+
+    use super::*;
+
+    use crate::tool_runner::{DryRunner, ToolOutput};
+
+    /// Writes a DLL like KBDUTOOL would, recording the commands.
+    struct FakeKbdutool {
+        succeeds: bool,
+        commands: RefCell<Vec<ToolCommand>>,
+    }
+
+    impl ToolRunner for FakeKbdutool {
+        fn run(&self, command: &ToolCommand) -> io::Result<ToolOutput> {
+            self.commands.borrow_mut().push(command.clone());
+            if !self.succeeds {
+                return Ok(ToolOutput {
+                    success: false,
+                    stdout: String::new(),
+                    stderr: "The KBD section is missing.".to_string(),
+                });
+            }
+
+            fs::write(command.working_dir.join("kbdmine.dll"), b"MZ")?;
+            Ok(ToolOutput {
+                success: true,
+                ..Default::default()
+            })
+        }
+    }
+
+    #[test]
+    fn test_compile_klc_file() {
+        let scratch_dir = std::env::temp_dir().join(format!("compile-{}", std::process::id()));
+        fs::create_dir_all(&scratch_dir).unwrap();
+        let klc_path = scratch_dir.join("kbdmine.klc");
+        fs::write(&klc_path, "KBD\tkbdmine\t\"Mine\"").unwrap();
+        let kbdutool_path = Path::new("kbdutool.exe");
+
+        let compile = |runner: &dyn ToolRunner| {
+            compile_klc_file(
+                runner,
+                kbdutool_path,
+                &klc_path,
+                "kbdmine",
+                'm',
+                &scratch_dir,
+            )
+        };
+
+        let failing = FakeKbdutool {
+            succeeds: false,
+            commands: RefCell::default(),
+        };
+        let failed = compile(&failing);
+        let dry_dll_path = compile(&DryRunner);
+
+        let kbdutool = FakeKbdutool {
+            succeeds: true,
+            commands: RefCell::default(),
+        };
+        let compiled = compile(&kbdutool);
+        let verified = compiled.as_deref().map(verify_dll_file);
+        _ = fs::remove_dir_all(&scratch_dir);
+
+        assert!(matches!(failed, Err(CompileError::Failed(_))));
+        assert_eq!(dry_dll_path.unwrap(), scratch_dir.join("kbdmine.dll"));
+        assert!(verified.unwrap().is_ok());
+        assert_eq!(kbdutool.commands.borrow()[0].args[0], "-wum");
+        assert_eq!(kbdutool.commands.borrow()[0].working_dir, scratch_dir);
+    }
+}
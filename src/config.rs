@@ -0,0 +1,36 @@
+use std::{fs, io, path::PathBuf};
+
+use serde::Deserialize;
+use windows::Win32::UI::Shell::FOLDERID_ProgramData;
+
+use crate::{get_known_folder::get_known_folder, hooks::HooksConfig};
+
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+/// Settings of the machine, read from `%ProgramData%\klc-install\config.toml`.
+///
+/// The file is optional, and missing settings are the same as no file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub hooks: HooksConfig,
+}
+
+/// Shared by every user, like the history.
+pub fn get_config_path() -> Result<PathBuf, String> {
+    let program_data = get_known_folder(&FOLDERID_ProgramData)?;
+    Ok(program_data.join("klc-install").join(CONFIG_FILE_NAME))
+}
+
+impl Config {
+    pub fn read() -> Result<Config, String> {
+        let config_path = get_config_path()?;
+        let content = match fs::read_to_string(&config_path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(format!("Couldn't read {}. {}", config_path.display(), e)),
+        };
+
+        toml::from_str(&content).map_err(|e| format!("Invalid {}. {}", config_path.display(), e))
+    }
+}
@@ -0,0 +1,175 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display, Formatter},
+};
+
+use crate::{
+    registry_key::{RegistryError, RegistryKey},
+    registry_value::RegistryValueData,
+};
+
+/// Registry values of a layout, by name.
+pub type LayoutValues = BTreeMap<String, RegistryValueData>;
+
+/// A way a layout in another control set differs from the current one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LayoutDifference {
+    /// The layout isn't in the other control set.
+    Missing(String),
+    /// The layout is only in the other control set.
+    Extra(String),
+    /// The value differs or is missing in the other control set.
+    Value { key: String, name: String },
+}
+
+impl Display for LayoutDifference {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutDifference::Missing(key) => write!(f, "{} is missing", key),
+            LayoutDifference::Extra(key) => write!(f, "{} is only in this control set", key),
+            LayoutDifference::Value { key, name } => write!(f, "{} has a different {}", key, name),
+        }
+    }
+}
+
+/// Returns the names of the control sets, e.g. `ControlSet001`, the current one first.
+pub fn find_control_sets() -> Result<Vec<String>, RegistryError> {
+    let system_key = RegistryKey::from_path("HKLM\\SYSTEM")?;
+    let current = get_current_control_set(&system_key)?;
+
+    let mut control_sets = vec![current.clone()];
+    for name in system_key.iter_children_names() {
+        let name = name?;
+        let is_control_set = name
+            .strip_prefix("ControlSet")
+            .is_some_and(|number| number.len() == 3 && number.chars().all(|c| c.is_ascii_digit()));
+
+        if is_control_set && name != current {
+            control_sets.push(name);
+        }
+    }
+
+    Ok(control_sets)
+}
+
+/// Reads the control set Windows boots with from the `Select` key of a SYSTEM hive,
+/// e.g. `ControlSet001`.
+pub fn get_current_control_set(system_key: &RegistryKey) -> Result<String, RegistryError> {
+    let select_key = system_key.get_subkey("Select")?;
+    match select_key.get_value(Some("Current"))?.get_value() {
+        RegistryValueData::Dword(current) => Ok(format!("ControlSet{:03}", current)),
+        _ => Err(RegistryError::Other(
+            "Invalid current control set.".to_string(),
+        )),
+    }
+}
+
+pub fn get_control_set_layouts_key(control_set: &str) -> Result<RegistryKey, RegistryError> {
+    RegistryKey::from_path(&format!(
+        "HKLM\\SYSTEM\\{}\\Control\\Keyboard Layouts",
+        control_set
+    ))
+}
+
+/// Reads the values of the custom layouts in the Keyboard Layouts key.
+pub fn read_custom_layouts(
+    layouts_key: &RegistryKey,
+) -> Result<BTreeMap<String, LayoutValues>, RegistryError> {
+    let mut layouts = BTreeMap::new();
+
+    for layout_key in layouts_key.iter_children() {
+        let layout_key = layout_key?;
+        let is_custom =
+            u32::from_str_radix(layout_key.get_name(), 16).is_ok_and(|klid| klid >= 0x00800000);
+        if !is_custom {
+            continue;
+        }
+
+        let mut values = LayoutValues::new();
+        for value in layout_key.iter_values() {
+            let value = value?;
+            if let Some(name) = value.get_name() {
+                values.insert(name.to_string(), value.get_value().clone());
+            }
+        }
+
+        layouts.insert(layout_key.get_name().to_ascii_lowercase(), values);
+    }
+
+    Ok(layouts)
+}
+
+/// Lists how the layouts of another control set differ from the current ones.
+pub fn compare_layouts(
+    current: &BTreeMap<String, LayoutValues>,
+    other: &BTreeMap<String, LayoutValues>,
+) -> Vec<LayoutDifference> {
+    let mut differences = Vec::new();
+
+    for (key, values) in current {
+        let Some(other_values) = other.get(key) else {
+            differences.push(LayoutDifference::Missing(key.clone()));
+            continue;
+        };
+
+        for (name, value) in values {
+            if other_values.get(name) != Some(value) {
+                differences.push(LayoutDifference::Value {
+                    key: key.clone(),
+                    name: name.clone(),
+                });
+            }
+        }
+    }
+
+    for key in other.keys() {
+        if !current.contains_key(key) {
+            differences.push(LayoutDifference::Extra(key.clone()));
+        }
+    }
+
+    differences
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn layout(id: &str, file: &str) -> LayoutValues {
+        LayoutValues::from([
+            (
+                "Layout Id".to_string(),
+                RegistryValueData::String(id.to_string()),
+            ),
+            (
+                "Layout File".to_string(),
+                RegistryValueData::String(file.to_string()),
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_compare_layouts() {
+        let current = BTreeMap::from([
+            ("f0010415".to_string(), layout("0F00", "kbdplx.dll")),
+            ("f0020415".to_string(), layout("0F01", "kbdply.dll")),
+        ]);
+        let other = BTreeMap::from([
+            ("f0010415".to_string(), layout("0F02", "kbdplx.dll")),
+            ("f0000409".to_string(), layout("0F00", "kbdus.dll")),
+        ]);
+
+        assert_eq!(
+            compare_layouts(&current, &other),
+            vec![
+                LayoutDifference::Value {
+                    key: "f0010415".to_string(),
+                    name: "Layout Id".to_string(),
+                },
+                LayoutDifference::Missing("f0020415".to_string()),
+                LayoutDifference::Extra("f0000409".to_string()),
+            ]
+        );
+        assert!(compare_layouts(&current, &current).is_empty());
+    }
+}
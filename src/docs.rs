@@ -0,0 +1,394 @@
+use clap::{Arg, Command, ValueEnum};
+
+use crate::exit_code::ExitCode;
+
+/// Registry keys klc-install reads or changes, which clap doesn't know about.
+const REGISTRY_LOCATIONS: [(&str, &str); 8] = [
+    (
+        "HKLM\\SYSTEM\\CurrentControlSet\\Control\\Keyboard Layouts\\<KLID>",
+        "The installed layouts. klc-install also records where it installed a layout from in its key, in values starting with \"klc-install\".",
+    ),
+    (
+        "HKLM\\SYSTEM\\ControlSet<NNN>\\Control\\Keyboard Layouts",
+        "The layouts of the other control sets, compared and synchronized by control-sets.",
+    ),
+    (
+        "HKLM\\SYSTEM\\CurrentControlSet\\Control\\Keyboard Layout",
+        "The Scancode Map of the machine, exported and applied with profiles.",
+    ),
+    (
+        "HKLM\\SYSTEM\\CurrentControlSet\\Services\\EventLog\\Application\\klc-install",
+        "The event source the changes to the layouts are recorded under in the Application log.",
+    ),
+    (
+        "HKCU\\Keyboard Layout\\Preload",
+        "The input methods of the user. The same key of other users and the Default profile is changed with --all-users.",
+    ),
+    (
+        "HKCU\\Keyboard Layout\\Substitutes",
+        "The custom layouts the preloaded input methods stand for.",
+    ),
+    (
+        "HKCU\\Keyboard Layout\\Toggle",
+        "The hotkeys switching the input methods, exported and applied with profiles.",
+    ),
+    (
+        "HKCU\\Control Panel\\International\\User Profile",
+        "The default input method of the user, set with --set-default.",
+    ),
+];
+
+/// Files klc-install uses.
+const FILE_LOCATIONS: [(&str, &str); 5] = [
+    (
+        "%SystemRoot%\\System32\\<DLL>",
+        "The layout DLLs, with the builds for other architectures in SysWOW64 and SysArm32.",
+    ),
+    (
+        "%ProgramData%\\klc-install\\state",
+        "The journals of the operations in progress, resolved by recover if klc-install is interrupted.",
+    ),
+    (
+        "%ProgramData%\\klc-install\\history.log",
+        "The commands that changed the system, shown by history.",
+    ),
+    (
+        "%ProgramData%\\klc-install\\backups",
+        "The layouts removed by uninstall --purge-custom, a directory for each purge.",
+    ),
+    (
+        "%ProgramData%\\klc-install\\config.toml",
+        "The optional settings of the machine, like the hooks run by installs and uninstalls.",
+    ),
+];
+
+/// What the reference is generated as.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DocsFormat {
+    #[default]
+    Markdown,
+    /// A man page.
+    Roff,
+}
+
+/// Generates the reference of all commands from their clap definitions.
+pub fn generate_docs(command: &Command, format: DocsFormat) -> String {
+    // Building fills in the names used in the usage of the subcommands
+    let mut command = command.clone();
+    command.build();
+
+    match format {
+        DocsFormat::Markdown => generate_markdown(&command),
+        DocsFormat::Roff => generate_roff(&command),
+    }
+}
+
+/// The subcommands shown in the reference, without clap's `help`.
+fn get_documented_subcommands(command: &Command) -> impl Iterator<Item = &Command> {
+    command
+        .get_subcommands()
+        .filter(|subcommand| !subcommand.is_hide_set() && subcommand.get_name() != "help")
+}
+
+fn get_documented_arguments(command: &Command) -> impl Iterator<Item = &Arg> {
+    command
+        .get_arguments()
+        .filter(|arg| !arg.is_hide_set() && !matches!(arg.get_id().as_str(), "help" | "version"))
+}
+
+fn get_about(command: &Command) -> String {
+    command
+        .get_long_about()
+        .or(command.get_about())
+        .map(|about| about.to_string())
+        .unwrap_or_default()
+}
+
+fn get_usage(command: &Command) -> String {
+    let usage = command.clone().render_usage().to_string();
+    usage.strip_prefix("Usage: ").unwrap_or(&usage).to_string()
+}
+
+/// Formats how the argument is given, e.g. `-l, --locale <LOCALE>` or `<FILES>...`.
+fn format_arg_spec(arg: &Arg) -> String {
+    let value_names: Vec<String> = match arg.get_value_names() {
+        Some(names) => names.iter().map(|name| format!("<{}>", name)).collect(),
+        None => vec![format!("<{}>", arg.get_id().as_str().to_uppercase())],
+    };
+
+    if arg.is_positional() {
+        return value_names.join(" ");
+    }
+
+    let mut spec = match (arg.get_short(), arg.get_long()) {
+        (Some(short), Some(long)) => format!("-{}, --{}", short, long),
+        (Some(short), None) => format!("-{}", short),
+        (None, Some(long)) => format!("--{}", long),
+        (None, None) => String::new(),
+    };
+    if arg.get_action().takes_values() {
+        spec.push(' ');
+        spec.push_str(&value_names.join(" "));
+    }
+    spec
+}
+
+/// The help of the argument followed by its possible and default values.
+fn format_arg_help(arg: &Arg) -> String {
+    let mut help = arg
+        .get_long_help()
+        .or(arg.get_help())
+        .map(|help| help.to_string())
+        .unwrap_or_default();
+
+    let possible_values: Vec<String> = arg
+        .get_possible_values()
+        .iter()
+        .filter(|value| !value.is_hide_set())
+        .map(|value| value.get_name().to_string())
+        .collect();
+    if !possible_values.is_empty() && arg.get_action().takes_values() {
+        help.push_str(&format!("\n\nValues: {}.", possible_values.join(", ")));
+    }
+
+    let default_values: Vec<String> = arg
+        .get_default_values()
+        .iter()
+        .map(|value| value.to_string_lossy().to_string())
+        .collect();
+    if !default_values.is_empty() && arg.get_action().takes_values() {
+        help.push_str(&format!("\n\nDefault: {}.", default_values.join(", ")));
+    }
+
+    help
+}
+
+fn generate_markdown(command: &Command) -> String {
+    let name = command.get_name();
+    let mut docs = format!("# {}\n\n{}\n", name, get_about(command));
+
+    let global_args: Vec<&Arg> = get_documented_arguments(command).collect();
+    if !global_args.is_empty() {
+        docs.push_str("\n## Global options\n");
+        for arg in global_args {
+            push_markdown_arg(&mut docs, arg);
+        }
+    }
+
+    docs.push_str("\n## Commands\n");
+    for subcommand in get_documented_subcommands(command) {
+        push_markdown_command(&mut docs, subcommand, &format!("{} ", name));
+    }
+
+    docs.push_str("\n## Exit codes\n\n| Code | Meaning |\n| ---- | ------- |\n");
+    for exit_code in ExitCode::ALL {
+        docs.push_str(&format!(
+            "| {} | {} |\n",
+            exit_code.get_code(),
+            exit_code.get_description()
+        ));
+    }
+
+    docs.push_str("\n## Registry locations\n\n");
+    for (path, description) in REGISTRY_LOCATIONS {
+        docs.push_str(&format!("- `{}`: {}\n", path, description));
+    }
+
+    docs.push_str("\n## Files\n\n");
+    for (path, description) in FILE_LOCATIONS {
+        docs.push_str(&format!("- `{}`: {}\n", path, description));
+    }
+
+    docs
+}
+
+fn push_markdown_arg(docs: &mut String, arg: &Arg) {
+    docs.push_str(&format!("\n- `{}`\n", format_arg_spec(arg)));
+    for line in format_arg_help(arg).lines() {
+        if line.is_empty() {
+            docs.push('\n');
+        } else {
+            docs.push_str(&format!("\n  {}", line));
+        }
+    }
+    docs.push('\n');
+}
+
+fn push_markdown_command(docs: &mut String, command: &Command, prefix: &str) {
+    let name = format!("{}{}", prefix, command.get_name());
+    docs.push_str(&format!(
+        "\n### {}\n\n{}\n\n```text\n{}\n```\n",
+        name,
+        get_about(command),
+        get_usage(command)
+    ));
+
+    for arg in get_documented_arguments(command).filter(|arg| !arg.is_global_set()) {
+        push_markdown_arg(docs, arg);
+    }
+
+    for subcommand in get_documented_subcommands(command) {
+        push_markdown_command(docs, subcommand, &format!("{} ", name));
+    }
+}
+
+/// Escapes text for roff, so it isn't read as requests or escapes.
+fn escape_roff(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            let line = line.replace('\\', "\\e").replace('-', "\\-");
+            if line.starts_with('.') || line.starts_with('\'') {
+                format!("\\&{}", line)
+            } else {
+                line
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Formats paragraphs separated by empty lines as roff paragraphs, started by the request,
+/// e.g. `.IP` to keep them indented under an option.
+fn format_roff_paragraphs(text: &str, request: &str) -> String {
+    text.split("\n\n")
+        .map(|paragraph| escape_roff(paragraph.trim()))
+        .filter(|paragraph| !paragraph.is_empty())
+        .collect::<Vec<_>>()
+        .join(&format!("\n{}\n", request))
+}
+
+fn generate_roff(command: &Command) -> String {
+    let name = command.get_name();
+    // Only the short description fits the NAME section
+    let about = command
+        .get_about()
+        .map(|about| about.to_string())
+        .unwrap_or_default();
+    let mut docs = format!(
+        ".TH {} 1 \"\" \"{} {}\"\n.SH NAME\n{} \\- {}\n.SH SYNOPSIS\n{}\n",
+        name.to_uppercase(),
+        name,
+        command.get_version().unwrap_or_default(),
+        escape_roff(name),
+        escape_roff(&about),
+        escape_roff(&get_usage(command))
+    );
+
+    let global_args: Vec<&Arg> = get_documented_arguments(command).collect();
+    if !global_args.is_empty() {
+        docs.push_str(".SH OPTIONS\n");
+        for arg in global_args {
+            push_roff_arg(&mut docs, arg);
+        }
+    }
+
+    docs.push_str(".SH COMMANDS\n");
+    for subcommand in get_documented_subcommands(command) {
+        push_roff_command(&mut docs, subcommand, &format!("{} ", name));
+    }
+
+    docs.push_str(".SH EXIT STATUS\n");
+    for exit_code in ExitCode::ALL {
+        docs.push_str(&format!(
+            ".TP\n{}\n{}\n",
+            exit_code.get_code(),
+            escape_roff(exit_code.get_description())
+        ));
+    }
+
+    docs.push_str(".SH REGISTRY\n");
+    for (path, description) in REGISTRY_LOCATIONS {
+        docs.push_str(&format!(
+            ".TP\n\\fI{}\\fR\n{}\n",
+            escape_roff(path),
+            escape_roff(description)
+        ));
+    }
+
+    docs.push_str(".SH FILES\n");
+    for (path, description) in FILE_LOCATIONS {
+        docs.push_str(&format!(
+            ".TP\n\\fI{}\\fR\n{}\n",
+            escape_roff(path),
+            escape_roff(description)
+        ));
+    }
+
+    docs
+}
+
+fn push_roff_arg(docs: &mut String, arg: &Arg) {
+    docs.push_str(&format!(
+        ".TP\n\\fB{}\\fR\n{}\n",
+        escape_roff(&format_arg_spec(arg)),
+        format_roff_paragraphs(&format_arg_help(arg), ".IP")
+    ));
+}
+
+fn push_roff_command(docs: &mut String, command: &Command, prefix: &str) {
+    let name = format!("{}{}", prefix, command.get_name());
+    docs.push_str(&format!(
+        ".SS {}\n{}\n.PP\n\\fB{}\\fR\n",
+        escape_roff(&name),
+        format_roff_paragraphs(&get_about(command), ".PP"),
+        escape_roff(&get_usage(command))
+    ));
+
+    for arg in get_documented_arguments(command).filter(|arg| !arg.is_global_set()) {
+        push_roff_arg(docs, arg);
+    }
+
+    for subcommand in get_documented_subcommands(command) {
+        push_roff_command(docs, subcommand, &format!("{} ", name));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use clap::{Arg, ArgAction, Command};
+
+    use super::*;
+
+    fn get_test_command() -> Command {
+        Command::new("klc-install")
+            .about("Installs layouts")
+            .arg(
+                Arg::new("no_color")
+                    .long("no-color")
+                    .global(true)
+                    .action(ArgAction::SetTrue)
+                    .help("Prints without colors."),
+            )
+            .subcommand(
+                Command::new("uninstall").about("Uninstalls a layout").arg(
+                    Arg::new("registry_key")
+                        .long("registry-key")
+                        .value_name("KEY")
+                        .help("Registry key of the layout."),
+                ),
+            )
+            .subcommand(Command::new("gen-docs").hide(true))
+    }
+
+    #[test]
+    fn test_generate_markdown() {
+        let docs = generate_docs(&get_test_command(), DocsFormat::Markdown);
+
+        assert!(docs.starts_with("# klc-install\n\nInstalls layouts\n"));
+        assert!(docs.contains("\n- `--no-color`\n\n  Prints without colors.\n"));
+        assert!(docs.contains("\n### klc-install uninstall\n\nUninstalls a layout\n"));
+        assert!(docs.contains("\n- `--registry-key <KEY>`\n\n  Registry key of the layout.\n"));
+        assert!(docs.contains("| 4 | no installed layout matches |"));
+        assert!(!docs.contains("gen-docs"));
+        assert!(!docs.contains("### klc-install help"));
+    }
+
+    #[test]
+    fn test_escape_roff() {
+        assert_eq!(escape_roff("--no-color"), "\\-\\-no\\-color");
+        assert_eq!(
+            escape_roff(".hidden\nC:\\Windows"),
+            "\\&.hidden\nC:\\eWindows"
+        );
+    }
+}
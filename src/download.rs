@@ -0,0 +1,139 @@
+use std::path::{Path, PathBuf};
+#[cfg(feature = "download")]
+use std::{fs, io::Read};
+
+#[cfg(feature = "download")]
+use sha2::{Digest, Sha256};
+
+/// Largest file that will be downloaded. Layout files are far smaller.
+#[cfg(feature = "download")]
+const MAX_DOWNLOAD_SIZE: u64 = 64 * 1024 * 1024;
+
+pub fn is_url(file: &str) -> bool {
+    let lowercase = file.to_ascii_lowercase();
+    lowercase.starts_with("https://") || lowercase.starts_with("http://")
+}
+
+/// Gets the name of the downloaded file from the last segment of the URL's path.
+pub fn get_file_name(url: &str) -> Result<String, String> {
+    let without_scheme = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let path = without_scheme.split(['?', '#']).next().unwrap_or_default();
+
+    match path
+        .split_once('/')
+        .and_then(|(_, path)| path.rsplit('/').next())
+    {
+        Some(name) if !name.is_empty() && name != "." && name != ".." => Ok(name.to_string()),
+        _ => Err(format!("The URL {} doesn't end with a file name.", url)),
+    }
+}
+
+/// Parses a SHA-256 checksum, given as 64 hexadecimal digits.
+pub fn parse_sha256(checksum: &str) -> Result<String, String> {
+    let checksum = checksum.trim();
+
+    if checksum.len() != 64 || !checksum.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!(
+            "The checksum {} isn't a SHA-256 hash of 64 hexadecimal digits!",
+            checksum
+        ));
+    }
+
+    Ok(checksum.to_ascii_lowercase())
+}
+
+/// Downloads the file into the directory, checking it against the SHA-256 checksum if given.
+///
+/// Returns the path of the downloaded file and its checksum.
+#[cfg(feature = "download")]
+pub fn download_file(
+    url: &str,
+    target_dir: &Path,
+    sha256: Option<&str>,
+) -> Result<(PathBuf, String), String> {
+    let file_name = get_file_name(url)?;
+
+    let response = ureq::get(url)
+        .call()
+        .map_err(|e| format!("Couldn't download {}. {}", url, e))?;
+
+    let mut content = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_DOWNLOAD_SIZE + 1)
+        .read_to_end(&mut content)
+        .map_err(|e| format!("Couldn't download {}. {}", url, e))?;
+    if content.len() as u64 > MAX_DOWNLOAD_SIZE {
+        return Err(format!(
+            "The file at {} is larger than {} MiB.",
+            url,
+            MAX_DOWNLOAD_SIZE / 1024 / 1024
+        ));
+    }
+
+    let checksum = format!("{:x}", Sha256::digest(&content));
+    if let Some(expected) = sha256 {
+        if checksum != expected {
+            return Err(format!(
+                "The checksum of {} doesn't match! Expected {}, got {}.",
+                url, expected, checksum
+            ));
+        }
+    }
+
+    fs::create_dir_all(target_dir).map_err(|e| {
+        format!(
+            "Couldn't create the directory {}. {}",
+            target_dir.display(),
+            e
+        )
+    })?;
+    let file_path = target_dir.join(file_name);
+    fs::write(&file_path, content)
+        .map_err(|e| format!("Couldn't write {}. {}", file_path.display(), e))?;
+
+    Ok((file_path, checksum))
+}
+
+/// Downloads need the `download` feature, which slim builds leave out.
+#[cfg(not(feature = "download"))]
+pub fn download_file(
+    url: &str,
+    _target_dir: &Path,
+    _sha256: Option<&str>,
+) -> Result<(PathBuf, String), String> {
+    Err(format!(
+        "This build of klc-install can't download files. Download {} and install the file instead.",
+        url
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_get_file_name() {
+        assert!(is_url("HTTPS://example.com/layout.klc"));
+        assert!(!is_url("C:\\layouts\\layout.klc"));
+
+        assert_eq!(
+            get_file_name("https://example.com/layouts/multilin.klc").unwrap(),
+            "multilin.klc"
+        );
+        assert_eq!(
+            get_file_name("http://intranet/kbd/kbdpl.dll?version=2#top").unwrap(),
+            "kbdpl.dll"
+        );
+        assert!(get_file_name("https://example.com").is_err());
+        assert!(get_file_name("https://example.com/layouts/").is_err());
+    }
+
+    #[test]
+    fn test_parse_sha256() {
+        let checksum = "E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855";
+        assert_eq!(parse_sha256(checksum).unwrap(), checksum.to_lowercase());
+        assert!(parse_sha256("e3b0c442").is_err());
+        assert!(parse_sha256(&"g".repeat(64)).is_err());
+    }
+}
@@ -0,0 +1,145 @@
+use std::io;
+
+use thiserror::Error;
+
+use crate::{
+    exit_code::{CommandError, ExitCode},
+    klc::KlcError,
+    registry_key::RegistryError,
+};
+
+/// Errors finding KBDUTOOL and compiling a KLC file with it.
+#[derive(Debug, Error)]
+pub enum CompileError {
+    #[error("KBDUTOOL was not found in the MSKLC directory!")]
+    NotInMsklcDir,
+    #[error("MSKLC was not found in PATH.")]
+    NotInPath,
+    #[error("Couldn't run KBDUTOOL.")]
+    Run(#[source] io::Error),
+    #[error("Failed to compile the KLC file. {0}")]
+    Failed(String),
+    #[error("The compiled DLL file was not found.")]
+    DllNotFound(#[source] io::Error),
+    #[error("{0}")]
+    Other(String),
+}
+
+impl CompileError {
+    pub fn get_hint(&self) -> Option<&'static str> {
+        match self {
+            CompileError::NotInMsklcDir | CompileError::NotInPath => Some(
+                "Install MSKLC and add its directory to the PATH, or pass it with --msklc <DIR>.",
+            ),
+            CompileError::Failed(_) => Some(
+                "Open the layout in MSKLC and run Project > Validate Layout to see what's wrong.",
+            ),
+            _ => None,
+        }
+    }
+}
+
+impl From<String> for CompileError {
+    fn from(message: String) -> Self {
+        CompileError::Other(message)
+    }
+}
+
+/// Errors installing a layout, keeping the typed errors of the steps as their source.
+#[derive(Debug, Error)]
+pub enum InstallError {
+    #[error("Couldn't read the layout file.")]
+    Klc(#[from] KlcError),
+    #[error("Couldn't compile the layout.")]
+    Compile(#[from] CompileError),
+    #[error("Couldn't change the registry.")]
+    Registry(#[from] RegistryError),
+    #[error("{0}")]
+    Other(String),
+}
+
+impl InstallError {
+    pub fn get_hint(&self) -> Option<&'static str> {
+        match self {
+            InstallError::Klc(e) => e.get_hint(),
+            InstallError::Compile(e) => e.get_hint(),
+            InstallError::Registry(e) => e.get_hint(),
+            InstallError::Other(_) => None,
+        }
+    }
+}
+
+/// The steps without a typed error report it as text.
+impl From<String> for InstallError {
+    fn from(message: String) -> Self {
+        InstallError::Other(message)
+    }
+}
+
+/// Formats the error followed by the errors that caused it, one per line.
+pub fn format_error_chain(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message.push_str(&format!("\n  Caused by: {}", cause));
+        source = cause.source();
+    }
+    message
+}
+
+// The commands without typed errors get the whole chain as text
+
+impl From<KlcError> for String {
+    fn from(error: KlcError) -> Self {
+        format_error_chain(&error)
+    }
+}
+
+impl From<CompileError> for String {
+    fn from(error: CompileError) -> Self {
+        format_error_chain(&error)
+    }
+}
+
+impl From<InstallError> for String {
+    fn from(error: InstallError) -> Self {
+        format_error_chain(&error)
+    }
+}
+
+impl From<InstallError> for CommandError {
+    fn from(error: InstallError) -> Self {
+        let exit_code = match error {
+            InstallError::Compile(_) => ExitCode::CompileFailed,
+            _ => ExitCode::Error,
+        };
+
+        CommandError {
+            exit_code,
+            message: format_error_chain(&error),
+            hint: error.get_hint(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_error_chain() {
+        let error = InstallError::Klc(KlcError::Open {
+            path: "layout.klc".into(),
+            source: io::Error::new(io::ErrorKind::NotFound, "not found"),
+        });
+
+        assert_eq!(
+            format_error_chain(&error),
+            "Couldn't read the layout file.\n  Caused by: Couldn't open layout.klc.\n  Caused by: not found"
+        );
+        assert_eq!(
+            CommandError::from(InstallError::Compile(CompileError::NotInPath)).exit_code,
+            ExitCode::CompileFailed
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use std::fmt::{self, Display, Formatter};
+
+use widestring::U16CString;
+use windows::{
+    core::PCWSTR,
+    Win32::{
+        Security::PSID,
+        System::EventLog::{
+            DeregisterEventSource, RegisterEventSourceW, ReportEventW, EVENTLOG_INFORMATION_TYPE,
+        },
+    },
+};
+
+use crate::{
+    color::print_warning, history::record_layout, registry_key::RegistryKey,
+    registry_value::RegistryValueData,
+};
+
+/// Event source the changes to the layouts are recorded under, in the Application log.
+pub const EVENT_SOURCE: &str = "klc-install";
+
+const EVENT_SOURCE_KEY: &str =
+    "SYSTEM\\CurrentControlSet\\Services\\EventLog\\Application\\klc-install";
+
+/// EventCreate's messages are just the text of the event, for any event ID from 1 to 1000,
+/// so klc-install doesn't need a message DLL of its own.
+const MESSAGE_FILE: &str = "%SystemRoot%\\System32\\EventCreate.exe";
+
+/// A change to the installed layouts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    Install,
+    Update,
+    Uninstall,
+}
+
+impl AuditAction {
+    /// Lets the events be filtered by action in the Event Viewer.
+    fn get_event_id(self) -> u32 {
+        match self {
+            AuditAction::Install => 1,
+            AuditAction::Update => 2,
+            AuditAction::Uninstall => 3,
+        }
+    }
+}
+
+impl Display for AuditAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let action = match self {
+            AuditAction::Install => "installed",
+            AuditAction::Update => "updated",
+            AuditAction::Uninstall => "uninstalled",
+        };
+        write!(f, "{}", action)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEvent {
+    pub action: AuditAction,
+    /// Registry key of the layout.
+    pub klid: String,
+    /// SHA-256 of the layout DLL, if there is one.
+    pub sha256: Option<String>,
+    /// `DOMAIN\user` running klc-install.
+    pub user: String,
+}
+
+/// Gets the user running klc-install, which is still the signed in user when elevated.
+fn get_current_user() -> String {
+    match (std::env::var("USERDOMAIN"), std::env::var("USERNAME")) {
+        (Ok(domain), Ok(user)) => format!("{}\\{}", domain, user),
+        (Err(_), Ok(user)) => user,
+        _ => "UNKNOWN".to_string(),
+    }
+}
+
+impl AuditEvent {
+    /// Describes a change made by the current user.
+    pub fn new(action: AuditAction, klid: &str, sha256: Option<String>) -> Self {
+        AuditEvent {
+            action,
+            klid: klid.to_string(),
+            sha256,
+            user: get_current_user(),
+        }
+    }
+
+    pub fn format_message(&self) -> String {
+        format!(
+            "The keyboard layout {} was {} by {}.\r\nDLL SHA-256: {}\r\nklc-install version: {}",
+            self.klid,
+            self.action,
+            self.user,
+            self.sha256.as_deref().unwrap_or("-"),
+            env!("CARGO_PKG_VERSION")
+        )
+    }
+}
+
+/// Registers the event source, so the Event Viewer shows the messages without complaining
+/// about a missing description.
+fn register_event_source() -> Result<(), String> {
+    let source_key = RegistryKey::local_machine()
+        .create_subkey(EVENT_SOURCE_KEY)
+        .map_err(|e| format!("Couldn't register the event source. {}", e))?;
+
+    source_key
+        .set_value(
+            Some("EventMessageFile"),
+            RegistryValueData::ExpandString(MESSAGE_FILE.to_string()),
+        )
+        .and_then(|_| {
+            // Errors, warnings and information
+            source_key.set_value(Some("TypesSupported"), RegistryValueData::Dword(7))
+        })
+        .map_err(|e| format!("Couldn't register the event source. {}", e))
+}
+
+/// Records the change in the Application event log, for the audit trail of security teams.
+pub fn write_audit_event(event: &AuditEvent) -> Result<(), String> {
+    register_event_source()?;
+
+    let source = U16CString::from_str(EVENT_SOURCE).unwrap();
+    let message = U16CString::from_str_truncate(event.format_message());
+
+    let event_log = unsafe { RegisterEventSourceW(PCWSTR::null(), PCWSTR(source.as_ptr())) }
+        .map_err(|e| format!("Couldn't open the event log. {}", e))?;
+
+    let result = unsafe {
+        ReportEventW(
+            event_log,
+            EVENTLOG_INFORMATION_TYPE,
+            0,
+            event.action.get_event_id(),
+            PSID::default(),
+            0,
+            Some(&[PCWSTR(message.as_ptr())]),
+            None,
+        )
+    };
+    unsafe {
+        _ = DeregisterEventSource(event_log);
+    }
+
+    result.map_err(|e| format!("Couldn't write to the event log. {}", e))
+}
+
+/// Records the changes to the layouts in the Application event log, only warning if it can't,
+/// as the changes are already made.
+///
+/// The layouts are also noted for the history.
+pub fn audit_layouts(action: AuditAction, layout_keys: &[&str], sha256: Option<&str>) {
+    for layout_key_name in layout_keys {
+        record_layout(layout_key_name);
+        let event = AuditEvent::new(action, layout_key_name, sha256.map(str::to_string));
+        if let Err(e) = write_audit_event(&event) {
+            print_warning(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_message() {
+        let event = AuditEvent {
+            action: AuditAction::Uninstall,
+            klid: "a0010415".to_string(),
+            sha256: None,
+            user: "OFFICE\\jan".to_string(),
+        };
+
+        assert_eq!(
+            event.format_message(),
+            format!(
+                "The keyboard layout a0010415 was uninstalled by OFFICE\\jan.\r\nDLL SHA-256: -\r\nklc-install version: {}",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+    }
+}
@@ -0,0 +1,126 @@
+use std::fmt::{self, Display, Formatter};
+
+/// The exit codes of klc-install, so scripts and deployment tools can tell outcomes apart.
+///
+/// The codes are part of the command line interface: existing ones must never change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitCode {
+    Success = 0,
+    /// Any failure without a more specific code.
+    Error = 1,
+    /// `update --check` found a newer version than the installed one.
+    UpdateAvailable = 2,
+    NotElevated = 3,
+    /// No installed layout matches the selected one.
+    LayoutNotFound = 4,
+    /// KBDUTOOL couldn't compile the KLC file.
+    CompileFailed = 5,
+    /// The changes were made, but are only used once Windows restarts.
+    RebootRequired = 6,
+    /// Some of several layouts failed, the others succeeded.
+    PartialSuccess = 7,
+    /// Another klc-install process kept changing the layouts for too long.
+    Busy = 8,
+}
+
+impl ExitCode {
+    pub const ALL: [ExitCode; 9] = [
+        ExitCode::Success,
+        ExitCode::Error,
+        ExitCode::UpdateAvailable,
+        ExitCode::NotElevated,
+        ExitCode::LayoutNotFound,
+        ExitCode::CompileFailed,
+        ExitCode::RebootRequired,
+        ExitCode::PartialSuccess,
+        ExitCode::Busy,
+    ];
+
+    pub fn get_code(self) -> i32 {
+        self as i32
+    }
+
+    pub fn get_description(self) -> &'static str {
+        match self {
+            ExitCode::Success => "success",
+            ExitCode::Error => "error",
+            ExitCode::UpdateAvailable => "update --check found a newer version",
+            ExitCode::NotElevated => "not running as an administrator",
+            ExitCode::LayoutNotFound => "no installed layout matches",
+            ExitCode::CompileFailed => "the KLC file failed to compile",
+            ExitCode::RebootRequired => "done, but the changes are used after a restart",
+            ExitCode::PartialSuccess => "some of the layouts failed",
+            ExitCode::Busy => "another klc-install process is still running",
+        }
+    }
+}
+
+/// Lists the exit codes for the help.
+pub fn format_exit_codes() -> String {
+    let mut help = "Exit codes:".to_string();
+    for exit_code in ExitCode::ALL {
+        help.push_str(&format!(
+            "\n  {}  {}",
+            exit_code.get_code(),
+            exit_code.get_description()
+        ));
+    }
+    help
+}
+
+/// An error of a command, with the code klc-install exits with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandError {
+    pub exit_code: ExitCode,
+    pub message: String,
+    /// What the user can do about the error.
+    pub hint: Option<&'static str>,
+}
+
+impl CommandError {
+    pub fn new(exit_code: ExitCode, message: impl Into<String>) -> Self {
+        CommandError {
+            exit_code,
+            message: message.into(),
+            hint: None,
+        }
+    }
+}
+
+/// Errors without a specific code, so `?` works on the `String` errors of the rest of the tool.
+impl From<String> for CommandError {
+    fn from(message: String) -> Self {
+        CommandError::new(ExitCode::Error, message)
+    }
+}
+
+impl From<&str> for CommandError {
+    fn from(message: &str) -> Self {
+        CommandError::new(ExitCode::Error, message)
+    }
+}
+
+impl From<CommandError> for String {
+    fn from(error: CommandError) -> Self {
+        error.message
+    }
+}
+
+impl Display for CommandError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes() {
+        for (i, exit_code) in ExitCode::ALL.iter().enumerate() {
+            assert_eq!(exit_code.get_code(), i as i32);
+        }
+        assert!(format_exit_codes().contains("\n  4  no installed layout matches"));
+    }
+}
@@ -0,0 +1,199 @@
+//! A C API for programs that can't use the library directly, e.g. C# or PowerShell through
+//! P/Invoke, built into `klc_install.dll`.
+//!
+//! Requests and responses are UTF-8 JSON strings. Every response is an object with the
+//! `schema_version`, `ok`, the `exit_code` the command line tool would exit with, and either
+//! `layouts` or `error`, see `json_schema`.
+//! Responses must be freed with `klc_install_free_string`.
+
+use std::{
+    ffi::{c_char, CStr, CString},
+    path::PathBuf,
+    time::Duration,
+};
+
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::{
+    exit_code::{CommandError, ExitCode},
+    install::InstallOptions,
+    instance_lock::InstanceLock,
+    json_schema::{LayoutJson, ResponseJson, SCHEMA_VERSION},
+    klid::Klid,
+    layout_store::{InstalledLayout, LayoutStore},
+    registry_key::RegistryError,
+    uninstall::UninstallOptions,
+};
+
+/// How long a change waits for another klc-install process, like the command line tool.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// E.g. `{"file": "C:\\Layouts\\kbdmine.klc", "options": {"preload": true}}`.
+///
+/// The options are named like the arguments of the install command, with underscores.
+#[derive(Deserialize)]
+struct InstallRequest {
+    file: PathBuf,
+    #[serde(default)]
+    msklc: Option<String>,
+    #[serde(default)]
+    options: InstallOptions,
+}
+
+/// E.g. `{"klid": "a0010415", "options": {"remove_dll": true}}`.
+#[derive(Deserialize)]
+struct UninstallRequest {
+    klid: String,
+    #[serde(default)]
+    options: UninstallOptions,
+}
+
+fn to_response(result: Result<Vec<InstalledLayout>, CommandError>) -> *mut c_char {
+    let response = match result {
+        Ok(layouts) => ResponseJson {
+            schema_version: SCHEMA_VERSION,
+            ok: true,
+            exit_code: ExitCode::Success.get_code(),
+            layouts: Some(layouts.into_iter().map(LayoutJson::from).collect()),
+            error: None,
+        },
+        Err(e) => ResponseJson {
+            schema_version: SCHEMA_VERSION,
+            ok: false,
+            exit_code: e.exit_code.get_code(),
+            layouts: None,
+            error: Some(e.message),
+        },
+    };
+
+    // JSON escapes control characters, so there's no NUL in it
+    CString::new(serde_json::to_string(&response).unwrap())
+        .unwrap()
+        .into_raw()
+}
+
+unsafe fn read_request<T: DeserializeOwned>(request: *const c_char) -> Result<T, CommandError> {
+    if request.is_null() {
+        return Err("The request is null.".into());
+    }
+
+    let request = CStr::from_ptr(request)
+        .to_str()
+        .map_err(|e| format!("The request isn't valid UTF-8. {}", e))?;
+    serde_json::from_str(request).map_err(|e| format!("Invalid request. {}", e).into())
+}
+
+fn open_store() -> Result<LayoutStore, CommandError> {
+    LayoutStore::open()
+        .map_err(|e| format!("Couldn't open the Keyboard Layouts registry key. {}", e).into())
+}
+
+/// Lists every installed layout.
+#[no_mangle]
+pub extern "C" fn klc_install_list() -> *mut c_char {
+    to_response(open_store().and_then(|store| {
+        store
+            .get_installed_layouts()
+            .map_err(|e| e.to_string().into())
+    }))
+}
+
+/// Installs a layout file, responding with the layouts it registered.
+///
+/// Needs administrator rights, like the install command.
+///
+/// # Safety
+///
+/// `request` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn klc_install_install(request: *const c_char) -> *mut c_char {
+    to_response(read_request(request).and_then(|request: InstallRequest| {
+        let _lock = InstanceLock::acquire(LOCK_TIMEOUT)?;
+
+        // There's no one to answer the confirmation
+        let options = InstallOptions {
+            yes: true,
+            ..request.options
+        };
+        open_store()?.install(&request.file, request.msklc.as_deref(), &options)
+    }))
+}
+
+/// Uninstalls a layout, responding with the layout as it was before.
+///
+/// Needs administrator rights, like the uninstall command.
+///
+/// # Safety
+///
+/// `request` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn klc_install_uninstall(request: *const c_char) -> *mut c_char {
+    to_response(read_request(request).and_then(|request: UninstallRequest| {
+        let klid = Klid::parse(&request.klid)?;
+        // Same as the uninstall command, lower KLIDs are the layouts shipped with Windows
+        if klid.device < 0x0080 && !request.options.force {
+            return Err(format!(
+                "{} is a system layout. Set force to uninstall it anyway.",
+                klid
+            )
+            .into());
+        }
+
+        let _lock = InstanceLock::acquire(LOCK_TIMEOUT)?;
+
+        let store = open_store()?;
+        let layout = match store.get(klid) {
+            Ok(layout) => layout,
+            Err(RegistryError::NotFound) => {
+                return Err(CommandError::new(
+                    ExitCode::LayoutNotFound,
+                    format!("The layout {} isn't installed.", klid),
+                ))
+            }
+            Err(e) => return Err(e.to_string().into()),
+        };
+
+        let options = UninstallOptions {
+            yes: true,
+            ..request.options
+        };
+        store.remove(klid, &options)?;
+
+        Ok(vec![layout])
+    }))
+}
+
+/// Frees a response of the other functions.
+///
+/// # Safety
+///
+/// `response` must be returned by one of the functions and not freed yet, or null.
+#[no_mangle]
+pub unsafe extern "C" fn klc_install_free_string(response: *mut c_char) {
+    if !response.is_null() {
+        drop(CString::from_raw(response));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_request() {
+        let request = CString::new(
+            r#"{"file": "kbdmine.klc", "options": {"preload": true, "locale": ["pl-PL"], "arch": ["amd64", "wow64"]}}"#,
+        )
+        .unwrap();
+        let request: InstallRequest = unsafe { read_request(request.as_ptr()) }.unwrap();
+        assert_eq!(request.file, PathBuf::from("kbdmine.klc"));
+        assert!(request.options.preload);
+        assert!(!request.options.force);
+        assert_eq!(request.options.locale, ["pl-PL"]);
+        assert_eq!(request.options.arch.len(), 2);
+
+        let invalid = CString::new(r#"{"options": {}}"#).unwrap();
+        assert!(unsafe { read_request::<UninstallRequest>(invalid.as_ptr()) }.is_err());
+        assert!(unsafe { read_request::<UninstallRequest>(std::ptr::null()) }.is_err());
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    ffi::c_void,
+    fmt::{self, Display, Formatter},
+    mem::size_of,
+    path::Path,
+    ptr::null_mut,
+};
+
+use widestring::U16CString;
+use windows::{
+    core::{HRESULT, PCWSTR},
+    Win32::{
+        Foundation::{FreeLibrary, HWND, TRUST_E_NOSIGNATURE},
+        Security::WinTrust::{
+            WinVerifyTrust, WINTRUST_ACTION_GENERIC_VERIFY_V2, WINTRUST_DATA, WINTRUST_FILE_INFO,
+            WTD_CHOICE_FILE, WTD_REVOKE_NONE, WTD_STATEACTION_CLOSE, WTD_STATEACTION_VERIFY,
+            WTD_UI_NONE,
+        },
+        Storage::FileSystem::{
+            GetFileVersionInfoSizeW, GetFileVersionInfoW, VerQueryValueW, VS_FIXEDFILEINFO,
+        },
+        System::LibraryLoader::{
+            FindResourceW, LoadLibraryExW, LOAD_LIBRARY_AS_DATAFILE, LOAD_LIBRARY_AS_IMAGE_RESOURCE,
+        },
+    },
+};
+
+fn to_wide_path(path: &Path) -> Result<U16CString, String> {
+    U16CString::from_os_str(path.as_os_str())
+        .map_err(|e| format!("Couldn't convert path to UTF16! {}", e))
+}
+
+/// Reads the file version from the `VERSIONINFO` resource of the file.
+///
+/// Returns `None` if the file has no version resource.
+pub fn get_file_version(path: &Path) -> Result<Option<String>, String> {
+    let path = to_wide_path(path)?;
+
+    let size = unsafe { GetFileVersionInfoSizeW(PCWSTR(path.as_ptr()), None) };
+    if size == 0 {
+        return Ok(None);
+    }
+
+    let mut buf = vec![0u8; size as usize];
+    unsafe {
+        GetFileVersionInfoW(
+            PCWSTR(path.as_ptr()),
+            0,
+            size,
+            buf.as_mut_ptr() as *mut c_void,
+        )
+    }
+    .map_err(|e| e.to_string())?;
+
+    let root = U16CString::from_str("\\").unwrap();
+    let mut info_ptr: *mut c_void = null_mut();
+    let mut info_len: u32 = 0;
+    let found = unsafe {
+        VerQueryValueW(
+            buf.as_ptr() as *const c_void,
+            PCWSTR(root.as_ptr()),
+            &mut info_ptr,
+            &mut info_len,
+        )
+    };
+
+    if !found.as_bool() || (info_len as usize) < size_of::<VS_FIXEDFILEINFO>() {
+        return Ok(None);
+    }
+
+    let info = unsafe { &*(info_ptr as *const VS_FIXEDFILEINFO) };
+
+    Ok(Some(format!(
+        "{}.{}.{}.{}",
+        info.dwFileVersionMS >> 16,
+        info.dwFileVersionMS & 0xFFFF,
+        info.dwFileVersionLS >> 16,
+        info.dwFileVersionLS & 0xFFFF
+    )))
+}
+
+/// Checks whether the string table of the file contains the string with the given ID.
+///
+/// This is what an indirect string like `@file.dll,-1000` needs to be resolved.
+pub fn has_string_resource(path: &Path, id: u16) -> Result<bool, String> {
+    const RT_STRING: PCWSTR = PCWSTR(std::ptr::without_provenance(6));
+
+    let path = to_wide_path(path)?;
+
+    let module = unsafe {
+        LoadLibraryExW(
+            PCWSTR(path.as_ptr()),
+            None,
+            LOAD_LIBRARY_AS_DATAFILE | LOAD_LIBRARY_AS_IMAGE_RESOURCE,
+        )
+    }
+    .map_err(|e| format!("Couldn't load the DLL. {}", e))?;
+
+    // Strings are stored in blocks of 16
+    let block = PCWSTR(std::ptr::without_provenance((id / 16 + 1) as usize));
+    let found = !unsafe { FindResourceW(module, block, RT_STRING) }.is_invalid();
+
+    unsafe { FreeLibrary(module) }.map_err(|e| e.to_string())?;
+
+    Ok(found)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureStatus {
+    /// The file has a valid, trusted Authenticode signature.
+    Valid,
+    /// The file has no embedded signature. Catalog-signed system files show up as this too.
+    Unsigned,
+    /// The file is signed, but the signature isn't valid or trusted (e.g. the file was modified).
+    Invalid(HRESULT),
+}
+
+impl Display for SignatureStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureStatus::Valid => write!(f, "Valid"),
+            SignatureStatus::Unsigned => write!(f, "Unsigned"),
+            SignatureStatus::Invalid(e) => write!(f, "INVALID ({:#010X})", e.0),
+        }
+    }
+}
+
+/// Verifies the Authenticode signature embedded in the file.
+pub fn get_signature_status(path: &Path) -> Result<SignatureStatus, String> {
+    let path = to_wide_path(path)?;
+
+    let mut file_info = WINTRUST_FILE_INFO {
+        cbStruct: size_of::<WINTRUST_FILE_INFO>() as u32,
+        pcwszFilePath: PCWSTR(path.as_ptr()),
+        ..Default::default()
+    };
+
+    let mut trust_data = WINTRUST_DATA {
+        cbStruct: size_of::<WINTRUST_DATA>() as u32,
+        dwUIChoice: WTD_UI_NONE,
+        fdwRevocationChecks: WTD_REVOKE_NONE,
+        dwUnionChoice: WTD_CHOICE_FILE,
+        dwStateAction: WTD_STATEACTION_VERIFY,
+        ..Default::default()
+    };
+    trust_data.Anonymous.pFile = &mut file_info;
+
+    let mut action = WINTRUST_ACTION_GENERIC_VERIFY_V2;
+    let result = unsafe {
+        WinVerifyTrust(
+            HWND::default(),
+            &mut action,
+            &mut trust_data as *mut _ as *mut c_void,
+        )
+    };
+
+    // The state has to be released regardless of the result
+    trust_data.dwStateAction = WTD_STATEACTION_CLOSE;
+    unsafe {
+        WinVerifyTrust(
+            HWND::default(),
+            &mut action,
+            &mut trust_data as *mut _ as *mut c_void,
+        )
+    };
+
+    Ok(match HRESULT(result) {
+        HRESULT(0) => SignatureStatus::Valid,
+        TRUST_E_NOSIGNATURE => SignatureStatus::Unsigned,
+        e => SignatureStatus::Invalid(e),
+    })
+}
@@ -0,0 +1,250 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use windows::Win32::UI::Shell::FOLDERID_ProgramData;
+
+use crate::{exit_code::ExitCode, get_known_folder::get_known_folder, journal::get_unix_time};
+
+const HISTORY_FILE_NAME: &str = "history.log";
+
+/// Separates the files of an entry, as it can't be a part of a Windows path.
+const FILE_SEPARATOR: char = '|';
+
+/// The layouts and files changed by the running command, saved with it in the history.
+static CHANGES: Mutex<(Vec<String>, Vec<PathBuf>)> = Mutex::new((Vec::new(), Vec::new()));
+
+/// Notes that the running command changed the layout.
+pub fn record_layout(layout_key_name: &str) {
+    let mut changes = CHANGES.lock().unwrap();
+    if !changes.0.iter().any(|key| key == layout_key_name) {
+        changes.0.push(layout_key_name.to_string());
+    }
+}
+
+/// Notes that the running command created, replaced or removed the file.
+pub fn record_file(path: &Path) {
+    let mut changes = CHANGES.lock().unwrap();
+    if !changes.1.iter().any(|file| file == path) {
+        changes.1.push(path.to_path_buf());
+    }
+}
+
+/// A command that changed the system, as recorded in the history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    /// Unix time in seconds when the command finished.
+    pub time: u64,
+    /// The arguments klc-install was run with.
+    pub command: String,
+    pub exit_code: i32,
+    /// Registry keys of the changed layouts.
+    pub layouts: Vec<String>,
+    pub files: Vec<PathBuf>,
+    /// The error the command failed with.
+    pub error: Option<String>,
+}
+
+/// Keeps a field on its line, without the tabs separating the fields.
+fn to_field(text: &str) -> String {
+    text.replace(['\t', '\r', '\n'], " ")
+}
+
+impl HistoryEntry {
+    fn to_line(&self) -> String {
+        let files: Vec<String> = self
+            .files
+            .iter()
+            .map(|file| file.display().to_string())
+            .collect();
+
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            self.time,
+            self.exit_code,
+            to_field(&self.command),
+            self.layouts.join(","),
+            to_field(&files.join(&FILE_SEPARATOR.to_string())),
+            to_field(self.error.as_deref().unwrap_or_default())
+        )
+    }
+
+    fn from_line(line: &str) -> Result<HistoryEntry, String> {
+        let invalid = || format!("Invalid history entry: {}", line);
+
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [time, exit_code, command, layouts, files, error] = fields[..] else {
+            return Err(invalid());
+        };
+
+        let split = |field: &str, separator: char| -> Vec<String> {
+            field
+                .split(separator)
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+
+        Ok(HistoryEntry {
+            time: time.parse().map_err(|_| invalid())?,
+            exit_code: exit_code.parse().map_err(|_| invalid())?,
+            command: command.to_string(),
+            layouts: split(layouts, ','),
+            files: split(files, FILE_SEPARATOR)
+                .into_iter()
+                .map(PathBuf::from)
+                .collect(),
+            error: (!error.is_empty()).then(|| error.to_string()),
+        })
+    }
+
+    /// Describes how the command ended, e.g. `success` or `exit code 1 (error)`.
+    pub fn get_outcome(&self) -> String {
+        match ExitCode::ALL
+            .into_iter()
+            .find(|exit_code| exit_code.get_code() == self.exit_code)
+        {
+            Some(ExitCode::Success) => "success".to_string(),
+            Some(exit_code) => format!(
+                "exit code {} ({})",
+                self.exit_code,
+                exit_code.get_description()
+            ),
+            None => format!("exit code {}", self.exit_code),
+        }
+    }
+}
+
+/// Shared by every user, like the journals of the interrupted operations.
+fn get_history_path() -> Result<PathBuf, String> {
+    let program_data = get_known_folder(&FOLDERID_ProgramData)?;
+    Ok(program_data.join("klc-install").join(HISTORY_FILE_NAME))
+}
+
+/// Appends the finished command to the history, with the changes it recorded.
+pub fn append_to_history(
+    command: &str,
+    exit_code: ExitCode,
+    error: Option<&str>,
+) -> Result<(), String> {
+    let (layouts, files) = CHANGES.lock().unwrap().clone();
+    let entry = HistoryEntry {
+        time: get_unix_time(),
+        command: command.to_string(),
+        exit_code: exit_code.get_code(),
+        layouts,
+        files,
+        error: error.map(str::to_string),
+    };
+
+    let path = get_history_path()?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|e| format!("Couldn't open {}. {}", path.display(), e))?;
+    writeln!(file, "{}", entry.to_line())
+        .map_err(|e| format!("Couldn't write to {}. {}", path.display(), e))
+}
+
+/// Reads the history, oldest first.
+pub fn read_history() -> Result<Vec<HistoryEntry>, String> {
+    let path = get_history_path()?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    fs::read_to_string(&path)
+        .map_err(|e| format!("Couldn't read {}. {}", path.display(), e))?
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(HistoryEntry::from_line)
+        .collect()
+}
+
+/// Formats a Unix time as a UTC date and time, e.g. `2024-11-05 14:03:27 UTC`.
+pub fn format_unix_time(time: u64) -> String {
+    let days = (time / 86400) as i64;
+    let seconds = time % 86400;
+
+    // Converts the days since 1970-01-01 to a date, counting in 400-year eras from 0000-03-01
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_entry_lines() {
+        let entry = HistoryEntry {
+            time: 1730815407,
+            command: "install layout.klc --preload".to_string(),
+            exit_code: 0,
+            layouts: vec!["a0010415".to_string(), "a0020409".to_string()],
+            files: vec![
+                PathBuf::from("C:\\Windows\\System32\\kbdpl2.dll"),
+                PathBuf::from("C:\\Windows\\SysWOW64\\kbdpl2.dll"),
+            ],
+            error: None,
+        };
+        assert_eq!(HistoryEntry::from_line(&entry.to_line()), Ok(entry));
+
+        let failed = HistoryEntry {
+            time: 1730815407,
+            command: "uninstall --key a0010415".to_string(),
+            exit_code: 4,
+            layouts: Vec::new(),
+            files: Vec::new(),
+            error: Some("The layout wasn't found.\n  Caused by: not found".to_string()),
+        };
+        let read = HistoryEntry::from_line(&failed.to_line()).unwrap();
+        assert_eq!(
+            read.error.as_deref(),
+            Some("The layout wasn't found.   Caused by: not found")
+        );
+        assert_eq!(
+            read.get_outcome(),
+            "exit code 4 (no installed layout matches)"
+        );
+
+        assert!(HistoryEntry::from_line("1730815407\tinstall").is_err());
+    }
+
+    #[test]
+    fn test_format_unix_time() {
+        assert_eq!(format_unix_time(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_unix_time(951825600), "2000-02-29 12:00:00 UTC");
+        assert_eq!(format_unix_time(1730815407), "2024-11-05 14:03:27 UTC");
+    }
+}
@@ -0,0 +1,98 @@
+use std::fmt::{self, Display, Formatter};
+
+/// The keyboard layout part of an HKL (the high word).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HklLayout {
+    /// A custom layout, identified by its `Layout Id` registry value.
+    LayoutId(u16),
+    /// A system layout or an IME, identified directly by its registry key (KLID).
+    Klid(String),
+}
+
+/// An input locale handle (HKL), as reported by `GetKeyboardLayout` or found in RDP and VM logs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hkl {
+    /// The input language.
+    pub language: u16,
+    pub layout: HklLayout,
+}
+
+impl Hkl {
+    /// Parses an HKL written as hexadecimal, optionally prefixed with `0x`.
+    pub fn parse(hkl: &str) -> Result<Hkl, String> {
+        let digits = hkl
+            .strip_prefix("0x")
+            .or_else(|| hkl.strip_prefix("0X"))
+            .unwrap_or(hkl);
+
+        let value = u32::from_str_radix(digits, 16)
+            .map_err(|_| format!("Invalid HKL {}. It must be a hexadecimal number.", hkl))?;
+
+        Ok(Hkl::from(value))
+    }
+}
+
+impl From<u32> for Hkl {
+    fn from(hkl: u32) -> Self {
+        let language = hkl as u16;
+        let device = (hkl >> 16) as u16;
+
+        let layout = match device & 0xF000 {
+            // Custom layouts have 0xF000 ORed with their layout ID
+            0xF000 => HklLayout::LayoutId(device & 0x0FFF),
+            // IMEs use the KLID itself as the handle
+            0xE000 => HklLayout::Klid(format!("{:08x}", hkl)),
+            // The high word is the language of the system layout
+            _ => HklLayout::Klid(format!("0000{:04x}", device)),
+        };
+
+        Hkl { language, layout }
+    }
+}
+
+impl Display for HklLayout {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            HklLayout::LayoutId(id) => write!(f, "layout ID {:04X}", id),
+            HklLayout::Klid(klid) => write!(f, "registry key {}", klid),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_hkl() {
+        assert_eq!(
+            Hkl::parse("0xF0010415"),
+            Ok(Hkl {
+                language: 0x0415,
+                layout: HklLayout::LayoutId(0x0001),
+            })
+        );
+        assert_eq!(
+            Hkl::parse("04090409"),
+            Ok(Hkl {
+                language: 0x0409,
+                layout: HklLayout::Klid("00000409".to_string()),
+            })
+        );
+        assert_eq!(
+            Hkl::parse("08090409"),
+            Ok(Hkl {
+                language: 0x0409,
+                layout: HklLayout::Klid("00000809".to_string()),
+            })
+        );
+        assert_eq!(
+            Hkl::parse("E0010411"),
+            Ok(Hkl {
+                language: 0x0411,
+                layout: HklLayout::Klid("e0010411".to_string()),
+            })
+        );
+        assert!(Hkl::parse("hkl").is_err());
+    }
+}
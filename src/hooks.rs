@@ -0,0 +1,231 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    process::Command,
+    str::FromStr,
+    sync::OnceLock,
+};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::{color::print_warning, config::Config};
+
+/// Hooks given with `--hook`, run after the ones of the config file.
+static CLI_HOOKS: OnceLock<Vec<Hook>> = OnceLock::new();
+
+/// When a hook command runs.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookPoint {
+    /// Before an install changes anything, after it's confirmed.
+    PreInstall,
+    /// After a layout is installed.
+    PostInstall,
+    /// Before a layout is uninstalled, after it's confirmed.
+    PreUninstall,
+    /// After a layout is uninstalled.
+    PostUninstall,
+}
+
+impl HookPoint {
+    /// Hooks running before a change can stop it by failing.
+    fn is_pre(self) -> bool {
+        matches!(self, HookPoint::PreInstall | HookPoint::PreUninstall)
+    }
+}
+
+impl Display for HookPoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_possible_value().unwrap().get_name())
+    }
+}
+
+/// A command run at a point of installs or uninstalls, e.g. `post-install=gpupdate /target:user`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hook {
+    pub point: HookPoint,
+    pub command: String,
+}
+
+impl FromStr for Hook {
+    type Err = String;
+
+    fn from_str(hook: &str) -> Result<Self, Self::Err> {
+        let (point, command) = hook
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid hook {}. It must be POINT=COMMAND.", hook))?;
+        let point = HookPoint::from_str(point.trim(), true)?;
+        let command = command.trim();
+        if command.is_empty() {
+            return Err(format!("The {} hook has no command.", point));
+        }
+
+        Ok(Hook {
+            point,
+            command: command.to_string(),
+        })
+    }
+}
+
+/// Either a single command or a list of them.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum HookCommands {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Default for HookCommands {
+    fn default() -> Self {
+        HookCommands::Many(Vec::new())
+    }
+}
+
+impl HookCommands {
+    fn to_vec(&self) -> Vec<String> {
+        match self {
+            HookCommands::One(command) => vec![command.clone()],
+            HookCommands::Many(commands) => commands.clone(),
+        }
+    }
+}
+
+/// The `[hooks]` table of the config file, e.g. `post-install = "gpupdate /target:user"`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct HooksConfig {
+    pub pre_install: HookCommands,
+    pub post_install: HookCommands,
+    pub pre_uninstall: HookCommands,
+    pub post_uninstall: HookCommands,
+}
+
+impl HooksConfig {
+    pub fn get_hooks(&self) -> Vec<Hook> {
+        [
+            (HookPoint::PreInstall, &self.pre_install),
+            (HookPoint::PostInstall, &self.post_install),
+            (HookPoint::PreUninstall, &self.pre_uninstall),
+            (HookPoint::PostUninstall, &self.post_uninstall),
+        ]
+        .into_iter()
+        .flat_map(|(point, commands)| {
+            commands
+                .to_vec()
+                .into_iter()
+                .map(move |command| Hook { point, command })
+        })
+        .collect()
+    }
+}
+
+/// The layouts a hook runs for, passed to its command in environment variables.
+#[derive(Debug, Default)]
+pub struct HookContext<'a> {
+    /// `KLC_INSTALL_KLIDS`, separated by commas.
+    pub layout_keys: &'a [&'a str],
+    /// `KLC_INSTALL_LAYOUT_FILE`, the name of the DLL.
+    pub layout_file: Option<&'a str>,
+    /// `KLC_INSTALL_LAYOUT_TEXT`.
+    pub layout_text: Option<&'a str>,
+    /// `KLC_INSTALL_SOURCE`, the file or URL the layout is installed from.
+    pub source: Option<&'a str>,
+}
+
+/// Sets the hooks given on the command line.
+pub fn init(hooks: Vec<Hook>) {
+    _ = CLI_HOOKS.set(hooks);
+}
+
+fn run_hook(hook: &Hook, context: &HookContext) -> Result<(), String> {
+    println!("Running the {} hook: {}", hook.point, hook.command);
+
+    // Hooks are written like in the command prompt, e.g. with && or %VARIABLES%
+    let status = Command::new("cmd")
+        .arg("/C")
+        .arg(&hook.command)
+        .env("KLC_INSTALL_HOOK", hook.point.to_string())
+        .env("KLC_INSTALL_KLIDS", context.layout_keys.join(","))
+        .env(
+            "KLC_INSTALL_LAYOUT_FILE",
+            context.layout_file.unwrap_or_default(),
+        )
+        .env(
+            "KLC_INSTALL_LAYOUT_TEXT",
+            context.layout_text.unwrap_or_default(),
+        )
+        .env("KLC_INSTALL_SOURCE", context.source.unwrap_or_default())
+        .status()
+        .map_err(|e| format!("Couldn't run the {} hook. {}", hook.point, e))?;
+
+    if !status.success() {
+        return Err(format!(
+            "The {} hook {} failed with {}.",
+            hook.point, hook.command, status
+        ));
+    }
+
+    Ok(())
+}
+
+/// Runs the hooks of the config file and the command line registered for the point, in order.
+///
+/// A failing pre-install or pre-uninstall hook stops the change and the hooks after it.
+/// The change is already made when the post- hooks run, so their failures are only reported.
+pub fn run_hooks(point: HookPoint, context: &HookContext) -> Result<(), String> {
+    let mut hooks = Config::read()?.hooks.get_hooks();
+    hooks.extend(CLI_HOOKS.get().into_iter().flatten().cloned());
+
+    for hook in hooks.iter().filter(|hook| hook.point == point) {
+        match run_hook(hook, context) {
+            Ok(()) => {}
+            Err(e) if point.is_pre() => return Err(e),
+            Err(e) => print_warning(e),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_hook() {
+        assert_eq!(
+            "post-install=gpupdate /target:user".parse::<Hook>(),
+            Ok(Hook {
+                point: HookPoint::PostInstall,
+                command: "gpupdate /target:user".to_string()
+            })
+        );
+        assert_eq!(
+            "Pre-Uninstall = echo %KLC_INSTALL_KLIDS%"
+                .parse::<Hook>()
+                .map(|hook| hook.point),
+            Ok(HookPoint::PreUninstall)
+        );
+        assert!("post-install".parse::<Hook>().is_err());
+        assert!("post-install=".parse::<Hook>().is_err());
+        assert!("after-install=echo".parse::<Hook>().is_err());
+    }
+
+    #[test]
+    fn test_hooks_config() {
+        let config: Config = toml::from_str(
+            r#"
+                [hooks]
+                post-install = "gpupdate /target:user"
+                pre-uninstall = ["echo one", "echo two"]
+            "#,
+        )
+        .unwrap();
+        let hooks = config.hooks.get_hooks();
+        assert_eq!(hooks.len(), 3);
+        assert_eq!(hooks[0].point, HookPoint::PostInstall);
+        assert_eq!(hooks[2].command, "echo two");
+
+        let empty: Config = toml::from_str("").unwrap();
+        assert!(empty.hooks.get_hooks().is_empty());
+    }
+}
@@ -0,0 +1,265 @@
+use std::{ffi::c_void, process::Command};
+
+use widestring::U16CString;
+use windows::{
+    core::PCWSTR,
+    Win32::{
+        Foundation::{LPARAM, WPARAM},
+        UI::{
+            Input::KeyboardAndMouse::{
+                ActivateKeyboardLayout, GetKeyboardLayout, LoadKeyboardLayoutW,
+                UnloadKeyboardLayout, HKL, KLF_ACTIVATE, KLF_NOTELLSHELL, KLF_SETFORPROCESS,
+                KLF_SUBSTITUTE_OK,
+            },
+            WindowsAndMessaging::{
+                PostMessageW, SendMessageTimeoutW, SystemParametersInfoW, HWND_BROADCAST,
+                SMTO_ABORTIFHUNG, SPIF_SENDCHANGE, SPI_SETDEFAULTINPUTLANG,
+                WM_INPUTLANGCHANGEREQUEST, WM_SETTINGCHANGE,
+            },
+        },
+    },
+};
+
+use crate::{
+    hkl::{Hkl, HklLayout},
+    loaded_layouts::get_loaded_hkls,
+};
+
+/// Scheduled task that starts the text input host (`ctfmon.exe`) for the signed in user.
+const CTF_MONITOR_TASK: &str = "\\Microsoft\\Windows\\TextServicesFramework\\MsCtfMonitor";
+
+/// Tells running applications that the international settings changed,
+/// so they reload the list of input languages.
+pub fn broadcast_settings_change() -> Result<(), String> {
+    let area = U16CString::from_str("intl").map_err(|e| e.to_string())?;
+
+    let result = unsafe {
+        SendMessageTimeoutW(
+            HWND_BROADCAST,
+            WM_SETTINGCHANGE,
+            WPARAM(0),
+            LPARAM(area.as_ptr() as isize),
+            SMTO_ABORTIFHUNG,
+            5000,
+            None,
+        )
+    };
+
+    if result.0 == 0 {
+        return Err(format!(
+            "Couldn't broadcast the settings change. {}",
+            windows::core::Error::from_win32()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Restarts `ctfmon.exe`, which caches the installed layouts and can hide new ones
+/// until it's restarted.
+pub fn restart_text_services() -> Result<(), String> {
+    // Fails if it isn't running, which is fine
+    _ = Command::new("taskkill")
+        .args(["/IM", "ctfmon.exe", "/F"])
+        .output();
+
+    let output = Command::new("schtasks")
+        .args(["/Run", "/TN", CTF_MONITOR_TASK])
+        .output()
+        .map_err(|e| format!("Couldn't run schtasks. {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "Couldn't start the text input host. {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Loads the layout and makes it the active one, then asks the other windows to switch
+/// to it too, so it can be used without signing out.
+///
+/// Returns the HKL of the loaded layout.
+pub fn activate_layout(klid: &str) -> Result<u32, String> {
+    let klid_wide =
+        U16CString::from_str(klid).map_err(|e| format!("Invalid layout {}. {}", klid, e))?;
+
+    let hkl = unsafe { LoadKeyboardLayoutW(PCWSTR(klid_wide.as_ptr()), KLF_ACTIVATE) }
+        .map_err(|e| format!("Couldn't load the layout {}. {}", klid, e))?;
+
+    unsafe { ActivateKeyboardLayout(hkl, KLF_SETFORPROCESS) }
+        .map_err(|e| format!("Couldn't activate the layout {}. {}", klid, e))?;
+
+    // Windows that don't handle the request keep their current layout, which is fine
+    _ = unsafe {
+        PostMessageW(
+            HWND_BROADCAST,
+            WM_INPUTLANGCHANGEREQUEST,
+            WPARAM(0),
+            LPARAM(hkl.0 as isize),
+        )
+    };
+
+    Ok(hkl.0 as usize as u32)
+}
+
+/// Loads the layout into this session without switching to it, which adds it to the
+/// input switcher.
+fn load_layout(klid: &str) -> Result<HKL, String> {
+    let klid_wide =
+        U16CString::from_str(klid).map_err(|e| format!("Invalid layout {}. {}", klid, e))?;
+
+    unsafe { LoadKeyboardLayoutW(PCWSTR(klid_wide.as_ptr()), KLF_SUBSTITUTE_OK) }
+        .map_err(|e| format!("Couldn't load the layout {}. {}", klid, e))
+}
+
+/// Makes the layout the default input language of this session, which new windows start with.
+fn set_default_input_language(hkl: HKL) -> Result<(), String> {
+    let mut hkl = hkl;
+
+    unsafe {
+        SystemParametersInfoW(
+            SPI_SETDEFAULTINPUTLANG,
+            0,
+            Some(&mut hkl as *mut HKL as *mut c_void),
+            SPIF_SENDCHANGE,
+        )
+    }
+    .map_err(|e| format!("Couldn't set the default input language. {}", e))
+}
+
+/// What refreshing the session achieved.
+#[derive(Debug, Default)]
+pub struct SessionRefresh {
+    /// Why the session couldn't pick up every change, e.g. a layout Windows couldn't load.
+    pub problems: Vec<String>,
+}
+
+impl SessionRefresh {
+    /// Whether the changes only show up after signing out and back in.
+    pub fn needs_sign_out(&self) -> bool {
+        !self.problems.is_empty()
+    }
+}
+
+/// Makes this session pick up layouts added to the input methods of the current user,
+/// so they show up in the input switcher without signing out.
+///
+/// Loads the layouts, makes `default_klid` the default input language and asks the windows to
+/// switch to it, then tells the applications the settings changed and restarts the text input
+/// host. Windows only delivers the messages to this session, so other signed in users still
+/// have to sign out.
+pub fn refresh_session(klids: &[&str], default_klid: Option<&str>) -> SessionRefresh {
+    let mut problems = Vec::new();
+
+    for klid in klids {
+        let hkl = match load_layout(klid) {
+            Ok(hkl) => hkl,
+            Err(e) => {
+                problems.push(e);
+                continue;
+            }
+        };
+
+        if default_klid.is_some_and(|default_klid| default_klid.eq_ignore_ascii_case(klid)) {
+            if let Err(e) = set_default_input_language(hkl) {
+                problems.push(e);
+            }
+
+            // Windows that don't handle the request keep their current layout, which is fine
+            _ = unsafe {
+                PostMessageW(
+                    HWND_BROADCAST,
+                    WM_INPUTLANGCHANGEREQUEST,
+                    WPARAM(0),
+                    LPARAM(hkl.0 as isize),
+                )
+            };
+        }
+    }
+
+    if let Err(e) = broadcast_settings_change() {
+        problems.push(e);
+    }
+    if let Err(e) = restart_text_services() {
+        problems.push(e);
+    }
+
+    SessionRefresh { problems }
+}
+
+/// Whether the HKL is of the layout with the KLID or the `Layout Id`.
+pub fn is_hkl_of_layout(hkl: &Hkl, klid: &str, layout_id: Option<u16>) -> bool {
+    match &hkl.layout {
+        // Only the low 12 bits of the ID fit in the HKL
+        HklLayout::LayoutId(id) => layout_id.is_some_and(|layout_id| layout_id & 0x0FFF == *id),
+        HklLayout::Klid(hkl_klid) => hkl_klid.eq_ignore_ascii_case(klid),
+    }
+}
+
+/// Finds the HKLs of the layout loaded in this session, by its KLID or its `Layout Id`.
+fn find_loaded_layout(klid: &str, layout_id: Option<u16>) -> Vec<HKL> {
+    get_loaded_hkls()
+        .into_iter()
+        .filter(|hkl| is_hkl_of_layout(&Hkl::from(hkl.0 as usize as u32), klid, layout_id))
+        .collect()
+}
+
+/// Reloads the layout wherever it's loaded in this session, so the new tables of its
+/// replaced DLL are used without signing out.
+///
+/// Returns whether the layout was loaded. Applications that hold on to the old tables keep them
+/// until they switch layouts or restart.
+pub fn reload_layout(klid: &str, layout_id: Option<u16>) -> Result<bool, String> {
+    let layout_hkls = find_loaded_layout(klid, layout_id);
+    if layout_hkls.is_empty() {
+        return Ok(false);
+    }
+
+    let active = unsafe { GetKeyboardLayout(0) };
+
+    // Windows only reads the DLL again once no one has the layout loaded
+    for hkl in &layout_hkls {
+        unsafe { UnloadKeyboardLayout(*hkl) }
+            .map_err(|e| format!("Couldn't unload the layout {}. {}", klid, e))?;
+    }
+
+    let klid_wide =
+        U16CString::from_str(klid).map_err(|e| format!("Invalid layout {}. {}", klid, e))?;
+    let flags = if layout_hkls.contains(&active) {
+        KLF_ACTIVATE
+    } else {
+        KLF_NOTELLSHELL
+    };
+    let hkl = unsafe { LoadKeyboardLayoutW(PCWSTR(klid_wide.as_ptr()), flags) }
+        .map_err(|e| format!("Couldn't load the layout {}. {}", klid, e))?;
+
+    // Windows that were using the layout switch to the reloaded one
+    _ = unsafe {
+        PostMessageW(
+            HWND_BROADCAST,
+            WM_INPUTLANGCHANGEREQUEST,
+            WPARAM(0),
+            LPARAM(hkl.0 as isize),
+        )
+    };
+
+    Ok(true)
+}
+
+/// Unloads the layout wherever it's loaded in this session, so it can be removed without
+/// leaving the session with a broken layout.
+///
+/// Returns whether the layout was loaded. Windows using it switch to another loaded layout.
+pub fn unload_layout(klid: &str, layout_id: Option<u16>) -> Result<bool, String> {
+    let layout_hkls = find_loaded_layout(klid, layout_id);
+
+    for hkl in &layout_hkls {
+        unsafe { UnloadKeyboardLayout(*hkl) }
+            .map_err(|e| format!("Couldn't unload the layout {}. {}", klid, e))?;
+    }
+
+    Ok(!layout_hkls.is_empty())
+}
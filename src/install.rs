@@ -0,0 +1,1627 @@
+use std::{
+    fs,
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use clap::Args;
+use dialoguer::Confirm;
+use indoc::printdoc;
+use serde::Deserialize;
+use windows::Win32::UI::Shell::FOLDERID_System;
+
+use crate::{
+    arch::Arch,
+    bundle::Bundle,
+    color::{paint, print_warning, Style},
+    compile::{
+        build_layout_dlls, get_default_archs, get_work_dir, prepare_klc_file, verify_dll_file,
+        TemplateVars,
+    },
+    error::InstallError,
+    event_log::{audit_layouts, AuditAction},
+    exit_code::{CommandError, ExitCode},
+    file_info::has_string_resource,
+    get_known_folder::get_known_folder,
+    history::record_file,
+    hooks::{run_hooks, HookContext, HookPoint},
+    input_refresh::{activate_layout, broadcast_settings_change, refresh_session},
+    install_plan::{InstallPlan, PlannedOperation},
+    install_progress::{InstallProgress, InstallStep},
+    journal::Journal,
+    klc::{import_keylayout, import_xkb, KlcInfo},
+    klid::{is_transient_lcid, Klid, LOCALE_CUSTOM_UNSPECIFIED},
+    layout_expiry::{parse_duration, to_expiry_value, EXPIRES_VALUE_NAME},
+    layout_icon::{get_icon_file_name, verify_icon_file, ICON_VALUE_NAME},
+    layout_provenance::Provenance,
+    layout_version::LAYOUT_VERSION_VALUE_NAME,
+    layouts::{
+        get_layouts_key, get_next_layout_id, get_next_layout_id_in, get_next_layout_key,
+        get_next_layout_key_in, is_layout_id_used, is_layout_id_used_in, parse_layout_id,
+        parse_layout_key, parse_locale, verify_installed_layout, BUILT_DLL_VALUE_NAME,
+    },
+    manifest::Manifest,
+    offline_image::OfflineImage,
+    pe_image::dll_builds_equal,
+    preload::{get_input_method, preload_layout, set_input_method_override, USER_PROFILE_KEY_PATH},
+    registry_key::{RegistryError, RegistryKey},
+    registry_value::RegistryValueData,
+    tool_runner::{DryRunner, ProcessRunner},
+    transaction::Transaction,
+    user_hives::UserProfiles,
+    utils::{files_equal, is_file_in_use},
+};
+
+/// Options customizing how a layout is installed.
+///
+/// Missing fields of deserialized options are off, like missing arguments.
+#[derive(Args, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct InstallOptions {
+    /// Registry key to install the layout under.
+    ///
+    /// Must be an 8-digit hexadecimal number, where the last 4 digits signify the language code.
+    /// By default, it starts at F000xxxx and increments by 1 for each layout.
+    #[clap(short, long, visible_alias("key"), value_name = "KEY")]
+    pub registry_key: Option<String>,
+
+    /// Overwrite the layout if the registry key is already in use.
+    #[clap(short('F'), long)]
+    pub force: bool,
+
+    #[command(flatten)]
+    pub template_vars: TemplateVars,
+
+    /// ID of the layout to use.
+    ///
+    /// Must be a 4-digit hexadecimal number between 0F00 and EFFF that is not already in use.
+    /// Uses the lowest available ID by default.
+    #[clap(short, long)]
+    pub id: Option<String>,
+
+    /// Text (description) of the layout to use.
+    ///
+    /// If not provided, the name is taken from the layout file, or the DLL's file name.
+    #[clap(short, long, visible_alias("description"))]
+    pub text: Option<String>,
+
+    /// Language to register the layout for.
+    ///
+    /// Can be a hexadecimal LCID (e.g. 0415) or a language tag (e.g. pl-PL).
+    /// Can be repeated to register the layout under a separate key for each language.
+    /// By default, the LOCALEID of the layout file is used. Required for .DLL files.
+    #[clap(long, visible_alias("language-tag"), value_name = "LOCALE")]
+    pub locale: Vec<String>,
+
+    /// Installs the layout for a trial period only, e.g. 7d, 2w or 12h.
+    ///
+    /// Expired layouts are marked in the layout list until kept with the keep command.
+    #[clap(long, value_name = "DURATION")]
+    pub expires: Option<String>,
+
+    /// Name of the DLL file in System32.
+    ///
+    /// By default, the name of the compiled file is used. If a different file with the name
+    /// already exists, a number is appended to it.
+    #[clap(long, value_name = "NAME")]
+    pub dll_name: Option<String>,
+
+    /// Architectures to build the layout for.
+    ///
+    /// DLLs for other machines are left in the temporary directory. By default, the layout is
+    /// built for this machine and, on 64-bit Windows, for WOW64.
+    #[clap(long, value_enum, value_name = "ARCH")]
+    pub arch: Vec<Arch>,
+
+    /// Don't install the WOW64 DLL used by 32-bit applications on 64-bit Windows.
+    #[clap(long)]
+    pub no_wow64: bool,
+
+    /// Copy the DLL file to System32 instead of moving it.
+    ///
+    /// Always on when installing a .DLL file, so the original is left intact.
+    #[clap(long)]
+    pub copy: bool,
+
+    /// Keep the compiled DLLs and KBDUTOOL's intermediate files (.c, .h, .rc, ...).
+    #[clap(long)]
+    pub keep_artifacts: bool,
+
+    /// Directory to keep the build artifacts in, with a subdirectory for each architecture.
+    ///
+    /// Defaults to the current directory.
+    #[clap(long, value_name = "DIR", requires = "keep_artifacts")]
+    pub output_dir: Option<PathBuf>,
+
+    /// Print the files and registry values the install would create, without changing anything.
+    ///
+    /// The layout is still compiled in the temporary directory.
+    #[clap(long)]
+    pub dry_run: bool,
+
+    /// Only print the KBDUTOOL commands of a dry run instead of compiling the layout.
+    ///
+    /// The rest of the plan depends on the compiled DLL, so it isn't shown.
+    #[clap(long, requires = "dry_run")]
+    pub no_compile: bool,
+
+    /// Doesn't show the planned changes and ask for confirmation before installing.
+    ///
+    /// Only asked when run in a terminal.
+    #[clap(short, long)]
+    pub yes: bool,
+
+    /// Make all changes in a single Kernel Transaction Manager transaction.
+    ///
+    /// Nothing is changed unless every step succeeds, even after a crash or power loss.
+    /// Needs System32 on an NTFS volume, and uses Transactional NTFS, which Microsoft deprecated.
+    #[clap(long)]
+    pub transactional: bool,
+
+    /// Icon (.ICO file) of the layout.
+    ///
+    /// Copied next to the DLL in System32 and recorded in the layout's registry key.
+    #[clap(long, value_name = "FILE")]
+    pub icon: Option<PathBuf>,
+
+    /// Expected SHA-256 checksum of the file downloaded from a URL.
+    ///
+    /// The install stops if the downloaded file doesn't match it.
+    #[clap(long, value_name = "HASH")]
+    pub sha256: Option<String>,
+
+    /// Add localized Display Name registry value.
+    ///
+    /// Will use the localized name in the layout file if available.
+    ///
+    /// By default, true if explicit name is not provided.
+    #[clap(short, long, action = clap::ArgAction::Set, value_name = "BOOL")]
+    pub localize_name: Option<bool>,
+
+    /// The URL or bundle the file came from, recorded instead of its temporary path.
+    #[arg(skip)]
+    pub source: Option<String>,
+
+    /// Add the layout to the input methods of the current user.
+    ///
+    /// Appends it to the Preload list of HKEY_CURRENT_USER, through a substitute for custom
+    /// layouts, so it shows up in the language switcher without changing the settings.
+    #[clap(long)]
+    pub preload: bool,
+
+    /// Load the layout and switch to it right away, without signing out.
+    ///
+    /// Other running applications are asked to switch to it too.
+    #[clap(long)]
+    pub activate: bool,
+
+    /// Add the layout to the input methods of every user and of the Default profile.
+    ///
+    /// The registry hives of users that aren't signed in are loaded from their profiles,
+    /// and new users get the layout from the Default profile.
+    #[clap(long)]
+    pub all_users: bool,
+
+    /// Make the layout the default input method of the current user.
+    ///
+    /// Puts it first in the preloaded layouts and sets it as the input method override,
+    /// so new sessions start with it.
+    #[clap(long)]
+    pub set_default: bool,
+
+    /// Install the layout into the Windows image mounted at the directory instead of this machine.
+    ///
+    /// Registers the layout in the image's SYSTEM hive and copies the DLL into its System32.
+    /// With --all-users, new users of the image get the layout from its Default profile.
+    /// The image must be for the same architecture as this machine.
+    #[clap(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = ["preload", "activate", "set_default", "transactional", "expires"]
+    )]
+    pub offline_image: Option<PathBuf>,
+}
+
+/// A layout already installed with a DLL of the same name as the new one, or a build of the same
+/// layout.
+struct ExistingInstall {
+    layout_key_name: String,
+    layout_id: Option<u16>,
+    locale_id: u16,
+    dll_name: String,
+    /// The same layout for the same language, so there's nothing to install.
+    identical: bool,
+}
+
+/// Finds the custom layouts installed with the DLL, either by its name or as another build of
+/// the same layout, as it might have been renamed to avoid a different DLL with the same name.
+fn find_existing_installs(
+    klc_info: &KlcInfo,
+    dll_path: &Path,
+    dll_name: &str,
+) -> Result<Vec<ExistingInstall>, String> {
+    let system32_path = get_known_folder(&FOLDERID_System)?;
+    let mut existing_installs = Vec::new();
+
+    for layout_key in get_layouts_key()
+        .map_err(|e| e.to_string())?
+        .iter_children()
+    {
+        let layout_key = layout_key.map_err(|e| e.to_string())?;
+        let Some(layout_file) = layout_key
+            .try_get_string(Some("Layout File"))
+            .map_err(|e| e.to_string())?
+        else {
+            continue;
+        };
+        // A system layout with a DLL of the same name isn't an earlier install of this one
+        let Ok(klid) = Klid::parse(layout_key.get_name()) else {
+            continue;
+        };
+        if !klid.is_custom() {
+            continue;
+        }
+
+        let installed_dll_path = system32_path.join(&layout_file);
+        let same_content =
+            installed_dll_path.exists() && dll_builds_equal(dll_path, &installed_dll_path)?;
+        if !same_content && !layout_file.eq_ignore_ascii_case(dll_name) {
+            continue;
+        }
+
+        let layout_id = layout_key
+            .try_get_string(Some("Layout Id"))
+            .map_err(|e| e.to_string())?
+            .and_then(|v| u16::from_str_radix(&v, 16).ok());
+
+        existing_installs.push(ExistingInstall {
+            layout_key_name: layout_key.get_name().to_string(),
+            layout_id,
+            locale_id: klid.language,
+            identical: is_identical_install(&layout_key, klc_info, dll_path, &layout_file)?,
+            dll_name: layout_file,
+        });
+    }
+
+    Ok(existing_installs)
+}
+
+fn is_identical_install(
+    layout_key: &RegistryKey,
+    klc_info: &KlcInfo,
+    dll_path: &Path,
+    dll_name: &str,
+) -> Result<bool, String> {
+    let layout_key_hex =
+        u32::from_str_radix(layout_key.get_name(), 16).map_err(|e| e.to_string())?;
+    if layout_key_hex as u16 != klc_info.locale_id {
+        return Ok(false);
+    }
+
+    let layout_text = layout_key
+        .try_get_string(Some("Layout Text"))
+        .map_err(|e| e.to_string())?;
+    if layout_text.as_deref() != Some(klc_info.layout_text.as_str()) {
+        return Ok(false);
+    }
+
+    let installed_dll_path = get_known_folder(&FOLDERID_System)?.join(dll_name);
+    if !installed_dll_path.exists() {
+        return Ok(false);
+    }
+
+    dll_builds_equal(dll_path, &installed_dll_path)
+}
+
+/// Validates the name the DLL should have in System32, adding the extension if it's missing.
+pub fn parse_dll_name(name: &str) -> Result<String, String> {
+    if name.is_empty() || name.contains(['\\', '/', ':']) {
+        return Err(format!(
+            "Invalid DLL name {}. It must be a file name without a directory.",
+            name
+        ));
+    }
+
+    if name.to_ascii_lowercase().ends_with(".dll") {
+        Ok(name.to_string())
+    } else {
+        Ok(format!("{}.dll", name))
+    }
+}
+
+/// Finds a name for the DLLs that doesn't clash with a different file in their system
+/// directories, like one of the system `kbd*.dll` layouts.
+///
+/// `targets` are pairs of DLLs and the directories they go to. A build of the same layout
+/// doesn't count as a clash, so reinstalls keep their name.
+fn get_free_dll_name(targets: &[(PathBuf, PathBuf)], dll_name: &str) -> Result<String, String> {
+    let stem = Path::new(dll_name)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or(dll_name);
+
+    (1..=u16::MAX)
+        .map(|n| match n {
+            1 => dll_name.to_string(),
+            n => format!("{}{}.dll", stem, n),
+        })
+        .find(|name| {
+            targets.iter().all(|(dll_path, system_dir)| {
+                let path = system_dir.join(name);
+                !path.exists() || dll_builds_equal(dll_path, &path).unwrap_or(false)
+            })
+        })
+        .ok_or_else(|| format!("Couldn't find a free name for {}.", dll_name))
+}
+
+/// The Layout Display Name of the layout, if the options ask for one.
+fn get_display_name(
+    klc_info: &KlcInfo,
+    dll_path: &Path,
+    dll_name: &str,
+    options: &InstallOptions,
+) -> Result<Option<String>, String> {
+    // Prefer the localized names from the DLL, which Windows picks by the UI language
+    if !options.localize_name.unwrap_or(options.text.is_none()) {
+        Ok(None)
+    } else if has_string_resource(dll_path, 1000)? {
+        Ok(Some(format!("@{},-1000", dll_name)))
+    } else {
+        let description = klc_info.names.get_description(klc_info.locale_id);
+        Ok(Some(
+            description.unwrap_or(&klc_info.layout_text).to_string(),
+        ))
+    }
+}
+
+/// Writes the values describing the layout to its registry key.
+pub fn plan_layout_values(
+    plan: &mut InstallPlan,
+    layout_key_path: &str,
+    layout_id: &str,
+    dll_name: &str,
+    layout_text: &str,
+    display_name: Option<&str>,
+) {
+    use RegistryValueData as RVD;
+
+    plan.set_value(
+        layout_key_path,
+        "Layout Id",
+        RVD::String(layout_id.to_string()),
+    );
+    plan.set_value(
+        layout_key_path,
+        "Layout File",
+        RVD::String(dll_name.to_string()),
+    );
+    plan.set_value(
+        layout_key_path,
+        "Layout Text",
+        RVD::String(layout_text.to_string()),
+    );
+    match display_name {
+        // Indirect strings are expandable, like the ones of the system layouts
+        Some(display_name) if display_name.starts_with('@') => plan.set_value(
+            layout_key_path,
+            "Layout Display Name",
+            RVD::ExpandString(display_name.to_string()),
+        ),
+        Some(display_name) => plan.set_value(
+            layout_key_path,
+            "Layout Display Name",
+            RVD::String(display_name.to_string()),
+        ),
+        // The overwritten layout might have had one
+        None => plan.delete_value(layout_key_path, "Layout Display Name"),
+    }
+    plan.set_value(
+        layout_key_path,
+        "Installed by",
+        RVD::String("klc-install".to_string()),
+    );
+}
+
+/// Checks that the layouts are registered and the DLL is in every system directory.
+pub fn verify_install(
+    layout_keys: &[&str],
+    dll_name: &str,
+    other_builds: &[(Arch, PathBuf)],
+    native_arch: Arch,
+) -> Result<(), String> {
+    for layout_key_name in layout_keys {
+        verify_installed_layout(layout_key_name, dll_name)?;
+    }
+    for (arch, _) in other_builds {
+        if let Some(system_dir) = arch.get_system_dir(native_arch)? {
+            if !system_dir.join(dll_name).exists() {
+                return Err(format!(
+                    "The DLL file {} is missing from {}.",
+                    dll_name,
+                    system_dir.display()
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Describes the file as the source of an install, by its absolute path.
+pub fn get_source(file_path: &Path) -> String {
+    std::path::absolute(file_path)
+        .unwrap_or_else(|_| file_path.to_path_buf())
+        .display()
+        .to_string()
+}
+
+/// Extracts the bundle to the temporary directory, returning its layout file and the options
+/// set by its metadata. Options given on the command line take precedence.
+pub fn extract_bundle(
+    bundle_path: &Path,
+    index: usize,
+    options: &InstallOptions,
+) -> Result<(PathBuf, InstallOptions), String> {
+    let bundle_dir = get_work_dir().join("bundles").join(index.to_string());
+    println!("Extracting {}...", bundle_path.display());
+    let bundle = Bundle::extract(bundle_path, &bundle_dir)?;
+    let metadata = bundle.metadata;
+
+    let mut bundle_options = options.clone();
+    bundle_options.registry_key = options.registry_key.clone().or(metadata.registry_key);
+    bundle_options.id = options.id.clone().or(metadata.id);
+    bundle_options.dll_name = options.dll_name.clone().or(metadata.dll_name);
+    bundle_options.text = options.text.clone().or(metadata.text);
+    if options.locale.is_empty() {
+        bundle_options.locale = metadata.locale.to_vec();
+    }
+
+    Ok((bundle.layout_path, bundle_options))
+}
+
+/// Converts a macOS .keylayout file or an XKB symbols file to a KLC file in a temporary directory.
+pub fn import_layout_file(file_path: &Path, is_keylayout: bool) -> Result<PathBuf, String> {
+    println!("Converting {}...", file_path.display());
+    let layout = if is_keylayout {
+        import_keylayout(file_path)?
+    } else {
+        import_xkb(file_path)?
+    };
+
+    let import_dir = get_work_dir().join("imported");
+    fs::create_dir_all(&import_dir).map_err(|e| e.to_string())?;
+
+    let klc_path = import_dir.join(format!("{}.klc", layout.name));
+    layout.write_to_file(&klc_path)?;
+
+    Ok(klc_path)
+}
+
+/// Installs every layout of the manifest, with the options it sets for them.
+pub fn install_manifest(
+    manifest_path: &Path,
+    msklc: Option<String>,
+    options: InstallOptions,
+) -> Result<(), CommandError> {
+    if options.registry_key.is_some() || options.id.is_some() || options.dll_name.is_some() {
+        return Err(
+            "The registry key, layout ID and DLL name can only be set in the manifest.".into(),
+        );
+    }
+
+    let manifest_path = manifest_path
+        .canonicalize()
+        .map_err(|e| format!("Couldn't find {}. {}", manifest_path.display(), e))?;
+    let manifest = Manifest::read_from_file(&manifest_path)?;
+    let manifest_dir = manifest_path.parent().unwrap();
+
+    let installs = manifest
+        .layouts
+        .iter()
+        .map(|layout| {
+            let mut layout_options = options.clone();
+            layout_options.registry_key = layout.registry_key.clone();
+            layout_options.id = layout.id.clone();
+            layout_options.dll_name = layout.dll_name.clone();
+            if layout.text.is_some() {
+                layout_options.text = layout.text.clone();
+            }
+            let locales = layout.locale.to_vec();
+            if !locales.is_empty() {
+                layout_options.locale = locales;
+            }
+            layout_options.preload = options.preload || layout.preload;
+
+            (layout.get_path(manifest_dir), layout_options)
+        })
+        .collect::<Vec<_>>();
+
+    install_batch(&installs, msklc.or(manifest.msklc).as_deref())
+}
+
+/// Installs the layouts one after another, so a failure doesn't stop the rest,
+/// and summarizes the results.
+pub fn install_batch(
+    installs: &[(PathBuf, InstallOptions)],
+    msklc: Option<&str>,
+) -> Result<(), CommandError> {
+    let mut results = Vec::new();
+    for (i, (path, options)) in installs.iter().enumerate() {
+        println!(
+            "Installing {} ({}/{})...",
+            path.display(),
+            i + 1,
+            installs.len()
+        );
+
+        let result = install_layout(path, msklc, options);
+        if let Err(e) = &result {
+            println!("{}", e);
+        }
+        results.push((path, result));
+    }
+
+    println!("Summary:");
+    for (path, result) in &results {
+        let status = if result.is_ok() { "OK" } else { "FAILED" };
+        println!("{:>8} {}", status, path.display());
+    }
+
+    let failed = results.iter().filter(|(_, result)| result.is_err()).count();
+    if failed > 0 {
+        let exit_code = if failed < results.len() {
+            ExitCode::PartialSuccess
+        } else {
+            ExitCode::Error
+        };
+        return Err(CommandError::new(
+            exit_code,
+            format!("{} of {} layouts failed to install.", failed, results.len()),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Installs the layout file, returning the keys of the layouts it registered.
+pub fn install_layout(
+    file: &Path,
+    msklc: Option<&str>,
+    options: &InstallOptions,
+) -> Result<Vec<String>, CommandError> {
+    let mut progress = InstallProgress::new();
+
+    run_install_steps(file, msklc, options, &mut progress).map_err(|e| {
+        let mut error = CommandError::from(e);
+        error.message = progress.wrap_error(error.message);
+        error
+    })
+}
+
+fn run_install_steps(
+    file: &Path,
+    msklc: Option<&str>,
+    options: &InstallOptions,
+    progress: &mut InstallProgress,
+) -> Result<Vec<String>, InstallError> {
+    progress.start(InstallStep::Parse);
+
+    let native_arch = Arch::get_native();
+
+    let file_path = file.canonicalize().map_err(|e| e.to_string())?;
+
+    let requested_key = options
+        .registry_key
+        .as_deref()
+        .map(parse_layout_key)
+        .transpose()?;
+    // An image is checked against its own layouts once it's loaded
+    let is_offline = options.offline_image.is_some();
+    let requested_key_exists = match &requested_key {
+        Some(key) if !is_offline => match get_layouts_key().and_then(|k| k.get_subkey(key)) {
+            Ok(_) if options.force => {
+                println!("The registry key {} already exists, it will be overwritten.", key);
+                true
+            }
+            Ok(_) => {
+                return Err(format!(
+                    "The registry key {} is already used by another layout! Use --force to overwrite it.",
+                    key
+                )
+                .into())
+            }
+            Err(RegistryError::NotFound) => false,
+            Err(e) => return Err(e.into()),
+        },
+        _ => false,
+    };
+
+    let expires = options.expires.as_deref().map(parse_duration).transpose()?;
+
+    let locale_ids = options
+        .locale
+        .iter()
+        .map(|locale| parse_locale(locale))
+        .collect::<Result<Vec<_>, _>>()?;
+    if locale_ids.len() > 1 && (options.registry_key.is_some() || options.id.is_some()) {
+        return Err(
+            "The registry key and layout ID can't be set when installing for multiple locales."
+                .to_string()
+                .into(),
+        );
+    }
+
+    let requested_id = options.id.as_deref().map(parse_layout_id).transpose()?;
+    if let (Some(id), false) = (requested_id, is_offline) {
+        if is_layout_id_used(id, requested_key.as_deref())? {
+            return Err(format!(
+                "The layout ID {:04X} is already used by another layout!",
+                id
+            )
+            .into());
+        }
+    }
+
+    // let is_dll = file_path.ends_with(".dll");
+    // if !is_dll && !file_path.ends_with(".klc") {
+    //     panic!("The file must be a .KLC or .DLL file.");
+    // }
+    let mut extension = file_path.extension().map(|ext| ext.to_ascii_lowercase());
+
+    // Layouts of other systems are converted to KLC and compiled like one
+    let file_path = if extension == Some("keylayout".into())
+        || extension == Some("xkb".into())
+        || extension.is_none()
+    {
+        let klc_path = import_layout_file(&file_path, extension == Some("keylayout".into()))?;
+        extension = Some("klc".into());
+        klc_path
+    } else {
+        file_path
+    };
+
+    if extension != Some("klc".into()) && extension != Some("dll".into()) {
+        return Err("The file must be a .KLC or .DLL file.".to_string().into());
+    }
+
+    // The user's own DLL is kept where it is
+    let dll_options;
+    let options = if extension == Some("dll".into()) && !options.copy {
+        dll_options = InstallOptions {
+            copy: true,
+            ..options.clone()
+        };
+        &dll_options
+    } else {
+        options
+    };
+
+    if let Some(icon_path) = &options.icon {
+        verify_icon_file(icon_path)?;
+    }
+
+    let (klc_info, dll_path, other_builds) = if extension == Some("klc".into()) {
+        // Included base files and template variables must be resolved for KBDUTOOL
+        let file_path = prepare_klc_file(&file_path, &options.template_vars)?;
+
+        // We have to parse some stuff from the KLC file
+        let mut klc_info = KlcInfo::read_from_file(&file_path)?;
+        if let Some(text) = options.text.clone() {
+            klc_info.layout_text = text;
+        }
+        if let Some(&locale_id) = locale_ids.first() {
+            klc_info.locale_id = locale_id;
+        } else if klc_info.locale_id == LOCALE_CUSTOM_UNSPECIFIED
+            || is_transient_lcid(klc_info.locale_id)
+        {
+            // The LCID in the file was only valid on the author's machine
+            if let Some(locale_name) = &klc_info.locale_name {
+                klc_info.locale_id = parse_locale(locale_name)?;
+            }
+        }
+        let KlcInfo {
+            ref layout_name,
+            ref layout_text,
+            locale_id,
+            ..
+        } = klc_info;
+
+        println!(
+            "Found layout with name {}, with text {} and locale ID {} ({2:#06X})!",
+            layout_name, layout_text, locale_id
+        );
+
+        // Now we need to compile KLC file
+        progress.start(InstallStep::Compile);
+
+        let archs = if options.arch.is_empty() {
+            get_default_archs(native_arch, options.no_wow64)
+        } else {
+            options.arch.clone()
+        };
+        if !archs.contains(&native_arch) {
+            return Err(format!(
+                "The layout must also be built for the architecture of this machine ({}).",
+                native_arch
+            )
+            .into());
+        }
+
+        let artifacts_dir = match (options.keep_artifacts, &options.output_dir) {
+            (false, _) => None,
+            (true, Some(output_dir)) => Some(output_dir.clone()),
+            (true, None) => Some(std::env::current_dir().map_err(|e| e.to_string())?),
+        };
+        if options.dry_run && options.no_compile {
+            build_layout_dlls(&DryRunner, &file_path, &klc_info, msklc, &archs, None)?;
+            progress.finish();
+            println!("Dry run, nothing was compiled nor changed.");
+            return Ok(Vec::new());
+        }
+
+        let mut builds = build_layout_dlls(
+            &ProcessRunner,
+            &file_path,
+            &klc_info,
+            msklc,
+            &archs,
+            artifacts_dir.as_deref(),
+        )?;
+
+        let native_index = builds
+            .iter()
+            .position(|(arch, _)| *arch == native_arch)
+            .unwrap();
+        let (_, dll_path) = builds.remove(native_index);
+
+        (klc_info, dll_path, builds)
+    } else {
+        // A DLL has no KLC file to read the layout from, so it's described by the options
+        let layout_name = file_path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or_else(|| format!("Invalid DLL path {}.", file_path.display()))?
+            .to_string();
+        let layout_text = options.text.clone().unwrap_or_else(|| layout_name.clone());
+        let Some(&locale_id) = locale_ids.first() else {
+            return Err(
+                "Set the locale of the layout with --locale when installing a .DLL file."
+                    .to_string()
+                    .into(),
+            );
+        };
+
+        println!(
+            "Installing DLL {} with text {} and locale ID {} ({2:#06X})!",
+            layout_name, layout_text, locale_id
+        );
+
+        (
+            KlcInfo::new(layout_name, layout_text, locale_id),
+            file_path.clone(),
+            Vec::new(),
+        )
+    };
+    let mut dll_name = match &options.dll_name {
+        Some(name) => parse_dll_name(name)?,
+        None => dll_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| format!("Invalid DLL path {}.", dll_path.display()))?
+            .to_string(),
+    };
+
+    // We have the DLL file now
+    progress.start(InstallStep::VerifyDll);
+
+    verify_dll_file(&dll_path)?;
+    for (_, arch_dll_path) in &other_builds {
+        verify_dll_file(arch_dll_path)?;
+    }
+
+    if options.offline_image.is_some() {
+        progress.finish();
+        let source = options.source.clone().unwrap_or_else(|| get_source(file));
+        let provenance = Provenance::new(&dll_path, source)?;
+        return Ok(install_into_image(
+            &klc_info,
+            &dll_path,
+            &other_builds,
+            dll_name,
+            locale_ids,
+            provenance,
+            options,
+        )?);
+    }
+
+    let existing_installs = find_existing_installs(&klc_info, &dll_path, &dll_name)?;
+
+    if let (false, Some(existing)) = (
+        options.force,
+        existing_installs.iter().find(|existing| existing.identical),
+    ) {
+        progress.finish();
+        println!(
+            "The layout is already installed under the key {}. Nothing to do.",
+            existing.layout_key_name
+        );
+        run_post_install_actions(&[existing.layout_key_name.as_str()], options)?;
+        return Ok(vec![existing.layout_key_name.clone()]);
+    }
+
+    // Reinstalling over the layout for the same language keeps its key and ID,
+    // instead of using up new ones
+    let mut requested_key = requested_key;
+    let mut requested_key_exists = requested_key_exists;
+    let mut requested_id = requested_id;
+    // The DLL of the layout is replaced under its name, instead of leaving the old one behind
+    let mut replace_dll = false;
+    if let Some(first_existing) = existing_installs.first() {
+        if !options.force {
+            return Err(format!(
+                "This layout seems already installed under the key {}! Use --force to reinstall it.",
+                first_existing.layout_key_name
+            )
+            .into());
+        }
+
+        let target_locale_id = locale_ids.first().copied().unwrap_or(klc_info.locale_id);
+        let same_locale = existing_installs
+            .iter()
+            .find(|existing| existing.locale_id == target_locale_id);
+
+        match same_locale {
+            Some(existing) if requested_key.is_none() && locale_ids.len() <= 1 => {
+                println!(
+                    "This layout is already installed under the key {}, reinstalling it in place.",
+                    existing.layout_key_name
+                );
+                requested_key = Some(existing.layout_key_name.clone());
+                requested_key_exists = true;
+                requested_id = requested_id.or(existing.layout_id);
+                if options.dll_name.is_none() {
+                    dll_name = existing.dll_name.clone();
+                    replace_dll = true;
+                }
+            }
+            _ => println!(
+                "This layout is already installed under the key {}, installing anyway.",
+                first_existing.layout_key_name
+            ),
+        }
+    }
+
+    // Plan the changes first, so they can be shown without making them
+    let mut file_plan = InstallPlan::new();
+
+    if dll_path.parent() != Some(Path::new("C:\\Windows\\System32")) {
+        let system32_path = get_known_folder(&FOLDERID_System)?;
+
+        // The DLLs and the system directories they go to
+        let mut targets = vec![(dll_path.clone(), system32_path.clone())];
+        for (arch, arch_dll_path) in &other_builds {
+            if let Some(system_dir) = arch.get_system_dir(native_arch)? {
+                targets.push((arch_dll_path.clone(), system_dir));
+            }
+        }
+
+        let free_dll_name = if replace_dll {
+            dll_name.clone()
+        } else {
+            get_free_dll_name(&targets, &dll_name)?
+        };
+        if free_dll_name != dll_name {
+            println!(
+                "A different {} already exists in the system directory, the layout will use {} instead.",
+                dll_name, free_dll_name
+            );
+            dll_name = free_dll_name;
+        }
+
+        for (source_path, system_dir) in targets {
+            let new_dll_path = system_dir.join(&dll_name);
+            let identical = new_dll_path.exists() && dll_builds_equal(&source_path, &new_dll_path)?;
+
+            if identical {
+                println!(
+                    "The identical DLL file is already in {}.",
+                    system_dir.display()
+                );
+            } else if new_dll_path.exists() && is_file_in_use(&new_dll_path) {
+                // Sessions using the layout keep the DLL loaded until they end
+                println!(
+                    "{} is in use, it will be replaced when Windows restarts.",
+                    new_dll_path.display()
+                );
+                file_plan.push(PlannedOperation::ReplaceFileOnReboot {
+                    source: source_path,
+                    target: new_dll_path,
+                });
+            } else if new_dll_path.exists() {
+                file_plan.push(PlannedOperation::ReplaceFile {
+                    source: source_path,
+                    target: new_dll_path,
+                });
+            } else if options.copy {
+                file_plan.push(PlannedOperation::CopyFile {
+                    source: source_path,
+                    target: new_dll_path,
+                });
+            } else {
+                file_plan.push(PlannedOperation::MoveFile {
+                    source: source_path,
+                    target: new_dll_path,
+                });
+            }
+        }
+    }
+
+    let icon_name = options.icon.as_ref().map(|_| get_icon_file_name(&dll_name));
+    if let (Some(icon_path), Some(icon_name)) = (&options.icon, &icon_name) {
+        let target = get_known_folder(&FOLDERID_System)?.join(icon_name);
+        if target.exists() && files_equal(icon_path, &target).map_err(|e| e.to_string())? {
+            println!("The identical icon is already in {}.", target.display());
+        } else {
+            file_plan.push(PlannedOperation::CopyFile {
+                source: icon_path.clone(),
+                target,
+            });
+        }
+    }
+
+    let display_name = get_display_name(&klc_info, &dll_path, &dll_name, options)?;
+
+    let mut registry_plan = InstallPlan::new();
+
+    let layouts_key = get_layouts_key().map_err(|e| e.to_string())?;
+
+    let locale_ids = if locale_ids.is_empty() {
+        vec![klc_info.locale_id]
+    } else {
+        locale_ids
+    };
+
+    let source = options.source.clone().unwrap_or_else(|| get_source(file));
+    let provenance = Provenance::new(&dll_path, source)?;
+
+    // Layout keys and IDs of every registered locale
+    let mut registered = Vec::new();
+    let mut planned_ids = Vec::new();
+
+    for locale_id in locale_ids {
+        if is_transient_lcid(locale_id) {
+            print_warning(format!(
+                "{:04X} is a transient LCID. Other users may have it assigned to a different language.",
+                locale_id
+            ));
+        }
+
+        // Use the requested layout key or find the next available one:
+        let layout_key_name = match &requested_key {
+            Some(key) => {
+                if !key.ends_with(&format!("{:04x}", locale_id)) {
+                    print_warning(format!(
+                        "the registry key {} doesn't end with the layout's locale ID {:04x}.",
+                        key, locale_id
+                    ));
+                }
+                key.clone()
+            }
+            None => get_next_layout_key(locale_id).map_err(|e| e.to_string())?,
+        };
+        // and create it:
+        let layout_key_path = format!("{}\\{}", layouts_key.get_path(), layout_key_name);
+        if !requested_key_exists {
+            registry_plan.push(PlannedOperation::CreateKey(layout_key_path.clone()));
+        }
+
+        // Use the requested layout ID or find the next available one:
+        let layout_id = match requested_id {
+            Some(id) => id,
+            None => get_next_layout_id(&planned_ids)?,
+        };
+        planned_ids.push(layout_id);
+        let layout_id_str = format!("{:04X}", layout_id);
+
+        println!(
+            "Using the layout key {} and layout ID {}!",
+            layout_key_name, layout_id_str
+        );
+
+        plan_layout_values(
+            &mut registry_plan,
+            &layout_key_path,
+            &layout_id_str,
+            &dll_name,
+            &klc_info.layout_text,
+            display_name.as_deref(),
+        );
+        provenance.plan(&mut registry_plan, &layout_key_path);
+        // Updates find the layout by it even if the DLL was renamed
+        registry_plan.set_value(
+            &layout_key_path,
+            BUILT_DLL_VALUE_NAME,
+            RegistryValueData::String(format!("{}.dll", klc_info.layout_name)),
+        );
+        match &klc_info.version {
+            Some(version) => registry_plan.set_value(
+                &layout_key_path,
+                LAYOUT_VERSION_VALUE_NAME,
+                RegistryValueData::String(version.clone()),
+            ),
+            None => registry_plan.delete_value(&layout_key_path, LAYOUT_VERSION_VALUE_NAME),
+        }
+        if let Some(icon_name) = &icon_name {
+            registry_plan.set_value(
+                &layout_key_path,
+                ICON_VALUE_NAME,
+                RegistryValueData::String(icon_name.clone()),
+            );
+        }
+        match expires {
+            Some(expires) => registry_plan.set_value(
+                &layout_key_path,
+                EXPIRES_VALUE_NAME,
+                to_expiry_value(SystemTime::now() + expires),
+            ),
+            // The overwritten layout might have been a trial
+            None => registry_plan.delete_value(&layout_key_path, EXPIRES_VALUE_NAME),
+        }
+
+        registered.push((layout_key_name, layout_id_str, locale_id));
+    }
+
+    // Without a terminal to answer in, the install goes ahead like before
+    let confirm = !options.yes && !options.dry_run && io::stdin().is_terminal();
+    if options.dry_run || confirm {
+        progress.finish();
+        if options.dry_run {
+            println!("Dry run, nothing was changed. The install would:");
+        } else {
+            println!("The install will:");
+        }
+        for operation in file_plan.operations.iter().chain(&registry_plan.operations) {
+            println!("  {}", operation);
+        }
+        if options.preload {
+            for (layout_key_name, _, _) in &registered {
+                println!(
+                    "  add {} to the preloaded layouts of the current user",
+                    layout_key_name
+                );
+            }
+        }
+        if options.all_users {
+            for (layout_key_name, _, _) in &registered {
+                println!(
+                    "  add {} to the preloaded layouts of every user and the Default profile",
+                    layout_key_name
+                );
+            }
+        }
+        if let (true, Some((layout_key_name, _, _))) = (options.set_default, registered.first()) {
+            println!(
+                "  make {} the default input method of the current user",
+                layout_key_name
+            );
+        }
+        if let (true, Some((layout_key_name, _, _))) = (options.activate, registered.first()) {
+            println!("  activate {}", layout_key_name);
+        }
+        if options.dry_run {
+            return Ok(Vec::new());
+        }
+        if !confirm_plan("Install the layout?")? {
+            println!("Nothing was installed.");
+            return Ok(Vec::new());
+        }
+    }
+
+    let registered_keys: Vec<&str> = registered.iter().map(|(key, _, _)| key.as_str()).collect();
+    let hook_context = HookContext {
+        layout_keys: &registered_keys,
+        layout_file: Some(&dll_name),
+        layout_text: Some(&klc_info.layout_text),
+        source: Some(&provenance.source),
+    };
+    run_hooks(HookPoint::PreInstall, &hook_context)?;
+
+    if options.transactional {
+        let transaction = Transaction::begin("klc-install install")?;
+
+        // We move it to System32
+        progress.start(InstallStep::Copy);
+        file_plan.execute_transacted(&transaction)?;
+
+        // We register the layout in the registry
+        progress.start(InstallStep::Register);
+        registry_plan.execute_transacted(&transaction)?;
+
+        transaction.commit()?;
+        file_plan.remove_moved_files()?;
+
+        // The changes are only visible outside the transaction after committing
+        progress.start(InstallStep::Verify);
+        verify_install(&registered_keys, &dll_name, &other_builds, native_arch)?;
+    } else {
+        let mut journal = Journal::begin("install")?;
+
+        let result = (|| {
+            // We move it to System32
+            progress.start(InstallStep::Copy);
+            file_plan.execute(&mut journal)?;
+
+            // We register the layout in the registry
+            progress.start(InstallStep::Register);
+            registry_plan.execute(&mut journal)?;
+
+            progress.start(InstallStep::Verify);
+            verify_install(&registered_keys, &dll_name, &other_builds, native_arch)
+        })();
+
+        // Don't leave the layout half-installed
+        if let Err(e) = result {
+            return Err(match journal.roll_back() {
+                Ok(()) => format!("{} The changes were rolled back.", e),
+                Err(rollback_error) => format!(
+                    "{} Rolling back the changes failed too, the next run will offer to retry. {}",
+                    e, rollback_error
+                ),
+            }
+            .into());
+        }
+
+        journal.commit()?;
+    }
+    progress.finish();
+    file_plan.get_target_files().for_each(record_file);
+    audit_layouts(
+        AuditAction::Install,
+        &registered_keys,
+        Some(&provenance.sha256),
+    );
+
+    println!(
+        "{}",
+        paint("Successfully installed the layout!", Style::Green)
+    );
+    for (layout_key_name, layout_id_str, locale_id) in &registered {
+        printdoc!(
+            "
+                Key: {}
+                ID: {}
+                Locale: {:04X}{}
+            ",
+            layout_key_name,
+            layout_id_str,
+            locale_id,
+            klc_info
+                .names
+                .get_language_name(*locale_id)
+                .map(|name| format!(" ({})", name))
+                .unwrap_or_default(),
+        );
+    }
+    printdoc!(
+        "
+            Name: {}
+            Display Name: {}
+            File: {}
+        ",
+        klc_info.layout_text,
+        display_name.as_deref().unwrap_or("-"),
+        dll_name
+    );
+    if file_plan.needs_reboot() {
+        println!("A restart is pending: the new DLL is only used once Windows restarts.");
+    }
+    for (arch, arch_dll_path) in &other_builds {
+        if arch.get_system_dir(native_arch)?.is_none() {
+            println!(
+                "The {} DLL for other machines is at: {}",
+                arch,
+                arch_dll_path.display()
+            );
+        }
+    }
+
+    run_post_install_actions(&registered_keys, options)?;
+    run_hooks(HookPoint::PostInstall, &hook_context)?;
+
+    Ok(registered
+        .into_iter()
+        .map(|(layout_key_name, _, _)| layout_key_name)
+        .collect())
+}
+
+/// Installs the compiled layout into a mounted Windows image instead of this machine.
+///
+/// The free keys, IDs and DLL names are found among the image's own layouts and files.
+fn install_into_image(
+    klc_info: &KlcInfo,
+    dll_path: &Path,
+    other_builds: &[(Arch, PathBuf)],
+    mut dll_name: String,
+    locale_ids: Vec<u16>,
+    provenance: Provenance,
+    options: &InstallOptions,
+) -> Result<Vec<String>, String> {
+    let image_path = options
+        .offline_image
+        .as_deref()
+        .ok_or("No image to install into.")?;
+    let image = OfflineImage::open(image_path)?;
+    let layouts_key = image.get_layouts_key()?;
+
+    let requested_key = options
+        .registry_key
+        .as_deref()
+        .map(parse_layout_key)
+        .transpose()?;
+    let requested_key_exists = match &requested_key {
+        Some(key) => match layouts_key.get_subkey(key) {
+            Ok(_) if options.force => true,
+            Ok(_) => {
+                return Err(format!(
+                    "The registry key {} is already used by another layout of the image! Use --force to overwrite it.",
+                    key
+                ))
+            }
+            Err(RegistryError::NotFound) => false,
+            Err(e) => return Err(e.to_string()),
+        },
+        None => false,
+    };
+    let requested_id = options.id.as_deref().map(parse_layout_id).transpose()?;
+    if let Some(id) = requested_id {
+        if is_layout_id_used_in(&layouts_key, id, requested_key.as_deref())? {
+            return Err(format!(
+                "The layout ID {:04X} is already used by another layout of the image!",
+                id
+            ));
+        }
+    }
+
+    let mut targets = vec![(dll_path.to_path_buf(), image.get_system_dir())];
+    for (arch, arch_dll_path) in other_builds {
+        if let (Arch::Wow64, Some(wow64_dir)) = (arch, image.get_wow64_dir()) {
+            targets.push((arch_dll_path.clone(), wow64_dir));
+        }
+    }
+
+    let free_dll_name = get_free_dll_name(&targets, &dll_name)?;
+    if free_dll_name != dll_name {
+        println!(
+            "A different {} already exists in the image, the layout will use {} instead.",
+            dll_name, free_dll_name
+        );
+        dll_name = free_dll_name;
+    }
+
+    let mut file_plan = InstallPlan::new();
+    for (source_path, system_dir) in targets {
+        let new_dll_path = system_dir.join(&dll_name);
+        if new_dll_path.exists() {
+            println!(
+                "The identical DLL file is already in {}.",
+                system_dir.display()
+            );
+        } else if options.copy {
+            file_plan.push(PlannedOperation::CopyFile {
+                source: source_path,
+                target: new_dll_path,
+            });
+        } else {
+            file_plan.push(PlannedOperation::MoveFile {
+                source: source_path,
+                target: new_dll_path,
+            });
+        }
+    }
+
+    let icon_name = options.icon.as_ref().map(|_| get_icon_file_name(&dll_name));
+    if let (Some(icon_path), Some(icon_name)) = (&options.icon, &icon_name) {
+        file_plan.push(PlannedOperation::CopyFile {
+            source: icon_path.clone(),
+            target: image.get_system_dir().join(icon_name),
+        });
+    }
+
+    let display_name = get_display_name(klc_info, dll_path, &dll_name, options)?;
+
+    let locale_ids = if locale_ids.is_empty() {
+        vec![klc_info.locale_id]
+    } else {
+        locale_ids
+    };
+
+    let mut registry_plan = InstallPlan::new();
+    let mut registered = Vec::new();
+    let mut planned_ids = Vec::new();
+
+    for locale_id in locale_ids {
+        let layout_key_name = match &requested_key {
+            Some(key) => key.clone(),
+            None => get_next_layout_key_in(&layouts_key, locale_id)?,
+        };
+        let layout_key_path = format!("{}\\{}", layouts_key.get_path(), layout_key_name);
+        if !requested_key_exists {
+            registry_plan.push(PlannedOperation::CreateKey(layout_key_path.clone()));
+        }
+
+        let layout_id = match requested_id {
+            Some(id) => id,
+            None => get_next_layout_id_in(&layouts_key, &planned_ids)?,
+        };
+        planned_ids.push(layout_id);
+
+        plan_layout_values(
+            &mut registry_plan,
+            &layout_key_path,
+            &format!("{:04X}", layout_id),
+            &dll_name,
+            &klc_info.layout_text,
+            display_name.as_deref(),
+        );
+        provenance.plan(&mut registry_plan, &layout_key_path);
+        // Updates find the layout by it even if the DLL was renamed
+        registry_plan.set_value(
+            &layout_key_path,
+            BUILT_DLL_VALUE_NAME,
+            RegistryValueData::String(format!("{}.dll", klc_info.layout_name)),
+        );
+        if let Some(version) = &klc_info.version {
+            registry_plan.set_value(
+                &layout_key_path,
+                LAYOUT_VERSION_VALUE_NAME,
+                RegistryValueData::String(version.clone()),
+            );
+        }
+        if let Some(icon_name) = &icon_name {
+            registry_plan.set_value(
+                &layout_key_path,
+                ICON_VALUE_NAME,
+                RegistryValueData::String(icon_name.clone()),
+            );
+        }
+
+        println!(
+            "Using the layout key {} and layout ID {:04X} in the image!",
+            layout_key_name, layout_id
+        );
+        registered.push(layout_key_name);
+    }
+
+    let confirm = !options.yes && !options.dry_run && io::stdin().is_terminal();
+    if options.dry_run || confirm {
+        if options.dry_run {
+            println!("Dry run, nothing was changed. The install would:");
+        } else {
+            println!("The install into {} will:", image.get_root().display());
+        }
+        for operation in file_plan.operations.iter().chain(&registry_plan.operations) {
+            println!("  {}", operation);
+        }
+        if options.all_users {
+            for layout_key_name in &registered {
+                println!(
+                    "  add {} to the preloaded layouts of the Default profile of the image",
+                    layout_key_name
+                );
+            }
+        }
+        if options.dry_run {
+            return Ok(Vec::new());
+        }
+        if !confirm_plan("Install the layout into the image?")? {
+            println!("Nothing was installed.");
+            return Ok(Vec::new());
+        }
+    }
+
+    // The journal rolls back a failed install while the hives are still loaded
+    let mut journal = Journal::begin("install")?;
+    let result = file_plan
+        .execute(&mut journal)
+        .and_then(|_| registry_plan.execute(&mut journal));
+    if let Err(e) = result {
+        return Err(match journal.roll_back() {
+            Ok(()) => format!("{} The changes were rolled back.", e),
+            Err(rollback_error) => format!(
+                "{} Rolling back the changes failed too. {}",
+                e, rollback_error
+            ),
+        });
+    }
+    journal.commit()?;
+
+    if options.all_users {
+        for layout_key_name in &registered {
+            preload_layout(image.get_default_user_key(), layout_key_name, false).map_err(|e| {
+                format!(
+                    "Couldn't add {} to the Default profile of the image. {}",
+                    layout_key_name, e
+                )
+            })?;
+        }
+    }
+
+    println!(
+        "{}",
+        paint(
+            format!(
+                "Successfully installed the layout into {}!",
+                image.get_root().display()
+            ),
+            Style::Green
+        )
+    );
+
+    Ok(registered)
+}
+
+/// Adds the installed layouts to the input methods and activates them, as the options ask.
+pub fn run_post_install_actions(
+    layout_keys: &[&str],
+    options: &InstallOptions,
+) -> Result<(), String> {
+    if options.preload {
+        preload_layouts(layout_keys)?;
+    }
+    if options.all_users {
+        preload_layouts_for_all_users(layout_keys)?;
+    }
+    // Only one layout can be the default or active, so it's the one for the first locale
+    if let (true, Some(layout_key_name)) = (options.set_default, layout_keys.first()) {
+        set_default_layout(layout_key_name)?;
+    }
+    if let (true, Some(layout_key_name)) = (options.activate, layout_keys.first()) {
+        activate_installed_layout(layout_key_name)?;
+    }
+
+    refresh_after_install(layout_keys, options);
+
+    Ok(())
+}
+
+/// Makes the running session pick up the installed layouts, and tells whether signing out
+/// is still needed.
+fn refresh_after_install(layout_keys: &[&str], options: &InstallOptions) {
+    // Only layouts in the input methods of the current user belong in its input switcher
+    let enabled = if options.preload || options.all_users {
+        layout_keys
+    } else if options.set_default {
+        &layout_keys[..layout_keys.len().min(1)]
+    } else {
+        &[]
+    };
+
+    if enabled.is_empty() {
+        // Running applications and the settings may still use the cached layout list
+        if let Err(e) = broadcast_settings_change() {
+            print_warning(e);
+        }
+        println!("Add the layout in the language settings to use it. If it doesn't show up there, try refresh-input before signing out.");
+        return;
+    }
+
+    let default_klid = layout_keys.first().filter(|_| options.set_default).copied();
+    let refresh = refresh_session(enabled, default_klid);
+    for problem in &refresh.problems {
+        print_warning(problem);
+    }
+    if refresh.needs_sign_out() {
+        println!("Sign out and back in for the layout to show up in the input switcher.");
+    } else {
+        println!("The layout is in the input switcher now, without signing out.");
+    }
+
+    if options.all_users {
+        let other_users = UserProfiles::read()
+            .map(|profiles| profiles.get_other_signed_in_users().len())
+            .unwrap_or_default();
+        if other_users > 0 {
+            println!(
+                "{} other signed in users get the layout once they sign out and back in.",
+                other_users
+            );
+        }
+    }
+}
+
+/// Adds the layouts to the input methods of every user profile and the Default profile.
+///
+/// A profile that can't be changed is reported without stopping the others.
+pub fn preload_layouts_for_all_users(layout_keys: &[&str]) -> Result<(), String> {
+    let mut failed = 0;
+
+    UserProfiles::read()?.for_each_hive(true, |hive| {
+        let hive = match hive {
+            Ok(hive) => hive,
+            Err(e) => {
+                print_warning(e);
+                failed += 1;
+                return;
+            }
+        };
+
+        for layout_key_name in layout_keys {
+            match preload_layout(hive.get_key(), layout_key_name, false) {
+                Ok(entry) => println!(
+                    "Added {} to the input methods of {} as {}.",
+                    layout_key_name, hive.name, entry
+                ),
+                Err(e) => {
+                    print_warning(format!(
+                        "Couldn't add {} to the input methods of {}. {}",
+                        layout_key_name, hive.name, e
+                    ));
+                    failed += 1;
+                }
+            }
+        }
+    })?;
+
+    if failed > 0 {
+        return Err(format!(
+            "The layout couldn't be added for {} profiles. It's installed, but they have to add it in the settings.",
+            failed
+        ));
+    }
+
+    Ok(())
+}
+
+/// Makes the layout the default input method of the current user.
+pub fn set_default_layout(layout_key_name: &str) -> Result<(), String> {
+    let current_user = RegistryKey::current_user();
+
+    let entry = preload_layout(&current_user, layout_key_name, true).map_err(|e| e.to_string())?;
+    let input_method = get_input_method(layout_key_name);
+    set_input_method_override(&current_user, USER_PROFILE_KEY_PATH, &input_method)
+        .map_err(|e| e.to_string())?;
+
+    println!(
+        "Made {} the default input method of the current user as {} ({}).",
+        layout_key_name, entry, input_method
+    );
+
+    Ok(())
+}
+
+/// Loads the installed layout and switches to it.
+pub fn activate_installed_layout(layout_key_name: &str) -> Result<(), String> {
+    let hkl = activate_layout(layout_key_name)?;
+    println!(
+        "Switched to the layout {} (HKL {:08X}).",
+        layout_key_name, hkl
+    );
+
+    Ok(())
+}
+
+/// Adds the layouts to the input methods of the current user.
+pub fn preload_layouts(layout_keys: &[&str]) -> Result<(), String> {
+    let current_user = RegistryKey::current_user();
+
+    for layout_key_name in layout_keys {
+        let entry =
+            preload_layout(&current_user, layout_key_name, false).map_err(|e| e.to_string())?;
+        println!(
+            "Added {} to the input methods of the current user as {}.",
+            layout_key_name, entry
+        );
+    }
+
+    Ok(())
+}
+
+/// Asks whether to make the changes that were just listed, which it does by default.
+pub fn confirm_plan(prompt: &str) -> Result<bool, String> {
+    Confirm::new()
+        .with_prompt(prompt)
+        .default(true)
+        .interact()
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,323 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    journal::{Journal, JournalAction},
+    registry_key::{RegistryError, RegistryKey},
+    registry_value::RegistryValueData,
+    transaction::Transaction,
+    utils::{is_file_in_use_error, move_file, move_file_on_reboot},
+};
+
+/// A change to the system planned by an install, made only once the whole plan is known.
+#[derive(Debug, Clone)]
+pub enum PlannedOperation {
+    CopyFile {
+        source: PathBuf,
+        target: PathBuf,
+    },
+    MoveFile {
+        source: PathBuf,
+        target: PathBuf,
+    },
+    /// Overwrites an existing file with a copy of the source, keeping a backup until it's done.
+    ReplaceFile {
+        source: PathBuf,
+        target: PathBuf,
+    },
+    /// Replaces a file that's in use when Windows restarts, copying the source next to it first.
+    ReplaceFileOnReboot {
+        source: PathBuf,
+        target: PathBuf,
+    },
+    /// Creates the registry key with the full path.
+    CreateKey(String),
+    SetValue {
+        key: String,
+        name: String,
+        value: RegistryValueData,
+    },
+    /// Deletes the value if it exists.
+    DeleteValue {
+        key: String,
+        name: String,
+    },
+}
+
+impl PlannedOperation {
+    fn execute(&self, journal: &mut Journal) -> Result<(), String> {
+        match self {
+            PlannedOperation::CopyFile { source, target } => {
+                journal.record(JournalAction::CreateFile(target.clone()))?;
+                fs::copy(source, target).map_err(|e| e.to_string())?;
+            }
+            PlannedOperation::MoveFile { source, target } => {
+                journal.record(JournalAction::CreateFile(target.clone()))?;
+                move_file(source, target).map_err(|e| e.to_string())?;
+            }
+            PlannedOperation::ReplaceFile { source, target } => {
+                journal.record_replace_file(target)?;
+                fs::copy(source, target).map_err(|e| {
+                    if is_file_in_use_error(&e) {
+                        format!("{} is in use by another process. {}", target.display(), e)
+                    } else {
+                        e.to_string()
+                    }
+                })?;
+            }
+            PlannedOperation::ReplaceFileOnReboot { source, target } => {
+                // Removing the staged file cancels the replacement, as there's nothing to move
+                let staged = get_staged_path(target);
+                journal.record(JournalAction::CreateFile(staged.clone()))?;
+                fs::copy(source, &staged).map_err(|e| e.to_string())?;
+                move_file_on_reboot(&staged, target).map_err(|e| {
+                    format!("Couldn't schedule replacing {}. {}", target.display(), e)
+                })?;
+            }
+            PlannedOperation::CreateKey(path) => {
+                let (parent_path, name) = path
+                    .rsplit_once('\\')
+                    .ok_or_else(|| format!("Invalid registry key path {}.", path))?;
+
+                journal.record(JournalAction::CreateKey(path.clone()))?;
+                RegistryKey::from_path(parent_path)
+                    .and_then(|parent| parent.create_subkey(name))
+                    .map_err(|e| e.to_string())?;
+            }
+            PlannedOperation::SetValue { key, name, value } => {
+                let key = open_and_record_value(journal, key, name)?;
+                key.set_value(Some(name), value.clone())
+                    .map_err(|e| e.to_string())?;
+            }
+            PlannedOperation::DeleteValue { key, name } => {
+                let key = open_and_record_value(journal, key, name)?;
+                match key.delete_value(Some(name)) {
+                    Ok(()) | Err(RegistryError::NotFound) => {}
+                    Err(e) => return Err(e.to_string()),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn execute_transacted(&self, transaction: &Transaction) -> Result<(), String> {
+        let handle = transaction.get_handle();
+
+        match self {
+            // The source is removed once the transaction is committed
+            PlannedOperation::CopyFile { source, target }
+            | PlannedOperation::MoveFile { source, target }
+            | PlannedOperation::ReplaceFile { source, target } => {
+                transaction.copy_file(source, target)?;
+            }
+            PlannedOperation::ReplaceFileOnReboot { target, .. } => {
+                return Err(format!(
+                    "{} is in use and can't be replaced in a transaction.",
+                    target.display()
+                ));
+            }
+            PlannedOperation::CreateKey(path) => {
+                let (parent_path, name) = path
+                    .rsplit_once('\\')
+                    .ok_or_else(|| format!("Invalid registry key path {}.", path))?;
+
+                RegistryKey::from_path_transacted(parent_path, handle)
+                    .and_then(|parent| parent.create_subkey_transacted(name, handle))
+                    .map_err(|e| e.to_string())?;
+            }
+            PlannedOperation::SetValue { key, name, value } => {
+                RegistryKey::from_path_transacted(key, handle)
+                    .and_then(|key| key.set_value(Some(name), value.clone()))
+                    .map_err(|e| e.to_string())?;
+            }
+            PlannedOperation::DeleteValue { key, name } => {
+                let key =
+                    RegistryKey::from_path_transacted(key, handle).map_err(|e| e.to_string())?;
+                match key.delete_value(Some(name)) {
+                    Ok(()) | Err(RegistryError::NotFound) => {}
+                    Err(e) => return Err(e.to_string()),
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Where a file replacing the target on reboot waits. It must be on the same volume.
+fn get_staged_path(target: &Path) -> PathBuf {
+    let mut file_name = target.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".klc-install-new");
+    target.with_file_name(file_name)
+}
+
+/// Opens the key and records the current value, so a failed install can restore it.
+fn open_and_record_value(
+    journal: &mut Journal,
+    key_path: &str,
+    name: &str,
+) -> Result<RegistryKey, String> {
+    let key = RegistryKey::from_path(key_path).map_err(|e| e.to_string())?;
+    let previous = key
+        .try_get_value(Some(name))
+        .map_err(|e| e.to_string())?
+        .map(|value| value.get_value().clone());
+
+    journal.record(JournalAction::ChangeValue {
+        key: key_path.to_string(),
+        name: name.to_string(),
+        previous,
+    })?;
+
+    Ok(key)
+}
+
+impl Display for PlannedOperation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PlannedOperation::CopyFile { source, target } => {
+                write!(f, "copy {} to {}", source.display(), target.display())
+            }
+            PlannedOperation::MoveFile { source, target } => {
+                write!(f, "move {} to {}", source.display(), target.display())
+            }
+            PlannedOperation::ReplaceFile { source, target } => {
+                write!(f, "replace {} with {}", target.display(), source.display())
+            }
+            PlannedOperation::ReplaceFileOnReboot { source, target } => write!(
+                f,
+                "replace {} with {} after a restart",
+                target.display(),
+                source.display()
+            ),
+            PlannedOperation::CreateKey(path) => write!(f, "create registry key {}", path),
+            PlannedOperation::SetValue { key, name, value } => {
+                let value = match value {
+                    RegistryValueData::String(string) => format!("\"{}\"", string),
+                    RegistryValueData::ExpandString(string) => {
+                        format!("\"{}\" (expandable)", string)
+                    }
+                    RegistryValueData::Dword(dword) => dword.to_string(),
+                    RegistryValueData::Qword(qword) => qword.to_string(),
+                    value => format!("{:?}", value),
+                };
+                write!(f, "set {}\\{} to {}", key, name, value)
+            }
+            PlannedOperation::DeleteValue { key, name } => {
+                write!(f, "delete {}\\{} if it exists", key, name)
+            }
+        }
+    }
+}
+
+/// The changes an install makes, in order.
+///
+/// Planning them first makes it possible to show them without touching the system.
+#[derive(Debug, Clone, Default)]
+pub struct InstallPlan {
+    pub operations: Vec<PlannedOperation>,
+}
+
+impl InstallPlan {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, operation: PlannedOperation) {
+        self.operations.push(operation);
+    }
+
+    pub fn set_value(&mut self, key: &str, name: &str, value: RegistryValueData) {
+        self.push(PlannedOperation::SetValue {
+            key: key.to_string(),
+            name: name.to_string(),
+            value,
+        });
+    }
+
+    pub fn delete_value(&mut self, key: &str, name: &str) {
+        self.push(PlannedOperation::DeleteValue {
+            key: key.to_string(),
+            name: name.to_string(),
+        });
+    }
+
+    /// Makes the planned changes, recording the ones that can be rolled back in the journal.
+    pub fn execute(&self, journal: &mut Journal) -> Result<(), String> {
+        for operation in &self.operations {
+            operation.execute(journal)?;
+        }
+
+        Ok(())
+    }
+
+    /// Makes the planned changes in the transaction.
+    ///
+    /// Moved files are only copied, call `remove_moved_files` after committing.
+    pub fn execute_transacted(&self, transaction: &Transaction) -> Result<(), String> {
+        for operation in &self.operations {
+            operation.execute_transacted(transaction)?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether some files are only replaced when Windows restarts.
+    pub fn needs_reboot(&self) -> bool {
+        self.operations
+            .iter()
+            .any(|operation| matches!(operation, PlannedOperation::ReplaceFileOnReboot { .. }))
+    }
+
+    /// The files the plan creates or replaces.
+    pub fn get_target_files(&self) -> impl Iterator<Item = &Path> {
+        self.operations
+            .iter()
+            .filter_map(|operation| match operation {
+                PlannedOperation::CopyFile { target, .. }
+                | PlannedOperation::MoveFile { target, .. }
+                | PlannedOperation::ReplaceFile { target, .. }
+                | PlannedOperation::ReplaceFileOnReboot { target, .. } => Some(target.as_path()),
+                _ => None,
+            })
+    }
+
+    pub fn remove_moved_files(&self) -> Result<(), String> {
+        for operation in &self.operations {
+            if let PlannedOperation::MoveFile { source, .. } = operation {
+                fs::remove_file(source).map_err(|e| e.to_string())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_display_planned_operation() {
+        let mut plan = InstallPlan::new();
+        plan.set_value(
+            "HKLM\\Layouts\\f0010415",
+            "Layout Id",
+            RegistryValueData::String("0F00".to_string()),
+        );
+        plan.delete_value("HKLM\\Layouts\\f0010415", "Layout Display Name");
+
+        let lines: Vec<String> = plan.operations.iter().map(|o| o.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "set HKLM\\Layouts\\f0010415\\Layout Id to \"0F00\"",
+                "delete HKLM\\Layouts\\f0010415\\Layout Display Name if it exists",
+            ]
+        );
+    }
+}
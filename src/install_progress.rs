@@ -0,0 +1,105 @@
+use std::fmt::{self, Display, Formatter};
+
+use crate::porcelain::{emit, Event};
+
+/// A named stage of the installation pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstallStep {
+    Parse,
+    Compile,
+    VerifyDll,
+    Copy,
+    Register,
+    Verify,
+}
+
+impl InstallStep {
+    pub const ALL: [InstallStep; 6] = [
+        InstallStep::Parse,
+        InstallStep::Compile,
+        InstallStep::VerifyDll,
+        InstallStep::Copy,
+        InstallStep::Register,
+        InstallStep::Verify,
+    ];
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            InstallStep::Parse => "parse",
+            InstallStep::Compile => "compile",
+            InstallStep::VerifyDll => "verify DLL",
+            InstallStep::Copy => "copy",
+            InstallStep::Register => "register",
+            InstallStep::Verify => "verify",
+        }
+    }
+
+    pub fn get_description(&self) -> &'static str {
+        match self {
+            InstallStep::Parse => "Parsing the layout file",
+            InstallStep::Compile => "Compiling the layout",
+            InstallStep::VerifyDll => "Verifying the layout DLL",
+            InstallStep::Copy => "Copying the DLL to System32",
+            InstallStep::Register => "Registering the layout",
+            InstallStep::Verify => "Verifying the installation",
+        }
+    }
+
+    fn get_number(&self) -> usize {
+        Self::ALL.iter().position(|s| s == self).unwrap() + 1
+    }
+}
+
+impl Display for InstallStep {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.get_name())
+    }
+}
+
+/// Reports install steps as a checklist, remembering the current one so
+/// failures can be attributed to the stage they happened in.
+///
+/// The steps are also events in porcelain mode.
+#[derive(Debug, Default)]
+pub struct InstallProgress {
+    current: Option<InstallStep>,
+}
+
+impl InstallProgress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn start(&mut self, step: InstallStep) {
+        self.finish();
+        emit(&Event::StepStarted {
+            step: step.get_name(),
+            number: step.get_number(),
+            total: InstallStep::ALL.len(),
+        });
+        println!(
+            "[{}/{}] {}...",
+            step.get_number(),
+            InstallStep::ALL.len(),
+            step.get_description()
+        );
+        self.current = Some(step);
+    }
+
+    pub fn finish(&mut self) {
+        if let Some(step) = self.current.take() {
+            emit(&Event::StepFinished {
+                step: step.get_name(),
+            });
+            println!("      Done: {}", step);
+        }
+    }
+
+    /// Prefixes an error with the name of the step that was running.
+    pub fn wrap_error(&self, error: String) -> String {
+        match self.current {
+            Some(step) => format!("Step \"{}\" failed. {}", step, error),
+            None => error,
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+use widestring::U16CString;
+use windows::{
+    core::PCWSTR,
+    Win32::{
+        Foundation::{CloseHandle, HANDLE, WAIT_ABANDONED, WAIT_OBJECT_0, WAIT_TIMEOUT},
+        System::Threading::{CreateMutexW, ReleaseMutex, WaitForSingleObject},
+    },
+};
+
+use crate::exit_code::{CommandError, ExitCode};
+
+/// Name of the system-wide mutex held while klc-install changes the system.
+const MUTEX_NAME: &str = "Global\\klc-install";
+
+/// A system-wide lock making sure only one klc-install process changes layouts at a time,
+/// so two processes can't allocate the same layout key or ID.
+///
+/// Released when dropped.
+#[derive(Debug)]
+pub struct InstanceLock {
+    handle: HANDLE,
+}
+
+impl InstanceLock {
+    /// Acquires the lock, waiting up to `timeout` for another process to release it.
+    pub fn acquire(timeout: Duration) -> Result<InstanceLock, CommandError> {
+        let name = U16CString::from_str(MUTEX_NAME).unwrap();
+        let handle = unsafe { CreateMutexW(None, false, PCWSTR(name.as_ptr())) }
+            .map_err(|e| format!("Couldn't create the instance lock. {}", e))?;
+        let lock = InstanceLock { handle };
+
+        let mut wait = unsafe { WaitForSingleObject(handle, 0) };
+        if wait == WAIT_TIMEOUT {
+            println!("Waiting for another klc-install process to finish...");
+            wait = unsafe { WaitForSingleObject(handle, timeout.as_millis() as u32) };
+        }
+
+        match wait {
+            WAIT_OBJECT_0 => Ok(lock),
+            // The other process died while holding the lock. Its journal will be recovered.
+            WAIT_ABANDONED => Ok(lock),
+            WAIT_TIMEOUT => Err(CommandError::new(
+                ExitCode::Busy,
+                "Another klc-install process is still running. Try again later.",
+            )),
+            _ => Err(format!(
+                "Couldn't acquire the instance lock. {}",
+                windows::core::Error::from_win32()
+            )
+            .into()),
+        }
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        unsafe {
+            // Fails if the lock was never acquired, which is fine
+            _ = ReleaseMutex(self.handle);
+            _ = CloseHandle(self.handle);
+        }
+    }
+}
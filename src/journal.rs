@@ -0,0 +1,463 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use windows::Win32::UI::Shell::FOLDERID_ProgramData;
+
+use crate::{
+    get_known_folder::get_known_folder,
+    registry_key::{RegistryError, RegistryKey},
+    registry_value::RegistryValueData,
+};
+
+const JOURNAL_EXTENSION: &str = "journal";
+
+/// A change to the system recorded in the journal before it's made.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JournalAction {
+    CreateFile(PathBuf),
+    /// Replaces a file, which was backed up to `backup` first.
+    ReplaceFile {
+        path: PathBuf,
+        backup: PathBuf,
+    },
+    CreateKey(String),
+    /// Changes a value, which had the previous value or didn't exist.
+    ChangeValue {
+        key: String,
+        name: String,
+        previous: Option<RegistryValueData>,
+    },
+}
+
+/// Escapes the characters that would split a journal line or field, like `%0A` for a newline.
+///
+/// Backslashes are kept, so the paths and keys stay readable.
+fn escape_field(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '%' => escaped.push_str("%25"),
+            '\t' => escaped.push_str("%09"),
+            '\n' => escaped.push_str("%0A"),
+            '\r' => escaped.push_str("%0D"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape_field(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(index) = rest.find('%') {
+        unescaped.push_str(&rest[..index]);
+        rest = &rest[index..];
+
+        let c = match rest.get(..3) {
+            Some("%25") => '%',
+            Some("%09") => '\t',
+            Some("%0A") => '\n',
+            Some("%0D") => '\r',
+            // Not an escape, e.g. an environment variable in a path
+            _ => {
+                unescaped.push('%');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        unescaped.push(c);
+        rest = &rest[3..];
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+/// Formats a value for a journal line, e.g. `sz:US` or `none` if it doesn't exist.
+fn value_to_field(value: &Option<RegistryValueData>) -> Result<String, String> {
+    match value {
+        None => Ok("none".to_string()),
+        Some(RegistryValueData::String(string)) => Ok(format!("sz:{}", escape_field(string))),
+        Some(RegistryValueData::ExpandString(string)) => {
+            Ok(format!("expand-sz:{}", escape_field(string)))
+        }
+        Some(RegistryValueData::Dword(dword)) => Ok(format!("dword:{}", dword)),
+        Some(RegistryValueData::Qword(qword)) => Ok(format!("qword:{}", qword)),
+        Some(value) => Err(format!(
+            "Can't record the value {:?} in the journal.",
+            value
+        )),
+    }
+}
+
+fn value_from_field(field: &str) -> Option<Option<RegistryValueData>> {
+    if field == "none" {
+        return Some(None);
+    }
+
+    let value = match field.split_once(':')? {
+        ("sz", string) => RegistryValueData::String(unescape_field(string)),
+        ("expand-sz", string) => RegistryValueData::ExpandString(unescape_field(string)),
+        ("dword", dword) => RegistryValueData::Dword(dword.parse().ok()?),
+        ("qword", qword) => RegistryValueData::Qword(qword.parse().ok()?),
+        _ => return None,
+    };
+
+    Some(Some(value))
+}
+
+impl JournalAction {
+    fn to_line(&self) -> Result<String, String> {
+        Ok(match self {
+            JournalAction::CreateFile(path) => {
+                format!("create-file\t{}", escape_field(&path.to_string_lossy()))
+            }
+            JournalAction::ReplaceFile { path, backup } => format!(
+                "replace-file\t{}\t{}",
+                escape_field(&path.to_string_lossy()),
+                escape_field(&backup.to_string_lossy())
+            ),
+            JournalAction::CreateKey(path) => format!("create-key\t{}", escape_field(path)),
+            JournalAction::ChangeValue {
+                key,
+                name,
+                previous,
+            } => format!(
+                "change-value\t{}\t{}\t{}",
+                escape_field(key),
+                escape_field(name),
+                value_to_field(previous)?
+            ),
+        })
+    }
+
+    fn from_line(line: &str) -> Result<JournalAction, String> {
+        let invalid = || format!("Invalid journal entry: {}", line);
+
+        match line.split_once('\t') {
+            Some(("create-file", path)) => Ok(JournalAction::CreateFile(PathBuf::from(
+                unescape_field(path),
+            ))),
+            Some(("replace-file", paths)) => {
+                let (path, backup) = paths.split_once('\t').ok_or_else(invalid)?;
+                Ok(JournalAction::ReplaceFile {
+                    path: PathBuf::from(unescape_field(path)),
+                    backup: PathBuf::from(unescape_field(backup)),
+                })
+            }
+            Some(("create-key", path)) => Ok(JournalAction::CreateKey(unescape_field(path))),
+            Some(("change-value", fields)) => {
+                let mut fields = fields.splitn(3, '\t');
+                let (Some(key), Some(name), Some(previous)) =
+                    (fields.next(), fields.next(), fields.next())
+                else {
+                    return Err(invalid());
+                };
+
+                Ok(JournalAction::ChangeValue {
+                    key: unescape_field(key),
+                    name: unescape_field(name),
+                    previous: value_from_field(previous).ok_or_else(invalid)?,
+                })
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Reverts the action. Actions that didn't happen yet are skipped.
+    pub fn undo(&self) -> Result<(), String> {
+        match self {
+            JournalAction::CreateFile(path) => {
+                if path.exists() {
+                    fs::remove_file(path).map_err(|e| e.to_string())?;
+                }
+            }
+            JournalAction::ReplaceFile { path, backup } => {
+                if backup.exists() {
+                    fs::copy(backup, path).map_err(|e| e.to_string())?;
+                    fs::remove_file(backup).map_err(|e| e.to_string())?;
+                }
+            }
+            JournalAction::CreateKey(path) => {
+                let (parent_path, name) = path
+                    .rsplit_once('\\')
+                    .ok_or_else(|| format!("Invalid registry key path {}.", path))?;
+
+                let parent = RegistryKey::from_path(parent_path).map_err(|e| e.to_string())?;
+                match parent.delete_subkey(name) {
+                    Ok(()) | Err(RegistryError::NotFound) => {}
+                    Err(e) => return Err(e.to_string()),
+                }
+            }
+            JournalAction::ChangeValue {
+                key,
+                name,
+                previous,
+            } => {
+                let key = match RegistryKey::from_path(key) {
+                    Ok(key) => key,
+                    Err(RegistryError::NotFound) => return Ok(()),
+                    Err(e) => return Err(e.to_string()),
+                };
+
+                match previous {
+                    Some(value) => key
+                        .set_value(Some(name), value.clone())
+                        .map_err(|e| e.to_string())?,
+                    None => match key.delete_value(Some(name)) {
+                        Ok(()) | Err(RegistryError::NotFound) => {}
+                        Err(e) => return Err(e.to_string()),
+                    },
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for JournalAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            JournalAction::CreateFile(path) => write!(f, "create file {}", path.display()),
+            JournalAction::ReplaceFile { path, .. } => write!(f, "replace file {}", path.display()),
+            JournalAction::CreateKey(path) => write!(f, "create registry key {}", path),
+            JournalAction::ChangeValue { key, name, .. } => {
+                write!(f, "change registry value {}\\{}", key, name)
+            }
+        }
+    }
+}
+
+fn get_state_dir() -> Result<PathBuf, String> {
+    let program_data = get_known_folder(&FOLDERID_ProgramData)?;
+    Ok(program_data.join("klc-install").join("state"))
+}
+
+pub fn get_unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Write-ahead journal of a multi-step operation.
+///
+/// Every action is recorded before it's performed, so if the process dies midway,
+/// the next run can find the journal and roll the operation back.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    recorded: Vec<JournalAction>,
+}
+
+impl Journal {
+    pub fn begin(operation: &str) -> Result<Journal, String> {
+        let state_dir = get_state_dir()?;
+        fs::create_dir_all(&state_dir).map_err(|e| e.to_string())?;
+
+        let started = get_unix_time();
+        let path = state_dir
+            .join(format!("{}-{}", started, std::process::id()))
+            .with_extension(JOURNAL_EXTENSION);
+
+        let mut file = File::create(&path).map_err(|e| e.to_string())?;
+        writeln!(file, "{}\t{}", operation, started).map_err(|e| e.to_string())?;
+        file.sync_all().map_err(|e| e.to_string())?;
+
+        Ok(Journal {
+            path,
+            file,
+            recorded: Vec::new(),
+        })
+    }
+
+    pub fn record(&mut self, action: JournalAction) -> Result<(), String> {
+        writeln!(self.file, "{}", action.to_line()?).map_err(|e| e.to_string())?;
+        self.file.sync_data().map_err(|e| e.to_string())?;
+        self.recorded.push(action);
+        Ok(())
+    }
+
+    /// Backs up the file and records that it's being replaced, so it can be restored.
+    pub fn record_replace_file(&mut self, path: &Path) -> Result<(), String> {
+        let backup = self
+            .path
+            .with_extension(format!("{}.bak", self.recorded.len()));
+        fs::copy(path, &backup)
+            .map_err(|e| format!("Couldn't back up {}. {}", path.display(), e))?;
+
+        self.record(JournalAction::ReplaceFile {
+            path: path.to_path_buf(),
+            backup,
+        })
+    }
+
+    /// Marks the operation as successfully finished.
+    pub fn commit(mut self) -> Result<(), String> {
+        for action in self.recorded.drain(..) {
+            if let JournalAction::ReplaceFile { backup, .. } = action {
+                _ = fs::remove_file(backup);
+            }
+        }
+        fs::remove_file(&self.path).map_err(|e| e.to_string())
+    }
+
+    /// Undoes the recorded actions in reverse order, for an operation that failed midway.
+    ///
+    /// If undoing fails, the journal is kept, so the next run offers to roll back the rest.
+    pub fn roll_back(mut self) -> Result<(), String> {
+        while let Some(action) = self.recorded.last() {
+            action
+                .undo()
+                .map_err(|e| format!("Couldn't {} back. {}", action, e))?;
+            self.recorded.pop();
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        // Nothing to roll back if the operation stopped before changing anything
+        if self.recorded.is_empty() {
+            _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// An operation whose journal was never committed.
+#[derive(Debug)]
+pub struct InterruptedOperation {
+    path: PathBuf,
+    pub operation: String,
+    pub started: u64,
+    pub actions: Vec<JournalAction>,
+}
+
+impl InterruptedOperation {
+    fn read_from_file(path: &Path) -> Result<InterruptedOperation, String> {
+        let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let mut lines = content.lines().filter(|line| !line.is_empty());
+
+        let (operation, started) = lines
+            .next()
+            .and_then(|line| line.split_once('\t'))
+            .ok_or_else(|| format!("Invalid journal {}.", path.display()))?;
+
+        Ok(InterruptedOperation {
+            path: path.to_path_buf(),
+            operation: operation.to_string(),
+            started: started.parse().unwrap_or_default(),
+            actions: lines
+                .map(JournalAction::from_line)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn find_all() -> Result<Vec<InterruptedOperation>, String> {
+        let state_dir = get_state_dir()?;
+
+        if !state_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut operations = Vec::new();
+        for entry in fs::read_dir(&state_dir).map_err(|e| e.to_string())? {
+            let path = entry.map_err(|e| e.to_string())?.path();
+
+            if path.extension().is_some_and(|ext| ext == JOURNAL_EXTENSION) {
+                operations.push(Self::read_from_file(&path)?);
+            }
+        }
+
+        operations.sort_by_key(|operation| operation.started);
+
+        Ok(operations)
+    }
+
+    /// Seconds since the operation started.
+    pub fn get_age(&self) -> u64 {
+        get_unix_time().saturating_sub(self.started)
+    }
+
+    /// Undoes the recorded actions in reverse order and removes the journal.
+    pub fn roll_back(self) -> Result<(), String> {
+        for action in self.actions.iter().rev() {
+            action
+                .undo()
+                .map_err(|e| format!("Couldn't {} back. {}", action, e))?;
+        }
+
+        self.discard()
+    }
+
+    /// Removes the journal, keeping the changes made so far.
+    pub fn discard(self) -> Result<(), String> {
+        for action in &self.actions {
+            if let JournalAction::ReplaceFile { backup, .. } = action {
+                _ = fs::remove_file(backup);
+            }
+        }
+
+        fs::remove_file(&self.path).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_action_lines() {
+        let actions = [
+            JournalAction::CreateFile(PathBuf::from("C:\\Windows\\System32\\kbdtest.dll")),
+            JournalAction::ReplaceFile {
+                path: PathBuf::from("C:\\Windows\\System32\\kbdtest.dll"),
+                backup: PathBuf::from("C:\\ProgramData\\klc-install\\state\\1-2.0.bak"),
+            },
+            JournalAction::CreateKey(
+                "HKEY_LOCAL_MACHINE\\SYSTEM\\CurrentControlSet\\Control\\Keyboard Layouts\\f0000409"
+                    .to_string(),
+            ),
+            JournalAction::ChangeValue {
+                key: "HKEY_LOCAL_MACHINE\\SYSTEM\\CurrentControlSet\\Control\\Keyboard Layouts\\f0000409"
+                    .to_string(),
+                name: "Layout Text".to_string(),
+                previous: Some(RegistryValueData::String("US\tCustom".to_string())),
+            },
+            JournalAction::ChangeValue {
+                key: "HKEY_CURRENT_USER\\Environment".to_string(),
+                name: "Line\r\nBreak".to_string(),
+                previous: Some(RegistryValueData::ExpandString(
+                    "%USERPROFILE%\\50%25\nsecond line".to_string(),
+                )),
+            },
+            JournalAction::ChangeValue {
+                key: "HKEY_CURRENT_USER\\Keyboard Layout\\Preload".to_string(),
+                name: "1".to_string(),
+                previous: None,
+            },
+        ];
+
+        for action in actions {
+            let line = action.to_line().unwrap();
+            assert!(!line.contains(['\n', '\r']));
+            assert_eq!(JournalAction::from_line(&line), Ok(action));
+        }
+
+        assert!(JournalAction::from_line("delete-everything\tC:\\").is_err());
+        assert_eq!(
+            JournalAction::from_line("create-file\tC:\\%TEMP%\\a%0Ab.dll"),
+            Ok(JournalAction::CreateFile(PathBuf::from(
+                "C:\\%TEMP%\\a\nb.dll"
+            )))
+        );
+    }
+}
@@ -0,0 +1,323 @@
+//! The JSON documents printed by the commands with JSON output and returned by the C API.
+//!
+//! Scripts rely on these shapes, so fields are only ever added. Renaming or removing a field,
+//! or changing what it holds, must bump `SCHEMA_VERSION`, which every document starts with.
+
+use std::time::UNIX_EPOCH;
+
+use serde::Serialize;
+
+use crate::{
+    control_sets::LayoutDifference,
+    klid::{Klid, KlidKind},
+    layout_store::InstalledLayout,
+    selftest::{SelftestReport, SelftestStep},
+};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// An installed layout, in the layout list and the responses of the C API.
+#[derive(Debug, Serialize)]
+pub struct LayoutJson {
+    pub klid: String,
+    /// 4-digit hexadecimal, like in the registry.
+    pub layout_id: Option<String>,
+    pub layout_file: Option<String>,
+    pub layout_text: Option<String>,
+    pub display_name: Option<String>,
+    pub version: Option<String>,
+    pub tags: Vec<String>,
+    /// Unix time in seconds.
+    pub expiry: Option<u64>,
+    pub source: Option<String>,
+    pub sha256: Option<String>,
+}
+
+impl From<InstalledLayout> for LayoutJson {
+    fn from(layout: InstalledLayout) -> Self {
+        LayoutJson {
+            klid: layout.get_key_name(),
+            layout_id: layout.layout_id.map(|id| format!("{:04X}", id)),
+            layout_file: layout.layout_file,
+            layout_text: layout.layout_text,
+            display_name: layout.display_name,
+            version: layout.version,
+            tags: layout.tags,
+            expiry: layout.expiry.and_then(|expiry| {
+                expiry
+                    .duration_since(UNIX_EPOCH)
+                    .ok()
+                    .map(|since| since.as_secs())
+            }),
+            source: layout.provenance.as_ref().map(|p| p.source.clone()),
+            sha256: layout.provenance.map(|p| p.sha256),
+        }
+    }
+}
+
+/// Printed by `list --format json`.
+#[derive(Debug, Serialize)]
+pub struct LayoutListJson {
+    pub schema_version: u32,
+    pub layouts: Vec<LayoutJson>,
+}
+
+impl LayoutListJson {
+    pub fn new(layouts: Vec<InstalledLayout>) -> Self {
+        LayoutListJson {
+            schema_version: SCHEMA_VERSION,
+            layouts: layouts.into_iter().map(LayoutJson::from).collect(),
+        }
+    }
+}
+
+/// Printed by `explain-klid --json`.
+#[derive(Debug, Serialize)]
+pub struct KlidInfoJson {
+    pub schema_version: u32,
+    pub klid: String,
+    /// 4-digit hexadecimal LCID.
+    pub language: String,
+    pub language_name: Option<String>,
+    /// 4-digit hexadecimal.
+    pub device: String,
+    /// `system`, `system_variant`, `ime` or `custom`.
+    pub kind: &'static str,
+    /// The layout registered under the KLID on this machine, if any.
+    pub layout: Option<LayoutJson>,
+}
+
+impl KlidInfoJson {
+    pub fn new(klid: Klid, language_name: Option<String>, layout: Option<InstalledLayout>) -> Self {
+        KlidInfoJson {
+            schema_version: SCHEMA_VERSION,
+            klid: klid.to_string(),
+            language: format!("{:04X}", klid.language),
+            language_name,
+            device: format!("{:04X}", klid.device),
+            kind: match klid.get_kind() {
+                KlidKind::System => "system",
+                KlidKind::SystemVariant(_) => "system_variant",
+                KlidKind::Ime => "ime",
+                KlidKind::Custom => "custom",
+            },
+            layout: layout.map(LayoutJson::from),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SelftestStepJson {
+    pub step: &'static str,
+    /// `pass`, `fail` or `skip`.
+    pub status: &'static str,
+    /// What the step found, or why it failed. Null for skipped steps.
+    pub detail: Option<String>,
+}
+
+/// Printed by `selftest --json`.
+#[derive(Debug, Serialize)]
+pub struct SelftestJson {
+    pub schema_version: u32,
+    pub passed: bool,
+    /// Every step in order, including the ones that didn't run.
+    pub steps: Vec<SelftestStepJson>,
+}
+
+impl SelftestJson {
+    pub fn new(report: &SelftestReport) -> Self {
+        let steps = SelftestStep::ALL
+            .into_iter()
+            .map(|step| {
+                let (status, detail) = match report.results.iter().find(|(s, _)| *s == step) {
+                    Some((_, Ok(detail))) => ("pass", Some(detail.clone())),
+                    Some((_, Err(e))) => ("fail", Some(e.clone())),
+                    None => ("skip", None),
+                };
+                SelftestStepJson {
+                    step: step.get_name(),
+                    status,
+                    detail,
+                }
+            })
+            .collect();
+
+        SelftestJson {
+            schema_version: SCHEMA_VERSION,
+            passed: report.passed(),
+            steps,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct LayoutDifferenceJson {
+    /// KLID of the layout.
+    pub klid: String,
+    /// `missing` from the other control set, `extra` in it, or a different `value`.
+    pub difference: &'static str,
+    /// Name of the differing value.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+}
+
+impl From<&LayoutDifference> for LayoutDifferenceJson {
+    fn from(difference: &LayoutDifference) -> Self {
+        let (klid, kind, value) = match difference {
+            LayoutDifference::Missing(key) => (key, "missing", None),
+            LayoutDifference::Extra(key) => (key, "extra", None),
+            LayoutDifference::Value { key, name } => (key, "value", Some(name.clone())),
+        };
+        LayoutDifferenceJson {
+            klid: klid.clone(),
+            difference: kind,
+            value,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ControlSetJson {
+    pub name: String,
+    /// Empty if the control set matches the current one.
+    pub differences: Vec<LayoutDifferenceJson>,
+}
+
+/// Printed by `control-sets --json`.
+#[derive(Debug, Serialize)]
+pub struct ControlSetsJson {
+    pub schema_version: u32,
+    pub current: String,
+    /// Number of custom layouts in the current control set.
+    pub layout_count: usize,
+    pub control_sets: Vec<ControlSetJson>,
+}
+
+/// A response of the C API.
+#[derive(Debug, Serialize)]
+pub struct ResponseJson {
+    pub schema_version: u32,
+    pub ok: bool,
+    pub exit_code: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layouts: Option<Vec<LayoutJson>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    use crate::layout_provenance::Provenance;
+
+    fn layout() -> InstalledLayout {
+        InstalledLayout {
+            klid: Klid::parse("f0010415").unwrap(),
+            layout_id: Some(0x0f01),
+            layout_file: Some("kbdmine.dll".to_string()),
+            layout_text: Some("Polish (Mine)".to_string()),
+            display_name: None,
+            version: Some("1.2".to_string()),
+            tags: vec!["work".to_string()],
+            expiry: Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            provenance: Some(Provenance {
+                source: "kbdmine.klc".to_string(),
+                sha256: "ab12".to_string(),
+                tool_version: "1.0.0".to_string(),
+                installed: UNIX_EPOCH,
+            }),
+        }
+    }
+
+    // Changing these snapshots breaks scripts, see the module documentation
+    #[test]
+    fn test_layout_list_json() {
+        assert_eq!(
+            serde_json::to_string(&LayoutListJson::new(vec![layout()])).unwrap(),
+            concat!(
+                r#"{"schema_version":1,"layouts":[{"klid":"f0010415","layout_id":"0F01","#,
+                r#""layout_file":"kbdmine.dll","layout_text":"Polish (Mine)","display_name":null,"#,
+                r#""version":"1.2","tags":["work"],"expiry":1700000000,"source":"kbdmine.klc","#,
+                r#""sha256":"ab12"}]}"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_klid_info_json() {
+        let info = KlidInfoJson::new(Klid::parse("00010409").unwrap(), None, None);
+        assert_eq!(
+            serde_json::to_string(&info).unwrap(),
+            concat!(
+                r#"{"schema_version":1,"klid":"00010409","language":"0409","language_name":null,"#,
+                r#""device":"0001","kind":"system_variant","layout":null}"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_selftest_json() {
+        let report = SelftestReport {
+            results: vec![
+                (SelftestStep::FindMsklc, Ok("C:\\MSKLC".to_string())),
+                (SelftestStep::Registry, Err("Access denied.".to_string())),
+            ],
+        };
+        let json = serde_json::to_string(&SelftestJson::new(&report)).unwrap();
+        assert!(json.starts_with(concat!(
+            r#"{"schema_version":1,"passed":false,"steps":["#,
+            r#"{"step":"find_msklc","status":"pass","detail":"C:\\MSKLC"},"#,
+            r#"{"step":"registry","status":"fail","detail":"Access denied."},"#,
+            r#"{"step":"parse","status":"skip","detail":null},"#
+        )));
+    }
+
+    #[test]
+    fn test_control_sets_json() {
+        let control_sets = ControlSetsJson {
+            schema_version: SCHEMA_VERSION,
+            current: "ControlSet001".to_string(),
+            layout_count: 2,
+            control_sets: vec![ControlSetJson {
+                name: "ControlSet002".to_string(),
+                differences: [
+                    LayoutDifference::Missing("f0010415".to_string()),
+                    LayoutDifference::Value {
+                        key: "f0000415".to_string(),
+                        name: "Layout File".to_string(),
+                    },
+                ]
+                .iter()
+                .map(LayoutDifferenceJson::from)
+                .collect(),
+            }],
+        };
+        assert_eq!(
+            serde_json::to_string(&control_sets).unwrap(),
+            concat!(
+                r#"{"schema_version":1,"current":"ControlSet001","layout_count":2,"#,
+                r#""control_sets":[{"name":"ControlSet002","differences":["#,
+                r#"{"klid":"f0010415","difference":"missing"},"#,
+                r#"{"klid":"f0000415","difference":"value","value":"Layout File"}]}]}"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_response_json() {
+        let response = ResponseJson {
+            schema_version: SCHEMA_VERSION,
+            ok: false,
+            exit_code: 4,
+            layouts: None,
+            error: Some("The layout f0010415 isn't installed.".to_string()),
+        };
+        assert_eq!(
+            serde_json::to_string(&response).unwrap(),
+            r#"{"schema_version":1,"ok":false,"exit_code":4,"error":"The layout f0010415 isn't installed."}"#
+        );
+    }
+}
@@ -0,0 +1,425 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use crate::utils::{ReadUtf16Line, ReadUtf16LineError, StringExt};
+
+mod import;
+mod include;
+mod stats;
+mod template;
+
+pub use import::*;
+pub use include::*;
+pub use stats::*;
+pub use template::*;
+
+/// Errors reading the header of a KLC file.
+#[derive(Debug, Error)]
+pub enum KlcError {
+    #[error("Couldn't open {}.", path.display())]
+    Open {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Couldn't read the KLC file.")]
+    Read(#[from] ReadUtf16LineError),
+    #[error("Invalid {keyword} line in the KLC file: {line}")]
+    InvalidLine { keyword: &'static str, line: String },
+    #[error("The KLC file has no {0} line.")]
+    MissingKeyword(&'static str),
+    #[error("Invalid LOCALEID {0} in the KLC file.")]
+    InvalidLocaleId(String),
+    #[error("{0}")]
+    Other(String),
+}
+
+impl KlcError {
+    pub fn get_hint(&self) -> Option<&'static str> {
+        match self {
+            KlcError::InvalidLine { .. } | KlcError::MissingKeyword(_) => {
+                Some("Open and save the layout in MSKLC, which writes a valid header.")
+            }
+            KlcError::Read(ReadUtf16LineError::Utf16(_)) => {
+                Some("KBDUTOOL only reads UTF-16 KLC files. Save the file as UTF-16 LE.")
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The rest of the tool reports the errors of the KLC sections as text.
+impl From<String> for KlcError {
+    fn from(message: String) -> Self {
+        KlcError::Other(message)
+    }
+}
+
+/// Strips the quotes around a KLC value, like `"Polish (Programmers)"`.
+fn unquote(value: &str) -> Option<&str> {
+    value.strip_prefix('"')?.strip_suffix('"')
+}
+
+pub struct KlcInfo {
+    pub layout_name: String,
+    pub layout_text: String,
+    pub locale_id: u16,
+    /// Language tag from `LOCALENAME`, needed for languages without their own LCID.
+    pub locale_name: Option<String>,
+    pub company: Option<String>,
+    pub copyright: Option<String>,
+    pub version: Option<String>,
+    pub names: KlcNames,
+}
+
+impl KlcInfo {
+    pub fn new(layout_name: String, layout_text: String, locale_id: u16) -> Self {
+        Self {
+            layout_name,
+            layout_text,
+            locale_id,
+            locale_name: None,
+            company: None,
+            copyright: None,
+            version: None,
+            names: KlcNames::default(),
+        }
+    }
+
+    pub fn read_from_file(file_path: &Path) -> Result<KlcInfo, KlcError> {
+        let file = std::fs::File::open(file_path).map_err(|source| KlcError::Open {
+            path: file_path.to_path_buf(),
+            source,
+        })?;
+        let reader = std::io::BufReader::new(file);
+
+        let names = KlcNames::from_document(&KlcDocument::read_from_file(file_path)?)?;
+
+        Ok(Self {
+            names,
+            ..Self::parse_header(reader.utf16_lines())?
+        })
+    }
+
+    /// Parses the values before the key definitions, without the names of the later sections.
+    fn parse_header(
+        lines: impl Iterator<Item = Result<String, ReadUtf16LineError>>,
+    ) -> Result<KlcInfo, KlcError> {
+        let mut layout_name = None;
+        let mut layout_text = None;
+        let mut locale_id_str = None;
+        let mut locale_name = None;
+        let mut company = None;
+        let mut copyright = None;
+        let mut version = None;
+        // The header ends where the key definitions start
+        for line in lines {
+            let mut line = line?;
+
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.remove_prefix("KBD\t") {
+                let Some((key, name)) = line
+                    .split_once('\t')
+                    .and_then(|(key, name)| Some((key, unquote(name)?)))
+                else {
+                    return Err(KlcError::InvalidLine {
+                        keyword: "KBD",
+                        line,
+                    });
+                };
+                layout_name = Some(key.to_string());
+                layout_text = Some(name.to_string());
+            } else if line.remove_prefix("LOCALEID\t") {
+                let Some(locale_id) = unquote(&line) else {
+                    return Err(KlcError::InvalidLine {
+                        keyword: "LOCALEID",
+                        line,
+                    });
+                };
+                locale_id_str = Some(locale_id.to_string());
+            } else if line.remove_prefix("LOCALENAME\t") {
+                locale_name = Some(line.trim_matches('"').to_string());
+            } else if line.remove_prefix("COMPANY\t") {
+                company = Some(line.trim_matches('"').to_string());
+            } else if line.remove_prefix("COPYRIGHT\t") {
+                copyright = Some(line.trim_matches('"').to_string());
+            } else if line.remove_prefix("VERSION\t") {
+                version = Some(line.trim().to_string());
+            } else if line.starts_with("SHIFTSTATE") {
+                break;
+            }
+        }
+
+        let (Some(layout_name), Some(layout_text)) = (layout_name, layout_text) else {
+            return Err(KlcError::MissingKeyword("KBD"));
+        };
+        let Some(locale_id_str) = locale_id_str else {
+            return Err(KlcError::MissingKeyword("LOCALEID"));
+        };
+
+        let locale_id = u16::from_str_radix(&locale_id_str, 16)
+            .map_err(|_| KlcError::InvalidLocaleId(locale_id_str))?;
+
+        Ok(Self {
+            locale_name,
+            company,
+            copyright,
+            version,
+            ..Self::new(layout_name, layout_text, locale_id)
+        })
+    }
+}
+
+/// Localized names from the `DESCRIPTIONS` and `LANGUAGENAMES` sections of a KLC file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KlcNames {
+    /// Descriptions of the layout by language ID.
+    pub descriptions: Vec<(u16, String)>,
+    /// Names of the layout's language by language ID.
+    pub language_names: Vec<(u16, String)>,
+}
+
+impl KlcNames {
+    pub fn from_document(document: &KlcDocument) -> Result<KlcNames, String> {
+        let mut names = KlcNames::default();
+
+        for section in &document.sections {
+            let target = match section.get_keyword() {
+                "DESCRIPTIONS" => &mut names.descriptions,
+                "LANGUAGENAMES" => &mut names.language_names,
+                _ => continue,
+            };
+
+            for row in &section.rows {
+                let row = strip_klc_comment(row).trim();
+                if row.is_empty() {
+                    continue;
+                }
+
+                let (language, name) = row
+                    .split_once(char::is_whitespace)
+                    .ok_or_else(|| format!("Invalid row in {}: {}", section.get_keyword(), row))?;
+                let language = u16::from_str_radix(language, 16).map_err(|e| e.to_string())?;
+
+                target.push((language, name.trim().to_string()));
+            }
+        }
+
+        Ok(names)
+    }
+
+    fn find(names: &[(u16, String)], language: u16) -> Option<&str> {
+        names
+            .iter()
+            .find(|(l, _)| *l == language)
+            .map(|(_, name)| name.as_str())
+    }
+
+    pub fn get_description(&self, language: u16) -> Option<&str> {
+        Self::find(&self.descriptions, language)
+    }
+
+    pub fn get_language_name(&self, language: u16) -> Option<&str> {
+        Self::find(&self.language_names, language)
+    }
+}
+
+/// A dead key defined in a `DEADKEY` section of a KLC file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeadKey {
+    pub dead_char: char,
+    /// Pairs of base characters and the characters they compose into.
+    pub compositions: Vec<(char, char)>,
+}
+
+impl DeadKey {
+    pub fn new(dead_char: char) -> Self {
+        Self {
+            dead_char,
+            compositions: Vec::new(),
+        }
+    }
+
+    /// Returns the character produced by typing the dead key followed by `base`.
+    pub fn compose(&self, base: char) -> Option<char> {
+        self.compositions
+            .iter()
+            .find(|(b, _)| *b == base)
+            .map(|(_, composed)| *composed)
+    }
+
+    /// Parses all `DEADKEY` sections from the lines of a KLC file.
+    pub fn parse<'a>(lines: impl Iterator<Item = &'a str>) -> Result<Vec<DeadKey>, String> {
+        let mut dead_keys = Vec::new();
+        let mut current: Option<DeadKey> = None;
+
+        for line in lines {
+            let line = strip_klc_comment(line);
+            let mut fields = line.split_whitespace();
+
+            let Some(first) = fields.next() else {
+                continue;
+            };
+
+            if first == "DEADKEY" {
+                if let Some(dead_key) = current.take() {
+                    dead_keys.push(dead_key);
+                }
+
+                let dead_char = fields
+                    .next()
+                    .ok_or_else(|| "DEADKEY section without a character.".to_string())?;
+                current = Some(DeadKey::new(parse_klc_char(dead_char)?));
+                continue;
+            }
+
+            let Some(dead_key) = current.as_mut() else {
+                continue;
+            };
+
+            // Any other keyword ends the dead key section
+            let Ok(base) = parse_klc_char(first) else {
+                dead_keys.push(current.take().unwrap());
+                continue;
+            };
+
+            let composed = fields
+                .next()
+                .ok_or_else(|| format!("Missing composed character for {} in DEADKEY.", first))?;
+            dead_key
+                .compositions
+                .push((base, parse_klc_char(composed)?));
+        }
+
+        if let Some(dead_key) = current {
+            dead_keys.push(dead_key);
+        }
+
+        Ok(dead_keys)
+    }
+}
+
+/// Removes a trailing `//` comment from a KLC line.
+pub fn strip_klc_comment(line: &str) -> &str {
+    match line.find("//") {
+        Some(index) => &line[..index],
+        None => line,
+    }
+}
+
+/// Parses a character in the KLC notation, which is either a 4-digit hex
+/// code point (optionally followed by `@` for dead keys) or a literal character.
+pub fn parse_klc_char(str: &str) -> Result<char, String> {
+    let hex = str.strip_suffix('@').unwrap_or(str);
+
+    if hex.len() >= 4 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        let code = u32::from_str_radix(hex, 16).map_err(|e| e.to_string())?;
+        return char::from_u32(code).ok_or_else(|| format!("Invalid code point {}.", hex));
+    }
+
+    let mut chars = hex.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(format!("Invalid KLC character {}.", str)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_klc_char() {
+        assert_eq!(parse_klc_char("0061"), Ok('a'));
+        assert_eq!(parse_klc_char("005e@"), Ok('^'));
+        assert_eq!(parse_klc_char("a"), Ok('a'));
+        assert!(parse_klc_char("-1").is_err());
+        assert!(parse_klc_char("KEYNAME").is_err());
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let parse =
+            |lines: &[&str]| KlcInfo::parse_header(lines.iter().map(|line| Ok(line.to_string())));
+
+        let klc_info = parse(&[
+            "KBD\tmultilin\t\"Multilin\"",
+            "LOCALEID\t\"00000415\"",
+            "VERSION\t1.2",
+            "SHIFTSTATE",
+        ])
+        .unwrap();
+        assert_eq!(klc_info.layout_name, "multilin");
+        assert_eq!(klc_info.layout_text, "Multilin");
+        assert_eq!(klc_info.locale_id, 0x0415);
+        assert_eq!(klc_info.version.as_deref(), Some("1.2"));
+
+        assert!(matches!(
+            parse(&["KBD\tmultilin\t\"Multilin\""]),
+            Err(KlcError::MissingKeyword("LOCALEID"))
+        ));
+        assert!(matches!(
+            parse(&["KBD\tmultilin\t\"", "LOCALEID\t\"00000415\""]),
+            Err(KlcError::InvalidLine { keyword: "KBD", .. })
+        ));
+        assert!(matches!(
+            parse(&["KBD\tmultilin\t\"Multilin\"", "LOCALEID\t\"pl\""]),
+            Err(KlcError::InvalidLocaleId(_))
+        ));
+    }
+
+    #[test]
+    fn test_klc_names() {
+        let klc = [
+            "KBD\tmultilin\t\"Multilin\"",
+            "DESCRIPTIONS",
+            "0409\tMultilingual",
+            "0415\tWieloj\u{119}zyczny // Polish",
+            "LANGUAGENAMES",
+            "0409\tEnglish (United States)",
+            "ENDKBD",
+        ];
+
+        let names = KlcNames::from_document(&KlcDocument::parse(klc.into_iter())).unwrap();
+        assert_eq!(names.get_description(0x0409), Some("Multilingual"));
+        assert_eq!(names.get_description(0x0415), Some("Wieloj\u{119}zyczny"));
+        assert_eq!(names.get_description(0x0407), None);
+        assert_eq!(
+            names.get_language_name(0x0409),
+            Some("English (United States)")
+        );
+    }
+
+    #[test]
+    fn test_parse_dead_keys() {
+        let klc = [
+            "DEADKEY\t005e",
+            "",
+            "0061\t00e2\t// a -> \u{e2}",
+            "0041\t00c2\t// A -> \u{c2}",
+            "",
+            "DEADKEY\t00b4",
+            "0065\t00e9",
+            "",
+            "KEYNAME",
+            "",
+            "01\tEsc",
+        ];
+
+        let dead_keys = DeadKey::parse(klc.into_iter()).unwrap();
+        assert_eq!(dead_keys.len(), 2);
+        assert_eq!(dead_keys[0].dead_char, '^');
+        assert_eq!(dead_keys[0].compose('a'), Some('\u{e2}'));
+        assert_eq!(dead_keys[0].compose('A'), Some('\u{c2}'));
+        assert_eq!(dead_keys[0].compose('e'), None);
+        assert_eq!(dead_keys[1].dead_char, '\u{b4}');
+        assert_eq!(dead_keys[1].compose('e'), Some('\u{e9}'));
+    }
+}
@@ -0,0 +1,281 @@
+use std::{collections::BTreeMap, path::Path};
+
+use super::{DeadKey, KlcDocument};
+
+mod keylayout;
+mod xkb;
+
+pub use keylayout::*;
+pub use xkb::*;
+
+/// Shift states of imported layouts: none, Shift, AltGr (Ctrl+Alt) and Shift+AltGr.
+pub const IMPORTED_SHIFT_STATES: [u8; 4] = [0, 1, 6, 7];
+
+/// Scancodes of the character keys and their virtual keys on a US keyboard.
+const KEY_POSITIONS: [(u8, &str); 49] = [
+    (0x02, "1"),
+    (0x03, "2"),
+    (0x04, "3"),
+    (0x05, "4"),
+    (0x06, "5"),
+    (0x07, "6"),
+    (0x08, "7"),
+    (0x09, "8"),
+    (0x0a, "9"),
+    (0x0b, "0"),
+    (0x0c, "OEM_MINUS"),
+    (0x0d, "OEM_PLUS"),
+    (0x10, "Q"),
+    (0x11, "W"),
+    (0x12, "E"),
+    (0x13, "R"),
+    (0x14, "T"),
+    (0x15, "Y"),
+    (0x16, "U"),
+    (0x17, "I"),
+    (0x18, "O"),
+    (0x19, "P"),
+    (0x1a, "OEM_4"),
+    (0x1b, "OEM_6"),
+    (0x1e, "A"),
+    (0x1f, "S"),
+    (0x20, "D"),
+    (0x21, "F"),
+    (0x22, "G"),
+    (0x23, "H"),
+    (0x24, "J"),
+    (0x25, "K"),
+    (0x26, "L"),
+    (0x27, "OEM_1"),
+    (0x28, "OEM_7"),
+    (0x29, "OEM_3"),
+    (0x2b, "OEM_5"),
+    (0x2c, "Z"),
+    (0x2d, "X"),
+    (0x2e, "C"),
+    (0x2f, "V"),
+    (0x30, "B"),
+    (0x31, "N"),
+    (0x32, "M"),
+    (0x33, "OEM_COMMA"),
+    (0x34, "OEM_PERIOD"),
+    (0x35, "OEM_2"),
+    (0x39, "SPACE"),
+    (0x56, "OEM_102"),
+];
+
+fn get_virtual_key(scancode: u8) -> Option<&'static str> {
+    KEY_POSITIONS
+        .iter()
+        .find(|(sc, _)| *sc == scancode)
+        .map(|(_, vk)| *vk)
+}
+
+/// What a key types in a shift state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyOutput {
+    /// One or more characters. More than one becomes a ligature.
+    Chars(String),
+    /// A dead key, showing the character.
+    DeadKey(char),
+}
+
+/// A layout converted from another system, before it's written as a KLC file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportedLayout {
+    /// Name of the layout DLL, up to 8 characters.
+    pub name: String,
+    pub description: String,
+    pub locale_id: u16,
+    /// Outputs of the keys by scancode, one for each of `IMPORTED_SHIFT_STATES`.
+    pub keys: BTreeMap<u8, [Option<KeyOutput>; 4]>,
+    pub dead_keys: Vec<DeadKey>,
+}
+
+/// Makes a layout name KBDUTOOL accepts from the name of the imported file.
+pub fn get_imported_layout_name(file_path: &Path) -> String {
+    let name: String = file_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .take(8)
+        .collect::<String>()
+        .to_ascii_lowercase();
+
+    if name.is_empty() {
+        "imported".to_string()
+    } else {
+        name
+    }
+}
+
+/// Formats the character like MSKLC does: letters and digits as they are, others as hex.
+fn format_klc_char(c: char) -> String {
+    if c.is_ascii_alphanumeric() {
+        c.to_string()
+    } else {
+        format!("{:04x}", c as u32)
+    }
+}
+
+impl ImportedLayout {
+    pub fn new(name: String, description: String) -> Self {
+        Self {
+            name,
+            description,
+            locale_id: 0x0409,
+            keys: BTreeMap::new(),
+            dead_keys: Vec::new(),
+        }
+    }
+
+    /// Sets the output of the key, ignoring keys that aren't character keys.
+    pub fn set_key(&mut self, scancode: u8, column: usize, output: KeyOutput) {
+        if get_virtual_key(scancode).is_some() {
+            self.keys.entry(scancode).or_default()[column] = Some(output);
+        }
+    }
+
+    pub fn to_lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!(
+                "KBD\t{}\t\"{}\"",
+                self.name,
+                self.description.replace('"', "'")
+            ),
+            "COPYRIGHT\t\"(c)\"".to_string(),
+            "COMPANY\t\"klc-install\"".to_string(),
+            format!("LOCALEID\t\"{:08x}\"", self.locale_id),
+            "VERSION\t1.0".to_string(),
+            "SHIFTSTATE".to_string(),
+        ];
+        lines.extend(IMPORTED_SHIFT_STATES.iter().map(|state| state.to_string()));
+
+        lines.push("LAYOUT".to_string());
+        let mut ligatures = Vec::new();
+        for (scancode, outputs) in &self.keys {
+            let vk = get_virtual_key(*scancode).unwrap();
+            let mut row = format!("{:02x}\t{}\t{}", scancode, vk, self.get_cap(outputs));
+
+            for (column, output) in outputs.iter().enumerate() {
+                let cell = match output {
+                    Some(KeyOutput::Chars(chars)) if chars.chars().count() == 1 => {
+                        format_klc_char(chars.chars().next().unwrap())
+                    }
+                    Some(KeyOutput::Chars(chars)) => {
+                        let chars = chars.chars().map(|c| format!("{:04x}", c as u32));
+                        ligatures.push(format!(
+                            "{}\t{}\t{}",
+                            vk,
+                            column,
+                            chars.collect::<Vec<_>>().join("\t")
+                        ));
+                        "%%".to_string()
+                    }
+                    Some(KeyOutput::DeadKey(c)) => format!("{:04x}@", *c as u32),
+                    None => "-1".to_string(),
+                };
+                row.push('\t');
+                row.push_str(&cell);
+            }
+
+            lines.push(row);
+        }
+
+        if !ligatures.is_empty() {
+            lines.push("LIGATURE".to_string());
+            lines.extend(ligatures);
+        }
+
+        for dead_key in &self.dead_keys {
+            lines.push(format!("DEADKEY\t{:04x}", dead_key.dead_char as u32));
+            for (base, composed) in &dead_key.compositions {
+                lines.push(format!("{:04x}\t{:04x}", *base as u32, *composed as u32));
+            }
+        }
+
+        lines.push("DESCRIPTIONS".to_string());
+        lines.push(format!("{:04x}\t{}", self.locale_id, self.description));
+        lines.push("ENDKBD".to_string());
+
+        lines
+    }
+
+    /// Whether Caps Lock shifts the key: its shifted output is the uppercase of its base one.
+    fn get_cap(&self, outputs: &[Option<KeyOutput>; 4]) -> u8 {
+        match (&outputs[0], &outputs[1]) {
+            (Some(KeyOutput::Chars(base)), Some(KeyOutput::Chars(shifted)))
+                if base.chars().all(char::is_lowercase) && base.to_uppercase() == *shifted =>
+            {
+                1
+            }
+            _ => 0,
+        }
+    }
+
+    /// Writes the layout as a KLC file, like MSKLC does.
+    pub fn write_to_file(&self, file_path: &Path) -> Result<(), String> {
+        let lines = self.to_lines();
+        KlcDocument::parse(lines.iter().map(|line| line.as_str())).write_to_file(file_path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_imported_layout_to_lines() {
+        let mut layout = ImportedLayout::new("test".to_string(), "Test".to_string());
+        layout.set_key(0x10, 0, KeyOutput::Chars("q".to_string()));
+        layout.set_key(0x10, 1, KeyOutput::Chars("Q".to_string()));
+        layout.set_key(0x10, 2, KeyOutput::DeadKey('^'));
+        layout.set_key(0x02, 3, KeyOutput::Chars("ij".to_string()));
+        // Not a character key
+        layout.set_key(0x1c, 0, KeyOutput::Chars("\r".to_string()));
+
+        let mut dead_key = DeadKey::new('^');
+        dead_key.compositions.push(('a', 'â'));
+        layout.dead_keys.push(dead_key);
+
+        let lines = layout.to_lines();
+        let document = KlcDocument::parse(lines.iter().map(|line| line.as_str()));
+        let keywords: Vec<&str> = document.sections.iter().map(|s| s.get_keyword()).collect();
+        assert_eq!(
+            keywords,
+            [
+                "KBD",
+                "COPYRIGHT",
+                "COMPANY",
+                "LOCALEID",
+                "VERSION",
+                "SHIFTSTATE",
+                "LAYOUT",
+                "LIGATURE",
+                "DEADKEY",
+                "DESCRIPTIONS",
+                "ENDKBD"
+            ]
+        );
+        assert_eq!(
+            document.sections[6].rows,
+            ["02\t1\t0\t-1\t-1\t-1\t%%", "10\tQ\t1\tq\tQ\t005e@\t-1"]
+        );
+        assert_eq!(document.sections[7].rows, ["1\t3\t0069\t006a"]);
+        assert_eq!(document.sections[8].rows, ["0061\t00e2"]);
+    }
+
+    #[test]
+    fn test_get_imported_layout_name() {
+        assert_eq!(
+            get_imported_layout_name(Path::new("Polish Pro-2.keylayout")),
+            "polishpr"
+        );
+        assert_eq!(
+            get_imported_layout_name(Path::new("---.keylayout")),
+            "imported"
+        );
+    }
+}
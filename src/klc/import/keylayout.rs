@@ -0,0 +1,571 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use super::{get_imported_layout_name, ImportedLayout, KeyOutput};
+use crate::klc::DeadKey;
+
+/// Scancodes of the macOS virtual key codes of the character keys.
+///
+/// The key left of 1 and the key right of the left Shift are swapped on ISO Macs;
+/// this uses the ANSI positions, like macOS does for the codes.
+const MAC_KEY_CODES: [(u16, u8); 49] = [
+    (0, 0x1e),
+    (1, 0x1f),
+    (2, 0x20),
+    (3, 0x21),
+    (4, 0x23),
+    (5, 0x22),
+    (6, 0x2c),
+    (7, 0x2d),
+    (8, 0x2e),
+    (9, 0x2f),
+    (10, 0x56),
+    (11, 0x30),
+    (12, 0x10),
+    (13, 0x11),
+    (14, 0x12),
+    (15, 0x13),
+    (16, 0x15),
+    (17, 0x14),
+    (18, 0x02),
+    (19, 0x03),
+    (20, 0x04),
+    (21, 0x05),
+    (22, 0x07),
+    (23, 0x06),
+    (24, 0x0d),
+    (25, 0x0a),
+    (26, 0x08),
+    (27, 0x0c),
+    (28, 0x09),
+    (29, 0x0b),
+    (30, 0x1b),
+    (31, 0x18),
+    (32, 0x16),
+    (33, 0x1a),
+    (34, 0x17),
+    (35, 0x19),
+    (37, 0x26),
+    (38, 0x24),
+    (39, 0x28),
+    (40, 0x25),
+    (41, 0x27),
+    (42, 0x2b),
+    (43, 0x33),
+    (44, 0x35),
+    (45, 0x31),
+    (46, 0x32),
+    (47, 0x34),
+    (49, 0x39),
+    (50, 0x29),
+];
+
+/// Shift and Option of the imported shift states.
+const SHIFT_STATE_MODIFIERS: [(bool, bool); 4] =
+    [(false, false), (true, false), (false, true), (true, true)];
+
+/// An element of the XML file, with its attributes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct XmlElement {
+    name: String,
+    attributes: HashMap<String, String>,
+    /// Names of the elements it's nested in, from the root.
+    parents: Vec<String>,
+}
+
+impl XmlElement {
+    fn get(&self, attribute: &str) -> Option<&str> {
+        self.attributes.get(attribute).map(|value| value.as_str())
+    }
+}
+
+/// Replaces the entities and character references of an attribute value.
+fn unescape_xml(value: &str) -> Result<String, String> {
+    let mut unescaped = String::new();
+    let mut rest = value;
+
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find(';')
+            .ok_or_else(|| format!("Unterminated entity in {}.", value))?;
+        let entity = &rest[start + 1..start + end];
+
+        let c = match entity {
+            "amp" => '&',
+            "lt" => '<',
+            "gt" => '>',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => {
+                let code = match entity.strip_prefix("#x").or(entity.strip_prefix("#X")) {
+                    Some(hex) => u32::from_str_radix(hex, 16),
+                    None => entity.trim_start_matches('#').parse(),
+                };
+                code.ok()
+                    .filter(|_| entity.starts_with('#'))
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| format!("Unknown entity &{};.", entity))?
+            }
+        };
+        unescaped.push(c);
+        rest = &rest[start + end + 1..];
+    }
+
+    unescaped.push_str(rest);
+    Ok(unescaped)
+}
+
+/// Reads the elements of the XML file in order. Text content isn't used by .keylayout files.
+fn parse_xml_elements(xml: &str) -> Result<Vec<XmlElement>, String> {
+    let mut elements = Vec::new();
+    let mut parents: Vec<String> = Vec::new();
+    let mut rest = xml;
+
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+
+        // Comments, declarations and the DOCTYPE
+        let skipped_end = if rest.starts_with("<!--") {
+            Some(rest.find("-->").map(|end| end + 3))
+        } else if rest.starts_with("<?") || rest.starts_with("<!") {
+            Some(rest.find('>').map(|end| end + 1))
+        } else {
+            None
+        };
+        if let Some(end) = skipped_end {
+            let end = end.ok_or_else(|| "Unterminated XML declaration.".to_string())?;
+            rest = &rest[end..];
+            continue;
+        }
+
+        let tag_end = find_tag_end(rest).ok_or_else(|| "Unterminated XML tag.".to_string())?;
+        let tag = &rest[1..tag_end];
+        rest = &rest[tag_end + 1..];
+
+        if let Some(name) = tag.strip_prefix('/') {
+            let name = name.trim();
+            match parents.pop() {
+                Some(open) if open == name => {}
+                _ => return Err(format!("Unexpected closing tag </{}>.", name)),
+            }
+            continue;
+        }
+
+        let self_closing = tag.ends_with('/');
+        let tag = tag.trim_end_matches('/');
+        let (name, mut attributes_str) = match tag.find(char::is_whitespace) {
+            Some(index) => (&tag[..index], &tag[index..]),
+            None => (tag, ""),
+        };
+
+        let mut attributes = HashMap::new();
+        loop {
+            attributes_str = attributes_str.trim_start();
+            let Some(eq) = attributes_str.find('=') else {
+                break;
+            };
+            let attribute = attributes_str[..eq].trim().to_string();
+            let value_str = attributes_str[eq + 1..].trim_start();
+            let quote = value_str
+                .chars()
+                .next()
+                .filter(|c| *c == '"' || *c == '\'')
+                .ok_or_else(|| format!("Unquoted value of {} in <{}>.", attribute, name))?;
+            let value_end = value_str[1..]
+                .find(quote)
+                .ok_or_else(|| format!("Unterminated value of {} in <{}>.", attribute, name))?;
+            attributes.insert(attribute, unescape_xml(&value_str[1..value_end + 1])?);
+            attributes_str = &value_str[value_end + 2..];
+        }
+
+        elements.push(XmlElement {
+            name: name.to_string(),
+            attributes,
+            parents: parents.clone(),
+        });
+        if !self_closing {
+            parents.push(name.to_string());
+        }
+    }
+
+    Ok(elements)
+}
+
+/// Finds the `>` closing the tag, skipping ones in quoted attribute values.
+fn find_tag_end(tag: &str) -> Option<usize> {
+    let mut quote = None;
+
+    for (index, c) in tag.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            (None, '>') => return Some(index),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Whether the `keys` of a `modifier` element match pressing Shift and Option.
+///
+/// Keys ending with `?` may be pressed or not, while the others have to be pressed.
+fn modifier_matches(keys: &str, shift: bool, option: bool) -> bool {
+    let mut allows_shift = false;
+    let mut allows_option = false;
+
+    for key in keys.split_whitespace() {
+        let (key, optional) = match key.strip_suffix('?') {
+            Some(key) => (key, true),
+            None => (key, false),
+        };
+
+        let pressed = match key {
+            "anyShift" | "shift" => {
+                allows_shift = true;
+                shift
+            }
+            "anyOption" | "option" => {
+                allows_option = true;
+                option
+            }
+            // Right-hand modifiers, Caps Lock, Command and Control aren't in the shift states
+            _ => false,
+        };
+
+        if !optional && !pressed {
+            return false;
+        }
+    }
+
+    (!shift || allows_shift) && (!option || allows_option)
+}
+
+/// A `when` element of an action.
+#[derive(Debug, Clone)]
+struct When {
+    state: String,
+    output: Option<String>,
+    next: Option<String>,
+}
+
+/// Converts a macOS `.keylayout` file into a layout for KLC.
+///
+/// The keys typed with Shift and Option become the Shift and AltGr shift states.
+/// Dead keys need a terminator, which gives their character. Chained dead keys aren't supported.
+pub fn import_keylayout(file_path: &Path) -> Result<ImportedLayout, String> {
+    let xml = fs::read_to_string(file_path)
+        .map_err(|e| format!("Couldn't read {}. {}", file_path.display(), e))?;
+
+    parse_keylayout(&xml, get_imported_layout_name(file_path))
+}
+
+fn parse_keylayout(xml: &str, name: String) -> Result<ImportedLayout, String> {
+    let elements = parse_xml_elements(xml)?;
+
+    let keyboard = elements
+        .iter()
+        .find(|e| e.name == "keyboard")
+        .ok_or_else(|| "The file isn't a .keylayout file.".to_string())?;
+    let description = keyboard.get("name").unwrap_or(&name).to_string();
+
+    // The first layout covers the built-in keyboards
+    let layout = elements
+        .iter()
+        .find(|e| e.name == "layout")
+        .ok_or_else(|| "The .keylayout file has no layout.".to_string())?;
+    let map_set_id = layout.get("mapSet").unwrap_or_default();
+    let modifiers_id = layout.get("modifiers").unwrap_or_default();
+
+    // Which key map each shift state uses
+    let mut current_modifier_map = None;
+    let mut current_map_index = None;
+    let mut default_index = None;
+    let mut map_indices: [Option<String>; 4] = Default::default();
+    for element in &elements {
+        match element.name.as_str() {
+            "modifierMap" => {
+                current_modifier_map = element.get("id");
+                if current_modifier_map == Some(modifiers_id) {
+                    default_index = element.get("defaultIndex").map(str::to_string);
+                }
+            }
+            "keyMapSelect" => current_map_index = element.get("mapIndex"),
+            "modifier" if current_modifier_map == Some(modifiers_id) => {
+                let keys = element.get("keys").unwrap_or_default();
+                for (column, (shift, option)) in SHIFT_STATE_MODIFIERS.iter().enumerate() {
+                    if map_indices[column].is_none() && modifier_matches(keys, *shift, *option) {
+                        map_indices[column] = current_map_index.map(str::to_string);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Keys of each key map, with the base maps they extend
+    let mut key_maps: HashMap<String, HashMap<u16, &XmlElement>> = HashMap::new();
+    let mut base_maps: HashMap<String, String> = HashMap::new();
+    let mut current_key_map_set = None;
+    let mut current_key_map = None;
+    for element in &elements {
+        match element.name.as_str() {
+            "keyMapSet" => current_key_map_set = element.get("id"),
+            "keyMap" if current_key_map_set == Some(map_set_id) => {
+                let index = element.get("index").unwrap_or_default().to_string();
+                if let (None | Some(""), Some(base_index)) =
+                    (element.get("baseMapSet"), element.get("baseIndex"))
+                {
+                    base_maps.insert(index.clone(), base_index.to_string());
+                }
+                current_key_map = Some(index);
+            }
+            "keyMap" => current_key_map = None,
+            "key" => {
+                if let (Some(index), Some(code)) = (&current_key_map, element.get("code")) {
+                    let code = code
+                        .parse()
+                        .map_err(|_| format!("Invalid key code {}.", code))?;
+                    key_maps
+                        .entry(index.clone())
+                        .or_default()
+                        .insert(code, element);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut actions: HashMap<&str, Vec<When>> = HashMap::new();
+    let mut terminators: HashMap<&str, &str> = HashMap::new();
+    let mut current_action = None;
+    for element in &elements {
+        match element.name.as_str() {
+            "action" => current_action = element.get("id"),
+            "when" if element.parents.iter().any(|p| p == "terminators") => {
+                if let (Some(state), Some(output)) = (element.get("state"), element.get("output")) {
+                    terminators.insert(state, output);
+                }
+            }
+            "when" => {
+                if let Some(action) = current_action {
+                    actions.entry(action).or_default().push(When {
+                        state: element.get("state").unwrap_or("none").to_string(),
+                        output: element.get("output").map(str::to_string),
+                        next: element.get("next").map(str::to_string),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let get_dead_char = |state: &str| -> Option<char> {
+        let mut chars = terminators.get(state)?.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c),
+            _ => None,
+        }
+    };
+
+    let mut imported = ImportedLayout::new(name, description);
+    let mut dead_states = Vec::new();
+
+    for (column, map_index) in map_indices.iter().enumerate() {
+        // Unmatched AltGr states are left empty rather than typing the base characters
+        let default_index = default_index.as_ref().filter(|_| column < 2);
+        let Some(map_index) = map_index.as_ref().or(default_index) else {
+            continue;
+        };
+
+        for (mac_code, scancode) in MAC_KEY_CODES {
+            let key = key_maps
+                .get(map_index)
+                .and_then(|keys| keys.get(&mac_code))
+                .or_else(|| {
+                    let base_index = base_maps.get(map_index)?;
+                    key_maps.get(base_index)?.get(&mac_code)
+                });
+            let Some(key) = key else {
+                continue;
+            };
+
+            let output = match (key.get("output"), key.get("action")) {
+                (Some(output), _) => Some(KeyOutput::Chars(output.to_string())),
+                (None, Some(action)) => {
+                    let none = actions
+                        .get(action)
+                        .and_then(|whens| whens.iter().find(|when| when.state == "none"));
+                    match none {
+                        Some(When {
+                            output: Some(output),
+                            ..
+                        }) => Some(KeyOutput::Chars(output.clone())),
+                        Some(When {
+                            next: Some(next), ..
+                        }) => get_dead_char(next).map(|dead_char| {
+                            if !dead_states.contains(next) {
+                                dead_states.push(next.clone());
+                            }
+                            KeyOutput::DeadKey(dead_char)
+                        }),
+                        _ => None,
+                    }
+                }
+                (None, None) => None,
+            };
+
+            // Control characters, like the ones of the Command shortcuts, aren't typed
+            if let Some(output) = output.filter(|output| match output {
+                KeyOutput::Chars(chars) => {
+                    !chars.is_empty() && !chars.chars().any(char::is_control)
+                }
+                KeyOutput::DeadKey(_) => true,
+            }) {
+                imported.set_key(scancode, column, output);
+            }
+        }
+    }
+
+    for state in dead_states {
+        let mut dead_key = DeadKey::new(get_dead_char(&state).unwrap());
+
+        for whens in actions.values() {
+            let base = whens
+                .iter()
+                .find(|when| when.state == "none")
+                .and_then(|when| when.output.as_deref());
+            let composed = whens
+                .iter()
+                .find(|when| when.state == state)
+                .and_then(|when| when.output.as_deref());
+
+            let (Some(base), Some(composed)) = (base, composed) else {
+                continue;
+            };
+            let (mut base, mut composed) = (base.chars(), composed.chars());
+            if let (Some(base), None, Some(composed), None) =
+                (base.next(), base.next(), composed.next(), composed.next())
+            {
+                if dead_key.compose(base).is_none() {
+                    dead_key.compositions.push((base, composed));
+                }
+            }
+        }
+
+        dead_key.compositions.sort();
+        imported.dead_keys.push(dead_key);
+    }
+
+    Ok(imported)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const KEYLAYOUT: &str = r#"<?xml version="1.1" encoding="UTF-8"?>
+<!DOCTYPE keyboard SYSTEM "file://localhost/System/Library/DTDs/KeyboardLayout.dtd">
+<!-- A tiny layout -->
+<keyboard group="126" id="-4242" name="Test &amp; Co" maxout="2">
+    <layouts>
+        <layout first="0" last="17" modifiers="mods" mapSet="keys"/>
+    </layouts>
+    <modifierMap id="mods" defaultIndex="0">
+        <keyMapSelect mapIndex="0">
+            <modifier keys=""/>
+        </keyMapSelect>
+        <keyMapSelect mapIndex="1">
+            <modifier keys="anyShift caps?"/>
+        </keyMapSelect>
+        <keyMapSelect mapIndex="2">
+            <modifier keys="anyOption"/>
+        </keyMapSelect>
+        <keyMapSelect mapIndex="3">
+            <modifier keys="command anyShift? anyOption?"/>
+        </keyMapSelect>
+    </modifierMap>
+    <keyMapSet id="keys">
+        <keyMap index="0">
+            <key code="0" action="a"/>
+            <key code="12" output="q"/>
+            <key code="18" output="&#x0031;"/>
+            <key code="33" action="dead_acute"/>
+            <key code="36" output="&#13;"/>
+        </keyMap>
+        <keyMap index="1">
+            <key code="0" output="A"/>
+            <key code="12" output="Q"/>
+            <key code="18" output="!"/>
+        </keyMap>
+        <keyMap index="2" baseMapSet="" baseIndex="0">
+            <key code="12" output="œ"/>
+        </keyMap>
+        <keyMap index="3">
+            <key code="12" output="&#x0011;"/>
+        </keyMap>
+    </keyMapSet>
+    <actions>
+        <action id="a">
+            <when state="none" output="a"/>
+            <when state="acute" output="á"/>
+        </action>
+        <action id="dead_acute">
+            <when state="none" next="acute"/>
+            <when state="acute" output="´"/>
+        </action>
+    </actions>
+    <terminators>
+        <when state="acute" output="´"/>
+    </terminators>
+</keyboard>
+"#;
+
+    #[test]
+    fn test_parse_keylayout() {
+        let layout = parse_keylayout(KEYLAYOUT, "test".to_string()).unwrap();
+
+        assert_eq!(layout.description, "Test & Co");
+        assert_eq!(
+            layout.keys[&0x10],
+            [
+                Some(KeyOutput::Chars("q".to_string())),
+                Some(KeyOutput::Chars("Q".to_string())),
+                Some(KeyOutput::Chars("œ".to_string())),
+                None,
+            ]
+        );
+        // Inherited from the base key map
+        assert_eq!(
+            layout.keys[&0x1e][2],
+            Some(KeyOutput::Chars("a".to_string()))
+        );
+        assert_eq!(
+            layout.keys[&0x02][0],
+            Some(KeyOutput::Chars("1".to_string()))
+        );
+        assert_eq!(layout.keys[&0x1a][0], Some(KeyOutput::DeadKey('´')));
+        assert!(!layout.keys.contains_key(&0x1c));
+
+        assert_eq!(layout.dead_keys.len(), 1);
+        assert_eq!(layout.dead_keys[0].dead_char, '´');
+        assert_eq!(layout.dead_keys[0].compositions, [('a', 'á')]);
+    }
+
+    #[test]
+    fn test_modifier_matches() {
+        assert!(modifier_matches("", false, false));
+        assert!(!modifier_matches("", true, false));
+        assert!(modifier_matches("anyShift caps?", true, false));
+        assert!(!modifier_matches("anyShift caps?", false, false));
+        assert!(modifier_matches("anyShift? anyOption", true, true));
+        assert!(!modifier_matches("command anyOption?", false, true));
+    }
+
+    #[test]
+    fn test_unescape_xml() {
+        assert_eq!(unescape_xml("&lt;&#x0041;&#66;&quot;").unwrap(), "<AB\"");
+        assert!(unescape_xml("&nbsp;").is_err());
+        assert!(unescape_xml("&amp").is_err());
+    }
+}
@@ -0,0 +1,580 @@
+use std::{fs, path::Path};
+
+use super::{get_imported_layout_name, ImportedLayout, KeyOutput};
+use crate::klc::DeadKey;
+
+/// Scancodes of the XKB key names of the character keys.
+const XKB_KEY_NAMES: [(&str, u8); 50] = [
+    ("TLDE", 0x29),
+    ("AE01", 0x02),
+    ("AE02", 0x03),
+    ("AE03", 0x04),
+    ("AE04", 0x05),
+    ("AE05", 0x06),
+    ("AE06", 0x07),
+    ("AE07", 0x08),
+    ("AE08", 0x09),
+    ("AE09", 0x0a),
+    ("AE10", 0x0b),
+    ("AE11", 0x0c),
+    ("AE12", 0x0d),
+    ("AD01", 0x10),
+    ("AD02", 0x11),
+    ("AD03", 0x12),
+    ("AD04", 0x13),
+    ("AD05", 0x14),
+    ("AD06", 0x15),
+    ("AD07", 0x16),
+    ("AD08", 0x17),
+    ("AD09", 0x18),
+    ("AD10", 0x19),
+    ("AD11", 0x1a),
+    ("AD12", 0x1b),
+    ("AC01", 0x1e),
+    ("AC02", 0x1f),
+    ("AC03", 0x20),
+    ("AC04", 0x21),
+    ("AC05", 0x22),
+    ("AC06", 0x23),
+    ("AC07", 0x24),
+    ("AC08", 0x25),
+    ("AC09", 0x26),
+    ("AC10", 0x27),
+    ("AC11", 0x28),
+    ("AC12", 0x2b),
+    ("BKSL", 0x2b),
+    ("AB01", 0x2c),
+    ("AB02", 0x2d),
+    ("AB03", 0x2e),
+    ("AB04", 0x2f),
+    ("AB05", 0x30),
+    ("AB06", 0x31),
+    ("AB07", 0x32),
+    ("AB08", 0x33),
+    ("AB09", 0x34),
+    ("AB10", 0x35),
+    ("LSGT", 0x56),
+    ("SPCE", 0x39),
+];
+
+/// Keysym names of the ASCII punctuation and of Latin-1, whose codes are their code points.
+const KEYSYM_NAMES: [(&str, char); 99] = [
+    ("space", ' '),
+    ("exclam", '!'),
+    ("quotedbl", '"'),
+    ("numbersign", '#'),
+    ("dollar", '$'),
+    ("percent", '%'),
+    ("ampersand", '&'),
+    ("apostrophe", '\''),
+    ("parenleft", '('),
+    ("parenright", ')'),
+    ("asterisk", '*'),
+    ("plus", '+'),
+    ("comma", ','),
+    ("minus", '-'),
+    ("period", '.'),
+    ("slash", '/'),
+    ("colon", ':'),
+    ("semicolon", ';'),
+    ("less", '<'),
+    ("equal", '='),
+    ("greater", '>'),
+    ("question", '?'),
+    ("at", '@'),
+    ("bracketleft", '['),
+    ("backslash", '\\'),
+    ("bracketright", ']'),
+    ("asciicircum", '^'),
+    ("underscore", '_'),
+    ("grave", '`'),
+    ("braceleft", '{'),
+    ("bar", '|'),
+    ("braceright", '}'),
+    ("asciitilde", '~'),
+    ("nobreakspace", '\u{a0}'),
+    ("exclamdown", '¡'),
+    ("cent", '¢'),
+    ("sterling", '£'),
+    ("currency", '¤'),
+    ("yen", '¥'),
+    ("brokenbar", '¦'),
+    ("section", '§'),
+    ("diaeresis", '¨'),
+    ("copyright", '©'),
+    ("ordfeminine", 'ª'),
+    ("guillemotleft", '«'),
+    ("notsign", '¬'),
+    ("hyphen", '\u{ad}'),
+    ("registered", '®'),
+    ("macron", '¯'),
+    ("degree", '°'),
+    ("plusminus", '±'),
+    ("twosuperior", '²'),
+    ("threesuperior", '³'),
+    ("acute", '´'),
+    ("mu", 'µ'),
+    ("paragraph", '¶'),
+    ("periodcentered", '·'),
+    ("cedilla", '¸'),
+    ("onesuperior", '¹'),
+    ("masculine", 'º'),
+    ("guillemotright", '»'),
+    ("onequarter", '¼'),
+    ("onehalf", '½'),
+    ("threequarters", '¾'),
+    ("questiondown", '¿'),
+    ("multiply", '×'),
+    ("division", '÷'),
+    ("ssharp", 'ß'),
+    ("agrave", 'à'),
+    ("aacute", 'á'),
+    ("acircumflex", 'â'),
+    ("atilde", 'ã'),
+    ("adiaeresis", 'ä'),
+    ("aring", 'å'),
+    ("ae", 'æ'),
+    ("ccedilla", 'ç'),
+    ("egrave", 'è'),
+    ("eacute", 'é'),
+    ("ecircumflex", 'ê'),
+    ("ediaeresis", 'ë'),
+    ("igrave", 'ì'),
+    ("iacute", 'í'),
+    ("icircumflex", 'î'),
+    ("idiaeresis", 'ï'),
+    ("eth", 'ð'),
+    ("ntilde", 'ñ'),
+    ("ograve", 'ò'),
+    ("oacute", 'ó'),
+    ("ocircumflex", 'ô'),
+    ("otilde", 'õ'),
+    ("odiaeresis", 'ö'),
+    ("oslash", 'ø'),
+    ("ugrave", 'ù'),
+    ("uacute", 'ú'),
+    ("ucircumflex", 'û'),
+    ("udiaeresis", 'ü'),
+    ("yacute", 'ý'),
+    ("thorn", 'þ'),
+    ("ydiaeresis", 'ÿ'),
+];
+
+/// Dead keysyms, the spacing characters shown for them and what they compose.
+///
+/// XKB leaves the compositions to the Compose file, so these are the common ones.
+const DEAD_KEYSYMS: [(&str, char, &str, &str); 9] = [
+    ("grave", '`', "aeiouAEIOU", "àèìòùÀÈÌÒÙ"),
+    ("acute", '´', "aeiouycnszAEIOUYCNSZ", "áéíóúýćńśźÁÉÍÓÚÝĆŃŚŹ"),
+    ("circumflex", '^', "aeiouAEIOU", "âêîôûÂÊÎÔÛ"),
+    ("tilde", '~', "anoANO", "ãñõÃÑÕ"),
+    ("diaeresis", '¨', "aeiouyAEIOUY", "äëïöüÿÄËÏÖÜŸ"),
+    ("cedilla", '¸', "cCsStT", "çÇşŞţŢ"),
+    ("ogonek", '˛', "aeiuAEIU", "ąęįųĄĘĮŲ"),
+    ("caron", 'ˇ', "cdenrstzCDENRSTZ", "čďěňřšťžČĎĚŇŘŠŤŽ"),
+    ("abovering", '˚', "auAU", "åůÅŮ"),
+];
+
+/// Shift levels of the imported shift states.
+const LEVEL_COUNT: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    String(String),
+    KeyName(String),
+    Punct(char),
+}
+
+fn tokenize_xkb(content: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = content.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            _ if c.is_whitespace() => {}
+            '/' if chars.peek() == Some(&'/') => while chars.next_if(|c| *c != '\n').is_some() {},
+            '#' => while chars.next_if(|c| *c != '\n').is_some() {},
+            '"' => {
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => string.extend(chars.next()),
+                        Some(c) => string.push(c),
+                        None => return Err("Unterminated string in the XKB file.".to_string()),
+                    }
+                }
+                tokens.push(Token::String(string));
+            }
+            '<' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('>') => break,
+                        Some(c) => name.push(c),
+                        None => return Err("Unterminated key name in the XKB file.".to_string()),
+                    }
+                }
+                tokens.push(Token::KeyName(name));
+            }
+            '{' | '}' | '[' | ']' | '(' | ')' | ';' | ',' | '=' => tokens.push(Token::Punct(c)),
+            _ => {
+                let mut ident = c.to_string();
+                while let Some(c) =
+                    chars.next_if(|c| c.is_alphanumeric() || matches!(c, '_' | '+' | '-' | '.'))
+                {
+                    ident.push(c);
+                }
+                tokens.push(Token::Ident(ident));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// A `xkb_symbols` section, with the tokens between its braces.
+struct SymbolsSection<'a> {
+    name: String,
+    is_default: bool,
+    tokens: &'a [Token],
+}
+
+fn find_symbols_sections(tokens: &[Token]) -> Result<Vec<SymbolsSection<'_>>, String> {
+    let mut sections = Vec::new();
+    let mut flags_start = 0;
+    let mut i = 0;
+
+    while i < tokens.len() {
+        match &tokens[i] {
+            Token::Ident(ident) if ident == "xkb_symbols" => {
+                let is_default = tokens[flags_start..i]
+                    .iter()
+                    .any(|t| *t == Token::Ident("default".to_string()));
+                let name = match tokens.get(i + 1) {
+                    Some(Token::String(name)) => name.clone(),
+                    _ => String::new(),
+                };
+                let start = tokens[i..]
+                    .iter()
+                    .position(|t| *t == Token::Punct('{'))
+                    .map(|offset| i + offset + 1)
+                    .ok_or_else(|| "xkb_symbols section without a body.".to_string())?;
+
+                let mut depth = 1;
+                let mut end = start;
+                while depth > 0 {
+                    match tokens.get(end) {
+                        Some(Token::Punct('{')) => depth += 1,
+                        Some(Token::Punct('}')) => depth -= 1,
+                        Some(_) => {}
+                        None => return Err(format!("Unterminated xkb_symbols \"{}\".", name)),
+                    }
+                    end += 1;
+                }
+
+                sections.push(SymbolsSection {
+                    name,
+                    is_default,
+                    tokens: &tokens[start..end - 1],
+                });
+                i = end;
+                flags_start = i;
+            }
+            Token::Punct(';') => {
+                i += 1;
+                flags_start = i;
+            }
+            _ => i += 1,
+        }
+    }
+
+    Ok(sections)
+}
+
+/// Splits the body of a section or key into its statements, separated by `;` or `,`
+/// outside of brackets and braces.
+fn split_statements(tokens: &[Token], separator: char) -> Vec<&[Token]> {
+    let mut statements = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Punct('{' | '[' | '(') => depth += 1,
+            Token::Punct('}' | ']' | ')') => depth -= 1,
+            Token::Punct(c) if *c == separator && depth == 0 => {
+                statements.push(&tokens[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    statements.push(&tokens[start..]);
+
+    statements.retain(|statement| !statement.is_empty());
+    statements
+}
+
+/// Returns the keysyms of the first `[ ... ]` list in the tokens.
+fn get_keysym_list(tokens: &[Token]) -> Option<Vec<&str>> {
+    let start = tokens.iter().position(|t| *t == Token::Punct('['))?;
+    let end = start
+        + tokens[start..]
+            .iter()
+            .position(|t| *t == Token::Punct(']'))?;
+
+    Some(
+        tokens[start + 1..end]
+            .iter()
+            .filter_map(|t| match t {
+                Token::Ident(keysym) => Some(keysym.as_str()),
+                _ => None,
+            })
+            .collect(),
+    )
+}
+
+/// Whether the tokens start with `name[Group1] =`, or `name =` which also means the first group.
+fn is_group1_assignment(tokens: &[Token], name: &str) -> bool {
+    if tokens.first() != Some(&Token::Ident(name.to_string())) {
+        return false;
+    }
+
+    match &tokens[1..] {
+        [Token::Punct('='), ..] => true,
+        [Token::Punct('['), Token::Ident(group), Token::Punct(']'), Token::Punct('='), ..] => {
+            group.eq_ignore_ascii_case("group1")
+        }
+        _ => false,
+    }
+}
+
+/// Returns the keysyms of the first group of a key body like `[ a, A ], [ b, B ]`
+/// or `type[Group1] = "...", symbols[Group1] = [ a, A ]`.
+fn get_group1_keysyms(body: &[Token]) -> Option<Vec<&str>> {
+    let parts = split_statements(body, ',');
+
+    if let Some(symbols) = parts
+        .iter()
+        .find(|part| is_group1_assignment(part, "symbols"))
+    {
+        let value = symbols.iter().position(|t| *t == Token::Punct('='))?;
+        return get_keysym_list(&symbols[value + 1..]);
+    }
+
+    let list = parts
+        .iter()
+        .find(|part| part.first() == Some(&Token::Punct('[')))?;
+    get_keysym_list(list)
+}
+
+/// Converts a keysym to what it types. Unknown keysyms and `NoSymbol` type nothing.
+fn get_keysym_output(keysym: &str) -> Option<KeyOutput> {
+    if let Some(name) = keysym.strip_prefix("dead_") {
+        let (_, dead_char, _, _) = DEAD_KEYSYMS.iter().find(|(n, ..)| *n == name)?;
+        return Some(KeyOutput::DeadKey(*dead_char));
+    }
+
+    let mut chars = keysym.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(KeyOutput::Chars(c.to_string()));
+    }
+
+    let code = if let Some(hex) = keysym.strip_prefix('U').filter(|hex| hex.len() >= 4) {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(hex) = keysym.strip_prefix("0x") {
+        // Unicode keysyms are offset by 0x1000000, Latin-1 ones are the code points
+        u32::from_str_radix(hex, 16)
+            .ok()
+            .and_then(|code| match code {
+                0x1000100.. => Some(code - 0x1000000),
+                0x20..=0xff => Some(code),
+                _ => None,
+            })
+    } else {
+        KEYSYM_NAMES
+            .iter()
+            .find(|(name, _)| *name == keysym)
+            .map(|(_, c)| *c as u32)
+    };
+
+    code.and_then(char::from_u32)
+        .filter(|c| !c.is_control())
+        .map(|c| KeyOutput::Chars(c.to_string()))
+}
+
+/// Converts the symbols of a Linux XKB layout into a layout for KLC.
+///
+/// The `default` section is used, or the first one if none is. The four levels of the first
+/// group become the base, Shift, AltGr and Shift+AltGr shift states. `include` statements
+/// aren't followed, so the keys the section takes from other layouts are left empty.
+pub fn import_xkb(file_path: &Path) -> Result<ImportedLayout, String> {
+    let content = fs::read_to_string(file_path)
+        .map_err(|e| format!("Couldn't read {}. {}", file_path.display(), e))?;
+
+    parse_xkb(&content, get_imported_layout_name(file_path))
+}
+
+fn parse_xkb(content: &str, name: String) -> Result<ImportedLayout, String> {
+    let tokens = tokenize_xkb(content)?;
+    let sections = find_symbols_sections(&tokens)?;
+    let section = sections
+        .iter()
+        .find(|section| section.is_default)
+        .or(sections.first())
+        .ok_or_else(|| "The file has no xkb_symbols section.".to_string())?;
+
+    let mut imported = ImportedLayout::new(name.clone(), section.name.clone());
+    if imported.description.is_empty() {
+        imported.description = name;
+    }
+    let mut dead_chars = Vec::new();
+
+    for mut statement in split_statements(section.tokens, ';') {
+        // Includes don't need a `;`, so they can prefix the next statement
+        while let [Token::Ident(keyword), Token::String(_), rest @ ..] = statement {
+            if !matches!(
+                keyword.as_str(),
+                "include" | "augment" | "override" | "replace"
+            ) {
+                break;
+            }
+            statement = rest;
+        }
+
+        // Leading modifiers of the statement, like `replace key` or `override key`
+        let statement = match statement
+            .iter()
+            .position(|t| matches!(t, Token::Ident(ident) if ident == "key"))
+        {
+            Some(index) if index > 0 => &statement[index..],
+            _ => statement,
+        };
+
+        if is_group1_assignment(statement, "name") {
+            if let Some(Token::String(description)) = statement.last() {
+                imported.description = description.clone();
+            }
+            continue;
+        }
+
+        let [Token::Ident(keyword), Token::KeyName(key_name), Token::Punct('{'), body @ .., Token::Punct('}')] =
+            statement
+        else {
+            continue;
+        };
+        if keyword != "key" {
+            continue;
+        }
+        let Some((_, scancode)) = XKB_KEY_NAMES.iter().find(|(name, _)| name == key_name) else {
+            continue;
+        };
+        let Some(keysyms) = get_group1_keysyms(body) else {
+            continue;
+        };
+
+        for (column, keysym) in keysyms.iter().take(LEVEL_COUNT).enumerate() {
+            let Some(output) = get_keysym_output(keysym) else {
+                continue;
+            };
+            if let KeyOutput::DeadKey(dead_char) = output {
+                if !dead_chars.contains(&dead_char) {
+                    dead_chars.push(dead_char);
+                }
+            }
+            imported.set_key(*scancode, column, output);
+        }
+    }
+
+    for dead_char in dead_chars {
+        let (_, _, bases, composed) = DEAD_KEYSYMS
+            .iter()
+            .find(|(_, c, ..)| *c == dead_char)
+            .unwrap();
+
+        let mut dead_key = DeadKey::new(dead_char);
+        dead_key.compositions.push((' ', dead_char));
+        dead_key
+            .compositions
+            .extend(bases.chars().zip(composed.chars()));
+        imported.dead_keys.push(dead_key);
+    }
+
+    Ok(imported)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const XKB: &str = r#"
+// A tiny layout
+partial alphanumeric_keys
+xkb_symbols "other" {
+    key <AD01> { [ x, X ] };
+};
+
+default partial alphanumeric_keys
+xkb_symbols "basic" {
+    include "us(basic)"
+    name[Group1] = "Test (Basic)";
+
+    key <AE01> { [ 1, exclam, onesuperior, exclamdown ] };
+    key <AD01> { [ q, Q, U0153, 0x1000152 ], [ Greek_theta ] };
+    replace key <AC01> { type[Group1] = "FOUR_LEVEL", symbols[Group1] = [ a, A, dead_acute ] };
+    key <RALT> { [ ISO_Level3_Shift ] };
+    key <SPCE> { [ space, space, nobreakspace ] };
+};
+"#;
+
+    #[test]
+    fn test_parse_xkb() {
+        let layout = parse_xkb(XKB, "test".to_string()).unwrap();
+
+        assert_eq!(layout.description, "Test (Basic)");
+        assert_eq!(
+            layout.keys[&0x10],
+            [
+                Some(KeyOutput::Chars("q".to_string())),
+                Some(KeyOutput::Chars("Q".to_string())),
+                Some(KeyOutput::Chars("œ".to_string())),
+                Some(KeyOutput::Chars("Œ".to_string())),
+            ]
+        );
+        assert_eq!(
+            layout.keys[&0x02][3],
+            Some(KeyOutput::Chars("¡".to_string()))
+        );
+        assert_eq!(layout.keys[&0x1e][2], Some(KeyOutput::DeadKey('´')));
+        assert_eq!(
+            layout.keys[&0x39][2],
+            Some(KeyOutput::Chars("\u{a0}".to_string()))
+        );
+        assert_eq!(layout.keys.len(), 4);
+
+        assert_eq!(layout.dead_keys.len(), 1);
+        assert_eq!(layout.dead_keys[0].compose(' '), Some('´'));
+        assert_eq!(layout.dead_keys[0].compose('z'), Some('ź'));
+    }
+
+    #[test]
+    fn test_get_keysym_output() {
+        assert_eq!(get_keysym_output("NoSymbol"), None);
+        assert_eq!(get_keysym_output("ISO_Level3_Shift"), None);
+        assert_eq!(
+            get_keysym_output("U20AC"),
+            Some(KeyOutput::Chars("€".to_string()))
+        );
+        assert_eq!(
+            get_keysym_output("0xe9"),
+            Some(KeyOutput::Chars("é".to_string()))
+        );
+        assert_eq!(
+            get_keysym_output("dead_caron"),
+            Some(KeyOutput::DeadKey('ˇ'))
+        );
+        assert_eq!(get_keysym_output("dead_belowdot"), None);
+    }
+}
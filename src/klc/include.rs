@@ -0,0 +1,348 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::utils::ReadUtf16Line;
+
+use super::substitute_variables;
+
+/// Prefix of the directive including a base KLC file. It's a comment for KBDUTOOL.
+pub const INCLUDE_DIRECTIVE: &str = ";#include";
+
+/// Keywords that start a section of a KLC file.
+const KEYWORDS: [&str; 17] = [
+    "KBD",
+    "COPYRIGHT",
+    "COMPANY",
+    "LOCALENAME",
+    "LOCALEID",
+    "VERSION",
+    "ATTRIBUTES",
+    "SHIFTSTATE",
+    "LAYOUT",
+    "LIGATURE",
+    "DEADKEY",
+    "KEYNAME",
+    "KEYNAME_EXT",
+    "KEYNAME_DEAD",
+    "DESCRIPTIONS",
+    "LANGUAGENAMES",
+    "ENDKBD",
+];
+
+/// Sections that are replaced as a whole instead of being merged row by row,
+/// because their rows only make sense together.
+const REPLACED_SECTIONS: [&str; 2] = ["SHIFTSTATE", "ATTRIBUTES"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KlcSection {
+    /// The whole header line, e.g. `DEADKEY\t005e`.
+    pub header: String,
+    pub rows: Vec<String>,
+}
+
+impl KlcSection {
+    pub fn get_keyword(&self) -> &str {
+        self.header.split_whitespace().next().unwrap_or_default()
+    }
+
+    /// Identifies the section for overlaying. Only dead keys can appear multiple times.
+    fn get_identity(&self) -> String {
+        let keyword = self.get_keyword();
+
+        if keyword == "DEADKEY" {
+            let dead_char = self.header.split_whitespace().nth(1).unwrap_or_default();
+            format!("{} {}", keyword, dead_char.to_ascii_lowercase())
+        } else {
+            keyword.to_string()
+        }
+    }
+
+    /// Identifies the row for overlaying, e.g. the scancode in `LAYOUT`.
+    fn get_row_key(&self, row: &str) -> String {
+        let mut fields = row.split_whitespace();
+        let first = fields.next().unwrap_or_default();
+
+        match fields.next() {
+            Some(second) if self.get_keyword() == "LIGATURE" => {
+                format!("{} {}", first, second).to_ascii_lowercase()
+            }
+            _ => first.to_ascii_lowercase(),
+        }
+    }
+
+    fn overlay(&mut self, other: KlcSection) {
+        if other.rows.is_empty() || REPLACED_SECTIONS.contains(&self.get_keyword()) {
+            *self = other;
+            return;
+        }
+
+        self.header = other.header.clone();
+
+        // Looking up the rows by key keeps merging large sections linear
+        let mut indices = HashMap::new();
+        for (index, row) in self.rows.iter().enumerate() {
+            indices.entry(self.get_row_key(row)).or_insert(index);
+        }
+
+        for row in other.rows {
+            let key = self.get_row_key(&row);
+
+            match indices.get(&key) {
+                Some(&index) => self.rows[index] = row,
+                None => {
+                    indices.insert(key, self.rows.len());
+                    self.rows.push(row);
+                }
+            }
+        }
+    }
+}
+
+/// A KLC file split into its sections, which allows overlaying one file over another.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KlcDocument {
+    /// Paths of the included base files, relative to this file.
+    pub includes: Vec<String>,
+    pub sections: Vec<KlcSection>,
+}
+
+fn is_comment(line: &str) -> bool {
+    line.starts_with("//") || line.starts_with(';')
+}
+
+impl KlcDocument {
+    pub fn parse<'a>(lines: impl Iterator<Item = &'a str>) -> KlcDocument {
+        let mut document = KlcDocument::default();
+
+        for line in lines {
+            let trimmed = line.trim();
+
+            if let Some(include) = trimmed.strip_prefix(INCLUDE_DIRECTIVE) {
+                document.includes.push(include.trim().to_string());
+                continue;
+            }
+
+            if trimmed.is_empty() || is_comment(trimmed) {
+                continue;
+            }
+
+            let first = trimmed.split_whitespace().next().unwrap_or_default();
+
+            if KEYWORDS.contains(&first) {
+                document.sections.push(KlcSection {
+                    header: line.trim_end().to_string(),
+                    rows: Vec::new(),
+                });
+            } else if let Some(section) = document.sections.last_mut() {
+                section.rows.push(line.trim_end().to_string());
+            }
+        }
+
+        document
+    }
+
+    pub fn read_from_file(file_path: &Path) -> Result<KlcDocument, String> {
+        let file = fs::File::open(file_path).map_err(|e| e.to_string())?;
+        let reader = std::io::BufReader::new(file);
+        let lines = reader
+            .utf16_lines()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+
+        Ok(Self::parse(lines.iter().map(|line| line.as_str())))
+    }
+
+    /// Reads the file and resolves its includes, overlaying it over its base files.
+    pub fn read_resolved(file_path: &Path) -> Result<KlcDocument, String> {
+        Self::read_resolved_inner(file_path, &mut Vec::new())
+    }
+
+    fn read_resolved_inner(
+        file_path: &Path,
+        stack: &mut Vec<PathBuf>,
+    ) -> Result<KlcDocument, String> {
+        let file_path = file_path
+            .canonicalize()
+            .map_err(|e| format!("Couldn't find {}. {}", file_path.display(), e))?;
+
+        if stack.contains(&file_path) {
+            return Err(format!(
+                "The KLC file {} includes itself!",
+                file_path.display()
+            ));
+        }
+
+        let mut document = Self::read_from_file(&file_path)?;
+        if document.includes.is_empty() {
+            return Ok(document);
+        }
+
+        stack.push(file_path.clone());
+
+        let dir = file_path.parent().unwrap_or(Path::new("."));
+        let mut resolved = KlcDocument::default();
+        for include in document.includes.drain(..) {
+            let base = Self::read_resolved_inner(&dir.join(include), stack)?;
+            resolved.overlay(base);
+        }
+        resolved.overlay(document);
+
+        stack.pop();
+
+        Ok(resolved)
+    }
+
+    /// Overlays the other document over this one.
+    ///
+    /// Single-line sections (like `KBD` or `LOCALEID`) are replaced, while other
+    /// sections are merged row by row, replacing rows with the same key.
+    pub fn overlay(&mut self, other: KlcDocument) {
+        let mut indices = HashMap::new();
+        for (index, section) in self.sections.iter().enumerate() {
+            indices.entry(section.get_identity()).or_insert(index);
+        }
+
+        for section in other.sections {
+            let identity = section.get_identity();
+
+            match indices.get(&identity) {
+                Some(&index) => self.sections[index].overlay(section),
+                None => {
+                    // New sections go before ENDKBD
+                    let index = self
+                        .sections
+                        .iter()
+                        .position(|s| s.get_keyword() == "ENDKBD")
+                        .unwrap_or(self.sections.len());
+                    self.sections.insert(index, section);
+
+                    for other_index in indices.values_mut() {
+                        if *other_index >= index {
+                            *other_index += 1;
+                        }
+                    }
+                    indices.insert(identity, index);
+                }
+            }
+        }
+    }
+
+    /// Replaces `${NAME}` placeholders in all sections.
+    ///
+    /// Returns the number of replaced placeholders.
+    pub fn substitute_variables(
+        &mut self,
+        variables: &HashMap<String, String>,
+    ) -> Result<usize, String> {
+        let mut count = 0;
+
+        for section in &mut self.sections {
+            for line in std::iter::once(&mut section.header).chain(section.rows.iter_mut()) {
+                let (substituted, replaced) = substitute_variables(line, variables)?;
+                *line = substituted;
+                count += replaced;
+            }
+        }
+
+        Ok(count)
+    }
+
+    pub fn to_lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        for section in &self.sections {
+            lines.push(section.header.clone());
+            lines.push(String::new());
+
+            if !section.rows.is_empty() {
+                lines.extend(section.rows.iter().cloned());
+                lines.push(String::new());
+            }
+        }
+
+        lines
+    }
+
+    /// Writes the document as UTF-16 with a BOM and CRLF line endings, like MSKLC does.
+    pub fn write_to_file(&self, file_path: &Path) -> Result<(), String> {
+        let file = fs::File::create(file_path).map_err(|e| e.to_string())?;
+        let mut writer = BufWriter::new(file);
+
+        let mut content = String::from("\u{feff}");
+        for line in self.to_lines() {
+            content.push_str(&line);
+            content.push_str("\r\n");
+        }
+
+        for unit in content.encode_utf16() {
+            writer
+                .write_all(&unit.to_le_bytes())
+                .map_err(|e| e.to_string())?;
+        }
+
+        writer.flush().map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_overlay() {
+        let base = KlcDocument::parse(
+            [
+                "KBD\tbase\t\"Base\"",
+                "LOCALEID\t\"00000409\"",
+                "LAYOUT\t\t;an extra '@' at the end is a dead key",
+                "//SC\tVK_\t\tCap\t0\t1",
+                "02\t1\t\t0\t1\t0021",
+                "03\t2\t\t0\t2\t0040",
+                "DEADKEY\t005e",
+                "0061\t00e2",
+                "ENDKBD",
+            ]
+            .into_iter(),
+        );
+        let derived = KlcDocument::parse(
+            [
+                ";#include base.klc",
+                "KBD\tderived\t\"Derived\"",
+                "LAYOUT",
+                "03\t2\t\t0\t2\t0022",
+                "04\t3\t\t0\t3\t0023",
+                "DEADKEY\t005E",
+                "0065\t00ea",
+                "DEADKEY\t00b4",
+                "0065\t00e9",
+            ]
+            .into_iter(),
+        );
+
+        assert_eq!(derived.includes, ["base.klc"]);
+
+        let mut merged = base;
+        merged.overlay(derived);
+
+        let keywords: Vec<&str> = merged.sections.iter().map(|s| s.get_keyword()).collect();
+        assert_eq!(
+            keywords,
+            ["KBD", "LOCALEID", "LAYOUT", "DEADKEY", "DEADKEY", "ENDKBD"]
+        );
+        assert_eq!(merged.sections[0].header, "KBD\tderived\t\"Derived\"");
+        assert_eq!(
+            merged.sections[2].rows,
+            [
+                "02\t1\t\t0\t1\t0021",
+                "03\t2\t\t0\t2\t0022",
+                "04\t3\t\t0\t3\t0023"
+            ]
+        );
+        assert_eq!(merged.sections[3].rows, ["0061\t00e2", "0065\t00ea"]);
+        assert_eq!(merged.sections[4].header, "DEADKEY\t00b4");
+    }
+}
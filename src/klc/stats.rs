@@ -0,0 +1,154 @@
+use super::{strip_klc_comment, DeadKey, KlcDocument};
+
+/// Value of a `LAYOUT` column meaning the key produces nothing in that shift state.
+const UNMAPPED: &str = "-1";
+
+/// Summary of a KLC layout, for a quick sanity check before shipping it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KlcStats {
+    /// Number of mapped keys for each shift state, in the order of `SHIFTSTATE`.
+    pub mapped_keys: Vec<(u8, usize)>,
+    /// Virtual keys in `LAYOUT` that don't produce anything in any shift state.
+    pub unmapped_keys: Vec<String>,
+    pub dead_keys: usize,
+    pub compositions: usize,
+    pub ligatures: usize,
+    /// Characters outside the BMP, which need a surrogate pair in UTF-16.
+    pub surrogate_pairs: Vec<char>,
+}
+
+/// Returns a readable name of the shift state, e.g. `Shift+AltGr` for 7.
+pub fn get_shift_state_name(shift_state: u8) -> String {
+    let mut modifiers = Vec::new();
+
+    if shift_state & 1 != 0 {
+        modifiers.push("Shift");
+    }
+    match shift_state & 6 {
+        2 => modifiers.push("Ctrl"),
+        4 => modifiers.push("Alt"),
+        6 => modifiers.push("AltGr"),
+        _ => {}
+    }
+
+    if modifiers.is_empty() {
+        "Base".to_string()
+    } else {
+        modifiers.join("+")
+    }
+}
+
+/// Decodes UTF-16 code units given as hex in a KLC row, keeping only characters outside the BMP.
+fn find_surrogate_pairs<'a>(units: impl Iterator<Item = &'a str>) -> Vec<char> {
+    let units: Vec<u16> = units
+        .filter_map(|unit| u16::from_str_radix(unit, 16).ok())
+        .collect();
+
+    char::decode_utf16(units)
+        .filter_map(|c| c.ok())
+        .filter(|c| c.len_utf16() == 2)
+        .collect()
+}
+
+impl KlcStats {
+    pub fn from_document(document: &KlcDocument) -> Result<KlcStats, String> {
+        let mut stats = KlcStats::default();
+
+        let rows = |keyword: &'static str| {
+            document
+                .sections
+                .iter()
+                .filter(move |s| s.get_keyword() == keyword)
+                .flat_map(|s| s.rows.iter())
+                .map(|row| strip_klc_comment(row).trim())
+                .filter(|row| !row.is_empty())
+        };
+
+        for row in rows("SHIFTSTATE") {
+            let shift_state = row
+                .parse()
+                .map_err(|_| format!("Invalid shift state {}.", row))?;
+            stats.mapped_keys.push((shift_state, 0));
+        }
+
+        for row in rows("LAYOUT") {
+            // Scancode, virtual key, Caps Lock behavior and a column for each shift state
+            let fields: Vec<&str> = row.split_whitespace().collect();
+            let (Some(vk), Some(columns)) = (fields.get(1), fields.get(3..)) else {
+                return Err(format!("Invalid row in LAYOUT: {}", row));
+            };
+
+            let mut mapped = false;
+            for (column, (_, count)) in columns.iter().zip(stats.mapped_keys.iter_mut()) {
+                if *column != UNMAPPED {
+                    *count += 1;
+                    mapped = true;
+                }
+            }
+
+            if !mapped {
+                stats.unmapped_keys.push(vk.to_string());
+            }
+        }
+
+        for row in rows("LIGATURE") {
+            stats.ligatures += 1;
+            // Virtual key, shift state column and the UTF-16 code units
+            stats
+                .surrogate_pairs
+                .extend(find_surrogate_pairs(row.split_whitespace().skip(2)));
+        }
+
+        let lines = document.to_lines();
+        let dead_keys = DeadKey::parse(lines.iter().map(|line| line.as_str()))?;
+        stats.dead_keys = dead_keys.len();
+        stats.compositions = dead_keys.iter().map(|d| d.compositions.len()).sum();
+
+        stats.surrogate_pairs.sort();
+        stats.surrogate_pairs.dedup();
+
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_get_shift_state_name() {
+        assert_eq!(get_shift_state_name(0), "Base");
+        assert_eq!(get_shift_state_name(1), "Shift");
+        assert_eq!(get_shift_state_name(2), "Ctrl");
+        assert_eq!(get_shift_state_name(6), "AltGr");
+        assert_eq!(get_shift_state_name(7), "Shift+AltGr");
+    }
+
+    #[test]
+    fn test_klc_stats() {
+        let klc = [
+            "SHIFTSTATE",
+            "0\t//Column 4",
+            "1\t//Column 5 : Shft",
+            "6\t//Column 6 :       Ctrl Alt",
+            "LAYOUT",
+            "02\t1\t0\t1\t0021\t-1",
+            "03\t2\t0\t2\t0040\t%%",
+            "29\tOEM_3\t0\t-1\t-1\t-1",
+            "LIGATURE",
+            "2\t2\td83d\tde00",
+            "DEADKEY\t005e",
+            "0061\t00e2",
+            "0065\t00ea",
+            "ENDKBD",
+        ];
+
+        let stats = KlcStats::from_document(&KlcDocument::parse(klc.into_iter())).unwrap();
+        assert_eq!(stats.mapped_keys, vec![(0, 2), (1, 2), (6, 1)]);
+        assert_eq!(stats.unmapped_keys, vec!["OEM_3".to_string()]);
+        assert_eq!(stats.ligatures, 1);
+        assert_eq!(stats.dead_keys, 1);
+        assert_eq!(stats.compositions, 2);
+        assert_eq!(stats.surrogate_pairs, vec!['\u{1f600}']);
+    }
+}
@@ -0,0 +1,128 @@
+use std::{collections::HashMap, fs, path::Path};
+
+/// Parses a `NAME=VALUE` variable definition.
+pub fn parse_variable(definition: &str) -> Result<(String, String), String> {
+    let (name, value) = definition
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid variable {}, expected NAME=VALUE.", definition))?;
+    let name = name.trim();
+
+    if !is_variable_name(name) {
+        return Err(format!("Invalid variable name {}.", name));
+    }
+
+    Ok((name.to_string(), value.to_string()))
+}
+
+/// Reads variables from a file with one `NAME=VALUE` per line.
+///
+/// Empty lines and lines starting with `#` are ignored.
+pub fn read_variables_file(file_path: &Path) -> Result<HashMap<String, String>, String> {
+    let content = fs::read_to_string(file_path)
+        .map_err(|e| format!("Couldn't read {}. {}", file_path.display(), e))?;
+
+    content
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(parse_variable)
+        .collect()
+}
+
+fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Replaces `${NAME}` placeholders in the text with the values of the variables.
+///
+/// Returns the text and the number of replaced placeholders, or an error listing
+/// the undefined variables.
+pub fn substitute_variables(
+    text: &str,
+    variables: &HashMap<String, String>,
+) -> Result<(String, usize), String> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut count = 0;
+    let mut undefined = Vec::new();
+
+    while let Some(start) = rest.find("${") {
+        let Some(length) = rest[start + 2..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 2..start + 2 + length];
+
+        result.push_str(&rest[..start]);
+
+        match variables.get(name) {
+            Some(value) if is_variable_name(name) => {
+                result.push_str(value);
+                count += 1;
+            }
+            _ => {
+                if is_variable_name(name) {
+                    undefined.push(name.to_string());
+                }
+                result.push_str(&rest[start..start + 3 + length]);
+            }
+        }
+
+        rest = &rest[start + 3 + length..];
+    }
+    result.push_str(rest);
+
+    if !undefined.is_empty() {
+        return Err(format!(
+            "Undefined template variables: {}",
+            undefined.join(", ")
+        ));
+    }
+
+    Ok((result, count))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_variable() {
+        assert_eq!(
+            parse_variable("NAME=kbdpl"),
+            Ok(("NAME".to_string(), "kbdpl".to_string()))
+        );
+        assert_eq!(
+            parse_variable("TEXT=A=B"),
+            Ok(("TEXT".to_string(), "A=B".to_string()))
+        );
+        assert!(parse_variable("NAME").is_err());
+        assert!(parse_variable("1NAME=x").is_err());
+    }
+
+    #[test]
+    fn test_substitute_variables() {
+        let variables = HashMap::from([
+            ("NAME".to_string(), "kbdpl".to_string()),
+            ("LOCALEID".to_string(), "00000415".to_string()),
+        ]);
+
+        assert_eq!(
+            substitute_variables("KBD\t${NAME}\t\"Polish\"", &variables),
+            Ok(("KBD\tkbdpl\t\"Polish\"".to_string(), 1))
+        );
+        assert_eq!(
+            substitute_variables("LOCALEID\t\"${LOCALEID}\"", &variables),
+            Ok(("LOCALEID\t\"00000415\"".to_string(), 1))
+        );
+        assert_eq!(
+            substitute_variables("02\t1\t0\t${ 0024", &variables),
+            Ok(("02\t1\t0\t${ 0024".to_string(), 0))
+        );
+        assert!(substitute_variables("${TEXT}", &variables).is_err());
+    }
+}
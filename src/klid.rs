@@ -0,0 +1,174 @@
+use std::fmt::{self, Display, Formatter};
+
+use widestring::{U16CStr, U16CString};
+use windows::Win32::Globalization::{
+    GetLocaleInfoEx, LCIDToLocaleName, LOCALE_ALLOW_NEUTRAL_NAMES, LOCALE_SLOCALIZEDDISPLAYNAME,
+};
+
+/// LCID of locales without their own LCID that aren't in the user's language list.
+pub const LOCALE_CUSTOM_UNSPECIFIED: u16 = 0x1000;
+
+/// LCIDs Windows assigns to languages without their own LCID in the user's language list.
+/// Which language gets which one depends on the user.
+const TRANSIENT_LCIDS: [u16; 4] = [0x2000, 0x2400, 0x2800, 0x2C00];
+
+pub fn is_transient_lcid(lcid: u16) -> bool {
+    TRANSIENT_LCIDS.contains(&lcid)
+}
+
+/// What range of KLIDs a layout belongs to, decided by the high word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KlidKind {
+    /// The default layout shipped with Windows for the language, e.g. `00000409`.
+    System,
+    /// Another layout shipped with Windows for the language, e.g. `00010409` for US-Dvorak.
+    SystemVariant(u16),
+    /// An input method editor, e.g. `e0010411`.
+    Ime,
+    /// A layout installed by MSKLC or klc-install, e.g. `a0000415` or `f0010415`.
+    Custom,
+}
+
+/// A keyboard layout identifier, the name of the layout's registry key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Klid {
+    /// The device identifier in the high word.
+    pub device: u16,
+    /// The language of the layout in the low word.
+    pub language: u16,
+}
+
+impl Klid {
+    /// Parses an 8-digit hexadecimal KLID.
+    pub fn parse(klid: &str) -> Result<Klid, String> {
+        let value = match u32::from_str_radix(klid, 16) {
+            Ok(value) if klid.len() == 8 && klid.chars().all(|c| c.is_ascii_hexdigit()) => value,
+            _ => {
+                return Err(format!(
+                    "Invalid KLID {}. It must be an 8-digit hexadecimal number.",
+                    klid
+                ))
+            }
+        };
+
+        Ok(Klid {
+            device: (value >> 16) as u16,
+            language: value as u16,
+        })
+    }
+
+    pub fn get_kind(&self) -> KlidKind {
+        match self.device >> 12 {
+            0 if self.device == 0 => KlidKind::System,
+            0 => KlidKind::SystemVariant(self.device),
+            0xE => KlidKind::Ime,
+            _ => KlidKind::Custom,
+        }
+    }
+
+    /// Whether the layout was installed by the user rather than Windows, like `list` tells them
+    /// apart. IMEs have high KLIDs too, but they come with Windows.
+    pub fn is_custom(&self) -> bool {
+        self.device >= 0x0080 && self.get_kind() != KlidKind::Ime
+    }
+}
+
+impl Display for Klid {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04x}{:04x}", self.device, self.language)
+    }
+}
+
+impl Display for KlidKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            KlidKind::System => write!(f, "system layout"),
+            KlidKind::SystemVariant(variant) => write!(f, "system layout, variant {}", variant),
+            KlidKind::Ime => write!(f, "input method editor (IME)"),
+            KlidKind::Custom => write!(f, "custom layout"),
+        }
+    }
+}
+
+/// Returns the language tag of the LCID, e.g. `pl-PL`, or `None` if Windows doesn't know it.
+pub fn get_locale_name(language: u16) -> Option<String> {
+    let mut locale_name = [0u16; 85];
+    let length = unsafe {
+        LCIDToLocaleName(
+            language as u32,
+            Some(&mut locale_name),
+            LOCALE_ALLOW_NEUTRAL_NAMES,
+        )
+    };
+    if length == 0 {
+        return None;
+    }
+
+    U16CStr::from_slice_truncate(&locale_name)
+        .ok()
+        .map(|name| name.to_string_lossy())
+}
+
+/// Returns the name of the language in the language of Windows, e.g. `Polish (Poland)`,
+/// or `None` if Windows doesn't know the LCID.
+pub fn get_language_display_name(language: u16) -> Option<String> {
+    let locale_name = U16CString::from_str_truncate(get_locale_name(language)?);
+
+    let mut display_name = [0u16; 128];
+    let length = unsafe {
+        GetLocaleInfoEx(
+            windows::core::PCWSTR(locale_name.as_ptr()),
+            LOCALE_SLOCALIZEDDISPLAYNAME,
+            Some(&mut display_name),
+        )
+    };
+    if length == 0 {
+        return None;
+    }
+
+    U16CStr::from_slice_truncate(&display_name)
+        .ok()
+        .map(|name| name.to_string_lossy())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_klid() {
+        let klid = Klid::parse("f0010415").unwrap();
+        assert_eq!(
+            klid,
+            Klid {
+                device: 0xf001,
+                language: 0x0415,
+            }
+        );
+        assert_eq!(klid.get_kind(), KlidKind::Custom);
+        assert_eq!(klid.to_string(), "f0010415");
+
+        assert_eq!(
+            Klid::parse("00000409").unwrap().get_kind(),
+            KlidKind::System
+        );
+        assert_eq!(
+            Klid::parse("00010409").unwrap().get_kind(),
+            KlidKind::SystemVariant(1)
+        );
+        assert_eq!(Klid::parse("E0010411").unwrap().get_kind(), KlidKind::Ime);
+        assert!(klid.is_custom());
+        assert!(!Klid::parse("00000415").unwrap().is_custom());
+        assert!(!Klid::parse("E0010411").unwrap().is_custom());
+        assert!(Klid::parse("0409").is_err());
+        assert!(Klid::parse("0000040g").is_err());
+    }
+
+    #[test]
+    fn test_is_transient_lcid() {
+        assert!(is_transient_lcid(0x2000));
+        assert!(is_transient_lcid(0x2C00));
+        assert!(!is_transient_lcid(0x0415));
+        assert!(!is_transient_lcid(LOCALE_CUSTOM_UNSPECIFIED));
+    }
+}
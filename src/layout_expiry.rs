@@ -0,0 +1,85 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{
+    registry_key::{RegistryError, RegistryKey},
+    registry_value::RegistryValueData,
+};
+
+/// Name of the value klc-install stores the expiry of trial installs in, under the layout's
+/// registry key. Holds the Unix time in seconds.
+pub const EXPIRES_VALUE_NAME: &str = "klc-install Expires";
+
+/// Parses a duration like `7d`, `2w` or `12h`.
+pub fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let invalid = || {
+        format!(
+            "Invalid duration {}. Use a number followed by h, d or w, e.g. 7d.",
+            duration
+        )
+    };
+
+    let (unit_index, _) = duration.char_indices().last().ok_or_else(invalid)?;
+    let (count, unit) = duration.split_at(unit_index);
+    let count: u64 = count.parse().map_err(|_| invalid())?;
+
+    let unit_secs = match unit {
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+
+    Ok(Duration::from_secs(count * unit_secs))
+}
+
+pub fn get_layout_expiry(layout_key: &RegistryKey) -> Result<Option<SystemTime>, RegistryError> {
+    let value = layout_key.try_get_value(Some(EXPIRES_VALUE_NAME))?;
+
+    Ok(match value.as_ref().map(|v| v.get_value()) {
+        Some(RegistryValueData::Qword(secs)) => Some(UNIX_EPOCH + Duration::from_secs(*secs)),
+        _ => None,
+    })
+}
+
+/// Returns the registry value holding the expiry.
+pub fn to_expiry_value(expiry: SystemTime) -> RegistryValueData {
+    let secs = expiry
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    RegistryValueData::Qword(secs)
+}
+
+/// Removes the expiry, keeping the layout installed for good.
+///
+/// Returns whether the layout had an expiry.
+pub fn clear_layout_expiry(layout_key: &RegistryKey) -> Result<bool, RegistryError> {
+    match layout_key.delete_value(Some(EXPIRES_VALUE_NAME)) {
+        Ok(()) => Ok(true),
+        Err(RegistryError::NotFound) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("12h"), Ok(Duration::from_secs(12 * 60 * 60)));
+        assert_eq!(
+            parse_duration("7d"),
+            Ok(Duration::from_secs(7 * 24 * 60 * 60))
+        );
+        assert_eq!(
+            parse_duration("2w"),
+            Ok(Duration::from_secs(14 * 24 * 60 * 60))
+        );
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("7").is_err());
+        assert!(parse_duration("7m").is_err());
+    }
+}
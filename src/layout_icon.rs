@@ -0,0 +1,58 @@
+use std::{fs, io::Read, path::Path};
+
+/// Name of the value klc-install stores the icon file of a layout in, under the layout's
+/// registry key. Holds the file name in the system directory.
+///
+/// Windows itself shows the language abbreviation for keyboard layouts, so the icon is for
+/// input switchers and tools that read it.
+pub const ICON_VALUE_NAME: &str = "klc-install Icon";
+
+/// Checks that the file is an icon, starting with the `ICONDIR` header of at least one image.
+pub fn verify_icon_file(icon_path: &Path) -> Result<(), String> {
+    let mut header = [0u8; 6];
+    fs::File::open(icon_path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .map_err(|e| format!("Couldn't read the icon {}. {}", icon_path.display(), e))?;
+
+    let image_count = u16::from_le_bytes([header[4], header[5]]);
+    if header[..4] != [0, 0, 1, 0] || image_count == 0 {
+        return Err(format!("{} isn't an .ICO file!", icon_path.display()));
+    }
+
+    Ok(())
+}
+
+/// Names the icon after the layout DLL, so they're found next to each other.
+pub fn get_icon_file_name(dll_name: &str) -> String {
+    let stem = Path::new(dll_name)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or(dll_name);
+
+    format!("{}.ico", stem)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_verify_icon_file() {
+        let icon_path = std::env::temp_dir().join(format!("icon-{}.ico", std::process::id()));
+
+        fs::write(&icon_path, [0, 0, 1, 0, 1, 0, 16, 16]).unwrap();
+        let icon = verify_icon_file(&icon_path);
+        fs::write(&icon_path, [0, 0, 2, 0, 1, 0, 16, 16]).unwrap();
+        let cursor = verify_icon_file(&icon_path);
+        _ = fs::remove_file(&icon_path);
+
+        assert!(icon.is_ok());
+        assert!(cursor.is_err());
+    }
+
+    #[test]
+    fn test_get_icon_file_name() {
+        assert_eq!(get_icon_file_name("multilin.dll"), "multilin.ico");
+        assert_eq!(get_icon_file_name("kbdpl2.DLL"), "kbdpl2.ico");
+    }
+}
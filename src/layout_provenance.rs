@@ -0,0 +1,127 @@
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    install_plan::InstallPlan,
+    registry_key::{RegistryError, RegistryKey},
+    registry_value::RegistryValueData,
+};
+
+/// Names of the values klc-install records how it installed a layout in, under the layout's
+/// registry key.
+pub const SHA256_VALUE_NAME: &str = "klc-install SHA256";
+pub const SOURCE_VALUE_NAME: &str = "klc-install Source";
+pub const VERSION_VALUE_NAME: &str = "klc-install Version";
+/// Holds the Unix time in seconds.
+pub const INSTALLED_VALUE_NAME: &str = "klc-install Installed";
+
+/// Where an installed layout came from, so later operations can tell what klc-install installed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    /// SHA-256 of the installed DLL, in lowercase hexadecimal.
+    pub sha256: String,
+    /// The file or URL the layout was installed from.
+    pub source: String,
+    /// Version of klc-install that installed the layout.
+    pub tool_version: String,
+    pub installed: SystemTime,
+}
+
+pub fn get_file_sha256(file_path: &Path) -> Result<String, String> {
+    let content =
+        fs::read(file_path).map_err(|e| format!("Couldn't read {}. {}", file_path.display(), e))?;
+
+    Ok(format!("{:x}", Sha256::digest(content)))
+}
+
+impl Provenance {
+    /// Describes the DLL being installed now by this version of the tool.
+    pub fn new(dll_path: &Path, source: String) -> Result<Provenance, String> {
+        Ok(Provenance {
+            sha256: get_file_sha256(dll_path)?,
+            source,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            installed: SystemTime::now(),
+        })
+    }
+
+    /// Reads the provenance of the layout, if klc-install recorded it.
+    pub fn read(layout_key: &RegistryKey) -> Result<Option<Provenance>, RegistryError> {
+        let read_str = |name: &str| -> Result<Option<String>, RegistryError> {
+            layout_key.try_get_string(Some(name))
+        };
+
+        let Some(sha256) = read_str(SHA256_VALUE_NAME)? else {
+            return Ok(None);
+        };
+        let installed_secs = match layout_key.try_get_value(Some(INSTALLED_VALUE_NAME))? {
+            Some(value) => match value.get_value() {
+                RegistryValueData::Qword(secs) => *secs,
+                _ => 0,
+            },
+            None => 0,
+        };
+
+        Ok(Some(Provenance {
+            sha256,
+            source: read_str(SOURCE_VALUE_NAME)?.unwrap_or_default(),
+            tool_version: read_str(VERSION_VALUE_NAME)?.unwrap_or_default(),
+            installed: UNIX_EPOCH + Duration::from_secs(installed_secs),
+        }))
+    }
+
+    /// Writes the provenance to the layout's registry key.
+    pub fn plan(&self, plan: &mut InstallPlan, layout_key_path: &str) {
+        use RegistryValueData as RVD;
+
+        let installed_secs = self
+            .installed
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        plan.set_value(
+            layout_key_path,
+            SHA256_VALUE_NAME,
+            RVD::String(self.sha256.clone()),
+        );
+        plan.set_value(
+            layout_key_path,
+            SOURCE_VALUE_NAME,
+            RVD::String(self.source.clone()),
+        );
+        plan.set_value(
+            layout_key_path,
+            VERSION_VALUE_NAME,
+            RVD::String(self.tool_version.clone()),
+        );
+        plan.set_value(
+            layout_key_path,
+            INSTALLED_VALUE_NAME,
+            RVD::Qword(installed_secs),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_get_file_sha256() {
+        let file_path = std::env::temp_dir().join(format!("sha256-{}.dll", std::process::id()));
+        fs::write(&file_path, b"abc").unwrap();
+        let sha256 = get_file_sha256(&file_path);
+        _ = fs::remove_file(&file_path);
+
+        assert_eq!(
+            sha256.unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
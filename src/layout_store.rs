@@ -0,0 +1,222 @@
+use std::{path::Path, time::SystemTime};
+
+use crate::{
+    exit_code::CommandError,
+    install::{install_layout, InstallOptions},
+    klid::Klid,
+    layout_expiry::get_layout_expiry,
+    layout_provenance::Provenance,
+    layout_tags::get_layout_tags,
+    layout_version::get_layout_version,
+    layouts::get_layouts_key,
+    registry_key::{RegistryError, RegistryKey},
+    uninstall::{remove_layout, UninstallOptions},
+};
+
+/// A layout registered in the Keyboard Layouts key, with what klc-install recorded about it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstalledLayout {
+    pub klid: Klid,
+    pub layout_id: Option<u16>,
+    /// Name of the DLL in System32.
+    pub layout_file: Option<String>,
+    pub layout_text: Option<String>,
+    pub display_name: Option<String>,
+    /// Version of the layout file, if it was installed from a .KLC file with one.
+    pub version: Option<String>,
+    pub tags: Vec<String>,
+    /// When the trial of the layout ends.
+    pub expiry: Option<SystemTime>,
+    pub provenance: Option<Provenance>,
+}
+
+impl InstalledLayout {
+    fn read(klid: Klid, layout_key: &RegistryKey) -> Result<InstalledLayout, RegistryError> {
+        let read_str = |name: &str| -> Result<Option<String>, RegistryError> {
+            layout_key.try_get_string(Some(name))
+        };
+
+        Ok(InstalledLayout {
+            klid,
+            layout_id: read_str("Layout Id")?.and_then(|id| u16::from_str_radix(&id, 16).ok()),
+            layout_file: read_str("Layout File")?,
+            layout_text: read_str("Layout Text")?,
+            display_name: read_str("Layout Display Name")?,
+            version: get_layout_version(layout_key)?,
+            tags: get_layout_tags(layout_key)?,
+            expiry: get_layout_expiry(layout_key)?,
+            provenance: Provenance::read(layout_key)?,
+        })
+    }
+
+    /// The name of the layout's registry key, e.g. `a0010415`.
+    pub fn get_key_name(&self) -> String {
+        self.klid.to_string()
+    }
+}
+
+/// Selects installed layouts, like the options of the uninstall command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LayoutQuery {
+    Klid(Klid),
+    Id(u16),
+    /// Matched ignoring case, or as a part of the text if no layout matches exactly.
+    Text(String),
+    /// Name of the DLL the layouts use, e.g. `kbdmine.dll`.
+    File(String),
+    Tag(String),
+}
+
+/// Picks the layouts matching the query.
+fn find_layouts(layouts: Vec<InstalledLayout>, query: &LayoutQuery) -> Vec<InstalledLayout> {
+    let mut exact = Vec::new();
+    let mut partial = Vec::new();
+
+    for layout in layouts {
+        let matches = match query {
+            LayoutQuery::Klid(klid) => layout.klid == *klid,
+            LayoutQuery::Id(id) => layout.layout_id == Some(*id),
+            LayoutQuery::File(dll_name) => layout
+                .layout_file
+                .as_ref()
+                .is_some_and(|file| file.eq_ignore_ascii_case(dll_name)),
+            LayoutQuery::Tag(tag) => layout.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)),
+            LayoutQuery::Text(text) => match &layout.layout_text {
+                Some(layout_text) if layout_text.eq_ignore_ascii_case(text) => true,
+                Some(layout_text) if layout_text.to_lowercase().contains(&text.to_lowercase()) => {
+                    partial.push(layout);
+                    continue;
+                }
+                _ => false,
+            },
+        };
+
+        if matches {
+            exact.push(layout);
+        }
+    }
+
+    if exact.is_empty() {
+        partial
+    } else {
+        exact
+    }
+}
+
+/// The layouts installed on this machine, for programs using klc-install as a library.
+///
+/// The methods do what the commands of the same name do, printing the same messages.
+pub struct LayoutStore {
+    layouts_key: RegistryKey,
+}
+
+impl LayoutStore {
+    pub fn open() -> Result<LayoutStore, RegistryError> {
+        Ok(LayoutStore {
+            layouts_key: get_layouts_key()?,
+        })
+    }
+
+    /// Reads every layout, skipping keys that aren't KLIDs.
+    pub fn get_installed_layouts(&self) -> Result<Vec<InstalledLayout>, RegistryError> {
+        let mut layouts = Vec::new();
+
+        for layout_key in self.layouts_key.iter_children() {
+            let layout_key = layout_key?;
+            // Other software can leave keys that aren't KLIDs
+            let Ok(klid) = Klid::parse(layout_key.get_name()) else {
+                continue;
+            };
+
+            layouts.push(InstalledLayout::read(klid, &layout_key)?);
+        }
+
+        Ok(layouts)
+    }
+
+    pub fn get(&self, klid: Klid) -> Result<InstalledLayout, RegistryError> {
+        let layout_key = self.layouts_key.get_subkey(&klid.to_string())?;
+        InstalledLayout::read(klid, &layout_key)
+    }
+
+    pub fn find(&self, query: &LayoutQuery) -> Result<Vec<InstalledLayout>, RegistryError> {
+        Ok(find_layouts(self.get_installed_layouts()?, query))
+    }
+
+    /// Installs the layout file, returning the layouts it registered, or the ones that
+    /// already had it installed.
+    ///
+    /// Nothing is installed nor returned for a dry run or an install that wasn't confirmed.
+    pub fn install(
+        &self,
+        file: &Path,
+        msklc: Option<&str>,
+        options: &InstallOptions,
+    ) -> Result<Vec<InstalledLayout>, CommandError> {
+        let layout_keys = install_layout(file, msklc, options)?;
+
+        layout_keys
+            .iter()
+            .map(|layout_key_name| {
+                let klid = Klid::parse(layout_key_name)?;
+                self.get(klid).map_err(|e| e.to_string())
+            })
+            .collect::<Result<_, String>>()
+            .map_err(CommandError::from)
+    }
+
+    pub fn remove(&self, klid: Klid, options: &UninstallOptions) -> Result<(), String> {
+        remove_layout(&klid.to_string(), options)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn layout(klid: &str, layout_id: u16, layout_text: &str, layout_file: &str) -> InstalledLayout {
+        InstalledLayout {
+            klid: Klid::parse(klid).unwrap(),
+            layout_id: Some(layout_id),
+            layout_file: Some(layout_file.to_string()),
+            layout_text: Some(layout_text.to_string()),
+            display_name: None,
+            version: None,
+            tags: vec!["work".to_string()],
+            expiry: None,
+            provenance: None,
+        }
+    }
+
+    #[test]
+    fn test_find_layouts() {
+        let layouts = vec![
+            layout("00000415", 0x0000, "Polish (Programmers)", "kbdpl1.dll"),
+            layout("a0000415", 0x0f00, "Polish Extended", "kbdplx.dll"),
+            layout("a0010409", 0x0f01, "Polish", "kbdplx.dll"),
+        ];
+        let find = |query: LayoutQuery| -> Vec<String> {
+            find_layouts(layouts.clone(), &query)
+                .iter()
+                .map(InstalledLayout::get_key_name)
+                .collect()
+        };
+
+        assert_eq!(find(LayoutQuery::Text("polish".to_string())), ["a0010409"]);
+        assert_eq!(
+            find(LayoutQuery::Text("pol".to_string())),
+            ["00000415", "a0000415", "a0010409"]
+        );
+        assert_eq!(
+            find(LayoutQuery::File("KBDPLX.DLL".to_string())),
+            ["a0000415", "a0010409"]
+        );
+        assert_eq!(find(LayoutQuery::Id(0x0f00)), ["a0000415"]);
+        assert_eq!(
+            find(LayoutQuery::Klid(Klid::parse("A0000415").unwrap())),
+            ["a0000415"]
+        );
+        assert_eq!(find(LayoutQuery::Tag("Work".to_string())).len(), 3);
+        assert!(find(LayoutQuery::Text("German".to_string())).is_empty());
+    }
+}
@@ -0,0 +1,67 @@
+use crate::{
+    registry_key::{RegistryError, RegistryKey},
+    registry_value::RegistryValueData,
+};
+
+/// Name of the value klc-install stores layout tags in, under the layout's registry key.
+pub const TAGS_VALUE_NAME: &str = "klc-install Tags";
+
+pub fn get_layout_tags(layout_key: &RegistryKey) -> Result<Vec<String>, RegistryError> {
+    let value = layout_key.try_get_value(Some(TAGS_VALUE_NAME))?;
+
+    let tags = match value.as_ref().map(|v| v.get_value()) {
+        Some(RegistryValueData::MultiString(tags)) => tags.clone(),
+        Some(RegistryValueData::String(tag)) => vec![tag.clone()],
+        _ => Vec::new(),
+    };
+
+    Ok(tags.into_iter().filter(|tag| !tag.is_empty()).collect())
+}
+
+pub fn set_layout_tags(layout_key: &RegistryKey, tags: Vec<String>) -> Result<(), RegistryError> {
+    layout_key.set_value(Some(TAGS_VALUE_NAME), RegistryValueData::MultiString(tags))
+}
+
+pub fn has_layout_tag(layout_key: &RegistryKey, tag: &str) -> Result<bool, RegistryError> {
+    Ok(get_layout_tags(layout_key)?
+        .iter()
+        .any(|t| t.eq_ignore_ascii_case(tag)))
+}
+
+/// Adds the tags to the layout, ignoring ones it already has.
+///
+/// Returns the tags that were actually added.
+pub fn add_layout_tags(
+    layout_key: &RegistryKey,
+    tags: &[String],
+) -> Result<Vec<String>, RegistryError> {
+    let mut current = get_layout_tags(layout_key)?;
+    let mut added = Vec::new();
+
+    for tag in tags {
+        if !current.iter().any(|t| t.eq_ignore_ascii_case(tag)) {
+            current.push(tag.clone());
+            added.push(tag.clone());
+        }
+    }
+
+    set_layout_tags(layout_key, current)?;
+
+    Ok(added)
+}
+
+/// Removes the tags from the layout.
+///
+/// Returns the tags that were actually removed.
+pub fn remove_layout_tags(
+    layout_key: &RegistryKey,
+    tags: &[String],
+) -> Result<Vec<String>, RegistryError> {
+    let (removed, kept): (Vec<String>, Vec<String>) = get_layout_tags(layout_key)?
+        .into_iter()
+        .partition(|t| tags.iter().any(|tag| tag.eq_ignore_ascii_case(t)));
+
+    set_layout_tags(layout_key, kept)?;
+
+    Ok(removed)
+}
@@ -0,0 +1,38 @@
+use std::cmp::Ordering;
+
+use crate::{
+    registry_key::{RegistryError, RegistryKey},
+    version_resource::parse_version,
+};
+
+/// Name of the value klc-install stores the `VERSION` of the installed KLC file in, under the
+/// layout's registry key.
+pub const LAYOUT_VERSION_VALUE_NAME: &str = "klc-install Layout Version";
+
+pub fn get_layout_version(layout_key: &RegistryKey) -> Result<Option<String>, RegistryError> {
+    layout_key.try_get_string(Some(LAYOUT_VERSION_VALUE_NAME))
+}
+
+/// Compares two layout versions part by part, so `1.10` is newer than `1.9`.
+pub fn compare_layout_versions(a: &str, b: &str) -> Result<Ordering, String> {
+    Ok(parse_version(a)?.cmp(&parse_version(b)?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compare_layout_versions() {
+        assert_eq!(
+            compare_layout_versions("1.10", "1.9"),
+            Ok(Ordering::Greater)
+        );
+        assert_eq!(
+            compare_layout_versions("1.0", "1.0.0.0"),
+            Ok(Ordering::Equal)
+        );
+        assert_eq!(compare_layout_versions("0.9", "1.0"), Ok(Ordering::Less));
+        assert!(compare_layout_versions("1.x", "1.0").is_err());
+    }
+}
@@ -0,0 +1,359 @@
+use std::path::{Path, PathBuf};
+
+use widestring::U16CString;
+use windows::{
+    core::PCWSTR,
+    Win32::{Globalization::LocaleNameToLCID, UI::Shell::FOLDERID_System},
+};
+
+use crate::{
+    arch::Arch,
+    get_known_folder::get_known_folder,
+    klc::KlcInfo,
+    klid::{Klid, LOCALE_CUSTOM_UNSPECIFIED},
+    registry_key::{RegistryError, RegistryKey},
+};
+
+pub fn get_layouts_key() -> Result<RegistryKey, RegistryError> {
+    RegistryKey::from_path("HKLM\\SYSTEM\\CurrentControlSet\\Control\\Keyboard Layouts")
+}
+
+/// Validates a layout registry key (KLID), returning it normalized to lowercase.
+pub fn parse_layout_key(key: &str) -> Result<String, String> {
+    if key.len() != 8 || !key.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!(
+            "Invalid registry key {}. It must be an 8-digit hexadecimal number.",
+            key
+        ));
+    }
+
+    Ok(key.to_ascii_lowercase())
+}
+
+/// Validates a layout ID, which must be in the range used by custom layouts.
+pub fn parse_layout_id(id: &str) -> Result<u16, String> {
+    let layout_id = match u16::from_str_radix(id, 16) {
+        Ok(layout_id) if id.len() == 4 => layout_id,
+        _ => {
+            return Err(format!(
+                "Invalid layout ID {}. It must be a 4-digit hexadecimal number.",
+                id
+            ))
+        }
+    };
+
+    if !(0x0F00..0xF000).contains(&layout_id) {
+        return Err(format!(
+            "Invalid layout ID {}. It must be between 0F00 and EFFF.",
+            id
+        ));
+    }
+
+    Ok(layout_id)
+}
+
+/// Resolves a hexadecimal LCID or a language tag to a language ID.
+pub fn parse_locale(locale: &str) -> Result<u16, String> {
+    if (locale.len() == 4 || locale.len() == 8) && locale.chars().all(|c| c.is_ascii_hexdigit()) {
+        return u32::from_str_radix(locale, 16)
+            .map(|lcid| lcid as u16)
+            .map_err(|e| e.to_string());
+    }
+
+    let name = U16CString::from_str(locale).map_err(|e| e.to_string())?;
+    let lcid = unsafe { LocaleNameToLCID(PCWSTR(name.as_ptr()), 0) };
+    if lcid == 0 {
+        return Err(format!(
+            "Unknown locale {}. Use a hexadecimal LCID or a language tag like pl-PL.",
+            locale
+        ));
+    }
+
+    // Windows only assigns a transient LCID to languages in the user's language list
+    if lcid as u16 == LOCALE_CUSTOM_UNSPECIFIED {
+        return Err(format!(
+            "The locale {} has no LCID. Add the language in the Windows settings first.",
+            locale
+        ));
+    }
+
+    Ok(lcid as u16)
+}
+
+pub fn get_next_layout_key(locale_id: u16) -> Result<String, String> {
+    let layouts_key = get_layouts_key().map_err(|e| e.to_string())?;
+    get_next_layout_key_in(&layouts_key, locale_id)
+}
+
+/// Finds the first free layout key for the locale in a Keyboard Layouts key,
+/// e.g. the one of an offline image.
+pub fn get_next_layout_key_in(layouts_key: &RegistryKey, locale_id: u16) -> Result<String, String> {
+    let mut id: u32 = 0xf0000000 | (locale_id as u32);
+
+    loop {
+        let id_str = format!("{:08x}", id);
+
+        let layout_key = layouts_key.get_subkey(&id_str);
+
+        if let Err(RegistryError::NotFound) = layout_key {
+            return Ok(id_str);
+        } else if layout_key.is_err() {
+            return Err(layout_key.unwrap_err().to_string());
+        }
+
+        drop(layout_key);
+
+        if id | 0xffff0000 == 0xffff0000 {
+            return Err("No more layout keys for this locale are available.".to_string());
+        }
+
+        id += 0x000f0000;
+    }
+}
+
+/// Finds the first layout ID not used by any layout, nor in `reserved`.
+pub fn get_next_layout_id(reserved: &[u16]) -> Result<u16, String> {
+    let layouts_key = get_layouts_key().map_err(|e| e.to_string())?;
+    get_next_layout_id_in(&layouts_key, reserved)
+}
+
+/// Like `get_next_layout_id`, but for the layouts of the Keyboard Layouts key.
+pub fn get_next_layout_id_in(layouts_key: &RegistryKey, reserved: &[u16]) -> Result<u16, String> {
+    let layout_keys_iter = layouts_key.iter_children();
+
+    let mut layout_ids_used = [false; 0xF000 - 0x0F00];
+
+    let mut mark_layout_id_used = |layout_id: u16| {
+        if layout_id < 0x0F00 {
+            return;
+        }
+
+        layout_ids_used[(layout_id - 0x0F00) as usize] = true;
+    };
+
+    for layout_err in layout_keys_iter {
+        let layout_key = layout_err.map_err(|e| e.to_string())?;
+        let layout_id = layout_key
+            .try_get_string(Some("Layout Id"))
+            .map_err(|e| e.to_string())?;
+
+        // An invalid ID can't collide with the ones allocated here
+        if let Some(Ok(id)) = layout_id.map(|id| u16::from_str_radix(&id, 16)) {
+            mark_layout_id_used(id);
+        }
+    }
+    for &layout_id in reserved {
+        mark_layout_id_used(layout_id);
+    }
+
+    let check_layout_id_used =
+        |layout_id: u16| -> bool { layout_ids_used[(layout_id - 0x0F00) as usize] };
+
+    for id in 0x0F00..0xF000 {
+        if !check_layout_id_used(id) {
+            return Ok(id);
+        }
+    }
+
+    Err("No more layout IDs are available.".to_string())
+}
+
+/// Checks whether any layout other than `ignored_key` uses the layout ID.
+pub fn is_layout_id_used(layout_id: u16, ignored_key: Option<&str>) -> Result<bool, String> {
+    let layouts_key = get_layouts_key().map_err(|e| e.to_string())?;
+    is_layout_id_used_in(&layouts_key, layout_id, ignored_key)
+}
+
+/// Like `is_layout_id_used`, but for the layouts of the Keyboard Layouts key.
+pub fn is_layout_id_used_in(
+    layouts_key: &RegistryKey,
+    layout_id: u16,
+    ignored_key: Option<&str>,
+) -> Result<bool, String> {
+    for layout_err in layouts_key.iter_children() {
+        let layout_key = layout_err.map_err(|e| e.to_string())?;
+        if ignored_key.is_some_and(|key| layout_key.get_name().eq_ignore_ascii_case(key)) {
+            continue;
+        }
+
+        let id = layout_key
+            .try_get_string(Some("Layout Id"))
+            .map_err(|e| e.to_string())?;
+
+        if let Some(id) = id {
+            if u16::from_str_radix(&id, 16) == Ok(layout_id) {
+                return Ok(true);
+            }
+        }
+    }
+
+    Ok(false)
+}
+
+/// Reads back the registered layout to make sure it points to an existing DLL.
+pub fn verify_installed_layout(layout_key_name: &str, dll_name: &str) -> Result<(), String> {
+    let layout_key = get_layouts_key()
+        .and_then(|key| key.get_subkey(layout_key_name))
+        .map_err(|e| e.to_string())?;
+    let layout_file = layout_key
+        .get_string(Some("Layout File"))
+        .map_err(|e| e.to_string())?;
+
+    if !layout_file.eq_ignore_ascii_case(dll_name) {
+        return Err(format!(
+            "The registered layout file {} doesn't match {}.",
+            layout_file, dll_name
+        ));
+    }
+
+    let system32_path = get_known_folder(&FOLDERID_System)?;
+    if !system32_path.join(dll_name).exists() {
+        return Err(format!(
+            "The DLL file {} is missing from System32.",
+            dll_name
+        ));
+    }
+
+    Ok(())
+}
+
+/// Finds the layouts whose `Layout File` is the DLL, with their `Layout Text`.
+pub fn find_layouts_using_dll(dll_name: &str) -> Result<Vec<(String, Option<String>)>, String> {
+    let mut layouts = Vec::new();
+
+    for layout_key in get_layouts_key()
+        .map_err(|e| e.to_string())?
+        .iter_children()
+    {
+        let layout_key = layout_key.map_err(|e| e.to_string())?;
+        let read_str = |name: &str| -> Result<Option<String>, String> {
+            layout_key
+                .try_get_string(Some(name))
+                .map_err(|e| e.to_string())
+        };
+
+        let layout_file = read_str("Layout File")?;
+        if layout_file.is_some_and(|file| file.eq_ignore_ascii_case(dll_name)) {
+            layouts.push((layout_key.get_name().to_string(), read_str("Layout Text")?));
+        }
+    }
+
+    Ok(layouts)
+}
+
+/// Name of the value klc-install stores the name the layout's DLL was built as in, under the
+/// layout's registry key. The `Layout File` differs when the DLL was renamed to avoid a different
+/// one with the same name.
+pub const BUILT_DLL_VALUE_NAME: &str = "klc-install Built DLL";
+
+/// A custom layout installed from a layout built as some DLL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayoutInstall {
+    pub layout_key_name: String,
+    /// Name of the DLL in the system directories, which may differ from the one it was built as.
+    pub layout_file: String,
+    pub layout_text: Option<String>,
+}
+
+/// Finds the custom layouts installed from the layout built as the DLL, even if their DLL was
+/// renamed.
+///
+/// Layouts installed before klc-install recorded the name are matched by their `Layout File`.
+pub fn find_installs_of_dll(dll_name: &str) -> Result<Vec<LayoutInstall>, String> {
+    let mut installs = Vec::new();
+
+    for layout_key in get_layouts_key()
+        .map_err(|e| e.to_string())?
+        .iter_children()
+    {
+        let layout_key = layout_key.map_err(|e| e.to_string())?;
+        // System layouts with a DLL of the same name aren't installs of this one
+        let Ok(klid) = Klid::parse(layout_key.get_name()) else {
+            continue;
+        };
+        if !klid.is_custom() {
+            continue;
+        }
+
+        let read_str = |name: &str| -> Result<Option<String>, String> {
+            layout_key
+                .try_get_string(Some(name))
+                .map_err(|e| e.to_string())
+        };
+        let Some(layout_file) = read_str("Layout File")? else {
+            continue;
+        };
+        let built_dll_name = read_str(BUILT_DLL_VALUE_NAME)?;
+
+        if built_dll_name
+            .as_deref()
+            .unwrap_or(&layout_file)
+            .eq_ignore_ascii_case(dll_name)
+        {
+            installs.push(LayoutInstall {
+                layout_key_name: layout_key.get_name().to_string(),
+                layout_file,
+                layout_text: read_str("Layout Text")?,
+            });
+        }
+    }
+
+    Ok(installs)
+}
+
+/// Gets the name of the DLL a layout file is installed as.
+pub fn get_layout_dll_name(file_path: &Path) -> Result<String, String> {
+    let extension = file_path.extension().map(|ext| ext.to_ascii_lowercase());
+    if extension == Some("klc".into()) {
+        let klc_info = KlcInfo::read_from_file(file_path)?;
+        return Ok(format!("{}.dll", klc_info.layout_name));
+    }
+
+    file_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| format!("Invalid layout file {}.", file_path.display()))
+}
+
+/// Lists the system directories layout DLLs are installed in: System32, and SysWOW64 on 64-bit
+/// Windows.
+pub fn get_dll_system_dirs() -> Result<Vec<PathBuf>, String> {
+    let native_arch = Arch::get_native();
+    let mut system_dirs = Vec::new();
+
+    for arch in [native_arch, Arch::Wow64] {
+        if let Some(system_dir) = arch.get_system_dir(native_arch)? {
+            system_dirs.push(system_dir);
+        }
+    }
+
+    Ok(system_dirs)
+}
+
+/// Finds the copies of the layout DLL in System32, and in SysWOW64 if it was installed there too.
+pub fn get_installed_dll_paths(dll_name: &str) -> Result<Vec<PathBuf>, String> {
+    Ok(get_dll_system_dirs()?
+        .into_iter()
+        .map(|system_dir| system_dir.join(dll_name))
+        // Only the native build is always installed
+        .filter(|dll_path| dll_path.exists())
+        .collect())
+}
+
+/// Where the copy of a layout DLL from the system directory is kept in a directory of saved
+/// layouts, like a backup or a profile.
+///
+/// The builds have the same name, so the ones from other directories than System32 go in a
+/// subdirectory named like it, e.g. `SysWOW64\kbdmine.dll`.
+pub fn get_saved_dll_path(
+    saved_dir: &Path,
+    system_dir: &Path,
+    dll_name: &str,
+) -> Result<PathBuf, String> {
+    if system_dir == get_known_folder(&FOLDERID_System)? {
+        return Ok(saved_dir.join(dll_name));
+    }
+
+    let system_dir_name = system_dir.file_name().unwrap_or_default();
+    Ok(saved_dir.join(system_dir_name).join(dll_name))
+}
@@ -0,0 +1,57 @@
+//! Installing custom keyboard layouts made with MSKLC, and managing the installed ones.
+//!
+//! The `klc-install` command line tool is a thin layer over this library, so other tools can
+//! read, compile and install layouts the same way without running it.
+
+pub mod arch;
+pub mod bundle;
+pub mod color;
+pub mod compile;
+pub mod config;
+pub mod control_sets;
+pub mod docs;
+pub mod download;
+pub mod error;
+pub mod event_log;
+pub mod exit_code;
+pub mod ffi;
+pub mod file_info;
+pub mod get_known_folder;
+pub mod history;
+pub mod hkl;
+pub mod hooks;
+pub mod input_refresh;
+pub mod install;
+pub mod install_plan;
+pub mod install_progress;
+pub mod instance_lock;
+pub mod journal;
+pub mod json_schema;
+pub mod klc;
+pub mod klid;
+pub mod layout_expiry;
+pub mod layout_icon;
+pub mod layout_provenance;
+pub mod layout_store;
+pub mod layout_tags;
+pub mod layout_version;
+pub mod layouts;
+pub mod list_theme;
+pub mod loaded_layouts;
+pub mod manifest;
+pub mod offline_image;
+pub mod pe_image;
+pub mod porcelain;
+pub mod preload;
+pub mod profile;
+pub mod reg_file;
+pub mod registry_key;
+pub mod registry_value;
+pub mod selftest;
+pub mod tool_runner;
+pub mod transaction;
+pub mod uninstall;
+pub mod update;
+pub mod user_hives;
+pub mod utils;
+pub mod version_resource;
@@ -0,0 +1,302 @@
+use clap::ValueEnum;
+
+/// A column of the layout list.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListColumn {
+    Key,
+    Id,
+    Name,
+    DisplayName,
+    Version,
+    Signature,
+    Tags,
+    /// Whether the layout is a custom or a system one.
+    Kind,
+    /// Whether the DLL of the layout is in System32, and in SysWOW64 on 64-bit Windows.
+    DllStatus,
+    /// Whether the layout is loaded in this session, or only preloaded for the current user.
+    Status,
+    File,
+}
+
+/// How much room the layout list takes.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ListStyle {
+    /// Columns as wide as their longest value, never cutting values.
+    #[default]
+    Wide,
+    /// Columns no wider than a limit for small terminals, cutting long values.
+    Compact,
+}
+
+/// What the layout list is printed as.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ListFormat {
+    /// Aligned columns for the terminal.
+    #[default]
+    Text,
+    /// A Markdown table, e.g. for documentation or tickets.
+    Markdown,
+    /// A JSON document with every field of the layouts for scripts, ignoring the columns.
+    Json,
+}
+
+/// Columns and style the layout list is printed with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListTheme {
+    pub columns: Vec<ListColumn>,
+    pub style: ListStyle,
+    pub format: ListFormat,
+    /// Width of each column, fitted to the values with `fit_rows`.
+    widths: Vec<usize>,
+}
+
+impl ListColumn {
+    fn get_title(self) -> &'static str {
+        match self {
+            ListColumn::Key => "Key",
+            ListColumn::Id => "ID",
+            ListColumn::Name => "Name",
+            ListColumn::DisplayName => "Display Name",
+            ListColumn::Version => "Version",
+            ListColumn::Signature => "Signature",
+            ListColumn::Tags => "Tags",
+            ListColumn::Kind => "Kind",
+            ListColumn::DllStatus => "DLL",
+            ListColumn::Status => "Status",
+            ListColumn::File => "File",
+        }
+    }
+
+    /// How wide the column can get in the compact style.
+    fn get_compact_width(self) -> usize {
+        match self {
+            ListColumn::Key => 8,
+            ListColumn::Id => 4,
+            ListColumn::Kind => 6,
+            ListColumn::DllStatus => 8,
+            ListColumn::Status => 7,
+            ListColumn::Name | ListColumn::DisplayName => 20,
+            ListColumn::Version => 11,
+            ListColumn::Signature | ListColumn::Tags => 12,
+            ListColumn::File => 16,
+        }
+    }
+}
+
+impl ListTheme {
+    /// Uses the given columns, or the default ones if there are none.
+    ///
+    /// Markdown reports also show the kind of the layouts and whether their DLLs exist.
+    pub fn new(
+        columns: Vec<ListColumn>,
+        style: ListStyle,
+        format: ListFormat,
+        file_info: bool,
+    ) -> Self {
+        let columns = if columns.is_empty() {
+            let mut columns = vec![
+                ListColumn::Key,
+                ListColumn::Id,
+                ListColumn::Name,
+                ListColumn::DisplayName,
+                ListColumn::Status,
+            ];
+            if format == ListFormat::Markdown {
+                columns.extend([ListColumn::Kind, ListColumn::DllStatus]);
+            }
+            if file_info {
+                columns.extend([ListColumn::Version, ListColumn::Signature]);
+            }
+            columns.push(ListColumn::File);
+            columns
+        } else {
+            columns
+        };
+
+        let widths = columns
+            .iter()
+            .map(|column| column.get_title().chars().count())
+            .collect();
+
+        Self {
+            columns,
+            style,
+            format,
+            widths,
+        }
+    }
+
+    /// Gets the cells of a line of the list, getting the value of each column from `get_value`.
+    pub fn get_cells(&self, get_value: impl Fn(ListColumn) -> String) -> Vec<String> {
+        self.columns
+            .iter()
+            .map(|column| get_value(*column))
+            .collect()
+    }
+
+    /// Widens the columns to fit the longest values of the rows, up to the limit of the compact
+    /// style.
+    pub fn fit_rows(&mut self, rows: &[Vec<String>]) {
+        for (i, column) in self.columns.iter().enumerate() {
+            let longest = rows
+                .iter()
+                .filter_map(|row| row.get(i))
+                .map(|value| value.chars().count())
+                .max()
+                .unwrap_or(0);
+            let mut width = self.widths[i].max(longest);
+            if self.style == ListStyle::Compact {
+                width = width.min(column.get_compact_width().max(self.widths[i]));
+            }
+            self.widths[i] = width;
+        }
+    }
+
+    pub fn has_column(&self, column: ListColumn) -> bool {
+        self.columns.contains(&column)
+    }
+
+    fn format_cell(&self, index: usize, value: &str) -> String {
+        if self.format == ListFormat::Markdown {
+            return value.replace('|', "\\|");
+        }
+
+        let column = self.columns[index];
+        let width = self.widths[index];
+        let last = index == self.columns.len() - 1;
+        let length = value.chars().count();
+
+        let value = if self.style == ListStyle::Compact && length > width {
+            let cut: String = value.chars().take(width - 1).collect();
+            format!("{}…", cut)
+        } else {
+            value.to_string()
+        };
+
+        if column == ListColumn::Key {
+            format!("{:>width$}", value)
+        } else if last {
+            value
+        } else {
+            format!("{:<width$}", value)
+        }
+    }
+
+    /// Formats a line of the list from the cells of `get_cells`.
+    pub fn format_row(&self, cells: &[String]) -> String {
+        let cells = cells
+            .iter()
+            .enumerate()
+            .map(|(i, value)| self.format_cell(i, value))
+            .collect::<Vec<_>>();
+
+        match self.format {
+            // JSON lists are printed as a `LayoutListJson` instead of rows
+            ListFormat::Text | ListFormat::Json => cells.join(" "),
+            ListFormat::Markdown => format!("| {} |", cells.join(" | ")),
+        }
+    }
+
+    /// Formats the column titles, followed by the delimiter row for Markdown tables.
+    pub fn format_header(&self) -> String {
+        let header = self.format_row(&self.get_cells(|column| column.get_title().to_string()));
+
+        match self.format {
+            ListFormat::Text | ListFormat::Json => header,
+            ListFormat::Markdown => format!("{}\n|{}", header, " --- |".repeat(self.columns.len())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_row() {
+        let mut theme = ListTheme::new(
+            vec![ListColumn::Key, ListColumn::Name, ListColumn::File],
+            ListStyle::Compact,
+            ListFormat::Text,
+            false,
+        );
+        let row = theme.get_cells(|column| match column {
+            ListColumn::Key => "f0010415".to_string(),
+            ListColumn::Name => "Polish (Programmers) Extended".to_string(),
+            _ => "multilin.dll".to_string(),
+        });
+        theme.fit_rows(std::slice::from_ref(&row));
+
+        assert_eq!(
+            theme.format_row(&row),
+            "f0010415 Polish (Programmers… multilin.dll"
+        );
+        assert_eq!(theme.format_header(), "     Key Name                 File");
+    }
+
+    #[test]
+    fn test_fit_rows() {
+        let mut theme = ListTheme::new(
+            vec![ListColumn::Name, ListColumn::Id, ListColumn::File],
+            ListStyle::Wide,
+            ListFormat::Text,
+            false,
+        );
+        let rows = vec![
+            vec![
+                "Polish (Programmers) Extended Dvorak".to_string(),
+                "-".to_string(),
+                "kbdpl.dll".to_string(),
+            ],
+            vec!["Czech".to_string(), "00c0".to_string(), "a.dll".to_string()],
+        ];
+        theme.fit_rows(&rows);
+
+        assert_eq!(
+            theme.format_row(&rows[0]),
+            "Polish (Programmers) Extended Dvorak -    kbdpl.dll"
+        );
+        assert_eq!(
+            theme.format_row(&rows[1]),
+            "Czech                                00c0 a.dll"
+        );
+        assert_eq!(
+            theme.format_header(),
+            "Name                                 ID   File"
+        );
+    }
+
+    #[test]
+    fn test_default_columns() {
+        let theme = ListTheme::new(Vec::new(), ListStyle::Wide, ListFormat::Text, true);
+
+        assert!(theme.has_column(ListColumn::Signature));
+        assert!(!theme.has_column(ListColumn::Kind));
+        assert_eq!(theme.columns.last(), Some(&ListColumn::File));
+    }
+
+    #[test]
+    fn test_format_markdown() {
+        let theme = ListTheme::new(
+            vec![ListColumn::Key, ListColumn::Name, ListColumn::Kind],
+            ListStyle::Compact,
+            ListFormat::Markdown,
+            false,
+        );
+
+        assert_eq!(
+            theme.format_header(),
+            "| Key | Name | Kind |\n| --- | --- | --- |"
+        );
+        let row = theme.get_cells(|column| match column {
+            ListColumn::Key => "f0010415".to_string(),
+            ListColumn::Name => "Polish | Programmers Extended".to_string(),
+            _ => "custom".to_string(),
+        });
+        assert_eq!(
+            theme.format_row(&row),
+            "| f0010415 | Polish \\| Programmers Extended | custom |"
+        );
+    }
+}
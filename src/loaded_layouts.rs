@@ -0,0 +1,126 @@
+//! The layouts loaded in this session, mapped back to the installed layouts.
+//!
+//! Windows only lists the layouts of the calling session, so other signed in users aren't seen.
+
+use windows::Win32::UI::Input::KeyboardAndMouse::{GetKeyboardLayout, GetKeyboardLayoutList, HKL};
+
+use crate::{
+    hkl::{Hkl, HklLayout},
+    layouts::get_layouts_key,
+    registry_key::{RegistryError, RegistryKey},
+};
+
+/// A layout loaded in this session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadedLayout {
+    /// The handle, e.g. `F0010415`.
+    pub handle: u32,
+    pub hkl: Hkl,
+    /// The registry key of the layout, or `None` if no installed layout has its `Layout Id`.
+    pub klid: Option<String>,
+    /// Whether it's the layout klc-install itself types with.
+    pub active: bool,
+}
+
+/// Lists the HKLs loaded in this session, which are the ones in the language list and the ones
+/// some window switched to.
+pub fn get_loaded_hkls() -> Vec<HKL> {
+    let count = unsafe { GetKeyboardLayoutList(None) };
+    let mut loaded = vec![HKL::default(); count.max(0) as usize];
+    let count = unsafe { GetKeyboardLayoutList(Some(&mut loaded)) };
+    loaded.truncate(count.max(0) as usize);
+    loaded
+}
+
+/// Reads the `Layout Id` of every installed layout that has one, by registry key.
+pub fn read_layout_ids(layouts_key: &RegistryKey) -> Result<Vec<(String, u16)>, RegistryError> {
+    let mut layout_ids = Vec::new();
+
+    for layout_key in layouts_key.iter_children() {
+        let layout_key = layout_key?;
+        let layout_id = layout_key
+            .try_get_string(Some("Layout Id"))?
+            .and_then(|id| u16::from_str_radix(&id, 16).ok());
+
+        if let Some(layout_id) = layout_id {
+            layout_ids.push((layout_key.get_name().to_ascii_lowercase(), layout_id));
+        }
+    }
+
+    Ok(layout_ids)
+}
+
+/// Finds the registry key of the layout the HKL is of.
+///
+/// HKLs of custom layouts only hold the low 12 bits of the `Layout Id`, so it's looked up among
+/// the installed layouts, preferring the one registered for the language of the HKL.
+pub fn resolve_hkl(hkl: &Hkl, layout_ids: &[(String, u16)]) -> Option<String> {
+    let id = match &hkl.layout {
+        HklLayout::Klid(klid) => return Some(klid.clone()),
+        HklLayout::LayoutId(id) => *id,
+    };
+
+    let mut matches = layout_ids
+        .iter()
+        .filter(|(_, layout_id)| layout_id & 0x0FFF == id)
+        .map(|(klid, _)| klid);
+    let language = format!("{:04x}", hkl.language);
+
+    matches
+        .clone()
+        .find(|klid| klid.ends_with(&language))
+        .or_else(|| matches.next())
+        .cloned()
+}
+
+/// Lists the layouts loaded in this session with their registry keys.
+pub fn get_loaded_layouts() -> Result<Vec<LoadedLayout>, String> {
+    let layouts_key = get_layouts_key().map_err(|e| e.to_string())?;
+    let layout_ids = read_layout_ids(&layouts_key).map_err(|e| e.to_string())?;
+    let active = unsafe { GetKeyboardLayout(0) };
+
+    Ok(get_loaded_hkls()
+        .into_iter()
+        .map(|handle| {
+            let hkl = Hkl::from(handle.0 as usize as u32);
+            LoadedLayout {
+                handle: handle.0 as usize as u32,
+                klid: resolve_hkl(&hkl, &layout_ids),
+                hkl,
+                active: handle == active,
+            }
+        })
+        .collect())
+}
+
+/// Whether the layout is loaded in this session, e.g. because it's in the language list or
+/// some window uses it.
+pub fn is_layout_loaded(klid: &str) -> Result<bool, String> {
+    Ok(get_loaded_layouts()?.iter().any(|loaded| {
+        loaded
+            .klid
+            .as_deref()
+            .is_some_and(|loaded_klid| loaded_klid.eq_ignore_ascii_case(klid))
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resolve_hkl() {
+        let layout_ids = vec![
+            ("f0010409".to_string(), 0x0F01),
+            ("f0010415".to_string(), 0x0F01),
+            ("f0020415".to_string(), 0x1F02),
+        ];
+
+        let resolve = |hkl: &str| resolve_hkl(&Hkl::parse(hkl).unwrap(), &layout_ids);
+        assert_eq!(resolve("FF010415"), Some("f0010415".to_string()));
+        assert_eq!(resolve("FF010407"), Some("f0010409".to_string()));
+        assert_eq!(resolve("FF020415"), Some("f0020415".to_string()));
+        assert_eq!(resolve("04150415"), Some("00000415".to_string()));
+        assert_eq!(resolve("F0030415"), None);
+    }
+}
@@ -1,27 +1,94 @@
 use std::{
-    env::current_dir,
+    fs,
+    io::{self, IsTerminal, Read},
     path::{Path, PathBuf},
+    time::{Duration, SystemTime},
 };
 
-use clap::{Args, Parser, Subcommand};
-use dialoguer::Confirm;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use dialoguer::{Input, Select};
 use indoc::printdoc;
 use is_elevated::is_elevated;
-mod get_known_folder;
-mod registry_key;
-mod registry_value;
-mod utils;
-use get_known_folder::get_known_folder;
-use registry_key::{RegistryError, RegistryKey};
-use registry_value::RegistryValueData;
-use utils::{move_file, ReadUtf16Line, StringExt};
+use klc_install::{
+    arch::Arch,
+    bundle::is_bundle,
+    color::{self, paint, paint_stderr, print_warning, Style},
+    compile::{get_work_dir, remove_work_dir, TemplateVars},
+    control_sets::{
+        compare_layouts, find_control_sets, get_control_set_layouts_key, read_custom_layouts,
+        LayoutDifference,
+    },
+    docs::{generate_docs, DocsFormat},
+    download::{download_file, is_url, parse_sha256},
+    exit_code::{format_exit_codes, CommandError, ExitCode},
+    file_info::{get_file_version, get_signature_status},
+    get_known_folder::get_known_folder,
+    history::{append_to_history, format_unix_time, read_history},
+    hkl::{Hkl, HklLayout},
+    hooks::{self, Hook},
+    input_refresh::{broadcast_settings_change, restart_text_services},
+    install::{
+        extract_bundle, get_source, install_batch, install_layout, install_manifest, InstallOptions,
+    },
+    instance_lock::InstanceLock,
+    journal::InterruptedOperation,
+    json_schema::{
+        ControlSetJson, ControlSetsJson, KlidInfoJson, LayoutDifferenceJson, LayoutListJson,
+        SelftestJson, SCHEMA_VERSION,
+    },
+    klc::{get_shift_state_name, parse_klc_char, DeadKey, KlcDocument, KlcStats},
+    klid::{
+        get_language_display_name, is_transient_lcid, Klid, KlidKind, LOCALE_CUSTOM_UNSPECIFIED,
+    },
+    layout_expiry::{clear_layout_expiry, get_layout_expiry},
+    layout_provenance::Provenance,
+    layout_store::{InstalledLayout, LayoutQuery, LayoutStore},
+    layout_tags::{add_layout_tags, get_layout_tags, has_layout_tag, remove_layout_tags},
+    layouts::{
+        get_installed_dll_paths, get_layout_dll_name, get_layouts_key, get_saved_dll_path,
+        parse_layout_key, parse_locale,
+    },
+    list_theme::{ListColumn, ListFormat, ListStyle, ListTheme},
+    loaded_layouts::{get_loaded_layouts, read_layout_ids, resolve_hkl},
+    porcelain::{self, emit, Event},
+    preload::{
+        get_input_method, preload_layout, read_preloaded_klids, set_input_method_override,
+        USER_PROFILE_KEY_PATH,
+    },
+    profile::Profile,
+    registry_key::{RegistryError, RegistryKey},
+    selftest::{run_selftest, SelftestStep},
+    uninstall::{purge_custom_layouts, remove_layout, uninstall_layouts, UninstallOptions},
+    update::{check_layout_update, update_all_layouts, update_layout},
+    user_hives::UserProfiles,
+    utils::{contains_wildcard, decode_text, expand_wildcard},
+};
 use windows::Win32::UI::Shell::FOLDERID_System;
 
 #[derive(Parser, Debug)]
-#[command(version, about)]
+#[command(version, about, after_long_help = format_exit_codes())]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Prints without colors. They're also off when the output is redirected or NO_COLOR is set.
+    #[clap(long, global = true)]
+    no_color: bool,
+
+    /// Prints progress events as JSON lines on the standard output, for front-ends and
+    /// deployment tools. The usual messages are printed to the standard error instead.
+    #[clap(long, global = true)]
+    porcelain: bool,
+
+    /// Runs a command at a point of installs and uninstalls, e.g.
+    /// post-install=gpupdate /target:user. Can be given multiple times.
+    ///
+    /// The points are pre-install, post-install, pre-uninstall and post-uninstall. Commands run
+    /// in cmd after the hooks of %ProgramData%\klc-install\config.toml, with the layouts in
+    /// KLC_INSTALL_KLIDS, KLC_INSTALL_LAYOUT_FILE, KLC_INSTALL_LAYOUT_TEXT and KLC_INSTALL_SOURCE.
+    /// A failing pre- hook cancels the change.
+    #[clap(long = "hook", global = true, value_name = "POINT=COMMAND")]
+    hooks: Vec<Hook>,
     // TODO /// Forces the program to run non-interactively.
     // #[clap(short, long)]
     // non_interactive: bool,
@@ -35,56 +102,123 @@ enum Commands {
         /// If off, only lists custom keyboard layouts
         #[clap(short, long, group = "kind")]
         all: bool,
+
+        /// Only lists layouts with the given tag.
+        #[clap(short, long)]
+        tag: Option<String>,
+
+        /// Only lists layouts for the given language, as a language tag like pl-PL or an LCID
+        /// like 0415.
+        #[clap(long)]
+        locale: Option<String>,
+
+        /// Only lists layouts whose name or display name contains the text, ignoring case.
+        ///
+        /// Can have * and ? wildcards, e.g. prog*dvorak.
+        #[clap(short, long)]
+        filter: Option<String>,
+
+        /// Shows the version and Authenticode signature status of the layout DLLs.
+        #[clap(long)]
+        file_info: bool,
+
+        /// Columns to show, separated by commas.
+        ///
+        /// Defaults to key, id, name, display-name, status and file.
+        #[clap(short, long, value_enum, value_delimiter = ',')]
+        columns: Vec<ListColumn>,
+
+        /// Compact cuts long values to fit narrow terminals.
+        #[clap(short, long, value_enum, default_value_t)]
+        style: ListStyle,
+
+        /// Markdown prints a table for documentation, with the kind and DLL status of each layout.
+        /// JSON prints a document for scripts, which keeps its shape across releases.
+        #[clap(long, value_enum, default_value_t)]
+        format: ListFormat,
+
+        /// Shows every registry value of each layout under it, like the version of the KLC file
+        /// it was installed from.
+        #[clap(short, long)]
+        verbose: bool,
+
+        /// Also shows the input methods of every user profile, including signed out users.
+        ///
+        /// Reading the profiles of signed out users needs administrator rights.
+        /// Not shown with --format json.
+        #[clap(long)]
+        users: bool,
     },
 
     /// Installs a keyboard layout
     Install {
-        /// Path to the keyboard layout file.
+        /// Paths to the keyboard layout files.
         ///
-        /// Can be a .KLC file or a .DLL file.
-        file: String,
+        /// Can be .KLC files or .DLL files. Wildcards like layouts\*.klc are expanded.
+        /// macOS .keylayout files and Linux XKB symbols files (.xkb or without an extension)
+        /// are converted to .KLC files first.
+        /// HTTP(S) URLs are downloaded to a temporary directory first.
+        /// - reads a .KLC file from the standard input, in UTF-16 or UTF-8.
+        ///
+        /// A .ZIP bundle contains a .KLC or .DLL file and optionally a layout.toml or layout.json
+        /// file setting its locale, description, id, registry key and DLL name.
+        #[clap(required_unless_present = "manifest", conflicts_with = "manifest")]
+        files: Vec<String>,
+
+        /// Installs the layouts listed in a TOML or JSON manifest instead.
+        ///
+        /// Each layout sets its file and optionally its locale, description, id, registry key,
+        /// DLL name and whether to preload it. Other options apply to every layout.
+        #[clap(long, value_name = "FILE")]
+        manifest: Option<PathBuf>,
 
         /// Path to MSKLC 1.4 directory.
         ///
         /// If the file is a .KLC file, MSKLC must be placed in %PATH% or provided here.
         #[clap(long)]
         msklc: Option<String>,
-        // /// Registry key to install the layout under.
-        // ///
-        // /// Must be an 8-digit hexadecimal number, where the last 4 digits signify the language code.
-        // /// By default, it starts at F000xxxx and increments by 1 for each layout.
-        // #[clap(short, long, visible_alias("key"), value_name = "KEY")]
-        // registry_key: Option<String>,
-
-        // /// ID of the layout to use.
-        // ///
-        // /// Must be a 4-digit hexadecimal number that is not already in use and
-        // /// is at most F000.
-        // /// Uses the highest available ID by default.
-        // #[clap(short, long)]
-        // id: Option<String>,
-
-        // /// Text (description) of the layout to use.
-        // ///
-        // /// If not provided, the name is taken from the layout file or left empty.
-        // #[clap(short, long, visible_alias("description"))]
-        // text: Option<String>,
-
-        // /// Add localized Display Name registry value.
-        // ///
-        // /// Will use the localized name in the layout file if available.
-        // ///
-        // /// By default, true if explicit name is not provided.
-        // #[clap(short, long, action = clap::ArgAction::Set, value_name = "BOOL")]
-        // localize_name: Option<bool>,
+
+        #[command(flatten)]
+        options: Box<InstallOptions>,
     },
 
     /// Tries to update the specific keyboard layout
+    ///
+    /// Recompiles the layout and replaces the DLL of the installed layouts using it,
+    /// keeping their registry keys and layout IDs, so the users' input methods keep working.
     Update {
         /// Path to the keyboard layout file.
         ///
-        /// Can be a .KLC file or a .DLL file.
-        file: String,
+        /// Can be a .KLC file or a .DLL file. The installed layouts are found by the name
+        /// of their DLL.
+        #[clap(required_unless_present = "all")]
+        file: Option<String>,
+
+        /// Updates every installed layout with a .KLC or .DLL file in the directory instead.
+        ///
+        /// Files of layouts that aren't installed are skipped.
+        #[clap(long, value_name = "DIR", conflicts_with_all = ["file", "check"])]
+        all: Option<PathBuf>,
+
+        /// Only reports whether the installed DLL differs from the new one, without changing
+        /// anything.
+        ///
+        /// Exits with 0 if the layout is up to date, 2 if an update is available and 1 on errors.
+        #[clap(long)]
+        check: bool,
+
+        /// Updates the layout even if its KLC file has a lower `VERSION` than the installed one.
+        #[clap(long)]
+        allow_downgrade: bool,
+
+        /// Path to MSKLC 1.4 directory.
+        ///
+        /// MSKLC must be placed in %PATH% or provided here.
+        #[clap(long)]
+        msklc: Option<String>,
+
+        #[command(flatten)]
+        template_vars: TemplateVars,
     },
 
     /// Uninstalls the specific keyboard layout
@@ -92,13 +226,199 @@ enum Commands {
         #[command(flatten)]
         layout: LayoutIdent,
 
-        /// Force uninstallation of the layout. WARNING: This can uninstall system layouts.
-        #[clap(short('F'), long)]
-        force: bool,
+        #[command(flatten)]
+        options: UninstallOptions,
+    },
+
+    /// Shows the dead key compositions of a keyboard layout
+    Deadkeys {
+        /// Path to the .KLC file.
+        file: String,
+
+        /// Interactively compose dead keys with base characters.
+        ///
+        /// Characters can be typed directly or given as hex code points (e.g. 005E or U+005E).
+        #[clap(short, long)]
+        interactive: bool,
+    },
+
+    /// Summarizes a keyboard layout file
+    ///
+    /// Shows the mapped keys per shift state, dead keys, ligatures and other details
+    /// worth checking before shipping the layout.
+    Stats {
+        /// Path to the .KLC file.
+        file: String,
+    },
+
+    /// Manages tags of installed keyboard layouts
+    Tag {
+        #[command(subcommand)]
+        action: TagAction,
+    },
+
+    /// Keeps a layout installed with --expires for good
+    Keep {
+        /// Registry key of the layout to keep.
+        #[arg(long, visible_alias("key"), value_name = "KEY")]
+        registry_key: String,
+    },
+
+    /// Makes Windows pick up newly installed layouts
+    ///
+    /// Notifies running applications and restarts the text input host (ctfmon.exe),
+    /// which may keep new layouts hidden from the language settings until it's restarted.
+    /// Only this session is refreshed, so it doesn't need administrator rights.
+    RefreshInput,
+
+    /// Finds the installed layout behind an input locale handle (HKL)
+    ///
+    /// Useful to match HKLs from RDP or VM logs (e.g. 0xF0010415) with the local layouts.
+    Lookup {
+        /// The HKL, as a hexadecimal number.
+        hkl: String,
+    },
+
+    /// Shows the layouts loaded in this session and which one is active
+    ///
+    /// Custom layouts are found by the layout ID in their HKL. Layouts of other signed in users
+    /// aren't shown.
+    Which,
+
+    /// Compares the custom layouts across the registry control sets
+    ///
+    /// Windows boots from another control set after choosing Last Known Good Configuration,
+    /// which may not have the layouts installed since.
+    ControlSets {
+        /// Copies the custom layouts of the current control set to the others.
+        ///
+        /// Layouts only in other control sets are left alone.
+        #[clap(long)]
+        sync: bool,
+
+        /// Prints the differences as a JSON document for scripts.
+        #[clap(long, conflicts_with = "sync")]
+        json: bool,
+    },
+
+    /// Explains the parts of a keyboard layout identifier (KLID)
+    ///
+    /// KLIDs are the registry keys of layouts, e.g. f0010415.
+    ExplainKlid {
+        /// The KLID, as an 8-digit hexadecimal number.
+        klid: String,
+
+        /// Prints the parts and the registered layout as a JSON document for scripts.
+        #[clap(long)]
+        json: bool,
+    },
+
+    /// Makes a layout the default for the welcome screen and new user accounts
+    SystemDefault {
+        /// Registry key of the layout.
+        #[arg(long, visible_alias("key"), value_name = "KEY")]
+        registry_key: String,
+    },
+
+    /// Checks that layouts can be compiled and installed on this machine
+    ///
+    /// Compiles a tiny bundled layout, then installs, lists and uninstalls it in a sandbox
+    /// registry key and directory, without touching the installed layouts.
+    Selftest {
+        /// Path to MSKLC 1.4 directory.
+        ///
+        /// MSKLC must be placed in %PATH% or provided here.
+        #[clap(long)]
+        msklc: Option<String>,
+
+        /// Prints the result of every check as a JSON document for scripts.
+        #[clap(long)]
+        json: bool,
+    },
+
+    /// Exports or applies the complete keyboard configuration
+    Profile {
+        #[command(subcommand)]
+        action: ProfileAction,
+    },
+
+    /// Rolls back or keeps operations interrupted before finishing, e.g. by a crash or power loss
+    ///
+    /// Other commands changing the system ask about them, but only when run in a terminal.
+    Recover {
+        /// Undoes the changes the interrupted operations made.
+        #[clap(long, conflicts_with = "keep", required_unless_present = "keep")]
+        roll_back: bool,
+
+        /// Keeps the changes the interrupted operations made and forgets them.
+        #[clap(long)]
+        keep: bool,
+    },
+
+    /// Shows the commands that changed the system, with the layouts and files they changed
+    ///
+    /// The history is kept in %ProgramData%\klc-install\history.log.
+    History {
+        /// Number of the most recent commands to show.
+        #[clap(short = 'n', long, default_value_t = 20)]
+        last: usize,
+    },
+
+    /// Generates the reference of all commands, exit codes and the registry locations touched
+    ///
+    /// Used to make the documentation shipped with packages.
+    #[command(hide = true)]
+    GenDocs {
+        /// Roff makes a man page.
+        #[clap(long, value_enum, default_value_t)]
+        format: DocsFormat,
+
+        /// File to write the reference to, instead of printing it.
+        #[clap(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ProfileAction {
+    /// Exports custom layouts, preloaded layouts, hotkeys and the scancode map to a profile
+    ///
+    /// The DLLs of custom layouts are copied next to the profile file, their SysWOW64 builds to a
+    /// SysWOW64 directory next to it.
+    Export {
+        /// Path to the profile file to create.
+        file: String,
+    },
+
+    /// Applies a profile, installing its layouts and restoring the configuration
+    Apply {
+        /// Path to the profile file.
+        file: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum TagAction {
+    /// Adds tags to a keyboard layout
+    Add {
+        /// Registry key of the layout to tag.
+        #[arg(long, visible_alias("key"), value_name = "KEY")]
+        registry_key: String,
+
+        /// Tags to add.
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
 
-        /// Remove the DLL file associated with the layout.
-        #[clap(short('d'), long)]
-        remove_dll: bool,
+    /// Removes tags from a keyboard layout
+    Remove {
+        /// Registry key of the layout to untag.
+        #[arg(long, visible_alias("key"), value_name = "KEY")]
+        registry_key: String,
+
+        /// Tags to remove.
+        #[arg(required = true)]
+        tags: Vec<String>,
     },
 }
 
@@ -114,34 +434,121 @@ struct LayoutIdent {
     id: Option<String>,
 
     /// Text (description) of the layout to uninstall.
+    ///
+    /// Matched ignoring case, or as a part of the text if no layout matches exactly.
     #[arg(long, visible_alias("description"))]
     text: Option<String>,
-}
 
-fn get_layouts_key() -> Result<RegistryKey, RegistryError> {
-    RegistryKey::from_path("HKLM\\SYSTEM\\CurrentControlSet\\Control\\Keyboard Layouts")
+    /// DLL file of the layout to uninstall, e.g. kbdmine.dll, or the .KLC file it was built from.
+    #[arg(long, value_name = "FILE")]
+    file: Option<String>,
+
+    /// Tag of the layouts to uninstall.
+    #[arg(long)]
+    tag: Option<String>,
+
+    /// Uninstalls every custom layout, along with their DLLs and their preload entries.
+    ///
+    /// They're backed up first, to --backup or %ProgramData%\klc-install\backups.
+    #[arg(long)]
+    purge_custom: bool,
 }
 
-fn list_layouts(all: bool) -> Result<(), String> {
-    let layouts_key: Result<RegistryKey, RegistryError> = get_layouts_key();
+/// Gets the version and signature status of a layout DLL for the list.
+fn get_file_info(system32_path: &Path, layout_file: Option<&str>) -> (String, String) {
+    let dll_path = match layout_file {
+        Some(file) => system32_path.join(file),
+        None => return ("-".to_string(), "-".to_string()),
+    };
 
-    if layouts_key.is_err() {
-        panic!(
-            "Failed to open the Keyboard Layouts registry key. {}",
-            layouts_key.unwrap_err()
-        );
+    if !dll_path.exists() {
+        return ("-".to_string(), "MISSING".to_string());
     }
 
-    let layouts_key = layouts_key.unwrap();
+    let version = match get_file_version(&dll_path) {
+        Ok(version) => version.unwrap_or_else(|| "-".to_string()),
+        Err(e) => format!("ERROR: {}", e),
+    };
+    let signature = match get_signature_status(&dll_path) {
+        Ok(status) => status.to_string(),
+        Err(e) => format!("ERROR: {}", e),
+    };
+
+    (version, signature)
+}
 
-    let layout_keys_iter = layouts_key.iter_children();
+/// Prints the layouts selected like in the table as a `LayoutListJson` document.
+fn print_layouts_json(
+    all: bool,
+    tag: Option<String>,
+    locale_id: Option<u16>,
+    filter: Option<String>,
+) -> Result<(), String> {
+    let layouts = LayoutStore::open()
+        .and_then(|store| store.get_installed_layouts())
+        .map_err(|e| format!("Failed to read the installed layouts. {}", e))?
+        .into_iter()
+        .filter(|layout| {
+            locale_id.is_none_or(|locale_id| layout.klid.language == locale_id)
+                && (all || layout.klid.device >= 0x0080)
+                && tag
+                    .as_ref()
+                    .is_none_or(|tag| layout.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)))
+                && filter.as_ref().is_none_or(|filter| {
+                    [&layout.layout_text, &layout.display_name]
+                        .into_iter()
+                        .flatten()
+                        .any(|text| contains_wildcard(filter, text))
+                })
+        })
+        .collect();
 
     println!(
-        "{:>8} {:<4} {:<32} {:<32} {}",
-        "Key", "ID", "Name", "Display Name", "File"
+        "{}",
+        serde_json::to_string_pretty(&LayoutListJson::new(layouts)).unwrap()
     );
 
+    Ok(())
+}
+
+fn list_layouts(
+    all: bool,
+    tag: Option<String>,
+    locale: Option<String>,
+    filter: Option<String>,
+    verbose: bool,
+    users: bool,
+    mut theme: ListTheme,
+) -> Result<(), String> {
+    let locale_id = locale.as_deref().map(parse_locale).transpose()?;
+
+    if theme.format == ListFormat::Json {
+        return print_layouts_json(all, tag, locale_id, filter);
+    }
+
+    let layouts_key = get_layouts_key()
+        .map_err(|e| format!("Failed to open the Keyboard Layouts registry key. {}", e))?;
+
+    let layout_keys_iter = layouts_key.iter_children();
+
+    let system32_path = get_known_folder(&FOLDERID_System)?;
+    // 32-bit applications on 64-bit Windows load the layouts from there
+    let syswow64_path = Arch::Wow64.get_system_dir(Arch::get_native())?;
+
+    let (preloaded, loaded) = if theme.has_column(ListColumn::Status) {
+        let preloaded =
+            read_preloaded_klids(&RegistryKey::current_user()).map_err(|e| e.to_string())?;
+        (preloaded, get_loaded_layouts()?)
+    } else {
+        Default::default()
+    };
+
     let mut skipped = 0;
+    let mut missing_dlls = 0;
+    let mut expiry_notes = Vec::new();
+    // The rows are printed once all are known, so the columns fit the longest values
+    let mut rows = Vec::new();
+    let mut row_details = Vec::new();
 
     for layout_key_err in layout_keys_iter {
         if layout_key_err.is_err() {
@@ -155,37 +562,179 @@ fn list_layouts(all: bool) -> Result<(), String> {
         let layout_key = layout_key_err.unwrap();
         let layout_key_name = layout_key.get_name();
 
-        let layout_key_hex = u32::from_str_radix(layout_key_name, 16).unwrap();
+        // Other software can leave keys that aren't KLIDs
+        let Ok(layout_key_hex) = u32::from_str_radix(layout_key_name, 16) else {
+            println!("Skipping {}, it isn't a valid layout key.", layout_key_name);
+            continue;
+        };
+
+        // The low word of the KLID is the language
+        if locale_id.is_some_and(|locale_id| layout_key_hex as u16 != locale_id) {
+            continue;
+        }
+
         if !all && layout_key_hex < 0x00800000 {
             skipped += 1;
             continue;
         }
 
-        let layout_id = layout_key
-            .try_get_value(Some("Layout Id"))
-            .unwrap()
-            .map(|v| v.unwrap_str());
-        let layout_name = layout_key
-            .try_get_value(Some("Layout Text"))
-            .unwrap()
-            .map(|v| v.unwrap_str());
-        let layout_display = layout_key
-            .try_get_value(Some("Layout Display Name"))
-            .unwrap()
-            .map(|v| v.unwrap_str());
-        let layout_file = layout_key
-            .try_get_value(Some("Layout File"))
-            .unwrap()
-            .map(|v| v.unwrap_str());
+        if let Some(tag) = &tag {
+            if !has_layout_tag(&layout_key, tag).map_err(|e| e.to_string())? {
+                continue;
+            }
+        }
+
+        let read_str = |name: &str| -> Result<Option<String>, String> {
+            layout_key
+                .try_get_string(Some(name))
+                .map_err(|e| e.to_string())
+        };
+        let layout_id = read_str("Layout Id")?;
+        let layout_name = read_str("Layout Text")?;
+        let layout_display = read_str("Layout Display Name")?;
+        let layout_file = read_str("Layout File")?;
+
+        if let Some(filter) = &filter {
+            let matches = [&layout_name, &layout_display]
+                .into_iter()
+                .flatten()
+                .any(|text| contains_wildcard(filter, text));
+            if !matches {
+                continue;
+            }
+        }
 
-        println!(
-            "{:>8} {:<4} {:<32} {:<32} {}",
-            layout_key_name,
-            layout_id.unwrap_or_else(|| "-".to_string()),
-            layout_name.unwrap_or_else(|| "UNKNOWN".to_string()),
-            layout_display.unwrap_or_else(|| "-".to_string()),
-            layout_file.unwrap_or_else(|| "???.DLL".to_string()),
-        );
+        if let Some(expiry) = get_layout_expiry(&layout_key).map_err(|e| e.to_string())? {
+            let note = match expiry.duration_since(SystemTime::now()) {
+                Ok(left) => format!(
+                    "The trial of {} ends in {} day(s).",
+                    layout_key_name,
+                    left.as_secs().div_ceil(24 * 60 * 60)
+                ),
+                Err(_) => format!(
+                    "The trial of {} has ended! Uninstall it or run keep --key {} to keep it.",
+                    layout_key_name, layout_key_name
+                ),
+            };
+            expiry_notes.push(note);
+        }
+
+        let (version, signature) =
+            if theme.has_column(ListColumn::Version) || theme.has_column(ListColumn::Signature) {
+                get_file_info(&system32_path, layout_file.as_deref())
+            } else {
+                Default::default()
+            };
+
+        let kind = match Klid::parse(layout_key_name).map(|klid| klid.get_kind()) {
+            Ok(KlidKind::Custom) => "custom",
+            Ok(KlidKind::Ime) => "IME",
+            Ok(KlidKind::System | KlidKind::SystemVariant(_)) => "system",
+            Err(_) => "-",
+        };
+
+        let missing_wow64 = |file: &str| {
+            syswow64_path
+                .as_ref()
+                .is_some_and(|dir| !dir.join(file).exists())
+        };
+        let dll_status = match &layout_file {
+            Some(file) if !system32_path.join(file).exists() => "missing",
+            Some(file) if missing_wow64(file) => "no WOW64",
+            Some(_) => "present",
+            None => "-",
+        };
+        // Shown in the file column, which is there by default
+        let file_marker = if dll_status == "missing" {
+            missing_dlls += 1;
+            "!"
+        } else {
+            ""
+        };
+
+        let status = if loaded.iter().any(|loaded| {
+            loaded
+                .klid
+                .as_deref()
+                .is_some_and(|klid| klid.eq_ignore_ascii_case(layout_key_name))
+        }) {
+            "in use"
+        } else if preloaded
+            .iter()
+            .any(|klid| klid.eq_ignore_ascii_case(layout_key_name))
+        {
+            "enabled"
+        } else {
+            "-"
+        };
+
+        let tags = if theme.has_column(ListColumn::Tags) {
+            get_layout_tags(&layout_key)
+                .map_err(|e| e.to_string())?
+                .join(",")
+        } else {
+            String::new()
+        };
+
+        let cells = theme.get_cells(|column| match column {
+            ListColumn::Key => layout_key_name.to_string(),
+            ListColumn::Id => layout_id.clone().unwrap_or_else(|| "-".to_string()),
+            ListColumn::Name => layout_name.clone().unwrap_or_else(|| "UNKNOWN".to_string()),
+            ListColumn::DisplayName => layout_display.clone().unwrap_or_else(|| "-".to_string()),
+            ListColumn::Version => version.clone(),
+            ListColumn::Signature => signature.clone(),
+            ListColumn::Tags => tags.clone(),
+            ListColumn::Kind => kind.to_string(),
+            ListColumn::DllStatus => dll_status.to_string(),
+            ListColumn::Status => status.to_string(),
+            ListColumn::File => format!(
+                "{}{}",
+                file_marker,
+                layout_file.as_deref().unwrap_or("???.DLL")
+            ),
+        });
+
+        let mut details = Vec::new();
+        // Lines between the rows would break the Markdown table
+        if verbose && theme.format != ListFormat::Markdown {
+            for value in layout_key.iter_values() {
+                let value = value.map_err(|e| e.to_string())?;
+                details.push(format!(
+                    "    {}: {}",
+                    value.get_name().unwrap_or("(Default)"),
+                    value.get_value()
+                ));
+            }
+        }
+
+        rows.push(cells);
+        row_details.push((dll_status == "missing", details));
+    }
+
+    theme.fit_rows(&rows);
+
+    // Escape codes would end up in the Markdown
+    let styled = theme.format == ListFormat::Text;
+    let style = |line: String, style: Style| if styled { paint(line, style) } else { line };
+
+    println!("{}", style(theme.format_header(), Style::Bold));
+    for (cells, (missing_dll, details)) in rows.iter().zip(&row_details) {
+        let row = theme.format_row(cells);
+        if *missing_dll {
+            println!("{}", style(row, Style::Red));
+        } else {
+            println!("{}", row);
+        }
+        for line in details {
+            println!("{}", style(line.clone(), Style::Dim));
+        }
+    }
+
+    // Otherwise the notes would continue the Markdown table
+    if theme.format == ListFormat::Markdown
+        && (skipped > 0 || missing_dlls > 0 || !expiry_notes.is_empty())
+    {
+        println!();
     }
 
     if skipped > 0 {
@@ -195,379 +744,1194 @@ fn list_layouts(all: bool) -> Result<(), String> {
         );
     }
 
-    Ok(())
-}
+    if missing_dlls > 0 {
+        println!(
+            "The DLLs of {} layouts marked with ! are missing from System32. Uninstall them or install their DLLs again.",
+            missing_dlls
+        );
+    }
 
-/// Checks if MSKLC is installed in the given directory.
-///
-/// Returns the path to KBDUTOOL if found.
-fn get_kbdutool(msklc_dir: &Path) -> Result<PathBuf, String> {
-    let msklc_path = msklc_dir.canonicalize().map_err(|e| e.to_string())?;
-    let mut kbdutool_path = msklc_path.join("kbdutool.exe");
+    for note in expiry_notes {
+        println!("{}", note);
+    }
+
+    if users {
+        println!();
+        print_user_input_methods()?;
+    }
 
-    if !kbdutool_path.exists() {
-        kbdutool_path = msklc_path.join("bin/i386/kbdutool.exe");
+    Ok(())
+}
 
-        if !kbdutool_path.exists() {
-            return Err("KBDUTOOL was not found in the MSKLC directory!".to_string());
+/// Prints the layouts each user profile has in its input methods.
+fn print_user_input_methods() -> Result<(), String> {
+    let profiles = UserProfiles::read()?;
+
+    println!("Input methods of the user profiles:");
+    let result = profiles.for_each_hive(true, |hive| {
+        let hive = match hive {
+            Ok(hive) => hive,
+            Err(e) => {
+                println!("  {}", e);
+                return;
+            }
+        };
+        let signed_in = profiles
+            .users
+            .iter()
+            .any(|user| user.loaded && user.name == hive.name);
+        let state = if signed_in { " (signed in)" } else { "" };
+        match read_preloaded_klids(hive.get_key()) {
+            Ok(klids) if klids.is_empty() => println!("  {}{}: none", hive.name, state),
+            Ok(klids) => println!("  {}{}: {}", hive.name, state, klids.join(", ")),
+            Err(e) => println!("  {}{}: couldn't read them. {}", hive.name, state, e),
         }
-    }
+    });
 
-    Ok(kbdutool_path)
+    result.map_err(|e| format!("{} Run as administrator to read every profile.", e))
 }
 
-/// Tries to find MSKLC's KBDUTOOL in the PATH.
-fn find_kbdutool_in_path() -> Result<PathBuf, String> {
-    let path_env = std::env::var("PATH").map_err(|e| e.to_string())?;
-    let path_env = path_env.split(';');
+fn install_layouts(
+    files: Vec<String>,
+    manifest: Option<PathBuf>,
+    msklc: Option<String>,
+    options: InstallOptions,
+) -> Result<(), CommandError> {
+    if let Some(manifest) = manifest {
+        return install_manifest(&manifest, msklc, options);
+    }
+
+    let url_count = files.iter().filter(|file| is_url(file)).count();
+    let sha256 = options.sha256.as_deref().map(parse_sha256).transpose()?;
+    if sha256.is_some() && url_count != 1 {
+        return Err("The checksum can only be checked when installing from a single URL.".into());
+    }
 
-    for path in path_env {
-        let path = Path::new(path);
+    if files.iter().filter(|file| *file == "-").count() > 1 {
+        return Err("The standard input can only be read once.".into());
+    }
 
-        // Check for MSKLC
-        let msklc_path = path.join("MSKLC.exe");
+    let mut paths = Vec::new();
+    for (index, file) in files.iter().enumerate() {
+        if file == "-" {
+            paths.push((read_stdin_klc()?, Some("stdin".to_string())));
+            continue;
+        }
 
-        if !msklc_path.exists() {
+        if is_url(file) {
+            // A directory for each download, as URLs can end with the same file name
+            let download_dir = get_work_dir().join("downloads").join(index.to_string());
+            println!("Downloading {}...", file);
+            let (path, checksum) = download_file(file, &download_dir, sha256.as_deref())?;
+            if sha256.is_none() {
+                print_warning(format!(
+                    "The download wasn't verified. Its SHA-256 checksum is {}.",
+                    checksum
+                ));
+            }
+            paths.push((path, Some(file.clone())));
             continue;
         }
 
-        return get_kbdutool(path);
+        let matches = expand_wildcard(file).map_err(|e| e.to_string())?;
+        if matches.is_empty() {
+            return Err(format!("No files match {}.", file).into());
+        }
+        paths.extend(matches.into_iter().map(|path| (path, None)));
     }
 
-    Err("MSKLC was not found in PATH. Please provide the path to MSKLC using --msklc.".to_string())
-}
+    if paths.len() > 1
+        && (options.registry_key.is_some() || options.id.is_some() || options.dll_name.is_some())
+    {
+        return Err(
+            "The registry key, layout ID and DLL name can't be set when installing multiple files."
+                .into(),
+        );
+    }
+
+    let mut installs = Vec::new();
+    for (index, (path, source)) in paths.into_iter().enumerate() {
+        if is_bundle(&path) {
+            let (layout_path, mut layout_options) = extract_bundle(&path, index, &options)?;
+            // The extracted file is temporary, so the bundle is recorded as the source
+            layout_options.source = Some(source.unwrap_or_else(|| get_source(&path)));
+            installs.push((layout_path, layout_options));
+        } else {
+            let mut layout_options = options.clone();
+            layout_options.source = source;
+            installs.push((path, layout_options));
+        }
+    }
+
+    if let [(path, options)] = installs.as_slice() {
+        install_layout(path, msklc.as_deref(), options)?;
+        return Ok(());
+    }
 
-struct KlcInfo {
-    layout_name: String,
-    layout_text: String,
-    locale_id: u16,
+    install_batch(&installs, msklc.as_deref())
 }
 
-impl KlcInfo {
-    fn new(layout_name: String, layout_text: String, locale_id: u16) -> Self {
-        Self {
-            layout_name,
-            layout_text,
-            locale_id,
-        }
+/// Reads a KLC file from the standard input and writes it to a temporary file for KBDUTOOL,
+/// which only reads UTF-16.
+fn read_stdin_klc() -> Result<PathBuf, String> {
+    let mut bytes = Vec::new();
+    std::io::stdin()
+        .read_to_end(&mut bytes)
+        .map_err(|e| format!("Couldn't read the standard input. {}", e))?;
+    let content = decode_text(&bytes).map_err(|e| format!("Couldn't read the KLC file. {}", e))?;
+    if content.trim().is_empty() {
+        return Err("The standard input is empty.".to_string());
     }
 
-    fn read_from_file(file_path: &Path) -> Result<KlcInfo, String> {
-        let file = std::fs::File::open(&file_path).map_err(|e| e.to_string())?;
-        let reader = std::io::BufReader::new(file);
-        let mut lines = reader.utf16_lines();
+    let stdin_dir = get_work_dir().join("stdin");
+    fs::create_dir_all(&stdin_dir).map_err(|e| e.to_string())?;
 
-        let mut layout_name = None;
-        let mut layout_text = None;
-        let mut locale_id_str = None;
-        loop {
-            let mut line = lines
-                .next()
-                .ok_or_else(|| "Couldn't find info in the KLC file.".to_string())?
-                .map_err(|e| e.to_string())?;
+    let mut utf16 = String::from("\u{feff}");
+    for line in content.lines() {
+        utf16.push_str(line);
+        utf16.push_str("\r\n");
+    }
+    let bytes: Vec<u8> = utf16.encode_utf16().flat_map(u16::to_le_bytes).collect();
 
-            if line.is_empty() {
-                continue;
-            }
+    let klc_path = stdin_dir.join("stdin.klc");
+    fs::write(&klc_path, bytes).map_err(|e| e.to_string())?;
 
-            if line.remove_prefix("KBD\t") {
-                let (key, name) = line
-                    .split_once('\t')
-                    .ok_or_else(|| "Invalid KLC file.".to_string())?;
-                layout_name = Some(key.to_string());
-                layout_text = Some(name[1..name.len() - 1].to_string());
-            } else if line.remove_prefix("LOCALEID\t") {
-                locale_id_str = Some(line[1..line.len() - 1].to_string());
+    Ok(klc_path)
+}
+
+fn tag_layout(action: TagAction) -> Result<(), String> {
+    let layouts_key = get_layouts_key().map_err(|e| e.to_string())?;
+
+    match action {
+        TagAction::Add { registry_key, tags } => {
+            let layout_key = layouts_key
+                .get_subkey(&registry_key)
+                .map_err(|e| format!("Couldn't open the layout {}. {}", registry_key, e))?;
+            let added = add_layout_tags(&layout_key, &tags).map_err(|e| e.to_string())?;
+
+            if added.is_empty() {
+                println!("The layout {} already has these tags.", registry_key);
+            } else {
+                println!("Added tags to {}: {}", registry_key, added.join(", "));
             }
 
-            if layout_name.is_some() && layout_text.is_some() && locale_id_str.is_some() {
-                break;
+            println!(
+                "Tags: {}",
+                get_layout_tags(&layout_key)
+                    .map_err(|e| e.to_string())?
+                    .join(", ")
+            );
+        }
+        TagAction::Remove { registry_key, tags } => {
+            let layout_key = layouts_key
+                .get_subkey(&registry_key)
+                .map_err(|e| format!("Couldn't open the layout {}. {}", registry_key, e))?;
+            let removed = remove_layout_tags(&layout_key, &tags).map_err(|e| e.to_string())?;
+
+            if removed.is_empty() {
+                println!("The layout {} doesn't have these tags.", registry_key);
+            } else {
+                println!("Removed tags from {}: {}", registry_key, removed.join(", "));
             }
         }
+    }
 
-        let layout_name = layout_name.unwrap();
-        let layout_text = layout_text.unwrap();
-        let locale_id_str = locale_id_str.unwrap();
+    Ok(())
+}
 
-        let locale_id = u16::from_str_radix(&locale_id_str, 16).map_err(|e| e.to_string())?;
+fn refresh_input() -> Result<(), String> {
+    broadcast_settings_change()?;
+    restart_text_services()?;
 
-        Ok(Self::new(layout_name, layout_text, locale_id))
-    }
+    println!("Refreshed the text input services. New layouts should show up in the language settings now.");
+
+    Ok(())
 }
 
-fn get_next_layout_key(locale_id: u16) -> Result<String, String> {
-    let layouts_key = get_layouts_key().map_err(|e| e.to_string())?;
+fn keep_layout(registry_key: String) -> Result<(), String> {
+    let layout_key = get_layouts_key()
+        .and_then(|key| key.get_subkey(&registry_key))
+        .map_err(|e| format!("Couldn't open the layout {}. {}", registry_key, e))?;
 
-    let mut id: u32 = 0xf0000000 | (locale_id as u32);
+    if clear_layout_expiry(&layout_key).map_err(|e| e.to_string())? {
+        println!("The layout {} will be kept installed.", registry_key);
+    } else {
+        println!(
+            "The layout {} wasn't installed for a trial period.",
+            registry_key
+        );
+    }
 
-    loop {
-        let id_str = format!("{:08x}", id);
+    Ok(())
+}
+
+fn lookup_hkl(hkl: String) -> Result<(), String> {
+    let hkl = Hkl::parse(&hkl)?;
 
-        let layout_key = layouts_key.get_subkey(&id_str);
+    println!(
+        "Input language {:04X}, keyboard {}.",
+        hkl.language, hkl.layout
+    );
 
-        if let Err(RegistryError::NotFound) = layout_key {
-            return Ok(id_str);
-        } else if layout_key.is_err() {
-            return Err(layout_key.unwrap_err().to_string());
+    let layouts_key = get_layouts_key().map_err(|e| e.to_string())?;
+    let layout_ids = read_layout_ids(&layouts_key).map_err(|e| e.to_string())?;
+
+    // The KLID of a system layout is in the HKL even if the layout isn't installed
+    let layout_key = match resolve_hkl(&hkl, &layout_ids) {
+        Some(klid) => match layouts_key.get_subkey(&klid) {
+            Ok(layout_key) => Some(layout_key),
+            Err(RegistryError::NotFound) => None,
+            Err(e) => return Err(e.to_string()),
+        },
+        None => None,
+    };
+    let Some(layout_key) = layout_key else {
+        println!("No installed layout matches this HKL.");
+        if let HklLayout::LayoutId(_) = hkl.layout {
+            println!("Custom layouts are only available in remote sessions if they're installed on both machines with the same layout ID.");
+            println!("Use install --id to install the layout with a specific layout ID.");
         }
+        return Ok(());
+    };
 
-        drop(layout_key);
+    let layout_text = layout_key
+        .try_get_string(Some("Layout Text"))
+        .map_err(|e| e.to_string())?;
+    let layout_file = layout_key
+        .try_get_string(Some("Layout File"))
+        .map_err(|e| e.to_string())?;
 
-        if id | 0xffff0000 == 0xffff0000 {
-            return Err("No more layout keys for this locale are available.".to_string());
-        }
+    println!(
+        "{:>8} {:<32} {}",
+        layout_key.get_name(),
+        layout_text.unwrap_or_else(|| "UNKNOWN".to_string()),
+        layout_file.unwrap_or_else(|| "???.DLL".to_string()),
+    );
 
-        id += 0x000f0000;
-    }
+    Ok(())
 }
 
-fn get_next_layout_id() -> Result<u16, String> {
+fn show_loaded_layouts() -> Result<(), String> {
     let layouts_key = get_layouts_key().map_err(|e| e.to_string())?;
 
-    let layout_keys_iter = layouts_key.iter_children();
+    for layout in get_loaded_layouts()? {
+        let layout_key = layout
+            .klid
+            .as_ref()
+            .and_then(|klid| layouts_key.get_subkey(klid).ok());
+        let layout_text = match &layout_key {
+            Some(layout_key) => layout_key
+                .try_get_string(Some("Layout Text"))
+                .map_err(|e| e.to_string())?,
+            None => None,
+        };
 
-    let mut layout_ids_used = [false; 0xF000 - 0x0F00];
+        println!(
+            "{} {:08X} {:>8} {}",
+            if layout.active { "*" } else { " " },
+            layout.handle,
+            layout.klid.as_deref().unwrap_or("?"),
+            layout_text.unwrap_or_else(|| "UNKNOWN".to_string()),
+        );
+    }
+    println!("The active layout is marked with *.");
 
-    let mut mark_layout_id_used = |layout_id: u16| {
-        if layout_id < 0x0F00 {
-            return;
-        }
+    Ok(())
+}
 
-        layout_ids_used[(layout_id - 0x0F00) as usize] = true;
+fn compare_control_sets(sync: bool, json: bool) -> Result<(), String> {
+    let control_sets = find_control_sets().map_err(|e| e.to_string())?;
+    let Some((current_set, other_sets)) = control_sets.split_first() else {
+        return Err("No registry control sets were found.".to_string());
     };
 
-    for layout_err in layout_keys_iter {
-        let layout_key = layout_err.map_err(|e| e.to_string())?;
-        let layout_id = layout_key
-            .try_get_value(Some("Layout Id"))
-            .map_err(|e| e.to_string())?;
+    let current_layouts = get_control_set_layouts_key(current_set)
+        .and_then(|key| read_custom_layouts(&key))
+        .map_err(|e| e.to_string())?;
+    if !json {
+        println!(
+            "The current control set {} has {} custom layouts.",
+            current_set,
+            current_layouts.len()
+        );
+    }
 
-        if let Some(id) = layout_id {
-            mark_layout_id_used(u16::from_str_radix(&id.unwrap_str(), 16).unwrap());
+    let mut diverged = false;
+    let mut control_sets_json = Vec::new();
+    for control_set in other_sets {
+        let layouts_key = get_control_set_layouts_key(control_set).map_err(|e| e.to_string())?;
+        let layouts = read_custom_layouts(&layouts_key).map_err(|e| e.to_string())?;
+
+        let differences = compare_layouts(&current_layouts, &layouts);
+        if json {
+            control_sets_json.push(ControlSetJson {
+                name: control_set.clone(),
+                differences: differences.iter().map(LayoutDifferenceJson::from).collect(),
+            });
+            continue;
+        }
+
+        if differences.is_empty() {
+            println!("{} matches.", control_set);
+            continue;
+        }
+        diverged = true;
+        println!("{} differs:", control_set);
+        for difference in &differences {
+            println!("  {}", difference);
         }
-    }
 
-    let check_layout_id_used =
-        |layout_id: u16| -> bool { layout_ids_used[(layout_id - 0x0F00) as usize] };
+        if !sync {
+            continue;
+        }
 
-    for id in 0x0F00..0xF000 {
-        if !check_layout_id_used(id) {
-            return Ok(id);
+        let mut synced_keys: Vec<&str> = differences
+            .iter()
+            .filter_map(|difference| match difference {
+                LayoutDifference::Missing(key) | LayoutDifference::Value { key, .. } => {
+                    Some(key.as_str())
+                }
+                LayoutDifference::Extra(_) => None,
+            })
+            .collect();
+        synced_keys.dedup();
+
+        for key in &synced_keys {
+            let layout_key = layouts_key.create_subkey(key).map_err(|e| e.to_string())?;
+            for (name, value) in &current_layouts[*key] {
+                layout_key
+                    .set_value(Some(name), value.clone())
+                    .map_err(|e| e.to_string())?;
+            }
         }
+        println!("Copied {} layouts to {}.", synced_keys.len(), control_set);
     }
 
-    Err("No more layout IDs are available.".to_string())
-}
+    if json {
+        let control_sets_json = ControlSetsJson {
+            schema_version: SCHEMA_VERSION,
+            current: current_set.clone(),
+            layout_count: current_layouts.len(),
+            control_sets: control_sets_json,
+        };
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&control_sets_json).unwrap()
+        );
+    } else if diverged && !sync {
+        println!("Use --sync to copy the custom layouts of the current control set to the others.");
+    }
 
-fn install_layout(file: String, msklc: Option<String>) -> Result<(), String> {
-    let file_path = Path::new(&file).canonicalize().map_err(|e| e.to_string())?;
+    Ok(())
+}
 
-    // let is_dll = file_path.ends_with(".dll");
-    // if !is_dll && !file_path.ends_with(".klc") {
-    //     panic!("The file must be a .KLC or .DLL file.");
-    // }
-    let extension = file_path.extension().map(|ext| ext.to_ascii_lowercase());
+fn write_docs(format: DocsFormat, output: Option<PathBuf>) -> Result<(), String> {
+    let docs = generate_docs(&Cli::command(), format);
 
-    if extension != Some("klc".into()) && extension != Some("dll".into()) {
-        return Err("The file must be a .KLC or .DLL file.".to_string());
+    match output {
+        Some(output) => fs::write(&output, docs)
+            .map_err(|e| format!("Couldn't write {}. {}", output.display(), e)),
+        None => {
+            print!("{}", docs);
+            Ok(())
+        }
     }
+}
 
-    let (klc_info, dll_path) = if extension == Some("klc".into()) {
-        // We have to parse some stuff from the KLC file
-        let klc_info = KlcInfo::read_from_file(&file_path).map_err(|e| e.to_string())?;
-        let KlcInfo {
-            ref layout_name,
-            ref layout_text,
-            locale_id,
-        } = klc_info;
+fn show_history(last: usize) -> Result<(), String> {
+    let history = read_history()?;
+    if history.is_empty() {
+        println!("No commands have changed the system yet.");
+        return Ok(());
+    }
 
+    for entry in &history[history.len().saturating_sub(last)..] {
+        let outcome = entry.get_outcome();
         println!(
-            "Found layout with name {}, with text {} and locale ID {} ({2:#06X})!",
-            layout_name, layout_text, locale_id
+            "{}  {}  {}",
+            format_unix_time(entry.time),
+            paint(&entry.command, Style::Bold),
+            if entry.exit_code == 0 {
+                paint(outcome, Style::Green)
+            } else {
+                paint(outcome, Style::Red)
+            }
         );
+        if !entry.layouts.is_empty() {
+            println!("  Layouts: {}", entry.layouts.join(", "));
+        }
+        for file in &entry.files {
+            println!("  File: {}", file.display());
+        }
+        if let Some(error) = &entry.error {
+            println!("  Error: {}", error);
+        }
+    }
+
+    Ok(())
+}
 
-        // Now we need to compile KLC file
+fn explain_klid(klid: String, json: bool) -> Result<(), String> {
+    let klid = Klid::parse(&klid)?;
 
-        // 1. Try to find MSKLC
-        let kbdutool_path = if let Some(msklc) = msklc {
-            get_kbdutool(&Path::new(&msklc))?
-        } else {
-            find_kbdutool_in_path()?
+    if json {
+        let layout = match LayoutStore::open().and_then(|store| store.get(klid)) {
+            Ok(layout) => Some(layout),
+            Err(RegistryError::NotFound) => None,
+            Err(e) => return Err(e.to_string()),
         };
+        let info = KlidInfoJson::new(klid, get_language_display_name(klid.language), layout);
+        println!("{}", serde_json::to_string_pretty(&info).unwrap());
+        return Ok(());
+    }
+
+    println!(
+        "Language: {:04X} ({})",
+        klid.language,
+        get_language_display_name(klid.language).unwrap_or_else(|| "unknown".to_string())
+    );
+    println!("Device:   {:04X} ({})", klid.device, klid.get_kind());
+    if is_transient_lcid(klid.language) {
+        println!("The language is a transient LCID, which Windows assigns to a language without its own LCID for each user.");
+    } else if klid.language == LOCALE_CUSTOM_UNSPECIFIED {
+        println!(
+            "The language is LOCALE_CUSTOM_UNSPECIFIED, used for languages without their own LCID."
+        );
+    }
+    match klid.get_kind() {
+        KlidKind::Custom if klid.device >> 12 == 0xF => {
+            println!("This is the range klc-install installs layouts in.")
+        }
+        KlidKind::Custom if klid.device >> 12 == 0xA => {
+            println!("This is the range MSKLC installs layouts in.")
+        }
+        _ => {}
+    }
 
-        // 2. Compile the KLC file
+    let layout_key = match get_layouts_key().and_then(|key| key.get_subkey(&klid.to_string())) {
+        Ok(layout_key) => layout_key,
+        Err(RegistryError::NotFound) => {
+            println!("It isn't registered on this machine.");
+            return Ok(());
+        }
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let layout_text = layout_key
+        .try_get_string(Some("Layout Text"))
+        .map_err(|e| e.to_string())?;
+    let layout_file = layout_key
+        .try_get_string(Some("Layout File"))
+        .map_err(|e| e.to_string())?;
 
-        let kbdutool_output = std::process::Command::new(kbdutool_path)
-            .arg("-wum")
-            .arg(&file_path)
-            .output()
-            .unwrap();
+    println!(
+        "It's registered on this machine as {} ({}).",
+        layout_text.unwrap_or_else(|| "UNKNOWN".to_string()),
+        layout_file.unwrap_or_else(|| "???.DLL".to_string()),
+    );
 
-        println!(
-            "KBDUTOOL output: {}",
-            String::from_utf8_lossy(&kbdutool_output.stdout)
+    if let Some(provenance) = Provenance::read(&layout_key).map_err(|e| e.to_string())? {
+        let days_ago = SystemTime::now()
+            .duration_since(provenance.installed)
+            .map(|d| d.as_secs() / (24 * 60 * 60))
+            .unwrap_or_default();
+        printdoc!(
+            "
+                klc-install {} installed it {} day(s) ago from {}.
+                DLL SHA-256: {}
+            ",
+            provenance.tool_version,
+            days_ago,
+            provenance.source,
+            provenance.sha256
         );
+    }
+
+    Ok(())
+}
+
+fn set_system_default_layout(registry_key: String) -> Result<(), String> {
+    let klid = parse_layout_key(&registry_key)?;
+
+    get_layouts_key()
+        .and_then(|key| key.get_subkey(&klid))
+        .map_err(|e| format!("Couldn't open the layout {}. {}", klid, e))?;
+
+    let default_user = RegistryKey::users()
+        .get_subkey(".DEFAULT")
+        .map_err(|e| e.to_string())?;
 
-        if !kbdutool_output.status.success() {
-            panic!(
-                "Failed to compile the KLC file. {}",
-                String::from_utf8_lossy(&kbdutool_output.stderr)
+    let entry = preload_layout(&default_user, &klid, true).map_err(|e| e.to_string())?;
+
+    // Windows copies these to new accounts when "Copy settings" is applied
+    let input_method = get_input_method(&klid);
+    for profile_key_path in [
+        USER_PROFILE_KEY_PATH,
+        "Control Panel\\International\\User Profile System Backup",
+    ] {
+        set_input_method_override(&default_user, profile_key_path, &input_method)
+            .map_err(|e| e.to_string())?;
+    }
+
+    printdoc!(
+        "
+            Made {} the default layout of the default user profile!
+            Preload entry: {}
+            Input method override: {}
+            New accounts and the welcome screen will start with this layout.
+        ",
+        klid,
+        entry,
+        input_method
+    );
+
+    Ok(())
+}
+
+fn export_profile(file: String) -> Result<(), String> {
+    let profile_path = Path::new(&file);
+    let profile_dir = profile_path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+
+    let profile = Profile::read_from_system()?;
+
+    for layout in &profile.layouts {
+        let dll_paths = get_installed_dll_paths(&layout.file)?;
+        if dll_paths.is_empty() {
+            println!(
+                "The DLL {} of layout {} is missing, it won't be exported.",
+                layout.file, layout.key
             );
+            continue;
         }
 
-        // 3. Get the compiled DLL file
+        // The SysWOW64 build too, for 32-bit applications
+        for dll_path in dll_paths {
+            let exported_dll_path =
+                get_saved_dll_path(profile_dir, dll_path.parent().unwrap(), &layout.file)?;
+            if let Some(dir) = exported_dll_path.parent() {
+                fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+            }
+            fs::copy(&dll_path, &exported_dll_path).map_err(|e| e.to_string())?;
+        }
+    }
 
-        // Doesn't work if file name isn't the same as layout name...
-        // Gotta parse the KLC file to get the layout name
+    profile.write_to_file(profile_path)?;
 
-        let dll_path = current_dir()
-            .unwrap()
-            .join(layout_name)
-            .with_extension("dll")
-            .canonicalize()
-            .unwrap();
+    println!(
+        "Exported {} custom layouts and {} preloaded layouts to {}.",
+        profile.layouts.len(),
+        profile.preload.len(),
+        profile_path.display()
+    );
 
-        println!("The compiled DLL file is at: {}", dll_path.display());
+    Ok(())
+}
 
-        // // if !dll_path.exists() {
-        // //     panic!("The compiled DLL file was not found.");
-        // // }
-        (klc_info, dll_path)
-    } else {
-        panic!("DLL installation is not yet implemented.");
-        // file_path
-    };
-    let dll_name = dll_path.file_name().unwrap().to_str().unwrap().to_string();
+fn apply_profile(file: String) -> Result<(), String> {
+    let profile_path = Path::new(&file).canonicalize().map_err(|e| e.to_string())?;
+    let profile = Profile::read_from_file(&profile_path)?;
 
-    // We have the DLL file now
+    profile.apply(profile_path.parent().unwrap())?;
 
-    for layout_key_err in get_layouts_key()
-        .map_err(|e| e.to_string())?
-        .iter_children()
-    {
-        let layout_key = layout_key_err.map_err(|e| e.to_string())?;
-        let layout_dll = layout_key
-            .get_value(Some("Layout File"))
-            .map_err(|e| e.to_string())?;
+    println!("Applied the profile. Sign out and back in for all changes to take effect.");
 
-        if layout_dll.unwrap_str() == dll_name {
-            return Err(format!(
-                "This layout DLL seems already installed under the key {}!",
-                layout_key.get_name()
-            ));
+    Ok(())
+}
+
+fn selftest(msklc: Option<String>, json: bool) -> Result<(), String> {
+    let report = run_selftest(msklc.as_deref());
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&SelftestJson::new(&report)).unwrap()
+        );
+        return if report.passed() {
+            Ok(())
+        } else {
+            Err("The self-test failed.".to_string())
+        };
+    }
+
+    for step in SelftestStep::ALL {
+        match report.results.iter().find(|(s, _)| *s == step) {
+            Some((_, Ok(detail))) => println!("PASS {}: {}", step, detail),
+            Some((_, Err(e))) => println!("FAIL {}: {}", step, e),
+            None => println!("SKIP {}", step),
         }
     }
 
-    // We move it to System32
-    if dll_path.parent() != Some(Path::new("C:\\Windows\\System32")) {
-        let system32_path = get_known_folder(&FOLDERID_System)?;
-        let new_dll_path = system32_path.join(&dll_name);
+    if !report.passed() {
+        return Err("The self-test failed.".to_string());
+    }
+
+    println!("All checks passed. Layouts can be installed on this machine.");
 
-        if new_dll_path.exists() {
-            let confirmation = Confirm::new()
-                .with_prompt("The DLL file already exists in System32. Proceed anyway?")
+    Ok(())
+}
+
+/// Resolves the selected layout to its registry key, asking which one is meant if several match.
+fn resolve_layout_ident(layout: &LayoutIdent) -> Result<String, CommandError> {
+    if let Some(registry_key) = &layout.registry_key {
+        let layout_key_name = parse_layout_key(registry_key)?;
+        return match get_layouts_key().and_then(|key| key.get_subkey(&layout_key_name)) {
+            Ok(_) => Ok(layout_key_name),
+            Err(RegistryError::NotFound) => Err(CommandError::new(
+                ExitCode::LayoutNotFound,
+                format!("The layout {} isn't installed.", layout_key_name),
+            )),
+            Err(e) => Err(e.to_string().into()),
+        };
+    }
+
+    let query = if let Some(file) = &layout.file {
+        LayoutQuery::File(get_layout_dll_name(Path::new(file))?)
+    } else if let Some(id) = &layout.id {
+        // Not parse_layout_id, system layouts have IDs outside the range of custom ones
+        let id = u16::from_str_radix(id, 16)
+            .map_err(|_| format!("Invalid layout ID {}. It must be a hexadecimal number.", id))?;
+        LayoutQuery::Id(id)
+    } else if let Some(text) = &layout.text {
+        LayoutQuery::Text(text.clone())
+    } else {
+        return Err("Select the layout with --registry-key, --id, --text, --file or --tag.".into());
+    };
+
+    let store = LayoutStore::open().map_err(|e| e.to_string())?;
+    choose_layout(store.find(&query).map_err(|e| e.to_string())?)
+}
+
+/// Picks the registry key of the only matching layout, or asks which one is meant.
+fn choose_layout(mut matches: Vec<InstalledLayout>) -> Result<String, CommandError> {
+    let describe = |layout: &InstalledLayout| {
+        format!(
+            "{} {}",
+            layout.klid,
+            layout.layout_text.as_deref().unwrap_or("UNKNOWN")
+        )
+    };
+
+    match matches.len() {
+        0 => Err(CommandError::new(
+            ExitCode::LayoutNotFound,
+            "No installed layout matches.",
+        )),
+        1 => Ok(matches.remove(0).get_key_name()),
+        _ if !io::stdin().is_terminal() => Err(format!(
+            "Several layouts match:\n{}\nUse --registry-key to pick one.",
+            matches.iter().map(describe).collect::<Vec<_>>().join("\n")
+        )
+        .into()),
+        _ => {
+            let items: Vec<String> = matches.iter().map(describe).collect();
+            let choice = Select::new()
+                .with_prompt("Several layouts match. Which one?")
+                .items(&items)
+                .default(0)
                 .interact()
                 .map_err(|e| e.to_string())?;
 
-            if !confirmation {
-                return Err("Installation aborted!".to_string());
-            }
-        } else {
-            move_file(&dll_path, &new_dll_path).map_err(|e| e.to_string())?;
+            Ok(matches.remove(choice).get_key_name())
         }
     }
+}
+
+fn uninstall_layout(layout: LayoutIdent, options: UninstallOptions) -> Result<(), CommandError> {
+    if layout.purge_custom {
+        return purge_custom_layouts(options);
+    }
+    if let Some(tag) = &layout.tag {
+        return uninstall_tagged_layouts(tag, options);
+    }
 
-    // We register the layout in the registry
+    let layout_key_name = resolve_layout_ident(&layout)?;
 
-    let layouts_key = get_layouts_key().map_err(|e| e.to_string())?;
+    // Same as list, lower KLIDs are the layouts shipped with Windows
+    let klid = Klid::parse(&layout_key_name)?;
+    if !klid.is_custom() && !options.force {
+        return Err(format!(
+            "{} is a system layout. Use --force to uninstall it anyway.",
+            layout_key_name
+        )
+        .into());
+    }
 
-    // Find the next available layout key:
-    let layout_key_name = get_next_layout_key(klc_info.locale_id).map_err(|e| e.to_string())?;
-    // and create it:
-    let layout_key = layouts_key
-        .create_subkey(&layout_key_name)
+    Ok(remove_layout(&layout_key_name, &options)?)
+}
+
+/// Uninstalls every layout with the tag, asking to confirm once for all of them.
+fn uninstall_tagged_layouts(tag: &str, options: UninstallOptions) -> Result<(), CommandError> {
+    let store = LayoutStore::open().map_err(|e| e.to_string())?;
+    let tagged = store
+        .find(&LayoutQuery::Tag(tag.to_string()))
         .map_err(|e| e.to_string())?;
+    if tagged.is_empty() {
+        return Err(CommandError::new(
+            ExitCode::LayoutNotFound,
+            format!("No installed layout is tagged {}.", tag),
+        ));
+    }
+
+    let mut layouts = Vec::new();
+    for layout in tagged {
+        // Same as for a single layout, the ones shipped with Windows need --force
+        if !layout.klid.is_custom() && !options.force {
+            print_warning(format!(
+                "{} is a system layout, it's skipped. Use --force to uninstall it too.",
+                layout.klid
+            ));
+            continue;
+        }
+        layouts.push((layout.get_key_name(), layout.layout_text));
+    }
+    if layouts.is_empty() {
+        return Err(format!(
+            "The layouts tagged {} are system layouts. Use --force to uninstall them anyway.",
+            tag
+        )
+        .into());
+    }
+
+    uninstall_layouts(&layouts, options)
+}
+
+/// Formats a character along with its code point, e.g. `â (U+00E2)`.
+fn format_char(c: char) -> String {
+    if c.is_control() || c.is_whitespace() {
+        format!("U+{:04X}", c as u32)
+    } else {
+        format!("{} (U+{:04X})", c, c as u32)
+    }
+}
+
+/// Parses a character typed by the user, either literally or as a code point.
+fn parse_char_input(input: &str) -> Result<char, String> {
+    let input = input.trim();
+    let code = input
+        .strip_prefix("U+")
+        .or_else(|| input.strip_prefix("u+"))
+        .unwrap_or(input);
+
+    parse_klc_char(code)
+}
 
-    // Find the next available layout ID:
-    let layout_id = get_next_layout_id().map_err(|e| e.to_string())?;
-    let layout_id_str = format!("{:04X}", layout_id);
+fn show_layout_stats(file: String) -> Result<(), String> {
+    let file_path = Path::new(&file).canonicalize().map_err(|e| e.to_string())?;
+    let stats = KlcStats::from_document(&KlcDocument::read_resolved(&file_path)?)?;
+
+    println!("Mapped keys:");
+    for (shift_state, count) in &stats.mapped_keys {
+        println!(
+            "  {:<12} {}",
+            format!("{} ({})", get_shift_state_name(*shift_state), shift_state),
+            count
+        );
+    }
 
     println!(
-        "Found the next layout key {} and layout ID {}!",
-        layout_key_name, layout_id_str
+        "Dead keys: {} with {} compositions",
+        stats.dead_keys, stats.compositions
     );
+    println!("Ligatures: {}", stats.ligatures);
 
-    use RegistryValueData as RVD;
+    if stats.unmapped_keys.is_empty() {
+        println!("Unmapped keys: none");
+    } else {
+        println!(
+            "Unmapped keys: {} ({})",
+            stats.unmapped_keys.len(),
+            stats.unmapped_keys.join(", ")
+        );
+    }
 
-    layout_key
-        .set_value(Some("Layout Id"), RVD::String(layout_id_str.clone()))
-        .map_err(|e| e.to_string())?;
-    layout_key
-        .set_value(Some("Layout File"), RVD::String(dll_name.clone()))
-        .map_err(|e| e.to_string())?;
-    layout_key
-        .set_value(
-            Some("Layout Text"),
-            RVD::String(klc_info.layout_text.clone()),
-        )
-        .map_err(|e| e.to_string())?;
-    let display_name = format!("@{},-1000", dll_name);
-    layout_key
-        .set_value(Some("Layout Display Name"), RVD::ExpandString(display_name))
-        .map_err(|e| e.to_string())?;
-    layout_key
-        .set_value(Some("Installed by"), RVD::String("klc-install".to_string()))
-        .map_err(|e| e.to_string())?;
+    if stats.surrogate_pairs.is_empty() {
+        println!("Characters needing surrogate pairs: none");
+    } else {
+        println!(
+            "Characters needing surrogate pairs: {} ({})",
+            stats.surrogate_pairs.len(),
+            stats
+                .surrogate_pairs
+                .iter()
+                .map(|c| format_char(*c))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
 
-    printdoc!(
-        "
-            Successfully installed the layout!
-            Key: {}
-            ID: {}
-            Name: {}
-            File: {}
-        ",
-        layout_key_name,
-        layout_id_str,
-        klc_info.layout_text,
-        dll_name
+    Ok(())
+}
+
+fn explore_dead_keys(file: String, interactive: bool) -> Result<(), String> {
+    let file_path = Path::new(&file).canonicalize().map_err(|e| e.to_string())?;
+    let lines = KlcDocument::read_resolved(&file_path)?.to_lines();
+    let dead_keys = DeadKey::parse(lines.iter().map(|line| line.as_str()))?;
+
+    if dead_keys.is_empty() {
+        println!("The layout doesn't define any dead keys.");
+        return Ok(());
+    }
+
+    if !interactive {
+        for dead_key in &dead_keys {
+            println!(
+                "Dead key {} with {} compositions:",
+                format_char(dead_key.dead_char),
+                dead_key.compositions.len()
+            );
+
+            for (base, composed) in &dead_key.compositions {
+                println!("  {} -> {}", format_char(*base), format_char(*composed));
+            }
+        }
+
+        return Ok(());
+    }
+
+    println!(
+        "Found {} dead keys: {}",
+        dead_keys.len(),
+        dead_keys
+            .iter()
+            .map(|d| d.dead_char.to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
     );
+    println!("Leave the dead key empty to quit.");
+
+    loop {
+        let dead_input: String = Input::new()
+            .with_prompt("Dead key")
+            .allow_empty(true)
+            .interact_text()
+            .map_err(|e| e.to_string())?;
+
+        if dead_input.is_empty() {
+            return Ok(());
+        }
+
+        let dead_char = match parse_char_input(&dead_input) {
+            Ok(c) => c,
+            Err(e) => {
+                println!("{e}");
+                continue;
+            }
+        };
+
+        let Some(dead_key) = dead_keys.iter().find(|d| d.dead_char == dead_char) else {
+            println!("{} is not a dead key in this layout.", format_char(dead_char));
+            continue;
+        };
+
+        let base_input: String = Input::new()
+            .with_prompt("Base character")
+            .interact_text()
+            .map_err(|e| e.to_string())?;
+
+        let base = match parse_char_input(&base_input) {
+            Ok(c) => c,
+            Err(e) => {
+                println!("{e}");
+                continue;
+            }
+        };
+
+        match dead_key.compose(base) {
+            Some(composed) => println!(
+                "{} + {} = {}",
+                format_char(dead_char),
+                format_char(base),
+                format_char(composed)
+            ),
+            None => println!(
+                "{} + {} has no composition defined!",
+                format_char(dead_char),
+                format_char(base)
+            ),
+        }
+    }
+}
+
+impl Commands {
+    /// Whether the command makes changes to the system, which needs administrator rights.
+    ///
+    /// The other commands only read, or only change the session of the user running them, and
+    /// run without them.
+    fn is_mutating(&self) -> bool {
+        match self {
+            // Reloads the input settings and restarts the text services of this session only
+            Commands::RefreshInput => false,
+            Commands::List { .. }
+            | Commands::Deadkeys { .. }
+            | Commands::Stats { .. }
+            | Commands::Lookup { .. }
+            | Commands::Which
+            | Commands::ExplainKlid { .. }
+            | Commands::History { .. }
+            | Commands::GenDocs { .. } => false,
+            Commands::Update { check, .. } => !check,
+            Commands::Install { options, .. } => !options.dry_run,
+            Commands::Uninstall { options, .. } => !options.dry_run,
+            Commands::ControlSets { sync, .. } => *sync,
+            Commands::Profile { action } => matches!(action, ProfileAction::Apply { .. }),
+            _ => true,
+        }
+    }
+}
+
+/// What to do with an interrupted operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Recovery {
+    RollBack,
+    Keep,
+}
+
+/// Offers to roll back operations that were interrupted before finishing,
+/// e.g. by a crash or power loss.
+///
+/// With no recovery given, asks for each of them, or leaves them for later when not run in a
+/// terminal.
+fn recover_interrupted_operations(recovery: Option<Recovery>) -> Result<(), String> {
+    for operation in InterruptedOperation::find_all()? {
+        println!(
+            "Found an interrupted {} operation started {} minutes ago, which was about to:",
+            operation.operation,
+            operation.get_age() / 60
+        );
+        for action in &operation.actions {
+            println!("  - {}", action);
+        }
+
+        let recovery = match recovery {
+            Some(recovery) => recovery,
+            // Nobody can answer, the journal stays until someone decides
+            None if !io::stdin().is_terminal() => {
+                print_warning(
+                    "It's left for later. Run klc-install recover with --roll-back or --keep to resolve it.",
+                );
+                continue;
+            }
+            None => {
+                let choice = Select::new()
+                    .with_prompt("What should be done with it?")
+                    .items(&["Roll it back", "Keep the changes", "Decide later"])
+                    .default(0)
+                    .interact()
+                    .map_err(|e| e.to_string())?;
+                match choice {
+                    0 => Recovery::RollBack,
+                    1 => Recovery::Keep,
+                    _ => continue,
+                }
+            }
+        };
+
+        match recovery {
+            Recovery::RollBack => {
+                operation.roll_back()?;
+                println!("Rolled back the interrupted operation.");
+            }
+            Recovery::Keep => {
+                operation.discard()?;
+                println!("Kept the changes of the interrupted operation.");
+            }
+        }
+    }
 
     Ok(())
 }
 
-fn update_layout(_file: String) -> Result<(), String> {
-    todo!();
+/// Resolves every interrupted operation the same way, for scripts.
+fn recover(roll_back: bool) -> Result<(), String> {
+    if InterruptedOperation::find_all()?.is_empty() {
+        println!("There are no interrupted operations.");
+        return Ok(());
+    }
+
+    recover_interrupted_operations(Some(if roll_back {
+        Recovery::RollBack
+    } else {
+        Recovery::Keep
+    }))
 }
 
-fn uninstall_layout(_layout: LayoutIdent, _force: bool, _remove_dll: bool) -> Result<(), String> {
-    todo!();
+/// Ends the events of porcelain mode with the error the command failed with, if any.
+fn emit_exit(exit_code: ExitCode, error: Option<&CommandError>) {
+    if let Some(error) = error {
+        emit(&Event::Error {
+            message: &error.message,
+            hint: error.hint,
+        });
+    }
+    emit(&Event::Finished {
+        exit_code: exit_code.get_code(),
+    });
 }
 
 fn main() {
     let args = Cli::parse();
+    if let Err(e) = porcelain::init(args.porcelain) {
+        eprintln!("Couldn't switch to porcelain mode. {e}");
+        std::process::exit(ExitCode::Error.get_code());
+    }
+    color::init(args.no_color);
+    hooks::init(args.hooks.clone());
 
     // println!("{:#?}", args);
 
-    if !is_elevated() {
-        eprintln!("Please run this program as an administrator. This program requires administrative privileges to access the registry.");
-        return;
+    let mutating = args.command.is_mutating();
+
+    // Commands that only read the layouts work for everyone
+    if mutating && !is_elevated() {
+        let error = CommandError::new(
+            ExitCode::NotElevated,
+            "Please run this command as an administrator. It requires administrative privileges to change the registry and System32.",
+        );
+        eprintln!("{}", paint_stderr(&error, Style::Red));
+        emit_exit(error.exit_code, Some(&error));
+        std::process::exit(error.exit_code.get_code());
         // TODO add a way to elevate the process
     }
 
-    let result = match args.command {
-        Commands::List { all } => list_layouts(all),
-        Commands::Install { file, msklc } => install_layout(file, msklc),
-        Commands::Update { file } => update_layout(file),
-        Commands::Uninstall {
-            layout,
-            force,
-            remove_dll,
-        } => uninstall_layout(layout, force, remove_dll),
+    // Held until the end of main, so concurrent processes don't allocate the same keys
+    let _lock = if mutating {
+        let lock = match InstanceLock::acquire(Duration::from_secs(60)) {
+            Ok(lock) => lock,
+            Err(e) => {
+                eprintln!("{e}");
+                emit_exit(e.exit_code, Some(&e));
+                std::process::exit(e.exit_code.get_code());
+            }
+        };
+
+        // The recover command resolves them itself
+        let recovery_result = match args.command {
+            Commands::Recover { .. } => Ok(()),
+            _ => recover_interrupted_operations(None),
+        };
+        if let Err(e) = recovery_result {
+            eprintln!("Couldn't recover interrupted operations.\n{e}");
+            emit_exit(ExitCode::Error, Some(&CommandError::from(e)));
+            drop(lock);
+            std::process::exit(ExitCode::Error.get_code());
+        }
+
+        Some(lock)
+    } else {
+        None
     };
 
-    if let Err(e) = result {
-        eprintln!("Encountered an error executing the command.\n{e}")
+    // Commands returning a String error exit with the generic error code
+    let result = (|| -> Result<ExitCode, CommandError> {
+        match args.command {
+            Commands::List {
+                all,
+                tag,
+                locale,
+                filter,
+                file_info,
+                columns,
+                style,
+                format,
+                verbose,
+                users,
+            } => list_layouts(
+                all,
+                tag,
+                locale,
+                filter,
+                verbose,
+                users,
+                ListTheme::new(columns, style, format, file_info),
+            )?,
+            Commands::Install {
+                files,
+                manifest,
+                msklc,
+                options,
+            } => install_layouts(files, manifest, msklc, *options)?,
+            Commands::Update {
+                all: Some(dir),
+                msklc,
+                template_vars,
+                allow_downgrade,
+                ..
+            } => return update_all_layouts(&dir, msklc, template_vars, allow_downgrade),
+            Commands::Update {
+                file: Some(file),
+                msklc,
+                template_vars,
+                check: true,
+                ..
+            } => {
+                if !check_layout_update(file, msklc, template_vars)? {
+                    return Ok(ExitCode::UpdateAvailable);
+                }
+            }
+            Commands::Update {
+                file: Some(file),
+                msklc,
+                template_vars,
+                allow_downgrade,
+                ..
+            } => return update_layout(file, msklc, template_vars, allow_downgrade),
+            Commands::Update { .. } => unreachable!("clap requires the file or --all"),
+            Commands::Uninstall { layout, options } => uninstall_layout(layout, options)?,
+            Commands::Deadkeys { file, interactive } => explore_dead_keys(file, interactive)?,
+            Commands::Stats { file } => show_layout_stats(file)?,
+            Commands::Tag { action } => tag_layout(action)?,
+            Commands::Keep { registry_key } => keep_layout(registry_key)?,
+            Commands::RefreshInput => refresh_input()?,
+            Commands::Lookup { hkl } => lookup_hkl(hkl)?,
+            Commands::Which => show_loaded_layouts()?,
+            Commands::ControlSets { sync, json } => compare_control_sets(sync, json)?,
+            Commands::ExplainKlid { klid, json } => explain_klid(klid, json)?,
+            Commands::SystemDefault { registry_key } => set_system_default_layout(registry_key)?,
+            Commands::Selftest { msklc, json } => selftest(msklc, json)?,
+            Commands::Profile { action } => match action {
+                ProfileAction::Export { file } => export_profile(file)?,
+                ProfileAction::Apply { file } => apply_profile(file)?,
+            },
+            Commands::Recover { roll_back, .. } => recover(roll_back)?,
+            Commands::History { last } => show_history(last)?,
+            Commands::GenDocs { format, output } => write_docs(format, output)?,
+        }
+
+        Ok(ExitCode::Success)
+    })();
+    // The built DLLs were copied out of it by now
+    remove_work_dir();
+
+    let (exit_code, error) = match result {
+        Ok(exit_code) => (exit_code, None),
+        Err(e) => {
+            eprintln!(
+                "{}\n{e}",
+                paint_stderr("Encountered an error executing the command.", Style::Red)
+            );
+            if let Some(hint) = e.hint {
+                eprintln!("Hint: {}", hint);
+            }
+            (e.exit_code, Some(e))
+        }
+    };
+
+    if mutating {
+        let command = std::env::args().skip(1).collect::<Vec<_>>().join(" ");
+        let message = error.as_ref().map(|e| e.message.as_str());
+        if let Err(e) = append_to_history(&command, exit_code, message) {
+            print_warning(format!("Couldn't record the command in the history. {}", e));
+        }
+    }
+    emit_exit(exit_code, error.as_ref());
+
+    if exit_code != ExitCode::Success {
+        // Exiting skips the destructors, so the lock is released first
+        drop(_lock);
+        std::process::exit(exit_code.get_code());
     }
 
     // let layouts_key =
@@ -686,3 +2050,37 @@ fn main() {
     // //     }
     // // }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_uninstall_by_tag() {
+        // The tag used to pass the argument group and then be rejected as unsupported
+        let cli = Cli::try_parse_from(["klc-install", "uninstall", "--tag", "work"]).unwrap();
+        let Commands::Uninstall { layout, .. } = cli.command else {
+            panic!("Expected the uninstall command");
+        };
+        assert_eq!(layout.tag.as_deref(), Some("work"));
+        assert!(layout.registry_key.is_none() && layout.id.is_none());
+        assert!(layout.text.is_none() && layout.file.is_none());
+
+        assert!(Cli::try_parse_from(["klc-install", "uninstall"]).is_err());
+    }
+
+    #[test]
+    fn test_recover_needs_a_choice() {
+        let parse =
+            |args: &[&str]| Cli::try_parse_from([&["klc-install", "recover"], args].concat());
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["--roll-back", "--keep"]).is_err());
+        assert!(matches!(
+            parse(&["--keep"]).unwrap().command,
+            Commands::Recover {
+                roll_back: false,
+                keep: true
+            }
+        ));
+    }
+}
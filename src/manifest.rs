@@ -0,0 +1,158 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+/// A set of layouts to install in one run, read from a TOML or JSON file.
+#[derive(Debug, Default, Deserialize)]
+pub struct Manifest {
+    /// Path to the MSKLC directory, used unless --msklc is given.
+    #[serde(default)]
+    pub msklc: Option<String>,
+
+    #[serde(default)]
+    pub layouts: Vec<ManifestLayout>,
+}
+
+/// A layout of the manifest. Unset options fall back to the command line ones.
+#[derive(Debug, Default, Deserialize)]
+pub struct ManifestLayout {
+    /// Path to the .KLC or .DLL file, relative to the manifest.
+    pub file: String,
+
+    #[serde(default)]
+    pub locale: Locales,
+
+    #[serde(default, alias = "description")]
+    pub text: Option<String>,
+
+    #[serde(default)]
+    pub id: Option<String>,
+
+    #[serde(default, alias = "key")]
+    pub registry_key: Option<String>,
+
+    #[serde(default)]
+    pub dll_name: Option<String>,
+
+    /// Adds the layout to the input methods of the current user.
+    #[serde(default)]
+    pub preload: bool,
+}
+
+/// Either a single locale or a list of them.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Locales {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Default for Locales {
+    fn default() -> Self {
+        Locales::Many(Vec::new())
+    }
+}
+
+impl Locales {
+    pub fn to_vec(&self) -> Vec<String> {
+        match self {
+            Locales::One(locale) => vec![locale.clone()],
+            Locales::Many(locales) => locales.clone(),
+        }
+    }
+}
+
+impl Manifest {
+    /// Reads the manifest, as JSON if the file has the .json extension and as TOML otherwise.
+    pub fn read_from_file(file_path: &Path) -> Result<Manifest, String> {
+        let content = fs::read_to_string(file_path)
+            .map_err(|e| format!("Couldn't read the manifest {}. {}", file_path.display(), e))?;
+
+        let is_json = file_path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+        let manifest: Manifest = if is_json {
+            serde_json::from_str(&content).map_err(|e| e.to_string())?
+        } else {
+            toml::from_str(&content).map_err(|e| e.to_string())?
+        };
+
+        if manifest.layouts.is_empty() {
+            return Err(format!(
+                "The manifest {} doesn't list any layouts.",
+                file_path.display()
+            ));
+        }
+
+        Ok(manifest)
+    }
+}
+
+impl ManifestLayout {
+    /// Resolves the layout file relative to the directory of the manifest.
+    pub fn get_path(&self, manifest_dir: &Path) -> PathBuf {
+        manifest_dir.join(&self.file)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_manifest_toml() {
+        let manifest: Manifest = toml::from_str(
+            r#"
+            msklc = "C:\\MSKLC"
+
+            [[layouts]]
+            file = "layouts/multilin.klc"
+            locale = ["pl-PL", "0409"]
+            description = "Multilingual"
+            preload = true
+
+            [[layouts]]
+            file = "kbdcz.dll"
+            locale = "cs-CZ"
+            key = "f0010405"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(manifest.msklc.as_deref(), Some("C:\\MSKLC"));
+        assert_eq!(manifest.layouts.len(), 2);
+        assert_eq!(manifest.layouts[0].locale.to_vec(), ["pl-PL", "0409"]);
+        assert_eq!(manifest.layouts[0].text.as_deref(), Some("Multilingual"));
+        assert!(manifest.layouts[0].preload);
+        assert_eq!(manifest.layouts[1].locale.to_vec(), ["cs-CZ"]);
+        assert_eq!(
+            manifest.layouts[1].registry_key.as_deref(),
+            Some("f0010405")
+        );
+        assert!(!manifest.layouts[1].preload);
+        assert_eq!(
+            manifest.layouts[0].get_path(Path::new("C:\\layouts")),
+            Path::new("C:\\layouts").join("layouts/multilin.klc")
+        );
+    }
+
+    #[test]
+    fn test_manifest_json() {
+        let manifest: Manifest = serde_json::from_str(
+            r#"{
+                "layouts": [
+                    { "file": "multilin.klc", "id": "0F00", "dll_name": "multilin" }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(manifest.msklc, None);
+        assert_eq!(manifest.layouts[0].id.as_deref(), Some("0F00"));
+        assert_eq!(manifest.layouts[0].dll_name.as_deref(), Some("multilin"));
+        assert!(manifest.layouts[0].locale.to_vec().is_empty());
+    }
+}
@@ -0,0 +1,122 @@
+//! Installing layouts into a mounted Windows image, e.g. one mounted with DISM, without booting it.
+
+use std::path::{Path, PathBuf};
+
+use windows::Win32::System::Registry::HKEY_LOCAL_MACHINE;
+
+use crate::{
+    control_sets::get_current_control_set,
+    registry_key::RegistryKey,
+    user_hives::{enable_hive_privileges, load_hive, unload_hive},
+};
+
+/// Prefix of the `HKEY_LOCAL_MACHINE` subkeys the hives of the image are loaded under.
+const IMAGE_HIVE_PREFIX: &str = "klc-install-image-";
+
+/// A registry hive of the image, unloaded when dropped.
+struct ImageHive {
+    key: Option<RegistryKey>,
+    loaded_as: String,
+}
+
+impl ImageHive {
+    fn load(name: &str, hive_path: &Path) -> Result<ImageHive, String> {
+        let loaded_as = format!("{}{}", IMAGE_HIVE_PREFIX, name);
+        load_hive(HKEY_LOCAL_MACHINE, &loaded_as, hive_path).map_err(|e| {
+            format!(
+                "Couldn't load the {} hive of the image from {}. {}",
+                name,
+                hive_path.display(),
+                e
+            )
+        })?;
+
+        // From here on, dropping the hive unloads it
+        let mut hive = ImageHive {
+            key: None,
+            loaded_as,
+        };
+        let key = RegistryKey::local_machine()
+            .get_subkey(&hive.loaded_as)
+            .map_err(|e| format!("Couldn't open the {} hive of the image. {}", name, e))?;
+        hive.key = Some(key);
+
+        Ok(hive)
+    }
+
+    fn get_key(&self) -> &RegistryKey {
+        self.key.as_ref().unwrap()
+    }
+}
+
+impl Drop for ImageHive {
+    fn drop(&mut self) {
+        // The hive can't be unloaded while a key in it is open
+        self.key = None;
+        unload_hive(HKEY_LOCAL_MACHINE, &self.loaded_as);
+    }
+}
+
+/// A Windows image mounted in a directory, with its SYSTEM hive and the hive of its Default
+/// profile loaded until dropped.
+///
+/// The image must be for the same architecture as this machine, as the layout is built for it.
+pub struct OfflineImage {
+    root: PathBuf,
+    system: ImageHive,
+    default_user: ImageHive,
+}
+
+impl OfflineImage {
+    /// Loads the hives of the image mounted at the directory, which needs administrator rights.
+    pub fn open(root: &Path) -> Result<OfflineImage, String> {
+        let system_hive_path = root.join("Windows\\System32\\config\\SYSTEM");
+        if !system_hive_path.is_file() {
+            return Err(format!(
+                "{} isn't a mounted Windows image, Windows\\System32\\config\\SYSTEM is missing.",
+                root.display()
+            ));
+        }
+        let default_hive_path = root.join("Users\\Default\\NTUSER.DAT");
+
+        enable_hive_privileges()?;
+        let system = ImageHive::load("SYSTEM", &system_hive_path)?;
+        let default_user = ImageHive::load("Default", &default_hive_path)?;
+
+        Ok(OfflineImage {
+            root: root.to_path_buf(),
+            system,
+            default_user,
+        })
+    }
+
+    pub fn get_root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The Keyboard Layouts key of the control set the image boots with.
+    pub fn get_layouts_key(&self) -> Result<RegistryKey, String> {
+        let system_key = self.system.get_key();
+        let control_set = get_current_control_set(system_key)
+            .map_err(|e| format!("Couldn't find the control set of the image. {}", e))?;
+
+        system_key
+            .get_subkey(&format!("{}\\Control\\Keyboard Layouts", control_set))
+            .map_err(|e| format!("Couldn't open the layouts of the image. {}", e))
+    }
+
+    /// The hive new users of the image copy their settings from.
+    pub fn get_default_user_key(&self) -> &RegistryKey {
+        self.default_user.get_key()
+    }
+
+    /// System32 of the image, where the layout DLLs go.
+    pub fn get_system_dir(&self) -> PathBuf {
+        self.root.join("Windows\\System32")
+    }
+
+    /// SysWOW64 of the image, for the DLLs of 32-bit applications. Only 64-bit images have it.
+    pub fn get_wow64_dir(&self) -> Option<PathBuf> {
+        Some(self.root.join("Windows\\SysWOW64")).filter(|dir| dir.is_dir())
+    }
+}
@@ -0,0 +1,97 @@
+use std::{fs, path::Path};
+
+/// Clears the fields of a PE image that change with every build of the same source:
+/// the link timestamp and the checksum.
+fn clear_build_stamps(image: &mut [u8]) {
+    let Some(pe_offset) = image
+        .get(0x3C..0x40)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+    else {
+        return;
+    };
+    if image.get(pe_offset..pe_offset + 4) != Some(b"PE\0\0") {
+        return;
+    }
+
+    // The COFF header follows the signature, and the optional header follows it
+    let timestamp = pe_offset + 8;
+    let checksum = pe_offset + 24 + 64;
+    for field in [timestamp, checksum] {
+        if let Some(bytes) = image.get_mut(field..field + 4) {
+            bytes.fill(0);
+        }
+    }
+}
+
+/// Whether the DLLs were built from the same layout, ignoring when they were linked.
+///
+/// KBDUTOOL stamps every build with the time, so the files are never equal byte for byte.
+pub fn dll_builds_equal(a: &Path, b: &Path) -> Result<bool, String> {
+    let read = |path: &Path| {
+        fs::read(path).map_err(|e| format!("Couldn't read {}. {}", path.display(), e))
+    };
+    let (mut image_a, mut image_b) = (read(a)?, read(b)?);
+    if image_a.len() != image_b.len() {
+        return Ok(false);
+    }
+
+    clear_build_stamps(&mut image_a);
+    clear_build_stamps(&mut image_b);
+
+    Ok(image_a == image_b)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn make_image(timestamp: u32, code: u8) -> Vec<u8> {
+        let mut image = vec![0u8; 0x100];
+        image[..2].copy_from_slice(b"MZ");
+        image[0x3C..0x40].copy_from_slice(&0x40u32.to_le_bytes());
+        image[0x40..0x44].copy_from_slice(b"PE\0\0");
+        image[0x48..0x4C].copy_from_slice(&timestamp.to_le_bytes());
+        image[0xFF] = code;
+        image
+    }
+
+    #[test]
+    fn test_clear_build_stamps() {
+        let mut a = make_image(1, 0);
+        let mut b = make_image(2, 0);
+        let mut c = make_image(2, 1);
+        clear_build_stamps(&mut a);
+        clear_build_stamps(&mut b);
+        clear_build_stamps(&mut c);
+
+        assert_eq!(a, b);
+        assert_ne!(b, c);
+
+        // Not a PE image, so nothing is cleared
+        let mut text = b"not an image".to_vec();
+        clear_build_stamps(&mut text);
+        assert_eq!(text, b"not an image");
+    }
+    #[test]
+    fn test_dll_builds_equal() {
+        let dir = std::env::temp_dir().join(format!("klc-install-test-pe-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, image: Vec<u8>| {
+            let path = dir.join(name);
+            fs::write(&path, image).unwrap();
+            path
+        };
+
+        // Two builds of the same layout, linked at different times
+        let first_build = write("first.dll", make_image(0x5F000000, 7));
+        let second_build = write("second.dll", make_image(0x6A000000, 7));
+        let other_layout = write("other.dll", make_image(0x6A000000, 8));
+
+        let same = dll_builds_equal(&first_build, &second_build);
+        let different = dll_builds_equal(&first_build, &other_layout);
+        _ = fs::remove_dir_all(&dir);
+
+        assert!(same.unwrap());
+        assert!(!different.unwrap());
+    }
+}
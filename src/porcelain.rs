@@ -0,0 +1,108 @@
+use std::{
+    fs::File,
+    io::{self, Write},
+    mem::ManuallyDrop,
+    os::windows::io::FromRawHandle,
+    sync::OnceLock,
+};
+
+use serde::Serialize;
+use windows::Win32::System::Console::{
+    GetStdHandle, SetStdHandle, STD_ERROR_HANDLE, STD_OUTPUT_HANDLE,
+};
+
+/// The original standard output, which only gets the events in porcelain mode.
+static EVENT_OUTPUT: OnceLock<isize> = OnceLock::new();
+
+/// A machine-readable progress event, printed as a line of JSON, e.g.
+/// `{"event":"step_started","step":"compile","number":2,"total":6}`.
+///
+/// Front-ends rely on the names of the events and their fields, so they must never change.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    StepStarted {
+        step: &'a str,
+        number: usize,
+        total: usize,
+    },
+    StepFinished {
+        step: &'a str,
+    },
+    Warning {
+        message: &'a str,
+    },
+    Error {
+        message: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        hint: Option<&'a str>,
+    },
+    /// Always the last event.
+    Finished {
+        exit_code: i32,
+    },
+}
+
+/// Switches to porcelain mode, where the standard output only gets the events.
+///
+/// The usual messages are printed to the standard error instead, as the standard output
+/// is looked up again for every write.
+pub fn init(porcelain: bool) -> Result<(), String> {
+    if !porcelain {
+        return Ok(());
+    }
+
+    _ = io::stdout().flush();
+    unsafe {
+        let stdout = GetStdHandle(STD_OUTPUT_HANDLE).map_err(|e| e.to_string())?;
+        let stderr = GetStdHandle(STD_ERROR_HANDLE).map_err(|e| e.to_string())?;
+        SetStdHandle(STD_OUTPUT_HANDLE, stderr).map_err(|e| e.to_string())?;
+        _ = EVENT_OUTPUT.set(stdout.0 as isize);
+    }
+
+    Ok(())
+}
+
+/// Prints the event in porcelain mode, otherwise does nothing.
+pub fn emit(event: &Event) {
+    let Some(&handle) = EVENT_OUTPUT.get() else {
+        return;
+    };
+
+    let mut line = serde_json::to_string(event).unwrap();
+    line.push('\n');
+
+    // The handle stays open for the other events
+    let mut output = ManuallyDrop::new(unsafe { File::from_raw_handle(handle as _) });
+    _ = output.write_all(line.as_bytes());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_event_json() {
+        assert_eq!(
+            serde_json::to_string(&Event::StepStarted {
+                step: "compile",
+                number: 2,
+                total: 6
+            })
+            .unwrap(),
+            r#"{"event":"step_started","step":"compile","number":2,"total":6}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&Event::Error {
+                message: "MSKLC was not found in PATH.",
+                hint: None
+            })
+            .unwrap(),
+            r#"{"event":"error","message":"MSKLC was not found in PATH."}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&Event::Finished { exit_code: 5 }).unwrap(),
+            r#"{"event":"finished","exit_code":5}"#
+        );
+    }
+}
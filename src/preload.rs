@@ -0,0 +1,481 @@
+use crate::{
+    klid::get_locale_name,
+    registry_key::{RegistryError, RegistryKey},
+    registry_value::RegistryValueData,
+};
+
+/// Key of a user hive with the input settings of the user.
+pub const USER_PROFILE_KEY_PATH: &str = "Control Panel\\International\\User Profile";
+
+/// Value of the `User Profile` key with the language tags of the Settings language list, in order.
+const LANGUAGES_VALUE_NAME: &str = "Languages";
+
+/// Opens (or creates) the `Keyboard Layout\Preload` key of the given user hive.
+pub fn get_preload_key(user_key: &RegistryKey) -> Result<RegistryKey, RegistryError> {
+    user_key.create_subkey("Keyboard Layout\\Preload")
+}
+
+/// Opens (or creates) the `Keyboard Layout\Substitutes` key of the given user hive.
+pub fn get_substitutes_key(user_key: &RegistryKey) -> Result<RegistryKey, RegistryError> {
+    user_key.create_subkey("Keyboard Layout\\Substitutes")
+}
+
+/// Reads the preloaded layouts in order.
+///
+/// The values are named `1`, `2`, … and the sequence ends at the first missing number.
+pub fn read_preload(preload_key: &RegistryKey) -> Result<Vec<String>, RegistryError> {
+    let mut klids = Vec::new();
+
+    for i in 1.. {
+        match preload_key.try_get_value(Some(&i.to_string()))? {
+            Some(value) => match value.get_value() {
+                RegistryValueData::String(klid) => klids.push(klid.clone()),
+                _ => {
+                    return Err(RegistryError::Other(format!(
+                        "Preload value {} is not a string!",
+                        i
+                    )))
+                }
+            },
+            None => break,
+        }
+    }
+
+    Ok(klids)
+}
+
+/// Replaces the preloaded layouts, numbering them from `1` in the given order.
+pub fn write_preload(preload_key: &RegistryKey, klids: &[String]) -> Result<(), RegistryError> {
+    let old_len = read_preload(preload_key)?.len();
+
+    for (i, klid) in klids.iter().enumerate() {
+        preload_key.set_value(
+            Some(&(i + 1).to_string()),
+            RegistryValueData::String(klid.clone()),
+        )?;
+    }
+
+    for i in klids.len()..old_len {
+        preload_key.delete_value(Some(&(i + 1).to_string()))?;
+    }
+
+    Ok(())
+}
+
+/// Opens the subkey of the user hive, or returns `None` if it doesn't exist.
+fn open_if_exists(
+    user_key: &RegistryKey,
+    path: &str,
+) -> Result<Option<RegistryKey>, RegistryError> {
+    match user_key.get_subkey(path) {
+        Ok(key) => Ok(Some(key)),
+        Err(RegistryError::NotFound) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads all layout substitutions as pairs of substituted and substitute KLIDs.
+pub fn read_substitutes(
+    substitutes_key: &RegistryKey,
+) -> Result<Vec<(String, String)>, RegistryError> {
+    let mut substitutes = Vec::new();
+
+    for value in substitutes_key.iter_values() {
+        let value = value?;
+        let name = value.get_name().unwrap_or_default().to_string();
+
+        if let RegistryValueData::String(substitute) = value.get_value() {
+            substitutes.push((name, substitute.clone()));
+        }
+    }
+
+    Ok(substitutes)
+}
+
+/// Finds an unused `dXXXllll` substitute name for a custom layout.
+fn get_next_substitute_name(
+    klid: &str,
+    substitutes: &[(String, String)],
+) -> Result<String, RegistryError> {
+    let language = &klid[klid.len().saturating_sub(4)..];
+
+    for n in 1..=0xfff {
+        let name = format!("d{:03x}{}", n, language.to_ascii_lowercase());
+
+        if !substitutes
+            .iter()
+            .any(|(existing, _)| existing.eq_ignore_ascii_case(&name))
+        {
+            return Ok(name);
+        }
+    }
+
+    Err(RegistryError::Other(format!(
+        "No more substitutes are available for {}!",
+        klid
+    )))
+}
+
+/// Adds the layout to the preloaded layouts of the user hive.
+///
+/// Custom layouts can't be preloaded by their KLID, so a `dXXXllll` substitute mapped
+/// to the KLID is preloaded instead. An already preloaded layout is only moved when
+/// it's requested to be `first`. The layout is also added to the language list of the
+/// Settings app, see `add_to_language_list`.
+///
+/// Returns the entry that was put in the preload list.
+pub fn preload_layout(
+    user_key: &RegistryKey,
+    klid: &str,
+    first: bool,
+) -> Result<String, RegistryError> {
+    let preload_key = get_preload_key(user_key)?;
+    let substitutes_key = get_substitutes_key(user_key)?;
+
+    let mut preload = read_preload(&preload_key)?;
+    let substitutes = read_substitutes(&substitutes_key)?;
+
+    let entry = if klid.starts_with("0000") {
+        klid.to_string()
+    } else {
+        match substitutes
+            .iter()
+            .find(|(_, substitute)| substitute.eq_ignore_ascii_case(klid))
+        {
+            Some((name, _)) => name.clone(),
+            None => {
+                let name = get_next_substitute_name(klid, &substitutes)?;
+                substitutes_key
+                    .set_value(Some(&name), RegistryValueData::String(klid.to_string()))?;
+                name
+            }
+        }
+    };
+
+    let existing = preload.iter().position(|p| p.eq_ignore_ascii_case(&entry));
+    match (existing, first) {
+        // It may still be missing from the language list
+        (Some(_), false) => {}
+        (Some(index), true) => {
+            preload.remove(index);
+            preload.insert(0, entry.clone());
+        }
+        (None, true) => preload.insert(0, entry.clone()),
+        (None, false) => preload.push(entry.clone()),
+    }
+
+    if existing.is_none() || first {
+        write_preload(&preload_key, &preload)?;
+    }
+    add_to_language_list(user_key, klid)?;
+
+    Ok(entry)
+}
+
+/// Reads the language tags of the Settings language list, e.g. `pl-PL`, in order.
+fn read_languages(profile_key: &RegistryKey) -> Result<Vec<String>, RegistryError> {
+    match profile_key.try_get_value(Some(LANGUAGES_VALUE_NAME))? {
+        Some(value) => match value.get_value() {
+            RegistryValueData::MultiString(languages) => Ok(languages.clone()),
+            _ => Err(RegistryError::Other(
+                "The Languages value is not a multi-string!".to_string(),
+            )),
+        },
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Reads the input methods of a language of the Settings list, with their positions.
+fn read_language_input_methods(
+    language_key: &RegistryKey,
+) -> Result<Vec<(String, u32)>, RegistryError> {
+    let mut input_methods = Vec::new();
+
+    for value in language_key.iter_values() {
+        let value = value?;
+        let name = value.get_name().unwrap_or_default();
+        // The other values, like CachedLanguageName, aren't input methods
+        if !name.contains(':') {
+            continue;
+        }
+        if let RegistryValueData::Dword(position) = value.get_value() {
+            input_methods.push((name.to_string(), *position));
+        }
+    }
+
+    Ok(input_methods)
+}
+
+/// Returns the language tag of the layout's language, e.g. `pl-PL` for `f0010415`.
+fn get_klid_locale_name(klid: &str) -> Option<String> {
+    u16::from_str_radix(&klid[klid.len().saturating_sub(4)..], 16)
+        .ok()
+        .and_then(get_locale_name)
+}
+
+/// Adds the layout to the input methods of its language in the language list of the Settings app,
+/// adding the language too if it's missing.
+///
+/// Since Windows 8, the settings and the language switcher show the input methods of the
+/// `User Profile` key, not the `Preload` list. Layouts of languages Windows doesn't know
+/// are left out, as it couldn't show them.
+pub fn add_to_language_list(user_key: &RegistryKey, klid: &str) -> Result<(), RegistryError> {
+    let Some(language_tag) = get_klid_locale_name(klid) else {
+        return Ok(());
+    };
+
+    let profile_key = user_key.create_subkey(USER_PROFILE_KEY_PATH)?;
+    let mut languages = read_languages(&profile_key)?;
+    if !languages
+        .iter()
+        .any(|language| language.eq_ignore_ascii_case(&language_tag))
+    {
+        languages.push(language_tag.clone());
+        profile_key.set_value(
+            Some(LANGUAGES_VALUE_NAME),
+            RegistryValueData::MultiString(languages),
+        )?;
+    }
+
+    let language_key = profile_key.create_subkey(&language_tag)?;
+    let input_method = get_input_method(klid);
+    let input_methods = read_language_input_methods(&language_key)?;
+    if !input_methods
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case(&input_method))
+    {
+        language_key.set_value(
+            Some(&input_method),
+            RegistryValueData::Dword(get_next_position(&input_methods)),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// The position after the last input method of a language, starting at 1.
+fn get_next_position(input_methods: &[(String, u32)]) -> u32 {
+    input_methods
+        .iter()
+        .map(|(_, position)| position + 1)
+        .max()
+        .unwrap_or(1)
+}
+
+/// Returns the preload entries that don't refer to the layout, and the names of the substitutes
+/// mapped to it.
+fn find_layout_references(
+    klid: &str,
+    preload: &[String],
+    substitutes: &[(String, String)],
+) -> (Vec<String>, Vec<String>) {
+    let substitute_names: Vec<String> = substitutes
+        .iter()
+        .filter(|(_, substitute)| substitute.eq_ignore_ascii_case(klid))
+        .map(|(name, _)| name.clone())
+        .collect();
+
+    let kept_preload = preload
+        .iter()
+        .filter(|entry| {
+            !entry.eq_ignore_ascii_case(klid)
+                && !substitute_names
+                    .iter()
+                    .any(|name| name.eq_ignore_ascii_case(entry))
+        })
+        .cloned()
+        .collect();
+
+    (kept_preload, substitute_names)
+}
+
+/// Removes the layout from the preloaded layouts of the user hive, renumbering the others,
+/// along with its substitutes and its entry in the language list of the Settings app.
+///
+/// Returns the values that referred to the layout, like `Preload\2 (d0010415)`. With `dry_run`,
+/// they're only found.
+pub fn unpreload_layout(
+    user_key: &RegistryKey,
+    klid: &str,
+    dry_run: bool,
+) -> Result<Vec<String>, RegistryError> {
+    let preload_key = open_if_exists(user_key, "Keyboard Layout\\Preload")?;
+    let substitutes_key = open_if_exists(user_key, "Keyboard Layout\\Substitutes")?;
+
+    let preload = match &preload_key {
+        Some(key) => read_preload(key)?,
+        None => Vec::new(),
+    };
+    let substitutes = match &substitutes_key {
+        Some(key) => read_substitutes(key)?,
+        None => Vec::new(),
+    };
+
+    let (kept_preload, substitute_names) = find_layout_references(klid, &preload, &substitutes);
+
+    let mut references: Vec<String> = preload
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| !kept_preload.contains(entry))
+        .map(|(i, entry)| format!("Preload\\{} ({})", i + 1, entry))
+        .collect();
+    references.extend(
+        substitute_names
+            .iter()
+            .map(|name| format!("Substitutes\\{}", name)),
+    );
+
+    // The language stays in the list, as the user may still use it with other layouts
+    let input_method = get_input_method(klid);
+    let language_key = match get_klid_locale_name(klid) {
+        Some(language_tag) => open_if_exists(
+            user_key,
+            &format!("{}\\{}", USER_PROFILE_KEY_PATH, language_tag),
+        )?,
+        None => None,
+    };
+    let language_input_method = match &language_key {
+        Some(key) => read_language_input_methods(key)?
+            .into_iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(&input_method)),
+        None => None,
+    };
+    if let (Some(key), Some((name, _))) = (&language_key, &language_input_method) {
+        references.push(format!("User Profile\\{}\\{}", key.get_name(), name));
+    }
+
+    if dry_run {
+        return Ok(references);
+    }
+
+    if let (Some(key), Some((name, _))) = (&language_key, &language_input_method) {
+        key.delete_value(Some(name))?;
+    }
+    if kept_preload.len() != preload.len() {
+        write_preload(preload_key.as_ref().unwrap(), &kept_preload)?;
+    }
+    if let Some(substitutes_key) = &substitutes_key {
+        for name in &substitute_names {
+            substitutes_key.delete_value(Some(name))?;
+        }
+    }
+
+    Ok(references)
+}
+
+/// Replaces the substitutes in the preload entries with the KLIDs they stand for.
+fn resolve_substitutes(preload: Vec<String>, substitutes: &[(String, String)]) -> Vec<String> {
+    preload
+        .into_iter()
+        .map(|entry| {
+            substitutes
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(&entry))
+                .map_or(entry, |(_, klid)| klid.clone())
+        })
+        .collect()
+}
+
+/// Reads the KLIDs of the layouts preloaded in the user hive, in order.
+pub fn read_preloaded_klids(user_key: &RegistryKey) -> Result<Vec<String>, RegistryError> {
+    let preload = match open_if_exists(user_key, "Keyboard Layout\\Preload")? {
+        Some(key) => read_preload(&key)?,
+        None => return Ok(Vec::new()),
+    };
+    let substitutes = match open_if_exists(user_key, "Keyboard Layout\\Substitutes")? {
+        Some(key) => read_substitutes(&key)?,
+        None => Vec::new(),
+    };
+
+    Ok(resolve_substitutes(preload, &substitutes))
+}
+
+/// Formats the layout as an input method of the language settings, e.g. `0415:F0010415`.
+pub fn get_input_method(klid: &str) -> String {
+    format!("{}:{}", &klid[klid.len().saturating_sub(4)..], klid).to_ascii_uppercase()
+}
+
+/// Sets the input method new sessions of the user hive start with, in the given
+/// `Control Panel\International\User Profile` key.
+pub fn set_input_method_override(
+    user_key: &RegistryKey,
+    profile_key_path: &str,
+    input_method: &str,
+) -> Result<(), RegistryError> {
+    user_key.create_subkey(profile_key_path)?.set_value(
+        Some("InputMethodOverride"),
+        RegistryValueData::String(input_method.to_string()),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_get_next_substitute_name() {
+        let substitutes = vec![
+            ("d0010415".to_string(), "f0010415".to_string()),
+            ("D0020415".to_string(), "f0020415".to_string()),
+        ];
+
+        assert_eq!(
+            get_next_substitute_name("f0030415", &substitutes).unwrap(),
+            "d0030415"
+        );
+        assert_eq!(
+            get_next_substitute_name("F0010409", &substitutes).unwrap(),
+            "d0010409"
+        );
+    }
+
+    #[test]
+    fn test_find_layout_references() {
+        let preload = vec![
+            "00000415".to_string(),
+            "D0010415".to_string(),
+            "d0020415".to_string(),
+        ];
+        let substitutes = vec![
+            ("d0010415".to_string(), "f0010415".to_string()),
+            ("d0020415".to_string(), "f0020415".to_string()),
+        ];
+
+        let (kept, names) = find_layout_references("F0010415", &preload, &substitutes);
+        assert_eq!(kept, vec!["00000415", "d0020415"]);
+        assert_eq!(names, vec!["d0010415"]);
+
+        let (kept, names) = find_layout_references("00000415", &preload, &substitutes);
+        assert_eq!(kept, vec!["D0010415", "d0020415"]);
+        assert!(names.is_empty());
+    }
+
+    #[test]
+    fn test_resolve_substitutes() {
+        let preload = vec!["00000415".to_string(), "D0010415".to_string()];
+        let substitutes = vec![("d0010415".to_string(), "f0010415".to_string())];
+
+        assert_eq!(
+            resolve_substitutes(preload, &substitutes),
+            vec!["00000415", "f0010415"]
+        );
+    }
+
+    #[test]
+    fn test_get_next_position() {
+        assert_eq!(get_next_position(&[]), 1);
+        assert_eq!(
+            get_next_position(&[
+                ("0415:00000415".to_string(), 1),
+                ("0415:F0010415".to_string(), 3),
+            ]),
+            4
+        );
+    }
+
+    #[test]
+    fn test_get_input_method() {
+        assert_eq!(get_input_method("f0010415"), "0415:F0010415");
+        assert_eq!(get_input_method("00000409"), "0409:00000409");
+    }
+}
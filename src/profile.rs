@@ -0,0 +1,403 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    layout_provenance::SOURCE_VALUE_NAME,
+    layouts::{
+        get_dll_system_dirs, get_layouts_key, get_next_layout_id, get_saved_dll_path,
+        is_layout_id_used,
+    },
+    preload::{
+        get_preload_key, get_substitutes_key, read_preload, read_substitutes, write_preload,
+    },
+    registry_key::{RegistryError, RegistryKey},
+    registry_value::RegistryValueData,
+};
+
+const TOGGLE_KEY_PATH: &str = "Keyboard Layout\\Toggle";
+const SCANCODE_MAP_KEY_PATH: &str = "HKLM\\SYSTEM\\CurrentControlSet\\Control\\Keyboard Layout";
+const SCANCODE_MAP_VALUE_NAME: &str = "Scancode Map";
+
+/// The complete keyboard configuration of a machine and its current user.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Profile {
+    /// KLIDs of the preloaded layouts, in order.
+    #[serde(default)]
+    pub preload: Vec<String>,
+
+    /// The `Scancode Map` of the machine as a hex string.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scancode_map: Option<String>,
+
+    #[serde(default)]
+    pub substitutes: BTreeMap<String, String>,
+
+    #[serde(default)]
+    pub toggle: ProfileToggle,
+
+    /// Custom layouts installed on the machine.
+    #[serde(default)]
+    pub layouts: Vec<ProfileLayout>,
+}
+
+/// The input language switching hotkeys.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ProfileToggle {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hotkey: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language_hotkey: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layout_hotkey: Option<String>,
+}
+
+impl ProfileToggle {
+    const VALUES: [&'static str; 3] = ["Hotkey", "Language Hotkey", "Layout Hotkey"];
+
+    fn get(&self, value_name: &str) -> &Option<String> {
+        match value_name {
+            "Hotkey" => &self.hotkey,
+            "Language Hotkey" => &self.language_hotkey,
+            _ => &self.layout_hotkey,
+        }
+    }
+
+    fn get_mut(&mut self, value_name: &str) -> &mut Option<String> {
+        match value_name {
+            "Hotkey" => &mut self.hotkey,
+            "Language Hotkey" => &mut self.language_hotkey,
+            _ => &mut self.layout_hotkey,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProfileLayout {
+    pub key: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+
+    pub file: String,
+
+    /// The .KLC file or URL the layout was installed from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+fn try_get_string(key: &RegistryKey, name: &str) -> Result<Option<String>, RegistryError> {
+    Ok(key
+        .try_get_value(Some(name))?
+        .and_then(|value| match value.get_value() {
+            RegistryValueData::String(s) | RegistryValueData::ExpandString(s) => Some(s.clone()),
+            _ => None,
+        }))
+}
+
+fn bytes_to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hex_to_bytes(hex: &str) -> Result<Vec<u8>, String> {
+    let hex: Vec<char> = hex.chars().filter(|c| !c.is_whitespace()).collect();
+
+    if !hex.len().is_multiple_of(2) {
+        return Err("Hex string must have an even length.".to_string());
+    }
+
+    hex.chunks(2)
+        .map(|pair| {
+            let pair: String = pair.iter().collect();
+            u8::from_str_radix(&pair, 16).map_err(|e| format!("Invalid hex byte {}. {}", pair, e))
+        })
+        .collect()
+}
+
+impl Profile {
+    pub fn read_from_system() -> Result<Profile, String> {
+        let mut profile = Profile::default();
+
+        let layouts_key = get_layouts_key().map_err(|e| e.to_string())?;
+        for layout_key in layouts_key.iter_children() {
+            let layout_key = layout_key.map_err(|e| e.to_string())?;
+            let key = layout_key.get_name().to_string();
+
+            // Only custom layouts, system ones are on every machine anyway
+            if u32::from_str_radix(&key, 16).map_or(true, |klid| klid < 0x00800000) {
+                continue;
+            }
+
+            let Some(file) =
+                try_get_string(&layout_key, "Layout File").map_err(|e| e.to_string())?
+            else {
+                continue;
+            };
+
+            profile.layouts.push(ProfileLayout {
+                key,
+                id: try_get_string(&layout_key, "Layout Id").map_err(|e| e.to_string())?,
+                text: try_get_string(&layout_key, "Layout Text").map_err(|e| e.to_string())?,
+                display_name: try_get_string(&layout_key, "Layout Display Name")
+                    .map_err(|e| e.to_string())?,
+                file,
+                source: try_get_string(&layout_key, SOURCE_VALUE_NAME)
+                    .map_err(|e| e.to_string())?,
+            });
+        }
+
+        let current_user = RegistryKey::current_user();
+
+        let preload_key = get_preload_key(&current_user).map_err(|e| e.to_string())?;
+        profile.preload = read_preload(&preload_key).map_err(|e| e.to_string())?;
+
+        let substitutes_key = get_substitutes_key(&current_user).map_err(|e| e.to_string())?;
+        profile.substitutes = read_substitutes(&substitutes_key)
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .collect();
+
+        // Users who never changed the hotkeys don't have the key
+        match current_user.get_subkey(TOGGLE_KEY_PATH) {
+            Ok(toggle_key) => {
+                for value_name in ProfileToggle::VALUES {
+                    *profile.toggle.get_mut(value_name) =
+                        try_get_string(&toggle_key, value_name).map_err(|e| e.to_string())?;
+                }
+            }
+            Err(RegistryError::NotFound) => {}
+            Err(e) => return Err(e.to_string()),
+        }
+
+        let scancode_key =
+            RegistryKey::from_path(SCANCODE_MAP_KEY_PATH).map_err(|e| e.to_string())?;
+        let scancode_map = scancode_key
+            .try_get_value(Some(SCANCODE_MAP_VALUE_NAME))
+            .map_err(|e| e.to_string())?;
+        if let Some(scancode_map) = scancode_map {
+            if let RegistryValueData::Binary(data) = scancode_map.get_value() {
+                profile.scancode_map = Some(bytes_to_hex(data));
+            }
+        }
+
+        Ok(profile)
+    }
+
+    pub fn read_from_file(file_path: &Path) -> Result<Profile, String> {
+        let content = fs::read_to_string(file_path).map_err(|e| e.to_string())?;
+        toml::from_str(&content).map_err(|e| e.to_string())
+    }
+
+    pub fn write_to_file(&self, file_path: &Path) -> Result<(), String> {
+        let content = toml::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(file_path, content).map_err(|e| e.to_string())
+    }
+
+    /// Registers the profile's layouts (taking their DLLs from `dll_dir` if they
+    /// aren't in System32 and SysWOW64 yet) and applies the rest of the configuration.
+    pub fn apply(&self, dll_dir: &Path) -> Result<(), String> {
+        let layouts_key = get_layouts_key().map_err(|e| e.to_string())?;
+        let system_dirs = get_dll_system_dirs()?;
+
+        for layout in &self.layouts {
+            match layouts_key.get_subkey(&layout.key) {
+                Ok(layout_key) => {
+                    let file =
+                        try_get_string(&layout_key, "Layout File").map_err(|e| e.to_string())?;
+                    if file.is_some_and(|f| f.eq_ignore_ascii_case(&layout.file)) {
+                        println!("Layout {} is already installed.", layout.key);
+                    } else {
+                        println!(
+                            "Skipping layout {}, the key is used by a different layout!",
+                            layout.key
+                        );
+                    }
+                    continue;
+                }
+                Err(RegistryError::NotFound) => {}
+                Err(e) => return Err(e.to_string()),
+            }
+
+            let mut dll_found = true;
+            for (i, system_dir) in system_dirs.iter().enumerate() {
+                let system_dll_path = system_dir.join(&layout.file);
+                if system_dll_path.exists() {
+                    continue;
+                }
+
+                let profile_dll_path = get_saved_dll_path(dll_dir, system_dir, &layout.file)?;
+                if profile_dll_path.exists() {
+                    fs::copy(&profile_dll_path, &system_dll_path).map_err(|e| e.to_string())?;
+                } else if i == 0 {
+                    dll_found = false;
+                    break;
+                } else {
+                    // The layout still works, but not in 32-bit applications
+                    println!(
+                        "The DLL {} of layout {} for {} wasn't found, it won't be restored.",
+                        layout.file,
+                        layout.key,
+                        system_dir.display()
+                    );
+                }
+            }
+            if !dll_found {
+                match &layout.source {
+                    Some(source) => println!(
+                        "Skipping layout {}, its DLL {} wasn't found! It was installed from {}.",
+                        layout.key, layout.file, source
+                    ),
+                    None => println!(
+                        "Skipping layout {}, its DLL {} wasn't found!",
+                        layout.key, layout.file
+                    ),
+                }
+                continue;
+            }
+
+            let requested_id = layout
+                .id
+                .as_deref()
+                .map(|id| u16::from_str_radix(id, 16).map_err(|e| e.to_string()))
+                .transpose()?;
+            let layout_id = match requested_id {
+                Some(id) if !is_layout_id_used(id, Some(&layout.key))? => id,
+                _ => get_next_layout_id(&[])?,
+            };
+            let layout_id_str = format!("{:04X}", layout_id);
+
+            let layout_key = layouts_key
+                .create_subkey(&layout.key)
+                .map_err(|e| e.to_string())?;
+
+            use RegistryValueData as RVD;
+
+            let mut values = vec![
+                ("Layout Id", RVD::String(layout_id_str.clone())),
+                ("Layout File", RVD::String(layout.file.clone())),
+                ("Installed by", RVD::String("klc-install".to_string())),
+            ];
+            if let Some(text) = &layout.text {
+                values.push(("Layout Text", RVD::String(text.clone())));
+            }
+            if let Some(display_name) = &layout.display_name {
+                values.push((
+                    "Layout Display Name",
+                    RVD::ExpandString(display_name.clone()),
+                ));
+            }
+
+            for (name, value) in values {
+                layout_key
+                    .set_value(Some(name), value)
+                    .map_err(|e| e.to_string())?;
+            }
+
+            println!(
+                "Installed layout {} with ID {} from {}.",
+                layout.key, layout_id_str, layout.file
+            );
+        }
+
+        let current_user = RegistryKey::current_user();
+
+        let substitutes_key = get_substitutes_key(&current_user).map_err(|e| e.to_string())?;
+        for (klid, substitute) in &self.substitutes {
+            substitutes_key
+                .set_value(Some(klid), RegistryValueData::String(substitute.clone()))
+                .map_err(|e| e.to_string())?;
+        }
+
+        if !self.preload.is_empty() {
+            let preload_key = get_preload_key(&current_user).map_err(|e| e.to_string())?;
+            write_preload(&preload_key, &self.preload).map_err(|e| e.to_string())?;
+            println!("Set preloaded layouts: {}", self.preload.join(", "));
+        }
+
+        let toggle_key = current_user
+            .create_subkey(TOGGLE_KEY_PATH)
+            .map_err(|e| e.to_string())?;
+        for value_name in ProfileToggle::VALUES {
+            if let Some(value) = self.toggle.get(value_name) {
+                toggle_key
+                    .set_value(Some(value_name), RegistryValueData::String(value.clone()))
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+
+        if let Some(scancode_map) = &self.scancode_map {
+            let scancode_key =
+                RegistryKey::from_path(SCANCODE_MAP_KEY_PATH).map_err(|e| e.to_string())?;
+            scancode_key
+                .set_value(
+                    Some(SCANCODE_MAP_VALUE_NAME),
+                    RegistryValueData::Binary(hex_to_bytes(scancode_map)?),
+                )
+                .map_err(|e| e.to_string())?;
+            println!("Set the scancode map. It will take effect after a restart.");
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hex_round_trip() {
+        let bytes = vec![0x00, 0x01, 0x3a, 0xe0, 0xff];
+        assert_eq!(bytes_to_hex(&bytes), "00013ae0ff");
+        assert_eq!(hex_to_bytes("00013ae0ff").unwrap(), bytes);
+        assert_eq!(hex_to_bytes("00 01 3A E0 FF").unwrap(), bytes);
+        assert!(hex_to_bytes("abc").is_err());
+    }
+
+    #[test]
+    fn test_profile_toml() {
+        let profile: Profile = toml::from_str(
+            r#"
+            preload = ["00000415", "d0010415"]
+
+            [substitutes]
+            d0010415 = "f0010415"
+
+            [toggle]
+            hotkey = "1"
+
+            [[layouts]]
+            key = "f0010415"
+            id = "0F00"
+            text = "Multilingual"
+            file = "multilin.dll"
+            source = "https://example.com/multilin.klc"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(profile.preload, ["00000415", "d0010415"]);
+        assert_eq!(profile.substitutes["d0010415"], "f0010415");
+        assert_eq!(profile.toggle.hotkey.as_deref(), Some("1"));
+        assert_eq!(profile.toggle.layout_hotkey, None);
+        assert_eq!(profile.layouts.len(), 1);
+        assert_eq!(profile.layouts[0].file, "multilin.dll");
+        assert_eq!(profile.scancode_map, None);
+
+        let serialized = toml::to_string_pretty(&profile).unwrap();
+        let reparsed: Profile = toml::from_str(&serialized).unwrap();
+        assert_eq!(reparsed.layouts[0].key, "f0010415");
+        assert_eq!(
+            reparsed.layouts[0].source.as_deref(),
+            Some("https://example.com/multilin.klc")
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use std::{fs, path::Path};
+
+use crate::{
+    registry_key::{RegistryError, RegistryKey},
+    registry_value::RegistryValueData,
+};
+
+/// Formats bytes like regedit, e.g. `hex(2):41,00,00,00`.
+fn format_hex(type_code: Option<u32>, bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+
+    match type_code {
+        Some(type_code) => format!("hex({:x}):{}", type_code, bytes.join(",")),
+        None => format!("hex:{}", bytes.join(",")),
+    }
+}
+
+fn to_utf16_bytes(strings: &[&str]) -> Vec<u8> {
+    strings
+        .iter()
+        .flat_map(|string| string.encode_utf16().chain([0]))
+        .flat_map(u16::to_le_bytes)
+        .collect()
+}
+
+fn escape(string: &str) -> String {
+    string.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn format_value(value: &RegistryValueData) -> String {
+    match value {
+        RegistryValueData::None => format_hex(Some(0), &[]),
+        RegistryValueData::Binary(bytes) => format_hex(None, bytes),
+        RegistryValueData::Dword(dword) => format!("dword:{:08x}", dword),
+        RegistryValueData::Qword(qword) => format_hex(Some(0xb), &qword.to_le_bytes()),
+        RegistryValueData::String(string) => format!("\"{}\"", escape(string)),
+        RegistryValueData::ExpandString(string) => format_hex(Some(2), &to_utf16_bytes(&[string])),
+        RegistryValueData::MultiString(strings) => {
+            let strings: Vec<&str> = strings.iter().map(String::as_str).collect();
+            // The list ends with an empty string
+            let mut bytes = to_utf16_bytes(&strings);
+            bytes.extend([0, 0]);
+            format_hex(Some(7), &bytes)
+        }
+    }
+}
+
+/// Formats the values of a key as a .reg file regedit can import.
+pub fn format_reg_file(key_path: &str, values: &[(Option<String>, RegistryValueData)]) -> String {
+    let mut lines = vec![
+        "Windows Registry Editor Version 5.00".to_string(),
+        String::new(),
+        format!("[{}]", key_path),
+    ];
+
+    for (name, value) in values {
+        let name = match name {
+            Some(name) => format!("\"{}\"", escape(name)),
+            None => "@".to_string(),
+        };
+        lines.push(format!("{}={}", name, format_value(value)));
+    }
+    lines.push(String::new());
+
+    lines.join("\r\n")
+}
+
+/// Exports the values of the key to a .reg file, in UTF-16 like regedit writes them.
+pub fn export_reg_file(key: &RegistryKey, file_path: &Path) -> Result<(), String> {
+    let values = key
+        .iter_values()
+        .map(|value| {
+            let value = value?;
+            Ok((
+                value.get_name().map(str::to_string),
+                value.get_value().clone(),
+            ))
+        })
+        .collect::<Result<Vec<_>, RegistryError>>()
+        .map_err(|e| e.to_string())?;
+
+    let content = format_reg_file(key.get_path(), &values);
+    let bytes: Vec<u8> = [0xFF, 0xFE]
+        .into_iter()
+        .chain(content.encode_utf16().flat_map(u16::to_le_bytes))
+        .collect();
+
+    fs::write(file_path, bytes)
+        .map_err(|e| format!("Couldn't write {}. {}", file_path.display(), e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_reg_file() {
+        let values = vec![
+            (
+                Some("Layout Text".to_string()),
+                RegistryValueData::String("Polish \"Dev\" C:\\".to_string()),
+            ),
+            (
+                Some("Layout Display Name".to_string()),
+                RegistryValueData::ExpandString("@A".to_string()),
+            ),
+            (
+                Some("klc-install Tags".to_string()),
+                RegistryValueData::MultiString(vec!["a".to_string()]),
+            ),
+            (Some("Id".to_string()), RegistryValueData::Dword(0x1F)),
+            (Some("Time".to_string()), RegistryValueData::Qword(1)),
+            (None, RegistryValueData::Binary(vec![0xAB, 0x01])),
+        ];
+
+        assert_eq!(
+            format_reg_file("HKEY_LOCAL_MACHINE\\Layouts\\f0010415", &values),
+            [
+                "Windows Registry Editor Version 5.00",
+                "",
+                "[HKEY_LOCAL_MACHINE\\Layouts\\f0010415]",
+                "\"Layout Text\"=\"Polish \\\"Dev\\\" C:\\\\\"",
+                "\"Layout Display Name\"=hex(2):40,00,41,00,00,00",
+                "\"klc-install Tags\"=hex(7):61,00,00,00,00,00",
+                "\"Id\"=dword:0000001f",
+                "\"Time\"=hex(b):01,00,00,00,00,00,00,00",
+                "@=hex:ab,01",
+                "",
+            ]
+            .join("\r\n")
+        );
+    }
+}
@@ -1,16 +1,15 @@
 #![allow(dead_code)]
 
-use std::{
-    fmt::{self, Display, Formatter},
-    iter::from_fn,
-    ptr::null_mut,
-};
+use std::{iter::from_fn, ptr::null_mut};
 
+use thiserror::Error;
 use widestring::U16CString;
 use windows::{
-    core::PWSTR,
+    core::{PCWSTR, PWSTR},
     Win32::{
-        Foundation::{ERROR_ACCESS_DENIED, ERROR_FILE_NOT_FOUND, ERROR_NO_MORE_ITEMS, WIN32_ERROR},
+        Foundation::{
+            ERROR_ACCESS_DENIED, ERROR_FILE_NOT_FOUND, ERROR_NO_MORE_ITEMS, HANDLE, WIN32_ERROR,
+        },
         System::Registry::*,
     },
 };
@@ -23,14 +22,29 @@ pub struct RegistryKey {
     path: String,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
 pub enum RegistryError {
+    #[error("Registry key or value not found!")]
     NotFound,
+    #[error("Access denied!")]
     AccessDenied,
+    #[error("Win32 error: {}", .0.0)]
     Win32(WIN32_ERROR),
+    #[error("Error: {0}")]
     Other(String),
 }
 
+impl RegistryError {
+    pub fn get_hint(&self) -> Option<&'static str> {
+        match self {
+            RegistryError::AccessDenied => {
+                Some("Run klc-install from a terminal opened with Run as administrator.")
+            }
+            _ => None,
+        }
+    }
+}
+
 impl From<WIN32_ERROR> for RegistryError {
     fn from(err: WIN32_ERROR) -> Self {
         match err {
@@ -42,17 +56,6 @@ impl From<WIN32_ERROR> for RegistryError {
     }
 }
 
-impl Display for RegistryError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        match self {
-            RegistryError::NotFound => write!(f, "Registry key or value not found!"),
-            RegistryError::AccessDenied => write!(f, "Access denied!"),
-            RegistryError::Win32(e) => write!(f, "Win32 error: {}", e.0),
-            RegistryError::Other(e) => write!(f, "Error: {}", e),
-        }
-    }
-}
-
 impl Drop for RegistryKey {
     fn drop(&mut self) {
         if self.is_root_key() {
@@ -93,7 +96,7 @@ impl RegistryKey {
             RegistryError::Other(format!("Couldn't convert string to UTF16! {}", e))
         })?;
         let mut hkey = Default::default();
-        let hkey_err = unsafe {
+        let mut hkey_err = unsafe {
             RegOpenKeyExW(
                 self.hkey,
                 PWSTR(name.as_mut_ptr()),
@@ -102,6 +105,13 @@ impl RegistryKey {
                 &mut hkey,
             )
         };
+        // Without administrator rights, keys like the ones in HKLM can still be read.
+        // Changing them fails with AccessDenied then.
+        if hkey_err == ERROR_ACCESS_DENIED {
+            hkey_err = unsafe {
+                RegOpenKeyExW(self.hkey, PWSTR(name.as_mut_ptr()), 0, KEY_READ, &mut hkey)
+            };
+        }
         if hkey_err.is_err() {
             return Err(RegistryError::from(hkey_err));
         }
@@ -153,6 +163,21 @@ impl RegistryKey {
         Ok(RegistryKey { hkey, path })
     }
 
+    /// Deletes the subkey along with all of its subkeys and values.
+    pub fn delete_subkey(&self, name: &str) -> Result<(), RegistryError> {
+        let mut name = U16CString::from_str(name).map_err(|e| {
+            RegistryError::Other(format!("Couldn't convert string to UTF16! {}", e))
+        })?;
+
+        // Given a subkey, RegDeleteTreeW deletes the key itself too, not only its contents
+        let delete_err = unsafe { RegDeleteTreeW(self.hkey, PWSTR(name.as_mut_ptr())) };
+        if delete_err.is_err() {
+            return Err(RegistryError::from(delete_err));
+        }
+
+        Ok(())
+    }
+
     pub fn get_value(&self, name: Option<&str>) -> Result<RegistryValue, RegistryError> {
         let mut name_str = if name == None {
             None
@@ -229,6 +254,18 @@ impl RegistryKey {
         Ok(Some(res.unwrap()))
     }
 
+    /// Gets a string value, failing if it has another type.
+    pub fn get_string(&self, name: Option<&str>) -> Result<String, RegistryError> {
+        self.get_value(name)?.into_string()
+    }
+
+    /// Gets a string value like [`RegistryKey::get_string`], or `None` if it doesn't exist.
+    pub fn try_get_string(&self, name: Option<&str>) -> Result<Option<String>, RegistryError> {
+        self.try_get_value(name)?
+            .map(RegistryValue::into_string)
+            .transpose()
+    }
+
     pub fn set_value(
         &self,
         name: Option<&str>,
@@ -268,6 +305,33 @@ impl RegistryKey {
         Ok(())
     }
 
+    pub fn delete_value(&self, name: Option<&str>) -> Result<(), RegistryError> {
+        let mut name_str = match name {
+            Some(name) => Some(U16CString::from_str(name).map_err(|e| {
+                RegistryError::Other(format!("Couldn't convert string to UTF16! {}", e))
+            })?),
+            None => None,
+        };
+
+        let value_err = unsafe {
+            RegDeleteValueW(
+                self.hkey,
+                PWSTR(
+                    name_str
+                        .as_mut()
+                        .map(|it| it.as_mut_ptr())
+                        .unwrap_or(null_mut()),
+                ),
+            )
+        };
+
+        if value_err.is_err() {
+            return Err(RegistryError::from(value_err));
+        }
+
+        Ok(())
+    }
+
     pub fn count_children(&self) -> Result<usize, RegistryError> {
         let mut children_count: u32 = 0;
         let info_err = unsafe {
@@ -366,6 +430,74 @@ impl RegistryKey {
         )
     }
 
+    pub fn iter_value_names(&self) -> Box<dyn Iterator<Item = Result<String, RegistryError>> + '_> {
+        // First we try getting the maximum length of the value names
+        let mut max_name_len: u32 = 0;
+        let info_err = unsafe {
+            RegQueryInfoKeyW(
+                self.hkey,
+                PWSTR::null(),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                Some(&mut max_name_len), // Maximum length of value names, not including null terminator
+                None,
+                None,
+                None,
+            )
+        };
+
+        if info_err.is_err() {
+            return Box::new([Err(RegistryError::from(info_err))].into_iter());
+        }
+
+        let mut name_buf = vec![0u16; max_name_len as usize + 1];
+        let mut index = 0;
+
+        Box::new(from_fn(move || {
+            let mut name_len = name_buf.len() as u32;
+            let enum_err = unsafe {
+                RegEnumValueW(
+                    self.hkey,
+                    index,
+                    PWSTR(name_buf.as_mut_ptr()),
+                    &mut name_len,
+                    None,
+                    None,
+                    None,
+                    None,
+                )
+            };
+
+            if enum_err.is_err() {
+                if enum_err == ERROR_NO_MORE_ITEMS {
+                    return None;
+                }
+
+                return Some(Err(RegistryError::from(enum_err)));
+            }
+
+            index += 1;
+
+            Some(
+                String::from_utf16(&name_buf[..name_len as usize])
+                    .map_err(|e| RegistryError::Other(e.to_string())),
+            )
+        }))
+    }
+
+    pub fn iter_values(
+        &self,
+    ) -> Box<dyn Iterator<Item = Result<RegistryValue<'_>, RegistryError>> + '_> {
+        Box::new(
+            self.iter_value_names()
+                .map(move |name_res| name_res.and_then(|name| self.get_value(Some(&name)))),
+        )
+    }
+
     pub fn close(self) {
         drop(self)
     }
@@ -431,6 +563,76 @@ impl RegistryKey {
         let root = Self::get_root_from_name(root_name)?;
         root.get_subkey(subkey_name)
     }
+
+    /// Opens the key as part of a KTM transaction.
+    ///
+    /// Changes made through the key and its subkeys are only visible once the transaction is
+    /// committed.
+    pub fn from_path_transacted(path: &str, transaction: HANDLE) -> Result<Self, RegistryError> {
+        let (root_name, subkey_name) = path.split_once("\\").ok_or_else(|| {
+            RegistryError::Other(format!(
+                "Can't open the root key {} in a transaction!",
+                path
+            ))
+        })?;
+        let root = Self::get_root_from_name(root_name)?;
+
+        let name = U16CString::from_str(subkey_name).map_err(|e| {
+            RegistryError::Other(format!("Couldn't convert string to UTF16! {}", e))
+        })?;
+        let mut hkey = HKEY::default();
+        let hkey_err = unsafe {
+            RegOpenKeyTransactedW(
+                root.hkey,
+                PCWSTR(name.as_ptr()),
+                0,
+                KEY_ALL_ACCESS,
+                &mut hkey,
+                transaction,
+                None,
+            )
+        };
+        if hkey_err.is_err() {
+            return Err(RegistryError::from(hkey_err));
+        }
+
+        let path = format!("{}\\{}", root.path, subkey_name);
+        Ok(RegistryKey { hkey, path })
+    }
+
+    /// Creates the subkey, or opens it if it exists, as part of a KTM transaction.
+    pub fn create_subkey_transacted(
+        &self,
+        name: &str,
+        transaction: HANDLE,
+    ) -> Result<RegistryKey, RegistryError> {
+        let name = U16CString::from_str(name).map_err(|e| {
+            RegistryError::Other(format!("Couldn't convert string to UTF16! {}", e))
+        })?;
+
+        let mut hkey = HKEY::default();
+        let hkey_err = unsafe {
+            RegCreateKeyTransactedW(
+                self.hkey,
+                PCWSTR(name.as_ptr()),
+                0,
+                PCWSTR::null(),
+                REG_OPTION_NON_VOLATILE,
+                KEY_ALL_ACCESS,
+                None,
+                &mut hkey,
+                None,
+                transaction,
+                None,
+            )
+        };
+        if hkey_err.is_err() {
+            return Err(RegistryError::from(hkey_err));
+        }
+
+        let path = format!("{}\\{}", self.path, name.to_string().unwrap());
+        Ok(RegistryKey { hkey, path })
+    }
 }
 
 #[cfg(test)]
@@ -1,6 +1,8 @@
 #![allow(dead_code)]
 
-use crate::registry_key::RegistryKey;
+use std::fmt::{self, Display, Formatter};
+
+use crate::registry_key::{RegistryError, RegistryKey};
 use crate::utils::AsU16Slice;
 use widestring::U16CString;
 use windows::Win32::System::Registry::*;
@@ -39,15 +41,22 @@ impl RegistryValue<'_> {
         &self.value
     }
 
-    pub fn unwrap_str(self) -> String {
+    /// The string of a `REG_SZ` or `REG_EXPAND_SZ` value, failing for values of other types.
+    pub fn into_string(self) -> Result<String, RegistryError> {
         match self.value {
-            RegistryValueData::String(string) => string,
-            _ => panic!("Value is not a string!"),
+            RegistryValueData::String(string) | RegistryValueData::ExpandString(string) => {
+                Ok(string)
+            }
+            _ => Err(RegistryError::Other(format!(
+                "The value {} of {} is not a string.",
+                self.name.as_deref().unwrap_or("(Default)"),
+                self.key.get_path()
+            ))),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum RegistryValueData {
     None,
     Binary(Vec<u8>),
@@ -171,3 +180,22 @@ impl RegistryValueData {
         }
     }
 }
+
+/// Formats the data for people, like regedit shows it.
+impl Display for RegistryValueData {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryValueData::None => write!(f, "(none)"),
+            RegistryValueData::Binary(data) => {
+                let bytes: Vec<String> = data.iter().map(|b| format!("{:02x}", b)).collect();
+                write!(f, "{}", bytes.join(" "))
+            }
+            RegistryValueData::Dword(dword) => write!(f, "0x{:08x} ({})", dword, dword),
+            RegistryValueData::Qword(qword) => write!(f, "0x{:016x} ({})", qword, qword),
+            RegistryValueData::String(string) | RegistryValueData::ExpandString(string) => {
+                write!(f, "{}", string)
+            }
+            RegistryValueData::MultiString(strings) => write!(f, "{}", strings.join(", ")),
+        }
+    }
+}
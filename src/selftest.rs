@@ -0,0 +1,343 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    arch::Arch,
+    compile::{
+        compile_klc_file, create_scratch_dir, find_kbdutool_in_path, get_kbdutool, remove_work_dir,
+        verify_dll_file,
+    },
+    install::plan_layout_values,
+    install_plan::{InstallPlan, PlannedOperation},
+    journal::Journal,
+    klc::{KlcDocument, KlcInfo},
+    layouts::get_layouts_key,
+    registry_key::{RegistryError, RegistryKey},
+    tool_runner::ProcessRunner,
+};
+
+/// Key of the current user the self-test registers its layout under,
+/// instead of the real layouts key.
+const SANDBOX_KEY_PATH: &str = "Software\\klc-install\\Selftest";
+
+/// A tiny layout bundled with the tool, with just a few keys.
+const SELFTEST_KLC: &str = include_str!("selftest.klc");
+
+const SELFTEST_KLID: &str = "f0000409";
+
+/// A check of the self-test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelftestStep {
+    FindMsklc,
+    Registry,
+    Parse,
+    Compile,
+    VerifyDll,
+    Install,
+    List,
+    Uninstall,
+}
+
+impl SelftestStep {
+    pub const ALL: [SelftestStep; 8] = [
+        SelftestStep::FindMsklc,
+        SelftestStep::Registry,
+        SelftestStep::Parse,
+        SelftestStep::Compile,
+        SelftestStep::VerifyDll,
+        SelftestStep::Install,
+        SelftestStep::List,
+        SelftestStep::Uninstall,
+    ];
+
+    /// The name of the step in JSON output.
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            SelftestStep::FindMsklc => "find_msklc",
+            SelftestStep::Registry => "registry",
+            SelftestStep::Parse => "parse",
+            SelftestStep::Compile => "compile",
+            SelftestStep::VerifyDll => "verify_dll",
+            SelftestStep::Install => "install",
+            SelftestStep::List => "list",
+            SelftestStep::Uninstall => "uninstall",
+        }
+    }
+
+    pub fn get_description(&self) -> &'static str {
+        match self {
+            SelftestStep::FindMsklc => "Finding MSKLC",
+            SelftestStep::Registry => "Accessing the registry",
+            SelftestStep::Parse => "Parsing the test layout",
+            SelftestStep::Compile => "Compiling the test layout",
+            SelftestStep::VerifyDll => "Verifying the compiled DLL",
+            SelftestStep::Install => "Installing into the sandbox",
+            SelftestStep::List => "Listing the sandbox layouts",
+            SelftestStep::Uninstall => "Uninstalling from the sandbox",
+        }
+    }
+}
+
+impl Display for SelftestStep {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.get_description())
+    }
+}
+
+/// Results of the steps that ran, in order. Steps after a failed one don't run.
+#[derive(Debug, Default)]
+pub struct SelftestReport {
+    pub results: Vec<(SelftestStep, Result<String, String>)>,
+}
+
+impl SelftestReport {
+    /// Runs the step and records its result, passing on the value it produced.
+    fn run<T>(
+        &mut self,
+        step: SelftestStep,
+        f: impl FnOnce() -> Result<(T, String), String>,
+    ) -> Option<T> {
+        match f() {
+            Ok((value, detail)) => {
+                self.results.push((step, Ok(detail)));
+                Some(value)
+            }
+            Err(e) => {
+                self.results.push((step, Err(e)));
+                None
+            }
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.results.len() == SelftestStep::ALL.len()
+            && self.results.iter().all(|(_, result)| result.is_ok())
+    }
+}
+
+/// The registry key and directory standing in for the layouts key and System32.
+struct Sandbox {
+    layouts_key_path: String,
+    system_dir: PathBuf,
+}
+
+impl Sandbox {
+    fn create() -> Result<Sandbox, String> {
+        let layouts_key = RegistryKey::current_user()
+            .create_subkey(&format!("{}\\Keyboard Layouts", SANDBOX_KEY_PATH))
+            .map_err(|e| format!("Couldn't create the sandbox registry key. {}", e))?;
+        let layouts_key_path = layouts_key.get_path().to_string();
+
+        let system_dir = std::env::temp_dir().join("klc-install-selftest");
+        fs::create_dir_all(&system_dir).map_err(|e| {
+            format!(
+                "Couldn't create the directory {}. {}",
+                system_dir.display(),
+                e
+            )
+        })?;
+
+        Ok(Sandbox {
+            layouts_key_path,
+            system_dir,
+        })
+    }
+
+    /// Removes the sandbox key and directory with everything left in them.
+    fn remove(&self) {
+        let (parent_path, name) = SANDBOX_KEY_PATH.rsplit_once('\\').unwrap();
+        if let Ok(parent) = RegistryKey::current_user().get_subkey(parent_path) {
+            _ = parent.delete_subkey(name);
+        }
+        _ = fs::remove_dir_all(&self.system_dir);
+    }
+}
+
+/// Compiles the bundled layout and installs, lists and uninstalls it in the sandbox,
+/// stopping at the first step that fails.
+pub fn run_selftest(msklc: Option<&str>) -> SelftestReport {
+    let mut report = SelftestReport::default();
+
+    let Some(kbdutool_path) = report.run(SelftestStep::FindMsklc, || {
+        let kbdutool_path = match msklc {
+            Some(msklc) => get_kbdutool(Path::new(msklc))?,
+            None => find_kbdutool_in_path()?,
+        };
+        let detail = format!("KBDUTOOL is at {}", kbdutool_path.display());
+        Ok((kbdutool_path, detail))
+    }) else {
+        return report;
+    };
+
+    let Some(sandbox) = report.run(SelftestStep::Registry, || {
+        let layouts_key = get_layouts_key()
+            .map_err(|e| format!("Couldn't open the Keyboard Layouts key. {}", e))?;
+        let count = layouts_key.count_children().map_err(|e| e.to_string())?;
+        let sandbox = Sandbox::create()?;
+        let detail = format!("{} layouts are installed, the sandbox is writable", count);
+        Ok((sandbox, detail))
+    }) else {
+        return report;
+    };
+
+    run_sandboxed_steps(&mut report, &kbdutool_path, &sandbox);
+    sandbox.remove();
+    // The compiler's scratch directory
+    remove_work_dir();
+
+    report
+}
+
+fn run_sandboxed_steps(report: &mut SelftestReport, kbdutool_path: &Path, sandbox: &Sandbox) {
+    let native_arch = Arch::get_native();
+
+    let Some((klc_path, klc_info)) = report.run(SelftestStep::Parse, || {
+        let klc_path = sandbox.system_dir.join("selftest.klc");
+        KlcDocument::parse(SELFTEST_KLC.lines()).write_to_file(&klc_path)?;
+        let klc_info = KlcInfo::read_from_file(&klc_path)?;
+        let detail = format!(
+            "found layout {} with locale ID {:04X}",
+            klc_info.layout_name, klc_info.locale_id
+        );
+        Ok(((klc_path, klc_info), detail))
+    }) else {
+        return;
+    };
+
+    let Some(dll_path) = report.run(SelftestStep::Compile, || {
+        let flag = native_arch
+            .get_kbdutool_flag()
+            .ok_or_else(|| format!("KBDUTOOL can't compile layouts for {}.", native_arch))?;
+        let scratch_dir = create_scratch_dir(native_arch)?;
+        let dll_path = compile_klc_file(
+            &ProcessRunner,
+            kbdutool_path,
+            &klc_path,
+            &klc_info.layout_name,
+            flag,
+            &scratch_dir,
+        )?;
+        let detail = format!("compiled for {}", native_arch);
+        Ok((dll_path, detail))
+    }) else {
+        return;
+    };
+
+    let dll_name = format!("{}.dll", klc_info.layout_name);
+
+    if report
+        .run(SelftestStep::VerifyDll, || {
+            verify_dll_file(&dll_path)?;
+            Ok(((), dll_path.display().to_string()))
+        })
+        .is_none()
+    {
+        return;
+    }
+
+    let layout_key_path = format!("{}\\{}", sandbox.layouts_key_path, SELFTEST_KLID);
+
+    if report
+        .run(SelftestStep::Install, || {
+            let mut plan = InstallPlan::new();
+            plan.push(PlannedOperation::CopyFile {
+                source: dll_path.clone(),
+                target: sandbox.system_dir.join(&dll_name),
+            });
+            plan.push(PlannedOperation::CreateKey(layout_key_path.clone()));
+            plan_layout_values(
+                &mut plan,
+                &layout_key_path,
+                "0F00",
+                &dll_name,
+                &klc_info.layout_text,
+                None,
+            );
+
+            let mut journal = Journal::begin("selftest")?;
+            if let Err(e) = plan.execute(&mut journal) {
+                return Err(match journal.roll_back() {
+                    Ok(()) => e,
+                    Err(rollback_error) => format!("{} {}", e, rollback_error),
+                });
+            }
+            journal.commit()?;
+
+            Ok(((), format!("registered {}", layout_key_path)))
+        })
+        .is_none()
+    {
+        return;
+    }
+
+    if report
+        .run(SelftestStep::List, || {
+            let layouts_key =
+                RegistryKey::from_path(&sandbox.layouts_key_path).map_err(|e| e.to_string())?;
+            let layout_key = layouts_key
+                .get_subkey(SELFTEST_KLID)
+                .map_err(|e| format!("The layout {} isn't listed. {}", SELFTEST_KLID, e))?;
+            let layout_file = layout_key
+                .get_string(Some("Layout File"))
+                .map_err(|e| e.to_string())?;
+
+            if !layout_file.eq_ignore_ascii_case(&dll_name) {
+                return Err(format!(
+                    "The listed layout file {} doesn't match {}.",
+                    layout_file, dll_name
+                ));
+            }
+            if !sandbox.system_dir.join(&layout_file).exists() {
+                return Err(format!("The DLL file {} is missing.", layout_file));
+            }
+
+            Ok(((), format!("{} uses {}", SELFTEST_KLID, layout_file)))
+        })
+        .is_none()
+    {
+        return;
+    }
+
+    report.run(SelftestStep::Uninstall, || {
+        let layouts_key =
+            RegistryKey::from_path(&sandbox.layouts_key_path).map_err(|e| e.to_string())?;
+        layouts_key
+            .delete_subkey(SELFTEST_KLID)
+            .map_err(|e| e.to_string())?;
+        fs::remove_file(sandbox.system_dir.join(&dll_name)).map_err(|e| e.to_string())?;
+
+        match layouts_key.get_subkey(SELFTEST_KLID) {
+            Err(RegistryError::NotFound) => {}
+            Ok(_) => return Err(format!("The layout {} is still registered.", SELFTEST_KLID)),
+            Err(e) => return Err(e.to_string()),
+        }
+
+        Ok(((), format!("removed {}", SELFTEST_KLID)))
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_selftest_klc() {
+        let klc_path = std::env::temp_dir().join(format!("selftest-{}.klc", std::process::id()));
+        KlcDocument::parse(SELFTEST_KLC.lines())
+            .write_to_file(&klc_path)
+            .unwrap();
+        let klc_info = KlcInfo::read_from_file(&klc_path);
+        _ = fs::remove_file(&klc_path);
+
+        let klc_info = klc_info.unwrap();
+        assert_eq!(klc_info.layout_name, "kbdkitst");
+        assert_eq!(klc_info.locale_id, 0x0409);
+        assert_eq!(
+            klc_info.names.get_description(0x0409),
+            Some("klc-install self-test")
+        );
+    }
+}
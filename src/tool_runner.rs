@@ -0,0 +1,111 @@
+use std::{
+    ffi::OsString,
+    fmt::{self, Display, Formatter},
+    io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// A run of an external tool like KBDUTOOL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolCommand {
+    pub program: PathBuf,
+    pub args: Vec<OsString>,
+    pub working_dir: PathBuf,
+}
+
+impl ToolCommand {
+    pub fn new(program: &Path, working_dir: &Path) -> Self {
+        ToolCommand {
+            program: program.to_path_buf(),
+            args: Vec::new(),
+            working_dir: working_dir.to_path_buf(),
+        }
+    }
+
+    pub fn arg(mut self, arg: impl Into<OsString>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+}
+
+/// E.g. `kbdutool -wum C:\Layouts\kbdmine.klc`.
+impl Display for ToolCommand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let program = self.program.file_stem().unwrap_or(self.program.as_os_str());
+        write!(f, "{}", program.to_string_lossy())?;
+        for arg in &self.args {
+            write!(f, " {}", arg.to_string_lossy())?;
+        }
+        Ok(())
+    }
+}
+
+/// What the tool printed and whether it succeeded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ToolOutput {
+    pub success: bool,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Runs the external tools, so tests can fake them and dry runs can skip them.
+pub trait ToolRunner {
+    fn run(&self, command: &ToolCommand) -> io::Result<ToolOutput>;
+
+    /// Whether the tools actually run, and so produce their files.
+    fn is_dry_run(&self) -> bool {
+        false
+    }
+}
+
+/// Runs the tools as processes.
+pub struct ProcessRunner;
+
+impl ToolRunner for ProcessRunner {
+    fn run(&self, command: &ToolCommand) -> io::Result<ToolOutput> {
+        let output = Command::new(&command.program)
+            .args(&command.args)
+            .current_dir(&command.working_dir)
+            .output()?;
+
+        Ok(ToolOutput {
+            success: output.status.success(),
+            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        })
+    }
+}
+
+/// Only prints what would run.
+pub struct DryRunner;
+
+impl ToolRunner for DryRunner {
+    fn run(&self, command: &ToolCommand) -> io::Result<ToolOutput> {
+        println!("Would run {}", command);
+        Ok(ToolOutput {
+            success: true,
+            ..Default::default()
+        })
+    }
+
+    fn is_dry_run(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_display_tool_command() {
+        let command = ToolCommand::new(
+            Path::new("C:/MSKLC/bin/i386/kbdutool.exe"),
+            Path::new("C:/Temp"),
+        )
+        .arg("-wum")
+        .arg("kbdmine.klc");
+        assert_eq!(command.to_string(), "kbdutool -wum kbdmine.klc");
+    }
+}
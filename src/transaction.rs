@@ -0,0 +1,83 @@
+use std::path::Path;
+
+use widestring::U16CString;
+use windows::{
+    core::PCWSTR,
+    Win32::{
+        Foundation::{CloseHandle, HANDLE},
+        Storage::FileSystem::{CommitTransaction, CopyFileTransactedW, CreateTransaction},
+    },
+};
+
+/// A Kernel Transaction Manager transaction.
+///
+/// Registry and file changes made in it are applied all at once when it's committed.
+/// Dropping it without committing rolls them back, and so does a crash or power loss.
+#[derive(Debug)]
+pub struct Transaction {
+    handle: HANDLE,
+}
+
+impl Transaction {
+    pub fn begin(description: &str) -> Result<Transaction, String> {
+        let description = U16CString::from_str(description).map_err(|e| e.to_string())?;
+
+        let handle = unsafe {
+            CreateTransaction(
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                0,
+                0,
+                0,
+                0,
+                PCWSTR(description.as_ptr()),
+            )
+        }
+        .map_err(|e| format!("Couldn't start a transaction. {}", e))?;
+
+        Ok(Transaction { handle })
+    }
+
+    pub fn get_handle(&self) -> HANDLE {
+        self.handle
+    }
+
+    /// Copies the file as part of the transaction.
+    ///
+    /// Uses Transactional NTFS, which Microsoft deprecated, but which still works on NTFS volumes.
+    pub fn copy_file(&self, from: &Path, to: &Path) -> Result<(), String> {
+        let from_str = U16CString::from_os_str(from).map_err(|e| e.to_string())?;
+        let to_str = U16CString::from_os_str(to).map_err(|e| e.to_string())?;
+
+        unsafe {
+            CopyFileTransactedW(
+                PCWSTR(from_str.as_ptr()),
+                PCWSTR(to_str.as_ptr()),
+                None,
+                None,
+                None,
+                0,
+                self.handle,
+            )
+        }
+        .map_err(|e| {
+            format!(
+                "Couldn't copy {} to {}. {}",
+                from.display(),
+                to.display(),
+                e
+            )
+        })
+    }
+
+    pub fn commit(self) -> Result<(), String> {
+        unsafe { CommitTransaction(self.handle) }
+            .map_err(|e| format!("Couldn't commit the transaction. {}", e))
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        _ = unsafe { CloseHandle(self.handle) };
+    }
+}
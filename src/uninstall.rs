@@ -0,0 +1,589 @@
+use std::{
+    fs,
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+};
+
+use clap::Args;
+use dialoguer::Confirm;
+use serde::Deserialize;
+use windows::Win32::UI::Shell::{FOLDERID_ProgramData, FOLDERID_System};
+
+use crate::{
+    color::{paint, print_warning, Style},
+    event_log::{audit_layouts, AuditAction},
+    exit_code::{CommandError, ExitCode},
+    get_known_folder::get_known_folder,
+    history::{format_unix_time, record_file},
+    hooks::{run_hooks, HookContext, HookPoint},
+    input_refresh::{activate_layout, unload_layout},
+    install::confirm_plan,
+    journal::get_unix_time,
+    klid::Klid,
+    layout_icon::ICON_VALUE_NAME,
+    layout_provenance::get_file_sha256,
+    layouts::{
+        find_layouts_using_dll, get_installed_dll_paths, get_layouts_key, get_saved_dll_path,
+    },
+    loaded_layouts::{get_loaded_layouts, is_layout_loaded},
+    preload::{read_preloaded_klids, unpreload_layout},
+    reg_file::export_reg_file,
+    registry_key::RegistryKey,
+    user_hives::UserProfiles,
+    utils::{delete_file_on_reboot, is_file_in_use_error},
+};
+
+#[derive(Args, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct UninstallOptions {
+    /// Force uninstallation of the layout, even if it's in use.
+    /// WARNING: This can uninstall system layouts.
+    #[clap(short('F'), long)]
+    pub force: bool,
+
+    /// Remove the DLL file associated with the layout.
+    #[clap(short('d'), long)]
+    pub remove_dll: bool,
+
+    /// Doesn't show the planned changes and ask for confirmation before uninstalling.
+    ///
+    /// Only asked when run in a terminal.
+    #[clap(short, long)]
+    pub yes: bool,
+
+    /// Saves the layout's registry key as a .reg file and copies of its DLL to the directory
+    /// before removing them, the SysWOW64 build in a SysWOW64 subdirectory.
+    #[clap(long, value_name = "DIR")]
+    pub backup: Option<PathBuf>,
+
+    /// Print the registry key, preload entries and DLL files the uninstall would remove,
+    /// without changing anything.
+    #[clap(long)]
+    pub dry_run: bool,
+}
+
+/// How [`find_sessions_using_layout`] names the session klc-install runs in.
+const THIS_SESSION: &str = "this session";
+
+/// The layout this session switches to when the one being uninstalled is the only one loaded,
+/// US English.
+const FALLBACK_KLID: &str = "00000409";
+
+/// Uninstalls every custom layout with its DLL, after showing them and asking to confirm.
+///
+/// They're backed up to the `--backup` directory, or a new one in
+/// `%ProgramData%\klc-install\backups`.
+pub fn purge_custom_layouts(options: UninstallOptions) -> Result<(), CommandError> {
+    let mut custom = Vec::new();
+
+    for layout_key in get_layouts_key()
+        .map_err(|e| e.to_string())?
+        .iter_children()
+    {
+        let layout_key = layout_key.map_err(|e| e.to_string())?;
+        let Ok(klid) = Klid::parse(layout_key.get_name()) else {
+            continue;
+        };
+
+        if !klid.is_custom() {
+            continue;
+        }
+
+        let layout_text = layout_key
+            .try_get_string(Some("Layout Text"))
+            .map_err(|e| e.to_string())?;
+        custom.push((layout_key.get_name().to_string(), layout_text));
+    }
+
+    if custom.is_empty() {
+        println!("There are no custom layouts installed.");
+        return Ok(());
+    }
+
+    // Always backed up, as nothing else is left to install them again from
+    let backup_dir = match &options.backup {
+        Some(backup_dir) => backup_dir.clone(),
+        None => get_purge_backup_dir()?,
+    };
+    let dry_run = options.dry_run;
+    let result = uninstall_layouts(
+        &custom,
+        UninstallOptions {
+            remove_dll: true,
+            backup: Some(backup_dir.clone()),
+            ..options
+        },
+    );
+
+    if !dry_run && backup_dir.exists() {
+        println!(
+            "The layouts were backed up to {}. To restore them, run as an administrator:",
+            backup_dir.display()
+        );
+        println!(
+            "  for %f in (\"{}\\*.reg\") do reg import \"%f\"",
+            backup_dir.display()
+        );
+        println!(
+            "  copy \"{}\\*.dll\" %SystemRoot%\\System32",
+            backup_dir.display()
+        );
+        if backup_dir.join("SysWOW64").exists() {
+            println!(
+                "  copy \"{}\\SysWOW64\\*.dll\" %SystemRoot%\\SysWOW64",
+                backup_dir.display()
+            );
+        }
+    }
+
+    result
+}
+
+/// A new directory for the backup of a purge, e.g.
+/// `%ProgramData%\klc-install\backups\2024-11-05_140327`.
+fn get_purge_backup_dir() -> Result<PathBuf, String> {
+    let program_data = get_known_folder(&FOLDERID_ProgramData)?;
+    // Colons can't be in file names
+    let timestamp = format_unix_time(get_unix_time())
+        .trim_end_matches(" UTC")
+        .replace(' ', "_")
+        .replace(':', "");
+
+    Ok(program_data
+        .join("klc-install")
+        .join("backups")
+        .join(timestamp))
+}
+
+/// Uninstalls the layouts, given by registry key and text, after showing them and asking once
+/// to confirm for all of them.
+pub fn uninstall_layouts(
+    layouts: &[(String, Option<String>)],
+    options: UninstallOptions,
+) -> Result<(), CommandError> {
+    if options.remove_dll {
+        println!("These layouts will be uninstalled along with their DLLs:");
+    } else {
+        println!("These layouts will be uninstalled:");
+    }
+    for (layout_key_name, layout_text) in layouts {
+        println!(
+            "  {} {}",
+            layout_key_name,
+            layout_text.as_deref().unwrap_or("UNKNOWN")
+        );
+    }
+
+    if !options.yes && !options.dry_run {
+        if !io::stdin().is_terminal() {
+            return Err("Use --yes to uninstall them without asking.".into());
+        }
+
+        let confirmed = Confirm::new()
+            .with_prompt(format!("Uninstall {} layouts?", layouts.len()))
+            .default(false)
+            .interact()
+            .map_err(|e| e.to_string())?;
+        if !confirmed {
+            println!("Nothing was uninstalled.");
+            return Ok(());
+        }
+    }
+
+    // Already confirmed for all of them
+    let options = UninstallOptions {
+        yes: true,
+        ..options
+    };
+    let mut failed = 0;
+    for (layout_key_name, _) in layouts {
+        if let Err(e) = remove_layout(layout_key_name, &options) {
+            print_warning(format!("Couldn't uninstall {}. {}", layout_key_name, e));
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        let exit_code = if failed < layouts.len() {
+            ExitCode::PartialSuccess
+        } else {
+            ExitCode::Error
+        };
+        return Err(CommandError::new(
+            exit_code,
+            format!(
+                "{} of {} layouts couldn't be uninstalled.",
+                failed,
+                layouts.len()
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Deletes the layout's registry key and removes it from the input methods of the users.
+///
+/// The DLL is only removed when asked and no other layout uses it. A layout that's loaded
+/// in this session or used by another signed in user is only removed when forced. When asking to
+/// confirm, switching this session to another layout first is offered instead.
+pub fn remove_layout(layout_key_name: &str, options: &UninstallOptions) -> Result<(), String> {
+    let layouts_key = get_layouts_key().map_err(|e| e.to_string())?;
+    let layout_key = layouts_key
+        .get_subkey(layout_key_name)
+        .map_err(|e| format!("Couldn't open the layout {}. {}", layout_key_name, e))?;
+
+    let mut sessions = find_sessions_using_layout(layout_key_name)?;
+    let confirm = !options.yes && !options.dry_run && io::stdin().is_terminal();
+    if sessions
+        .first()
+        .is_some_and(|session| session == THIS_SESSION)
+        && !options.force
+        && confirm
+    {
+        let layout_id = layout_key
+            .try_get_string(Some("Layout Id"))
+            .map_err(|e| e.to_string())?
+            .and_then(|id| u16::from_str_radix(&id, 16).ok());
+        if offer_fallback_layout(layout_key_name, layout_id)? {
+            sessions.remove(0);
+        }
+    }
+    if !sessions.is_empty() {
+        if !options.force {
+            return Err(format!(
+                "{} is in use by {}. Applications using it would be left with a broken layout until they sign out. Use --force to uninstall it anyway.",
+                layout_key_name,
+                sessions.join(", ")
+            ));
+        }
+        print_warning(format!(
+            "{} is in use by {}. Sign out after uninstalling it to stop using it.",
+            layout_key_name,
+            sessions.join(", ")
+        ));
+    }
+
+    let layout_file = layout_key
+        .try_get_string(Some("Layout File"))
+        .map_err(|e| e.to_string())?;
+    let layout_text = layout_key
+        .try_get_string(Some("Layout Text"))
+        .map_err(|e| e.to_string())?;
+    let icon_file = layout_key
+        .try_get_string(Some(ICON_VALUE_NAME))
+        .map_err(|e| e.to_string())?;
+
+    // Layouts installed for several locales share the DLL and the icon named after it
+    let other_layouts: Vec<String> = match &layout_file {
+        Some(layout_file) => find_layouts_using_dll(layout_file)?
+            .into_iter()
+            .map(|(key, _)| key)
+            .filter(|key| !key.eq_ignore_ascii_case(layout_key_name))
+            .collect(),
+        None => Vec::new(),
+    };
+    let dll_to_remove = match &layout_file {
+        Some(layout_file) if options.remove_dll => {
+            if other_layouts.is_empty() {
+                Some(layout_file.as_str())
+            } else {
+                println!(
+                    "Keeping {} as the layouts {} use it too.",
+                    layout_file,
+                    other_layouts.join(", ")
+                );
+                None
+            }
+        }
+        None if options.remove_dll => {
+            println!("The layout doesn't have a DLL file.");
+            None
+        }
+        _ => None,
+    };
+    let icon_to_remove = match icon_file {
+        Some(icon_file) if other_layouts.is_empty() => {
+            Some(get_known_folder(&FOLDERID_System)?.join(icon_file))
+        }
+        _ => None,
+    }
+    .filter(|icon_path| icon_path.exists());
+
+    if options.dry_run || confirm {
+        if options.dry_run {
+            println!(
+                "Dry run, nothing was changed. Uninstalling {} would:",
+                layout_key_name
+            );
+        } else {
+            println!("Uninstalling {} will:", layout_key_name);
+        }
+        if let Some(backup_dir) = &options.backup {
+            println!(
+                "  back up the registry key and the DLLs to {}",
+                backup_dir.display()
+            );
+        }
+        println!("  delete registry key {}", layout_key.get_path());
+        unpreload_layout_for_all_users(layout_key_name, true);
+        if let Some(dll_name) = dll_to_remove {
+            for dll_path in get_installed_dll_paths(dll_name)? {
+                println!("  remove {}", dll_path.display());
+            }
+        }
+        if let Some(icon_path) = &icon_to_remove {
+            println!("  remove {}", icon_path.display());
+        }
+        if options.dry_run {
+            return Ok(());
+        }
+        if !confirm_plan(&format!("Uninstall {}?", layout_key_name))? {
+            println!("Nothing was uninstalled.");
+            return Ok(());
+        }
+    }
+
+    let hook_context = HookContext {
+        layout_keys: &[layout_key_name],
+        layout_file: layout_file.as_deref(),
+        layout_text: layout_text.as_deref(),
+        source: None,
+    };
+    run_hooks(HookPoint::PreUninstall, &hook_context)?;
+
+    if let Some(backup_dir) = &options.backup {
+        backup_layout(&layout_key, layout_file.as_deref(), backup_dir)?;
+    }
+    layout_key.close();
+
+    // Hashed before the DLL is removed
+    let sha256 = match &layout_file {
+        Some(layout_file) => get_installed_dll_paths(layout_file)?
+            .first()
+            .and_then(|dll_path| get_file_sha256(dll_path).ok()),
+        None => None,
+    };
+
+    layouts_key
+        .delete_subkey(layout_key_name)
+        .map_err(|e| format!("Couldn't delete the layout {}. {}", layout_key_name, e))?;
+    println!(
+        "{}",
+        paint(
+            format!("Uninstalled the layout {}.", layout_key_name),
+            Style::Green
+        )
+    );
+    audit_layouts(
+        AuditAction::Uninstall,
+        &[layout_key_name],
+        sha256.as_deref(),
+    );
+
+    unpreload_layout_for_all_users(layout_key_name, false);
+
+    if let Some(dll_name) = dll_to_remove {
+        remove_layout_dll(dll_name)?;
+    }
+    if let Some(icon_path) = &icon_to_remove {
+        remove_system_file(icon_path)?;
+    }
+
+    run_hooks(HookPoint::PostUninstall, &hook_context)
+}
+
+/// Finds who is using the layout: this session if it's loaded in it, and the other signed in
+/// users that have it in their input methods.
+///
+/// Windows only lists the layouts loaded in the calling session, so other sessions are judged
+/// by their hives. Hives that can't be read are only reported.
+fn find_sessions_using_layout(layout_key_name: &str) -> Result<Vec<String>, String> {
+    let mut sessions = Vec::new();
+    if is_layout_loaded(layout_key_name)? {
+        sessions.push(THIS_SESSION.to_string());
+    }
+
+    let profiles = UserProfiles::read()?;
+    for user in profiles.get_other_signed_in_users() {
+        let klids = user
+            .open_hive()
+            .and_then(|hive| read_preloaded_klids(hive.get_key()).map_err(|e| e.to_string()));
+        match klids {
+            Ok(klids) => {
+                if klids
+                    .iter()
+                    .any(|klid| klid.eq_ignore_ascii_case(layout_key_name))
+                {
+                    sessions.push(user.name.clone());
+                }
+            }
+            Err(e) => print_warning(format!(
+                "Couldn't check whether {} uses {}. {}",
+                user.name, layout_key_name, e
+            )),
+        }
+    }
+
+    Ok(sessions)
+}
+
+/// Offers to switch this session to another layout and unload the one being uninstalled, so
+/// the keyboard keeps working without signing out.
+///
+/// The fallback is another layout loaded in the session, or US English if there's none.
+/// Returns whether the session was switched.
+fn offer_fallback_layout(layout_key_name: &str, layout_id: Option<u16>) -> Result<bool, String> {
+    let fallback = get_loaded_layouts()?
+        .into_iter()
+        .filter_map(|loaded| loaded.klid)
+        .find(|klid| !klid.eq_ignore_ascii_case(layout_key_name))
+        .unwrap_or_else(|| FALLBACK_KLID.to_string());
+
+    let confirmed = Confirm::new()
+        .with_prompt(format!(
+            "{} is in use in this session. Switch to {} and unload it first?",
+            layout_key_name, fallback
+        ))
+        .default(true)
+        .interact()
+        .map_err(|e| e.to_string())?;
+    if !confirmed {
+        return Ok(false);
+    }
+
+    activate_layout(&fallback)?;
+    unload_layout(layout_key_name, layout_id)?;
+    println!("Switched this session to {}.", fallback);
+
+    Ok(true)
+}
+
+/// Saves the layout's registry key as `<KLID>.reg` and copies of its DLL to the directory,
+/// so the layout can be restored by importing the file and copying the DLLs back.
+///
+/// The SysWOW64 build goes in a SysWOW64 subdirectory, as it has the same name.
+fn backup_layout(
+    layout_key: &RegistryKey,
+    layout_file: Option<&str>,
+    backup_dir: &Path,
+) -> Result<(), String> {
+    fs::create_dir_all(backup_dir)
+        .map_err(|e| format!("Couldn't create {}. {}", backup_dir.display(), e))?;
+
+    let reg_path = backup_dir.join(format!("{}.reg", layout_key.get_name()));
+    export_reg_file(layout_key, &reg_path)?;
+    println!("Backed up the registry key to {}.", reg_path.display());
+
+    let Some(layout_file) = layout_file else {
+        return Ok(());
+    };
+    let dll_paths = get_installed_dll_paths(layout_file)?;
+    if dll_paths.is_empty() {
+        print_warning(format!(
+            "{} is missing from System32, so it wasn't backed up.",
+            layout_file
+        ));
+        return Ok(());
+    }
+
+    for dll_path in dll_paths {
+        let backup_path = get_saved_dll_path(backup_dir, dll_path.parent().unwrap(), layout_file)?;
+        if let Some(dir) = backup_path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("Couldn't create {}. {}", dir.display(), e))?;
+        }
+        fs::copy(&dll_path, &backup_path).map_err(|e| {
+            format!(
+                "Couldn't copy {} to {}. {}",
+                dll_path.display(),
+                backup_path.display(),
+                e
+            )
+        })?;
+        println!("Backed up the DLL to {}.", backup_path.display());
+    }
+
+    Ok(())
+}
+
+pub fn remove_layout_dll(dll_name: &str) -> Result<(), String> {
+    for dll_path in get_installed_dll_paths(dll_name)? {
+        remove_system_file(&dll_path)?;
+    }
+
+    Ok(())
+}
+
+/// Removes a file of the layout, or schedules removing it when Windows restarts if it's in use.
+fn remove_system_file(path: &Path) -> Result<(), String> {
+    match fs::remove_file(path) {
+        Ok(()) => {
+            record_file(path);
+            println!("Removed {}.", path.display());
+        }
+        // Sessions that used the layout keep its files open until they end
+        Err(e) if is_file_in_use_error(&e) => {
+            delete_file_on_reboot(path)
+                .map_err(|e| format!("Couldn't schedule removing {}. {}", path.display(), e))?;
+            record_file(path);
+            println!(
+                "{} is in use, it will be removed when Windows restarts.",
+                path.display()
+            );
+        }
+        Err(e) => return Err(format!("Couldn't remove {}. {}", path.display(), e)),
+    }
+
+    Ok(())
+}
+
+/// Removes the uninstalled layout from the input methods of every user, the Default profile and
+/// the sign-in screen, so they don't show up as unknown layouts.
+///
+/// The layout is already gone, so failures are only reported. With `dry_run`, the entries that
+/// would be removed are only printed.
+fn unpreload_layout_for_all_users(layout_key_name: &str, dry_run: bool) {
+    let unpreload = |name: &str, user_key: &RegistryKey| {
+        let references = match unpreload_layout(user_key, layout_key_name, dry_run) {
+            Ok(references) => references,
+            Err(e) => {
+                print_warning(format!(
+                    "Couldn't remove {} from the input methods of {}. {}",
+                    layout_key_name, name, e
+                ));
+                return;
+            }
+        };
+
+        if dry_run {
+            for reference in references {
+                println!("  remove {} from the hive of {}", reference, name);
+            }
+        } else if !references.is_empty() {
+            println!(
+                "Removed {} from the input methods of {}.",
+                layout_key_name, name
+            );
+        }
+    };
+
+    let result = UserProfiles::read().and_then(|profiles| {
+        profiles.for_each_hive(true, |hive| match hive {
+            Ok(hive) => unpreload(&hive.name, hive.get_key()),
+            Err(e) => print_warning(e),
+        })
+    });
+    if let Err(e) = result {
+        print_warning(e);
+    }
+
+    // The hive of the system account, used by the sign-in screen
+    match RegistryKey::users().get_subkey(".DEFAULT") {
+        Ok(default_key) => unpreload("the sign-in screen", &default_key),
+        Err(e) => print_warning(format!(
+            "Couldn't open the hive of the sign-in screen. {}",
+            e
+        )),
+    }
+}
@@ -0,0 +1,478 @@
+use std::{cmp::Ordering, fs, path::Path};
+
+use windows::Win32::UI::Shell::FOLDERID_System;
+
+use crate::{
+    arch::Arch,
+    color::print_warning,
+    compile::{
+        build_layout_dlls, get_default_archs, prepare_klc_file, verify_dll_file, TemplateVars,
+    },
+    error::{format_error_chain, InstallError},
+    event_log::{audit_layouts, AuditAction},
+    exit_code::{CommandError, ExitCode},
+    file_info::get_file_version,
+    get_known_folder::get_known_folder,
+    history::record_file,
+    input_refresh::{broadcast_settings_change, reload_layout},
+    install::{get_source, verify_install},
+    install_plan::{InstallPlan, PlannedOperation},
+    journal::Journal,
+    klc::KlcInfo,
+    layout_provenance::{get_file_sha256, Provenance},
+    layout_version::{compare_layout_versions, get_layout_version, LAYOUT_VERSION_VALUE_NAME},
+    layouts::{find_installs_of_dll, get_layouts_key},
+    pe_image::dll_builds_equal,
+    registry_value::RegistryValueData,
+    tool_runner::ProcessRunner,
+    utils::is_file_in_use,
+};
+
+/// Compares the installed DLL with the compiled layout, or the given DLL, without changing
+/// anything.
+///
+/// Returns whether the installed layout is up to date.
+pub fn check_layout_update(
+    file: String,
+    msklc: Option<String>,
+    template_vars: TemplateVars,
+) -> Result<bool, InstallError> {
+    let file_path = Path::new(&file)
+        .canonicalize()
+        .map_err(|e| format!("Couldn't find {}. {}", file, e))?;
+    let extension = file_path.extension().map(|ext| ext.to_ascii_lowercase());
+
+    let (dll_path, dll_name) = if extension == Some("dll".into()) {
+        let dll_name = file_path.file_name().unwrap().to_string_lossy().to_string();
+        (file_path, dll_name)
+    } else if extension == Some("klc".into()) {
+        let prepared_path = prepare_klc_file(&file_path, &template_vars)?;
+        let klc_info = KlcInfo::read_from_file(&prepared_path)?;
+        let archs = [Arch::get_native()];
+        let mut builds = build_layout_dlls(
+            &ProcessRunner,
+            &prepared_path,
+            &klc_info,
+            msklc.as_deref(),
+            &archs,
+            None,
+        )?;
+        let (_, dll_path) = builds.remove(0);
+        (dll_path, format!("{}.dll", klc_info.layout_name))
+    } else {
+        return Err("The file must be a .KLC or .DLL file.".to_string().into());
+    };
+    verify_dll_file(&dll_path)?;
+
+    // The installed DLL may have been renamed
+    let Some(install) = find_installs_of_dll(&dll_name)?.into_iter().next() else {
+        return Err(format!("{} isn't installed. Use install to install it.", dll_name).into());
+    };
+    let installed_path = get_known_folder(&FOLDERID_System)?.join(&install.layout_file);
+    if !installed_path.exists() {
+        return Err(format!(
+            "The DLL file {} is missing from System32.",
+            install.layout_file
+        )
+        .into());
+    }
+
+    for (label, path) in [("Installed", &installed_path), ("New", &dll_path)] {
+        println!(
+            "{}: version {}, SHA-256 {}",
+            label,
+            get_file_version(path)?.as_deref().unwrap_or("unknown"),
+            get_file_sha256(path)?
+        );
+    }
+
+    let up_to_date = dll_builds_equal(&dll_path, &installed_path)?;
+    if up_to_date {
+        println!("{} is up to date.", dll_name);
+    } else {
+        println!("An update of {} is available.", dll_name);
+    }
+
+    Ok(up_to_date)
+}
+
+/// What updating a layout did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateOutcome {
+    Updated,
+    /// Updated and reloaded in this session, so running applications can use it right away.
+    Reloaded,
+    /// Updated, but the DLL is only replaced when Windows restarts.
+    RebootPending,
+    UpToDate,
+    /// No installed layout uses the layout's DLL.
+    NotInstalled,
+}
+
+impl UpdateOutcome {
+    fn get_label(self) -> &'static str {
+        match self {
+            UpdateOutcome::Updated | UpdateOutcome::Reloaded => "UPDATED",
+            UpdateOutcome::RebootPending => "RESTART",
+            UpdateOutcome::UpToDate => "UP TO DATE",
+            UpdateOutcome::NotInstalled => "SKIPPED",
+        }
+    }
+
+    fn get_exit_code(self) -> ExitCode {
+        match self {
+            UpdateOutcome::RebootPending => ExitCode::RebootRequired,
+            _ => ExitCode::Success,
+        }
+    }
+}
+
+/// Replaces the DLL of the installed layouts using it with the new build of the layout,
+/// keeping their registry keys and layout IDs.
+///
+/// A .DLL file only replaces the DLL in System32, a .KLC file is compiled for every system
+/// directory and updates the layout text too.
+pub fn update_layout_file(
+    file_path: &Path,
+    msklc: Option<&str>,
+    template_vars: &TemplateVars,
+    allow_downgrade: bool,
+) -> Result<UpdateOutcome, InstallError> {
+    let native_arch = Arch::get_native();
+    let extension = file_path.extension().map(|ext| ext.to_ascii_lowercase());
+
+    let (dll_name, klc) = if extension == Some("klc".into()) {
+        let prepared_path = prepare_klc_file(file_path, template_vars)?;
+        let klc_info = KlcInfo::read_from_file(&prepared_path)?;
+        (
+            format!("{}.dll", klc_info.layout_name),
+            Some((prepared_path, klc_info)),
+        )
+    } else if extension == Some("dll".into()) {
+        let dll_name = file_path.file_name().unwrap().to_string_lossy().to_string();
+        (dll_name, None)
+    } else {
+        return Err("The file must be a .KLC or .DLL file.".to_string().into());
+    };
+
+    // Only the layouts built as the DLL, the others were installed from a different file.
+    // Finding them first spares compiling layouts that aren't installed.
+    let installs = find_installs_of_dll(&dll_name)?;
+    if installs.is_empty() {
+        return Ok(UpdateOutcome::NotInstalled);
+    }
+    // Installs of the layout may use the DLL under different names
+    let mut layout_files: Vec<&str> = Vec::new();
+    for install in &installs {
+        if !layout_files
+            .iter()
+            .any(|file| file.eq_ignore_ascii_case(&install.layout_file))
+        {
+            layout_files.push(&install.layout_file);
+        }
+    }
+
+    let layouts_key = get_layouts_key().map_err(|e| e.to_string())?;
+    let new_version = klc
+        .as_ref()
+        .and_then(|(_, klc_info)| klc_info.version.clone());
+    if let Some(new_version) = new_version.as_ref().filter(|_| !allow_downgrade) {
+        for install in &installs {
+            let layout_key_name = &install.layout_key_name;
+            let layout_key = layouts_key
+                .get_subkey(layout_key_name)
+                .map_err(|e| e.to_string())?;
+            let Some(installed_version) =
+                get_layout_version(&layout_key).map_err(|e| e.to_string())?
+            else {
+                continue;
+            };
+
+            if compare_layout_versions(new_version, &installed_version)? == Ordering::Less {
+                return Err(format!(
+                    "{} has version {}, older than the installed {}. Use --allow-downgrade to install it anyway.",
+                    layout_key_name, new_version, installed_version
+                )
+                .into());
+            }
+        }
+    }
+
+    let (dll_path, builds, layout_text) = match klc {
+        Some((prepared_path, klc_info)) => {
+            let archs = get_default_archs(native_arch, false);
+            let mut builds = build_layout_dlls(
+                &ProcessRunner,
+                &prepared_path,
+                &klc_info,
+                msklc,
+                &archs,
+                None,
+            )?;
+            let native_index = builds
+                .iter()
+                .position(|(arch, _)| *arch == native_arch)
+                .unwrap();
+            let (_, dll_path) = builds.remove(native_index);
+            (dll_path, builds, Some(klc_info.layout_text))
+        }
+        None => (file_path.to_path_buf(), Vec::new(), None),
+    };
+    verify_dll_file(&dll_path)?;
+
+    let system32_path = get_known_folder(&FOLDERID_System)?;
+    let same_text = installs
+        .iter()
+        .all(|install| layout_text.is_none() || install.layout_text == layout_text);
+    let mut same_dll = true;
+    for layout_file in &layout_files {
+        let installed_path = system32_path.join(layout_file);
+        if !installed_path.exists() || !dll_builds_equal(&dll_path, &installed_path)? {
+            same_dll = false;
+            break;
+        }
+    }
+    if same_text && same_dll {
+        return Ok(UpdateOutcome::UpToDate);
+    }
+
+    let mut plan = InstallPlan::new();
+
+    let mut targets = vec![(dll_path.clone(), system32_path)];
+    for (arch, arch_dll_path) in &builds {
+        if let Some(system_dir) = arch.get_system_dir(native_arch)? {
+            targets.push((arch_dll_path.clone(), system_dir));
+        }
+    }
+    let targets = targets.iter().flat_map(|(source, system_dir)| {
+        layout_files
+            .iter()
+            .map(move |layout_file| (source.clone(), system_dir.join(layout_file)))
+    });
+    for (source, target) in targets {
+        if target.exists() && is_file_in_use(&target) {
+            // Sessions using the layout keep the DLL loaded until they end
+            println!(
+                "{} is in use, it will be replaced when Windows restarts.",
+                target.display()
+            );
+            plan.push(PlannedOperation::ReplaceFileOnReboot { source, target });
+        } else if target.exists() {
+            plan.push(PlannedOperation::ReplaceFile { source, target });
+        } else {
+            plan.push(PlannedOperation::CopyFile { source, target });
+        }
+    }
+
+    // The key and layout ID stay, so the preloaded layouts still point to the layout
+    let provenance = Provenance::new(&dll_path, get_source(file_path))?;
+    for install in &installs {
+        let layout_key_path = format!("{}\\{}", layouts_key.get_path(), install.layout_key_name);
+        if let Some(layout_text) = &layout_text {
+            plan.set_value(
+                &layout_key_path,
+                "Layout Text",
+                RegistryValueData::String(layout_text.clone()),
+            );
+        }
+        provenance.plan(&mut plan, &layout_key_path);
+        // A DLL doesn't say which version of the layout it is
+        match &new_version {
+            Some(version) => plan.set_value(
+                &layout_key_path,
+                LAYOUT_VERSION_VALUE_NAME,
+                RegistryValueData::String(version.clone()),
+            ),
+            None => plan.delete_value(&layout_key_path, LAYOUT_VERSION_VALUE_NAME),
+        }
+    }
+
+    let layout_keys: Vec<&str> = installs
+        .iter()
+        .map(|install| install.layout_key_name.as_str())
+        .collect();
+
+    let mut journal = Journal::begin("update")?;
+    let result = plan.execute(&mut journal).and_then(|_| {
+        layout_files.iter().try_for_each(|layout_file| {
+            let layout_keys: Vec<&str> = installs
+                .iter()
+                .filter(|install| install.layout_file.eq_ignore_ascii_case(layout_file))
+                .map(|install| install.layout_key_name.as_str())
+                .collect();
+            verify_install(&layout_keys, layout_file, &builds, native_arch)
+        })
+    });
+    if let Err(e) = result {
+        return Err(InstallError::from(match journal.roll_back() {
+            Ok(()) => format!("{} The changes were rolled back.", e),
+            Err(rollback_error) => format!(
+                "{} Rolling back the changes failed too, the next run will offer to retry. {}",
+                e, rollback_error
+            ),
+        }));
+    }
+    journal.commit()?;
+    plan.get_target_files().for_each(record_file);
+    audit_layouts(AuditAction::Update, &layout_keys, Some(&provenance.sha256));
+
+    println!(
+        "Updated {} for the layouts {}.",
+        dll_name,
+        layout_keys.join(", ")
+    );
+
+    if plan.needs_reboot() {
+        return Ok(UpdateOutcome::RebootPending);
+    }
+
+    let mut reloaded = false;
+    for layout_key_name in &layout_keys {
+        let layout_id = layouts_key
+            .get_subkey(layout_key_name)
+            .and_then(|key| {
+                Ok(key
+                    .try_get_string(Some("Layout Id"))?
+                    .and_then(|v| u16::from_str_radix(&v, 16).ok()))
+            })
+            .map_err(|e| e.to_string())?;
+
+        match reload_layout(layout_key_name, layout_id) {
+            Ok(true) => {
+                println!("Reloaded {} in this session.", layout_key_name);
+                reloaded = true;
+            }
+            Ok(false) => {}
+            Err(e) => print_warning(e),
+        }
+    }
+
+    if reloaded {
+        Ok(UpdateOutcome::Reloaded)
+    } else {
+        Ok(UpdateOutcome::Updated)
+    }
+}
+
+/// Tells the user when the updated layouts are used.
+pub fn finish_update(outcome: UpdateOutcome) {
+    match outcome {
+        UpdateOutcome::RebootPending => {
+            println!("A restart is pending: the new DLLs are only used once Windows restarts.")
+        }
+        UpdateOutcome::Reloaded => println!(
+            "Running applications switch to the new version. Ones that don't may need to be restarted."
+        ),
+        _ => println!(
+            "Applications that already loaded the layout use the new version after signing out."
+        ),
+    }
+
+    if let Err(e) = broadcast_settings_change() {
+        print_warning(e);
+    }
+}
+
+pub fn update_layout(
+    file: String,
+    msklc: Option<String>,
+    template_vars: TemplateVars,
+    allow_downgrade: bool,
+) -> Result<ExitCode, CommandError> {
+    let file_path = Path::new(&file)
+        .canonicalize()
+        .map_err(|e| format!("Couldn't find {}. {}", file, e))?;
+
+    let outcome = update_layout_file(
+        &file_path,
+        msklc.as_deref(),
+        &template_vars,
+        allow_downgrade,
+    )?;
+    match outcome {
+        UpdateOutcome::NotInstalled => {
+            return Err(CommandError::new(
+                ExitCode::LayoutNotFound,
+                "No installed layout uses its DLL. Use install to install it.",
+            ))
+        }
+        UpdateOutcome::UpToDate => println!("The installed layout is already up to date."),
+        outcome => finish_update(outcome),
+    }
+
+    Ok(outcome.get_exit_code())
+}
+
+/// Updates every installed layout with a .KLC or .DLL file in the directory,
+/// and summarizes the results.
+pub fn update_all_layouts(
+    dir: &Path,
+    msklc: Option<String>,
+    template_vars: TemplateVars,
+    allow_downgrade: bool,
+) -> Result<ExitCode, CommandError> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).map_err(|e| format!("Couldn't read {}. {}", dir.display(), e))? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        let extension = path.extension().map(|ext| ext.to_ascii_lowercase());
+        if path.is_file() && (extension == Some("klc".into()) || extension == Some("dll".into())) {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    if files.is_empty() {
+        return Err(format!("There are no .KLC or .DLL files in {}.", dir.display()).into());
+    }
+
+    let mut results = Vec::new();
+    for (i, path) in files.iter().enumerate() {
+        println!("Checking {} ({}/{})...", path.display(), i + 1, files.len());
+
+        let result = update_layout_file(path, msklc.as_deref(), &template_vars, allow_downgrade);
+        if let Err(e) = &result {
+            println!("{}", format_error_chain(e));
+        }
+        results.push((path, result));
+    }
+
+    println!("Summary:");
+    for (path, result) in &results {
+        let status = match result {
+            Ok(outcome) => outcome.get_label(),
+            Err(_) => "FAILED",
+        };
+        println!("{:>10} {}", status, path.display());
+    }
+
+    let outcomes: Vec<UpdateOutcome> = results
+        .iter()
+        .filter_map(|(_, result)| result.as_ref().ok().copied())
+        .collect();
+    // The layout that takes the longest to be used decides what to tell
+    let outcome = [
+        UpdateOutcome::RebootPending,
+        UpdateOutcome::Updated,
+        UpdateOutcome::Reloaded,
+    ]
+    .into_iter()
+    .find(|outcome| outcomes.contains(outcome));
+    if let Some(outcome) = outcome {
+        finish_update(outcome);
+    }
+
+    let failed = results.len() - outcomes.len();
+    if failed > 0 {
+        let exit_code = if outcomes.is_empty() {
+            ExitCode::Error
+        } else {
+            ExitCode::PartialSuccess
+        };
+        return Err(CommandError::new(
+            exit_code,
+            format!("{} of {} layouts failed to update.", failed, results.len()),
+        ));
+    }
+
+    Ok(outcome.map_or(ExitCode::Success, UpdateOutcome::get_exit_code))
+}
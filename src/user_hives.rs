@@ -0,0 +1,325 @@
+use std::path::{Path, PathBuf};
+
+use widestring::U16CString;
+use windows::{
+    core::PCWSTR,
+    Win32::{
+        Foundation::{CloseHandle, HANDLE, LUID},
+        Security::{
+            AdjustTokenPrivileges, LookupPrivilegeValueW, LUID_AND_ATTRIBUTES, SE_BACKUP_NAME,
+            SE_PRIVILEGE_ENABLED, SE_RESTORE_NAME, TOKEN_ADJUST_PRIVILEGES, TOKEN_PRIVILEGES,
+            TOKEN_QUERY,
+        },
+        System::{
+            Registry::{RegLoadKeyW, RegUnLoadKeyW, HKEY, HKEY_USERS},
+            Threading::{GetCurrentProcess, OpenProcessToken},
+        },
+    },
+};
+
+use crate::registry_key::{RegistryError, RegistryKey};
+
+const PROFILE_LIST_KEY_PATH: &str =
+    "HKLM\\SOFTWARE\\Microsoft\\Windows NT\\CurrentVersion\\ProfileList";
+
+/// Prefix of the `HKEY_USERS` subkeys the hives of signed out users are loaded under.
+const LOADED_HIVE_PREFIX: &str = "klc-install-";
+
+/// The registry hive of a user profile, unloaded when dropped if it had to be loaded.
+pub struct UserHive {
+    /// Name of the profile directory, e.g. the user name, or `Default`.
+    pub name: String,
+    key: Option<RegistryKey>,
+    /// The `HKEY_USERS` subkey the hive was loaded under, if it wasn't loaded already.
+    loaded_as: Option<String>,
+}
+
+impl UserHive {
+    pub fn get_key(&self) -> &RegistryKey {
+        self.key.as_ref().unwrap()
+    }
+
+    /// Opens the hive under `HKEY_USERS\<subkey_name>`, loading it from the profile if needed.
+    fn open(name: String, subkey_name: &str, profile_path: &Path) -> Result<UserHive, String> {
+        match RegistryKey::users().get_subkey(subkey_name) {
+            Ok(key) => {
+                return Ok(UserHive {
+                    name,
+                    key: Some(key),
+                    loaded_as: None,
+                })
+            }
+            Err(RegistryError::NotFound) => {}
+            Err(e) => return Err(format!("Couldn't open the hive of {}. {}", name, e)),
+        }
+
+        let loaded_as = format!("{}{}", LOADED_HIVE_PREFIX, subkey_name);
+        let hive_path = profile_path.join("NTUSER.DAT");
+        load_hive(HKEY_USERS, &loaded_as, &hive_path).map_err(|e| {
+            format!(
+                "Couldn't load the hive of {} from {}. {}",
+                name,
+                hive_path.display(),
+                e
+            )
+        })?;
+
+        // From here on, dropping the hive unloads it
+        let mut hive = UserHive {
+            name,
+            key: None,
+            loaded_as: Some(loaded_as),
+        };
+        let key = RegistryKey::users()
+            .get_subkey(hive.loaded_as.as_ref().unwrap())
+            .map_err(|e| format!("Couldn't open the hive of {}. {}", hive.name, e))?;
+        hive.key = Some(key);
+
+        Ok(hive)
+    }
+}
+
+impl Drop for UserHive {
+    fn drop(&mut self) {
+        // The hive can't be unloaded while a key in it is open
+        self.key = None;
+
+        if let Some(loaded_as) = &self.loaded_as {
+            unload_hive(HKEY_USERS, loaded_as);
+        }
+    }
+}
+
+/// Loads the hive file under the subkey of `HKEY_USERS` or `HKEY_LOCAL_MACHINE`,
+/// which needs the privileges enabled by `enable_hive_privileges`.
+pub fn load_hive(root: HKEY, subkey_name: &str, hive_path: &Path) -> Result<(), RegistryError> {
+    let subkey_wide = U16CString::from_str(subkey_name)
+        .map_err(|e| RegistryError::Other(format!("Invalid key name {}. {}", subkey_name, e)))?;
+    let hive_path_wide = U16CString::from_os_str(hive_path).map_err(|e| {
+        RegistryError::Other(format!("Invalid hive path {}. {}", hive_path.display(), e))
+    })?;
+
+    let result = unsafe {
+        RegLoadKeyW(
+            root,
+            PCWSTR(subkey_wide.as_ptr()),
+            PCWSTR(hive_path_wide.as_ptr()),
+        )
+    };
+    if result.is_err() {
+        return Err(RegistryError::from(result));
+    }
+
+    Ok(())
+}
+
+/// Unloads a hive loaded with `load_hive`. Every key in it must be closed first.
+pub fn unload_hive(root: HKEY, subkey_name: &str) {
+    // A name that can't be converted can't have been loaded either
+    let Ok(subkey_wide) = U16CString::from_str(subkey_name) else {
+        return;
+    };
+    _ = unsafe { RegUnLoadKeyW(root, PCWSTR(subkey_wide.as_ptr())) };
+}
+
+/// Replaces `%NAME%` references with the environment variables, like `%SystemDrive%`.
+/// Unknown variables are left as they are.
+fn expand_environment_variables(string: &str) -> String {
+    let mut expanded = String::new();
+    let mut rest = string;
+
+    while let Some(start) = rest.find('%') {
+        let Some(length) = rest[start + 1..].find('%') else {
+            break;
+        };
+        let name = &rest[start + 1..start + 1 + length];
+
+        expanded.push_str(&rest[..start]);
+        match (!name.is_empty()).then(|| std::env::var(name)) {
+            Some(Ok(value)) => expanded.push_str(&value),
+            _ => expanded.push_str(&rest[start..start + length + 2]),
+        }
+        rest = &rest[start + length + 2..];
+    }
+
+    expanded.push_str(rest);
+    expanded
+}
+
+fn enable_privilege(privilege: PCWSTR) -> Result<(), String> {
+    unsafe {
+        let mut token = HANDLE::default();
+        OpenProcessToken(
+            GetCurrentProcess(),
+            TOKEN_ADJUST_PRIVILEGES | TOKEN_QUERY,
+            &mut token,
+        )
+        .map_err(|e| e.to_string())?;
+
+        let mut luid = LUID::default();
+        let result = LookupPrivilegeValueW(PCWSTR::null(), privilege, &mut luid).and_then(|_| {
+            let privileges = TOKEN_PRIVILEGES {
+                PrivilegeCount: 1,
+                Privileges: [LUID_AND_ATTRIBUTES {
+                    Luid: luid,
+                    Attributes: SE_PRIVILEGE_ENABLED,
+                }],
+            };
+            AdjustTokenPrivileges(token, false, Some(&privileges), 0, None, None)
+        });
+        _ = CloseHandle(token);
+
+        result.map_err(|e| e.to_string())
+    }
+}
+
+/// Enables the privileges loading hives needs, which administrators have but don't enable.
+pub fn enable_hive_privileges() -> Result<(), String> {
+    enable_privilege(SE_BACKUP_NAME)
+        .and_then(|_| enable_privilege(SE_RESTORE_NAME))
+        .map_err(|e| format!("Couldn't enable the privileges to load hives. {}", e))
+}
+
+/// A local user profile, from the profile list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserProfile {
+    /// The SID of the user, or `Default` for the Default profile.
+    pub sid: String,
+    /// Name of the profile directory, e.g. the user name, or `Default`.
+    pub name: String,
+    pub path: PathBuf,
+    /// Whether the hive is loaded under `HKEY_USERS`, e.g. because the user is signed in.
+    pub loaded: bool,
+}
+
+impl UserProfile {
+    fn new(sid: String, name: String, path: PathBuf) -> Self {
+        let loaded = RegistryKey::users().get_subkey(&sid).is_ok();
+        UserProfile {
+            sid,
+            name,
+            path,
+            loaded,
+        }
+    }
+
+    /// Opens the hive of the profile, loading it from its `NTUSER.DAT` if it isn't loaded,
+    /// which needs the privileges enabled by `enable_hive_privileges`.
+    pub fn open_hive(&self) -> Result<UserHive, String> {
+        UserHive::open(self.name.clone(), &self.sid, &self.path)
+    }
+}
+
+/// The local user profiles and the Default profile new users are created from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserProfiles {
+    pub users: Vec<UserProfile>,
+    pub default: Option<UserProfile>,
+}
+
+impl UserProfiles {
+    /// Reads the profile list, which everyone can do.
+    pub fn read() -> Result<UserProfiles, String> {
+        let profile_list = RegistryKey::from_path(PROFILE_LIST_KEY_PATH)
+            .map_err(|e| format!("Couldn't open the profile list. {}", e))?;
+
+        let mut users = Vec::new();
+
+        for profile_key in profile_list.iter_children() {
+            let profile_key = profile_key.map_err(|e| e.to_string())?;
+            let sid = profile_key.get_name().to_string();
+
+            // Only real users, not the system and service accounts
+            if !sid.starts_with("S-1-5-21-") {
+                continue;
+            }
+
+            let Some(profile_path) = profile_key
+                .try_get_string(Some("ProfileImagePath"))
+                .map_err(|e| e.to_string())?
+            else {
+                continue;
+            };
+            let profile_path = PathBuf::from(expand_environment_variables(&profile_path));
+            let name = profile_path
+                .file_name()
+                .map_or(sid.clone(), |name| name.to_string_lossy().to_string());
+
+            users.push(UserProfile::new(sid, name, profile_path));
+        }
+
+        let default = profile_list
+            .try_get_string(Some("Default"))
+            .map_err(|e| e.to_string())?
+            .map(|default_path| {
+                UserProfile::new(
+                    "Default".to_string(),
+                    "Default".to_string(),
+                    PathBuf::from(expand_environment_variables(&default_path)),
+                )
+            });
+
+        Ok(UserProfiles { users, default })
+    }
+
+    /// The users signed in besides the one running klc-install, told apart by their profile.
+    pub fn get_other_signed_in_users(&self) -> Vec<&UserProfile> {
+        let own_profile = std::env::var("USERPROFILE").unwrap_or_default();
+
+        self.users
+            .iter()
+            .filter(|user| {
+                user.loaded && !own_profile.eq_ignore_ascii_case(&user.path.to_string_lossy())
+            })
+            .collect()
+    }
+
+    /// Runs the action with the hive of every user, then of the Default profile if
+    /// `include_default`. Each hive is unloaded again after its action if it had to be loaded.
+    ///
+    /// Loading hives of users that aren't signed in needs administrator rights. A hive that
+    /// can't be opened is passed as an error, without stopping the others.
+    pub fn for_each_hive(
+        &self,
+        include_default: bool,
+        mut action: impl FnMut(Result<&UserHive, String>),
+    ) -> Result<(), String> {
+        enable_hive_privileges()?;
+
+        for user in &self.users {
+            action(user.open_hive().as_ref().map_err(String::clone));
+        }
+
+        if include_default {
+            match &self.default {
+                Some(default) => action(default.open_hive().as_ref().map_err(String::clone)),
+                None => action(Err("The Default profile wasn't found.".to_string())),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_expand_environment_variables() {
+        std::env::set_var("KLC_INSTALL_TEST_DRIVE", "C:");
+
+        assert_eq!(
+            expand_environment_variables("%KLC_INSTALL_TEST_DRIVE%\\Users\\Default"),
+            "C:\\Users\\Default"
+        );
+        assert_eq!(
+            expand_environment_variables("%KLC_INSTALL_UNKNOWN%\\%%\\50%"),
+            "%KLC_INSTALL_UNKNOWN%\\%%\\50%"
+        );
+        assert_eq!(
+            expand_environment_variables("C:\\Users\\alice"),
+            "C:\\Users\\alice"
+        );
+    }
+}
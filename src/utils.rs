@@ -1,15 +1,21 @@
 #![allow(dead_code, unused_imports)]
 
 mod as_u16_slice;
+mod decode_text;
+mod files_equal;
 mod move_file;
 mod range_bounds_ext;
 mod string_ext;
 mod u16_iter;
 mod utf16_lines;
+mod wildcard;
 
 pub use as_u16_slice::*;
+pub use decode_text::*;
+pub use files_equal::*;
 pub use move_file::*;
 pub use range_bounds_ext::*;
 pub use string_ext::*;
 pub use u16_iter::*;
 pub use utf16_lines::*;
+pub use wildcard::*;
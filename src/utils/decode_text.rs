@@ -0,0 +1,53 @@
+/// Decodes text that's either UTF-16 or UTF-8, like a KLC file piped from another program.
+///
+/// UTF-16 is detected by its BOM, or without one by the zero bytes of ASCII characters.
+pub fn decode_text(bytes: &[u8]) -> Result<String, String> {
+    let utf16_le = match bytes {
+        [0xFF, 0xFE, ..] => Some(true),
+        [0xFE, 0xFF, ..] => Some(false),
+        [_, 0, ..] if bytes.len().is_multiple_of(2) => Some(true),
+        [0, _, ..] if bytes.len().is_multiple_of(2) => Some(false),
+        _ => None,
+    };
+
+    let Some(little_endian) = utf16_le else {
+        let text = std::str::from_utf8(bytes).map_err(|e| format!("Invalid UTF-8. {}", e))?;
+        return Ok(text.strip_prefix('\u{feff}').unwrap_or(text).to_string());
+    };
+
+    if !bytes.len().is_multiple_of(2) {
+        return Err("Invalid UTF-16: odd number of bytes.".to_string());
+    }
+    let units = bytes.chunks_exact(2).map(|pair| {
+        if little_endian {
+            u16::from_le_bytes([pair[0], pair[1]])
+        } else {
+            u16::from_be_bytes([pair[0], pair[1]])
+        }
+    });
+    let text = char::decode_utf16(units)
+        .collect::<Result<String, _>>()
+        .map_err(|e| format!("Invalid UTF-16. {}", e))?;
+
+    Ok(text.strip_prefix('\u{feff}').unwrap_or(&text).to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode_text() {
+        let utf16_le: Vec<u8> = "\u{feff}KBD\tł"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        let utf16_be: Vec<u8> = "KBD\tł".encode_utf16().flat_map(u16::to_be_bytes).collect();
+
+        assert_eq!(decode_text(&utf16_le).unwrap(), "KBD\tł");
+        assert_eq!(decode_text(&utf16_be).unwrap(), "KBD\tł");
+        assert_eq!(decode_text("\u{feff}KBD\tł".as_bytes()).unwrap(), "KBD\tł");
+        assert_eq!(decode_text(b"KBD").unwrap(), "KBD");
+        assert!(decode_text(&[0xFF, 0xFE, 0x4B]).is_err());
+    }
+}
@@ -0,0 +1,33 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, Read},
+    path::Path,
+};
+
+pub fn files_equal(a: &Path, b: &Path) -> Result<bool, io::Error> {
+    let file_a = File::open(a)?;
+    let file_b = File::open(b)?;
+
+    // Different sizes can never be equal
+    if file_a.metadata()?.len() != file_b.metadata()?.len() {
+        return Ok(false);
+    }
+
+    let mut reader_a = BufReader::new(file_a);
+    let mut reader_b = BufReader::new(file_b);
+    let mut buf_a = [0u8; 8192];
+    let mut buf_b = [0u8; 8192];
+
+    loop {
+        let read_a = reader_a.read(&mut buf_a)?;
+        if read_a == 0 {
+            return Ok(true);
+        }
+
+        reader_b.read_exact(&mut buf_b[..read_a])?;
+
+        if buf_a[..read_a] != buf_b[..read_a] {
+            return Ok(false);
+        }
+    }
+}
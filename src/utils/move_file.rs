@@ -1,5 +1,18 @@
 use std::{fs, io, path::Path};
 
+use widestring::U16CString;
+use windows::{
+    core::PCWSTR,
+    Win32::Storage::FileSystem::{
+        MoveFileExW, MOVEFILE_DELAY_UNTIL_REBOOT, MOVEFILE_REPLACE_EXISTING,
+    },
+};
+
+/// Errors of opening a file another process has open or mapped, like a loaded DLL.
+const ERROR_SHARING_VIOLATION: i32 = 32;
+const ERROR_LOCK_VIOLATION: i32 = 33;
+const ERROR_USER_MAPPED_FILE: i32 = 1224;
+
 pub fn move_file(from: &Path, to: &Path) -> Result<(), io::Error> {
     // First we check if the destination file already exists
     if to.exists() {
@@ -21,3 +34,53 @@ pub fn move_file(from: &Path, to: &Path) -> Result<(), io::Error> {
 
     Ok(())
 }
+
+/// Whether the error is because another process uses the file.
+pub fn is_file_in_use_error(error: &io::Error) -> bool {
+    matches!(
+        error.raw_os_error(),
+        Some(ERROR_SHARING_VIOLATION | ERROR_LOCK_VIOLATION | ERROR_USER_MAPPED_FILE)
+    )
+}
+
+/// Whether the file can't be overwritten because another process uses it,
+/// like a keyboard layout DLL loaded by a user session.
+pub fn is_file_in_use(path: &Path) -> bool {
+    match fs::OpenOptions::new().write(true).open(path) {
+        Ok(_) => false,
+        Err(e) => is_file_in_use_error(&e),
+    }
+}
+
+/// Schedules moving the file over the destination when Windows restarts,
+/// before any process can load it.
+pub fn move_file_on_reboot(from: &Path, to: &Path) -> Result<(), io::Error> {
+    let from_str = U16CString::from_os_str(from)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let to_str =
+        U16CString::from_os_str(to).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    unsafe {
+        MoveFileExW(
+            PCWSTR(from_str.as_ptr()),
+            PCWSTR(to_str.as_ptr()),
+            MOVEFILE_REPLACE_EXISTING | MOVEFILE_DELAY_UNTIL_REBOOT,
+        )
+    }
+    .map_err(io::Error::from)
+}
+
+/// Schedules deleting the file when Windows restarts, before any process can load it.
+pub fn delete_file_on_reboot(path: &Path) -> Result<(), io::Error> {
+    let path_str = U16CString::from_os_str(path)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    unsafe {
+        MoveFileExW(
+            PCWSTR(path_str.as_ptr()),
+            PCWSTR::null(),
+            MOVEFILE_DELAY_UNTIL_REBOOT,
+        )
+    }
+    .map_err(io::Error::from)
+}
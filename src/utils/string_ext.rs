@@ -16,22 +16,14 @@ impl StringExt for String {
             return;
         }
 
-        let (start, _end) = (range.start, range.end);
-        let amount = range.len();
-
-        let old_len = self.len();
-        let new_len = old_len - amount;
-
-        unsafe {
-            let bytes = self.as_bytes_mut();
-
-            for i in start..new_len {
-                bytes[i] = bytes[i + amount];
-            }
-
-            self.truncate(new_len);
-            self.shrink_to_fit();
+        // Removing a suffix doesn't need to move anything
+        if range.end == self.len() {
+            self.truncate(range.start);
+            return;
         }
+
+        // Keeps the capacity, as reallocating for every line adds up in large files
+        self.drain(range);
     }
 
     fn remove_prefix(&mut self, prefix: &str) -> bool {
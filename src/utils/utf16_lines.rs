@@ -1,27 +1,18 @@
-use std::{
-    fmt::Display,
-    io::{self, BufRead},
-};
+use std::io::{self, BufRead};
 
+use thiserror::Error;
 use widestring::{Utf16Str, Utf16String};
 
-use super::{AsU16Slice, StringExt};
+use super::AsU16Slice;
 
-#[derive(Debug)]
+#[derive(Debug, Error)]
 pub enum ReadUtf16LineError {
+    #[error("IO error: {0}")]
     Io(io::Error),
+    #[error("UTF-16 error: {0}")]
     Utf16(widestring::error::Utf16Error),
 }
 
-impl Display for ReadUtf16LineError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            ReadUtf16LineError::Io(e) => write!(f, "IO error: {}", e),
-            ReadUtf16LineError::Utf16(e) => write!(f, "UTF-16 error: {}", e),
-        }
-    }
-}
-
 pub trait ReadUtf16Line {
     fn read_utf16_line(&mut self) -> Result<Utf16String, ReadUtf16LineError>;
     fn utf16_lines(self) -> Utf16Lines<Self>
@@ -29,83 +20,128 @@ pub trait ReadUtf16Line {
         Self: Sized;
 }
 
-impl<T: BufRead> ReadUtf16Line for T {
-    fn read_utf16_line(&mut self) -> Result<Utf16String, ReadUtf16LineError> {
-        let mut buf: Vec<u8> = Vec::new();
-        let mut read = 0usize;
-        'l: loop {
-            let (done, used) = 'block: {
-                let available = match self.fill_buf() {
-                    Ok(n) => n,
-                    Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => continue 'l,
-                    Err(e) => return Err(ReadUtf16LineError::Io(e)),
-                };
+/// Appends the next line, including the line feed, to `buf`.
+///
+/// Takes the buffer from the caller, so it can be reused for every line of a file.
+fn read_utf16_line_into<R: BufRead>(
+    reader: &mut R,
+    buf: &mut Vec<u8>,
+) -> Result<(), ReadUtf16LineError> {
+    'l: loop {
+        let (done, used) = 'block: {
+            let available = match reader.fill_buf() {
+                Ok(n) => n,
+                Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => continue 'l,
+                Err(e) => return Err(ReadUtf16LineError::Io(e)),
+            };
 
-                if available.is_empty() {
-                    break 'block (true, 0);
+            if available.is_empty() {
+                break 'block (true, 0);
+            }
+
+            // Index of the second byte
+            let mut i = 1 - (buf.len() % 2);
+            loop {
+                if i >= available.len() {
+                    buf.extend_from_slice(available);
+                    break (false, available.len());
                 }
+                let first_byte = if i == 0 {
+                    *buf.last().unwrap()
+                } else {
+                    available[i - 1]
+                };
+                let second_byte = available[i];
 
-                // Index of the second byte
-                let mut i = 1 - (read % 2);
-                loop {
-                    if i >= available.len() {
-                        buf.extend_from_slice(available);
-                        break (false, available.len());
-                    }
-                    let first_byte = if i == 0 {
-                        *buf.last().unwrap()
-                    } else {
-                        available[i - 1]
-                    };
-                    let second_byte = available[i];
-
-                    if first_byte == b'\n' && second_byte == b'\0' {
-                        buf.extend_from_slice(&available[..=i]);
-                        break (true, i + 1);
-                    }
-
-                    i += 2;
+                if first_byte == b'\n' && second_byte == b'\0' {
+                    buf.extend_from_slice(&available[..=i]);
+                    break (true, i + 1);
                 }
-            };
-            self.consume(used);
-            read += used;
-            if done || used == 0 {
-                break;
+
+                i += 2;
             }
+        };
+        reader.consume(used);
+        if done || used == 0 {
+            break;
         }
+    }
+
+    Ok(())
+}
+
+impl<T: BufRead> ReadUtf16Line for T {
+    fn read_utf16_line(&mut self) -> Result<Utf16String, ReadUtf16LineError> {
+        let mut buf: Vec<u8> = Vec::new();
+        read_utf16_line_into(self, &mut buf)?;
 
         Ok(Utf16Str::from_slice(buf.as_u16_slice())
-            .map_err(|e| ReadUtf16LineError::Utf16(e))?
+            .map_err(ReadUtf16LineError::Utf16)?
             .to_owned())
     }
 
     fn utf16_lines(self) -> Utf16Lines<Self> {
-        Utf16Lines { reader: self }
+        Utf16Lines {
+            reader: self,
+            buf: Vec::new(),
+        }
     }
 }
 
 pub struct Utf16Lines<R> {
     reader: R,
+    buf: Vec<u8>,
 }
 
 impl<R: BufRead> Iterator for Utf16Lines<R> {
     type Item = Result<String, ReadUtf16LineError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let line = self.reader.read_utf16_line();
-        match line {
-            Ok(line) => {
-                if line.is_empty() {
-                    None
-                } else {
-                    let mut string = line.to_string();
-                    string.remove_prefix("\u{feff}");
-                    string.remove_suffix("\n");
-                    string.remove_suffix("\r");
-                    Some(Ok(string))
-                }
-            }
-            Err(e) => Some(Err(e)),
+        self.buf.clear();
+        if let Err(e) = read_utf16_line_into(&mut self.reader, &mut self.buf) {
+            return Some(Err(e));
+        }
+
+        if self.buf.is_empty() {
+            return None;
         }
+
+        // Strip the BOM and line ending before decoding, so the line is only copied once
+        let mut units = self.buf.as_u16_slice();
+        units = units.strip_prefix(&[0xFEFF]).unwrap_or(units);
+        units = units.strip_suffix(&[b'\n' as u16]).unwrap_or(units);
+        units = units.strip_suffix(&[b'\r' as u16]).unwrap_or(units);
+
+        Some(
+            Utf16Str::from_slice(units)
+                .map(|line| line.to_string())
+                .map_err(ReadUtf16LineError::Utf16),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::BufReader;
+
+    use super::*;
+
+    #[test]
+    fn test_utf16_lines() {
+        let bytes: Vec<u8> = "\u{feff}KBD\tmultilin\r\n\r\nLOCALEID\t\"00000415\"\r\nENDKBD"
+            .encode_utf16()
+            .flat_map(|unit| unit.to_le_bytes())
+            .collect();
+
+        // An odd capacity splits the code units between reads
+        let lines = BufReader::with_capacity(3, bytes.as_slice())
+            .utf16_lines()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(
+            lines,
+            ["KBD\tmultilin", "", "LOCALEID\t\"00000415\"", "ENDKBD"]
+        );
     }
 }
@@ -0,0 +1,94 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Checks whether the name matches a pattern with `*` and `?` wildcards, ignoring case like Windows.
+pub fn matches_wildcard(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+
+    // Where to retry after the last `*` if the rest doesn't match
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut n) = (0, 0);
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            // Let the `*` take one more character
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, n));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Checks whether the pattern matches a part of the text, e.g. `dvorak` or `prog*dvorak`.
+pub fn contains_wildcard(pattern: &str, text: &str) -> bool {
+    matches_wildcard(&format!("*{}*", pattern), text)
+}
+
+/// Expands wildcards in the file name part of the path, returning the matching files sorted.
+///
+/// Paths without wildcards are returned as they are.
+pub fn expand_wildcard(path: &str) -> Result<Vec<PathBuf>, io::Error> {
+    let path = Path::new(path);
+    let Some(pattern) = path.file_name().and_then(|name| name.to_str()) else {
+        return Ok(vec![path.to_path_buf()]);
+    };
+    if !pattern.contains(['*', '?']) {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let mut matches = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let is_match = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| matches_wildcard(pattern, name));
+
+        if is_match && entry.file_type()?.is_file() {
+            matches.push(entry.path());
+        }
+    }
+    matches.sort();
+
+    Ok(matches)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_matches_wildcard() {
+        assert!(matches_wildcard("*.klc", "Polish.KLC"));
+        assert!(matches_wildcard("kbd??.dll", "kbdpl.dll"));
+        assert!(matches_wildcard("*a*b", "xaxxab"));
+        assert!(matches_wildcard("*", ""));
+        assert!(!matches_wildcard("*.klc", "Polish.klc.bak"));
+        assert!(!matches_wildcard("kbd?.dll", "kbdpl.dll"));
+    }
+
+    #[test]
+    fn test_contains_wildcard() {
+        assert!(contains_wildcard("dvorak", "Programmer Dvorak"));
+        assert!(contains_wildcard("prog*dvorak", "Programmer Dvorak - Left"));
+        assert!(!contains_wildcard("dvorak*left", "Dvorak - Right"));
+    }
+}
@@ -0,0 +1,290 @@
+use std::{ffi::c_void, path::Path};
+
+use widestring::U16CString;
+use windows::{
+    core::PCWSTR,
+    Win32::{
+        Foundation::{FreeLibrary, BOOL, HMODULE, TRUE},
+        System::LibraryLoader::{
+            BeginUpdateResourceW, EndUpdateResourceW, EnumResourceLanguagesW, LoadLibraryExW,
+            UpdateResourceW, LOAD_LIBRARY_AS_DATAFILE, LOAD_LIBRARY_AS_IMAGE_RESOURCE,
+        },
+    },
+};
+
+/// `RT_VERSION`, the resource type of `VERSIONINFO`.
+const RT_VERSION: PCWSTR = PCWSTR(std::ptr::without_provenance(16));
+/// `VS_VERSION_INFO`, the resource ID every `VERSIONINFO` resource uses.
+const VS_VERSION_INFO: PCWSTR = PCWSTR(std::ptr::without_provenance(1));
+/// The language the version resource is written in if the file doesn't have one yet.
+const DEFAULT_LANGUAGE: u16 = 0x0409;
+/// The Unicode code page.
+const CODE_PAGE: u16 = 0x04B0;
+
+const VS_FFI_SIGNATURE: u32 = 0xFEEF04BD;
+const VS_FFI_STRUCVERSION: u32 = 0x00010000;
+const VS_FFI_FILEFLAGSMASK: u32 = 0x3F;
+const VOS_NT_WINDOWS32: u32 = 0x00040004;
+const VFT_DLL: u32 = 2;
+
+/// Metadata written to the `VERSIONINFO` resource of a layout DLL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionResource {
+    pub version: [u16; 4],
+    pub product_name: String,
+    pub internal_name: String,
+    pub original_filename: String,
+    pub company: Option<String>,
+    pub copyright: Option<String>,
+}
+
+/// Parses a dotted version like `1.0` into its four parts, filling the missing ones with 0.
+pub fn parse_version(version: &str) -> Result<[u16; 4], String> {
+    let mut parts = [0; 4];
+
+    for (i, part) in version.trim().split('.').enumerate() {
+        if i >= parts.len() {
+            return Err(format!(
+                "Invalid version {}. It has too many parts.",
+                version
+            ));
+        }
+
+        parts[i] = part
+            .parse()
+            .map_err(|_| format!("Invalid version {}.", version))?;
+    }
+
+    Ok(parts)
+}
+
+/// Appends a `VERSIONINFO` block with its value and children, as described in the `VS_VERSIONINFO`,
+/// `StringFileInfo` and `VarFileInfo` docs.
+///
+/// `text` is `true` for string values, whose length is counted in characters instead of bytes.
+fn write_block(buf: &mut Vec<u8>, key: &str, value: &[u8], text: bool, children: &[Vec<u8>]) {
+    fn pad(buf: &mut Vec<u8>) {
+        buf.resize(buf.len().next_multiple_of(4), 0);
+    }
+
+    let start = buf.len();
+    let value_length = if text { value.len() / 2 } else { value.len() };
+
+    buf.extend_from_slice(&[0, 0]); // Length, filled in at the end
+    buf.extend_from_slice(&(value_length as u16).to_le_bytes());
+    buf.extend_from_slice(&(text as u16).to_le_bytes());
+    for c in key.encode_utf16().chain([0]) {
+        buf.extend_from_slice(&c.to_le_bytes());
+    }
+    pad(buf);
+
+    buf.extend_from_slice(value);
+
+    for child in children {
+        pad(buf);
+        buf.extend_from_slice(child);
+    }
+
+    let length = (buf.len() - start) as u16;
+    buf[start..start + 2].copy_from_slice(&length.to_le_bytes());
+}
+
+fn block(key: &str, value: &[u8], text: bool, children: &[Vec<u8>]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_block(&mut buf, key, value, text, children);
+    buf
+}
+
+fn string_block(key: &str, value: &str) -> Vec<u8> {
+    let value: Vec<u8> = value
+        .encode_utf16()
+        .chain([0])
+        .flat_map(|c| c.to_le_bytes())
+        .collect();
+
+    block(key, &value, true, &[])
+}
+
+impl VersionResource {
+    /// Serializes the resource into the binary `VS_VERSIONINFO` format.
+    pub fn to_bytes(&self, language: u16) -> Vec<u8> {
+        let [major, minor, build, revision] = self.version;
+        let version_ms = (major as u32) << 16 | minor as u32;
+        let version_ls = (build as u32) << 16 | revision as u32;
+        let version_str = format!("{}.{}.{}.{}", major, minor, build, revision);
+
+        let fixed_info: Vec<u8> = [
+            VS_FFI_SIGNATURE,
+            VS_FFI_STRUCVERSION,
+            version_ms,
+            version_ls,
+            version_ms,
+            version_ls,
+            VS_FFI_FILEFLAGSMASK,
+            0,
+            VOS_NT_WINDOWS32,
+            VFT_DLL,
+            0,
+            0,
+            0,
+        ]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+
+        let mut strings = vec![
+            string_block("FileDescription", &self.product_name),
+            string_block("FileVersion", &version_str),
+            string_block("InternalName", &self.internal_name),
+            string_block("OriginalFilename", &self.original_filename),
+            string_block("ProductName", &self.product_name),
+            string_block("ProductVersion", &version_str),
+        ];
+        if let Some(company) = &self.company {
+            strings.push(string_block("CompanyName", company));
+        }
+        if let Some(copyright) = &self.copyright {
+            strings.push(string_block("LegalCopyright", copyright));
+        }
+
+        let string_table = block(
+            &format!("{:04x}{:04x}", language, CODE_PAGE),
+            &[],
+            true,
+            &strings,
+        );
+        let string_file_info = block("StringFileInfo", &[], true, &[string_table]);
+
+        let translation: Vec<u8> = [language, CODE_PAGE]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let var_file_info = block(
+            "VarFileInfo",
+            &[],
+            true,
+            &[block("Translation", &translation, false, &[])],
+        );
+
+        block(
+            "VS_VERSION_INFO",
+            &fixed_info,
+            false,
+            &[string_file_info, var_file_info],
+        )
+    }
+
+    /// Replaces the `VERSIONINFO` resource of the file.
+    ///
+    /// The version resource generated by KBDUTOOL is overwritten in its language,
+    /// so the file doesn't end up with two of them.
+    pub fn write_to_file(&self, path: &Path) -> Result<(), String> {
+        let path = U16CString::from_os_str(path.as_os_str())
+            .map_err(|e| format!("Couldn't convert path to UTF16! {}", e))?;
+
+        let languages = get_version_languages(&path)?;
+        let language = languages.first().copied().unwrap_or(DEFAULT_LANGUAGE);
+        let data = self.to_bytes(language);
+
+        let update = unsafe { BeginUpdateResourceW(PCWSTR(path.as_ptr()), false) }
+            .map_err(|e| format!("Couldn't open the DLL for updating. {}", e))?;
+
+        let result = (|| {
+            for &other in languages.iter().skip(1) {
+                unsafe { UpdateResourceW(update, RT_VERSION, VS_VERSION_INFO, other, None, 0) }?;
+            }
+
+            unsafe {
+                UpdateResourceW(
+                    update,
+                    RT_VERSION,
+                    VS_VERSION_INFO,
+                    language,
+                    Some(data.as_ptr() as *const c_void),
+                    data.len() as u32,
+                )
+            }
+        })();
+
+        // Discard the changes if any of the updates failed
+        unsafe { EndUpdateResourceW(update, result.is_err()) }
+            .and(result)
+            .map_err(|e| format!("Couldn't update the version resource. {}", e))
+    }
+}
+
+/// Lists the languages the `VERSIONINFO` resource of the file exists in.
+fn get_version_languages(path: &U16CString) -> Result<Vec<u16>, String> {
+    unsafe extern "system" fn collect_language(
+        _module: HMODULE,
+        _type: PCWSTR,
+        _name: PCWSTR,
+        language: u16,
+        param: isize,
+    ) -> BOOL {
+        let languages = &mut *(param as *mut Vec<u16>);
+        languages.push(language);
+        TRUE
+    }
+
+    let module = unsafe {
+        LoadLibraryExW(
+            PCWSTR(path.as_ptr()),
+            None,
+            LOAD_LIBRARY_AS_DATAFILE | LOAD_LIBRARY_AS_IMAGE_RESOURCE,
+        )
+    }
+    .map_err(|e| format!("Couldn't load the DLL. {}", e))?;
+
+    let mut languages: Vec<u16> = Vec::new();
+    // Fails if there's no version resource, in which case there's nothing to replace
+    _ = unsafe {
+        EnumResourceLanguagesW(
+            module,
+            RT_VERSION,
+            VS_VERSION_INFO,
+            Some(collect_language),
+            &mut languages as *mut Vec<u16> as isize,
+        )
+    };
+
+    unsafe { FreeLibrary(module) }.map_err(|e| e.to_string())?;
+
+    Ok(languages)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("1.0"), Ok([1, 0, 0, 0]));
+        assert_eq!(parse_version("2.1.3.4"), Ok([2, 1, 3, 4]));
+        assert!(parse_version("1.0.0.0.0").is_err());
+        assert!(parse_version("1.x").is_err());
+    }
+
+    #[test]
+    fn test_to_bytes() {
+        let resource = VersionResource {
+            version: [1, 2, 0, 0],
+            product_name: "Test".to_string(),
+            internal_name: "test".to_string(),
+            original_filename: "test.dll".to_string(),
+            company: None,
+            copyright: None,
+        };
+        let bytes = resource.to_bytes(0x0415);
+
+        let length = u16::from_le_bytes([bytes[0], bytes[1]]) as usize;
+        assert_eq!(length, bytes.len());
+        // Value length of VS_FIXEDFILEINFO
+        assert_eq!(u16::from_le_bytes([bytes[2], bytes[3]]), 52);
+
+        // The fixed info starts after the header and the padded "VS_VERSION_INFO" key
+        let fixed_info = &bytes[40..92];
+        assert_eq!(&fixed_info[0..4], &VS_FFI_SIGNATURE.to_le_bytes());
+        assert_eq!(&fixed_info[8..12], &0x0001_0002u32.to_le_bytes());
+    }
+}